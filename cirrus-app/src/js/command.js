@@ -26,4 +26,15 @@ export async function setPlaybackPosition(positionSec) {
 
 export async function setListenUpdatedEvents(isListen) {
   return await invoke('plugin:cirrus|set_listen_updated_events', { isListen: isListen } );
-}
\ No newline at end of file
+}
+export async function login({ username, password }) {
+  return await invoke('plugin:cirrus|login', { username, password });
+}
+
+export async function logout() {
+  return await invoke('plugin:cirrus|logout');
+}
+
+export async function getAuthStatus() {
+  return await invoke('plugin:cirrus|get_auth_status');
+}
@@ -1,6 +1,7 @@
 
 import HomePage from '../pages/home.svelte';
 import AudioListPage from '../pages/audio-list.svelte';
+import LoginPage from '../pages/login.svelte';
 import NotFoundPage from '../pages/404.svelte';
 
 var routes = [
@@ -12,6 +13,10 @@ var routes = [
     path: '/audio-list/',
     component: AudioListPage,
   },
+  {
+    path: '/login/',
+    component: LoginPage,
+  },
   {
     path: '(.*)',
     component: NotFoundPage,
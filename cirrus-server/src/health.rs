@@ -0,0 +1,47 @@
+use std::{sync::Arc, time::Duration};
+
+use tonic_health::{server::HealthReporter, ServingStatus};
+use tracing::warn;
+
+use crate::model::Storage;
+
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+// status of the whole server, which is what orchestrators check by default
+const SERVER_SERVICE_NAME: &'static str = "";
+
+// Updates the status of `grpc.health.v1` by reachability of databases, until the server stops
+pub async fn watch_db_health(
+    mut reporter: HealthReporter,
+    storage: Arc<Storage>,
+) {
+    let mut check_interval = tokio::time::interval(HEALTH_CHECK_INTERVAL);
+    let mut prev_status = None;
+
+    loop {
+        check_interval.tick().await;
+
+        let status = match check_db(&storage).await {
+            Ok(_) => ServingStatus::Serving,
+            Err(err) => {
+                warn!("database is not reachable: {}", err);
+                ServingStatus::NotServing
+            },
+        };
+
+        if prev_status != Some(status) {
+            reporter.set_service_status(SERVER_SERVICE_NAME, status).await;
+            prev_status = Some(status);
+        }
+    }
+}
+
+pub async fn set_not_serving(reporter: &mut HealthReporter) {
+    reporter.set_service_status(SERVER_SERVICE_NAME, ServingStatus::NotServing).await;
+}
+
+// All data is stored at the storage backend, and MongoDB is not connected if it is not the backend
+async fn check_db(storage: &Storage) -> Result<(), anyhow::Error> {
+    storage.library_root.count().await?;
+
+    Ok(())
+}
@@ -0,0 +1,63 @@
+use tracing::{warn, Subscriber};
+use tracing_appender::{non_blocking::WorkerGuard, rolling};
+use tracing_subscriber::{
+    fmt::{self, MakeWriter},
+    layer::SubscriberExt,
+    registry::LookupSpan,
+    util::SubscriberInitExt,
+    EnvFilter, Layer,
+};
+
+use crate::settings::{self, LogFormat, LogRotation};
+
+const DEFAULT_LOG_FILTER: &'static str = "info";
+const LOG_FILE_PREFIX: &'static str = "cirrus-server.log";
+
+// Logs are written to stdout, and to rotating files at `log.file_dir` if it is set. The returned
+// guard flushes logs of files when it is dropped, so it is kept until the server stops.
+// Default settings are used if the config is not loaded
+pub fn init(settings: Option<&settings::Log>) -> Result<Option<WorkerGuard>, anyhow::Error> {
+    let format = settings.map(|item| item.format).unwrap_or(LogFormat::Pretty);
+    let (filter, filter_err) = match settings.map(|item| EnvFilter::try_new(&item.filter)) {
+        Some(Ok(filter)) => (filter, None),
+        Some(Err(err)) => (EnvFilter::new(DEFAULT_LOG_FILTER), Some(err)),
+        None => (EnvFilter::new(DEFAULT_LOG_FILTER), None),
+    };
+
+    let (file_layer, guard) = match settings.filter(|item| !item.file_dir.is_empty()) {
+        Some(settings) => {
+            let file_appender = match settings.file_rotation {
+                LogRotation::Hourly => rolling::hourly(&settings.file_dir, LOG_FILE_PREFIX),
+                LogRotation::Daily => rolling::daily(&settings.file_dir, LOG_FILE_PREFIX),
+                LogRotation::Never => rolling::never(&settings.file_dir, LOG_FILE_PREFIX),
+            };
+            let (file_writer, guard) = tracing_appender::non_blocking(file_appender);
+
+            (Some(create_layer(format, file_writer, false)), Some(guard))
+        },
+        None => (None, None),
+    };
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(create_layer(format, std::io::stdout, true))
+        .with(file_layer)
+        .try_init()?;
+
+    if let Some(err) = filter_err {
+        warn!("invalid log.filter, '{}' is used instead: {}", DEFAULT_LOG_FILTER, err);
+    }
+
+    Ok(guard)
+}
+
+fn create_layer<S, W>(format: LogFormat, writer: W, ansi: bool) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    match format {
+        LogFormat::Pretty => fmt::layer().with_writer(writer).with_ansi(ansi).boxed(),
+        LogFormat::Json => fmt::layer().json().with_writer(writer).boxed(),
+    }
+}
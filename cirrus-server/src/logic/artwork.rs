@@ -0,0 +1,161 @@
+use std::{
+    io::Cursor,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use cirrus_protobuf::api::ArtworkRes;
+use image::{codecs::jpeg::JpegEncoder, imageops::FilterType};
+use tracing::warn;
+
+use crate::{
+    model::{dto, Storage},
+    settings,
+};
+
+#[derive(Debug)]
+pub enum ArtworkError {
+    NotFound { artwork_id: String },
+    SizeNotAllowed { size: u32, allowed_sizes: Vec<u32> },
+}
+
+impl std::fmt::Display for ArtworkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ArtworkError::NotFound { artwork_id } => write!(f, "artwork {} does not exist", artwork_id),
+            ArtworkError::SizeNotAllowed { size, allowed_sizes } => write!(
+                f,
+                "artwork size {} is not allowed, allowed sizes are {:?}",
+                size,
+                allowed_sizes
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ArtworkError {}
+
+// Dimensions that fit in a square of the size, keeping the aspect ratio
+fn get_resized_dimensions(width: u32, height: u32, size: u32) -> (u32, u32) {
+    if width >= height {
+        (size, ((height as u64 * size as u64) / width as u64).max(1) as u32)
+    } else {
+        (((width as u64 * size as u64) / height as u64).max(1) as u32, size)
+    }
+}
+
+fn resize_artwork(
+    data: &[u8],
+    width: u32,
+    height: u32,
+    jpeg_quality: u8,
+) -> Result<Vec<u8>, anyhow::Error> {
+    let image = image::load_from_memory(data)?;
+    let resized = image::imageops::resize(&image.to_rgb8(), width, height, FilterType::Lanczos3);
+
+    let mut encoded = Cursor::new(Vec::new());
+    JpegEncoder::new_with_quality(&mut encoded, jpeg_quality).encode_image(&resized)?;
+
+    Ok(encoded.into_inner())
+}
+
+// Resized artworks are cached at disk by the content hash and the size, and the number of
+// sizes is limited by `allowed_sizes`, so that the cache does not grow without bound
+pub struct Artwork {
+    storage: Arc<Storage>,
+    cache_path: PathBuf,
+    allowed_sizes: Vec<u32>,
+    jpeg_quality: u8,
+}
+
+impl Artwork {
+    pub fn new(settings: &settings::Artwork, storage: Arc<Storage>) -> Result<Self, anyhow::Error> {
+        let cache_path = PathBuf::from(&settings.cache_path);
+        std::fs::create_dir_all(&cache_path)?;
+
+        Ok(Self {
+            storage,
+            cache_path,
+            allowed_sizes: settings.allowed_sizes.clone(),
+            jpeg_quality: settings.jpeg_quality,
+        })
+    }
+
+    // Returns the original artwork if the size is 0 or not smaller than the artwork
+    pub async fn get_artwork(
+        &self,
+        artwork_id: &str,
+        size: u32,
+    ) -> Result<ArtworkRes, anyhow::Error> {
+        if size != 0 && !self.allowed_sizes.contains(&size) {
+            return Err(ArtworkError::SizeNotAllowed {
+                size,
+                allowed_sizes: self.allowed_sizes.clone(),
+            }.into());
+        }
+
+        let artwork = match self.storage.artwork.get_artwork(artwork_id).await? {
+            Some(artwork) => artwork,
+            None => return Err(ArtworkError::NotFound { artwork_id: artwork_id.to_string() }.into()),
+        };
+
+        if size == 0 || size >= artwork.width.max(artwork.height) {
+            return Ok(ArtworkRes {
+                artwork_id: artwork.id,
+                mime_type: artwork.mime_type,
+                width: artwork.width,
+                height: artwork.height,
+                data: artwork.data.bytes,
+            });
+        }
+
+        let (width, height) = get_resized_dimensions(artwork.width, artwork.height, size);
+        let data = self.get_resized_artwork(&artwork, width, height, size).await?;
+
+        Ok(ArtworkRes {
+            artwork_id: artwork.id,
+            mime_type: "image/jpeg".to_string(),
+            width,
+            height,
+            data,
+        })
+    }
+
+    async fn get_resized_artwork(
+        &self,
+        artwork: &dto::Artwork,
+        width: u32,
+        height: u32,
+        size: u32,
+    ) -> Result<Vec<u8>, anyhow::Error> {
+        let cache_file_path = self.cache_path.join(format!("{}_{}.jpg", artwork.id, size));
+
+        if let Ok(data) = tokio::fs::read(&cache_file_path).await {
+            return Ok(data);
+        }
+
+        let original = artwork.data.bytes.clone();
+        let jpeg_quality = self.jpeg_quality;
+
+        // decoding and resizing are CPU bound
+        let data = tokio::task::spawn_blocking(move || {
+            resize_artwork(&original, width, height, jpeg_quality)
+        }).await??;
+
+        if let Err(err) = store_cache_file(&cache_file_path, &data).await {
+            warn!("failed to cache artwork {:?}: {}", cache_file_path, err);
+        }
+
+        Ok(data)
+    }
+}
+
+// Writes to a temporary file first, so that a partially written file is not read
+async fn store_cache_file(path: &Path, data: &[u8]) -> Result<(), anyhow::Error> {
+    let tmp_path = path.with_extension("tmp");
+
+    tokio::fs::write(&tmp_path, data).await?;
+    tokio::fs::rename(&tmp_path, path).await?;
+
+    Ok(())
+}
@@ -0,0 +1,303 @@
+use std::sync::Arc;
+
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use bson::oid::ObjectId;
+use chrono::Utc;
+use cirrus_protobuf::api::{CreateUserReq, TokenRes, UserRes, UserRole};
+use jsonwebtoken::{DecodingKey, EncodingKey, Header, Validation};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::{
+    model::{dto, Storage},
+    settings,
+};
+
+const MIN_TOKEN_SECRET_LEN: usize = 32;
+const MIN_PASSWORD_LEN: usize = 8;
+const MAX_USERNAME_LEN: usize = 64;
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenKind {
+    Access,
+    Refresh,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Claims {
+    // id of the user
+    pub sub: String,
+    pub username: String,
+    pub role: dto::UserRole,
+    pub kind: TokenKind,
+    pub token_version: i64,
+    pub iat: i64,
+    pub exp: i64,
+}
+
+fn to_user_role(role: dto::UserRole) -> UserRole {
+    match role {
+        dto::UserRole::Listener => UserRole::Listener,
+        dto::UserRole::Admin => UserRole::Admin,
+    }
+}
+
+fn from_user_role(role: i32) -> Result<dto::UserRole, anyhow::Error> {
+    match UserRole::from_i32(role) {
+        Some(UserRole::Listener) => Ok(dto::UserRole::Listener),
+        Some(UserRole::Admin) => Ok(dto::UserRole::Admin),
+        None => Err(anyhow::anyhow!("unknown user role: {}", role)),
+    }
+}
+
+// Hashing takes a while on purpose, so that it runs out of async workers
+async fn hash_password(password: &str) -> Result<String, anyhow::Error> {
+    let password = password.to_string();
+
+    tokio::task::spawn_blocking(move || {
+        let salt = SaltString::generate(&mut OsRng);
+
+        match Argon2::default().hash_password(password.as_bytes(), &salt) {
+            Ok(password_hash) => Ok(password_hash.to_string()),
+            Err(err) => Err(anyhow::anyhow!("failed to hash password: {}", err)),
+        }
+    }).await?
+}
+
+async fn verify_password(password: &str, password_hash: &str) -> Result<bool, anyhow::Error> {
+    let password = password.to_string();
+    let password_hash = password_hash.to_string();
+
+    tokio::task::spawn_blocking(move || {
+        let password_hash = match PasswordHash::new(&password_hash) {
+            Ok(password_hash) => password_hash,
+            Err(err) => return Err(anyhow::anyhow!("invalid password hash: {}", err)),
+        };
+
+        Ok(Argon2::default().verify_password(password.as_bytes(), &password_hash).is_ok())
+    }).await?
+}
+
+pub struct Auth {
+    storage: Arc<Storage>,
+    enabled: bool,
+    encoding_key: EncodingKey,
+    decoding_key: DecodingKey,
+    access_token_ttl_sec: i64,
+    refresh_token_ttl_sec: i64,
+}
+
+impl Auth {
+    pub fn new(settings: &settings::Auth, storage: Arc<Storage>) -> Result<Self, anyhow::Error> {
+        Self::check_settings(settings)?;
+
+        Ok(Self {
+            storage,
+            enabled: settings.enabled,
+            encoding_key: EncodingKey::from_secret(settings.token_secret.as_bytes()),
+            decoding_key: DecodingKey::from_secret(settings.token_secret.as_bytes()),
+            access_token_ttl_sec: settings.access_token_ttl_sec,
+            refresh_token_ttl_sec: settings.refresh_token_ttl_sec,
+        })
+    }
+
+    pub fn check_settings(settings: &settings::Auth) -> Result<(), anyhow::Error> {
+        if settings.enabled && settings.token_secret.len() < MIN_TOKEN_SECRET_LEN {
+            return Err(anyhow::anyhow!(
+                "auth.token_secret should be at least {} characters", MIN_TOKEN_SECRET_LEN
+            ));
+        }
+
+        if settings.access_token_ttl_sec <= 0 || settings.refresh_token_ttl_sec <= 0 {
+            return Err(anyhow::anyhow!("lifetimes of tokens should be greater than 0"));
+        }
+
+        Ok(())
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    // Creates an admin with `initial_admin_username` and `initial_admin_password` if there are no
+    // users, so that the first admin can log in
+    pub async fn create_initial_admin(
+        &self,
+        settings: &settings::Auth,
+    ) -> Result<(), anyhow::Error> {
+        if !self.enabled {
+            return Ok(());
+        }
+
+        let user_count = self.storage.user.count().await?;
+        if user_count > 0 {
+            return Ok(());
+        }
+
+        if settings.initial_admin_username.is_empty() || settings.initial_admin_password.is_empty() {
+            warn!("there are no users, set auth.initial_admin_username and auth.initial_admin_password to create an admin");
+            return Ok(());
+        }
+
+        let req = CreateUserReq {
+            username: settings.initial_admin_username.clone(),
+            password: settings.initial_admin_password.clone(),
+            role: UserRole::Admin as i32,
+        };
+
+        self.create_user(&req).await?;
+
+        info!("created initial admin '{}'", settings.initial_admin_username);
+
+        Ok(())
+    }
+
+    pub async fn login(
+        &self,
+        username: &str,
+        password: &str,
+    ) -> Result<TokenRes, anyhow::Error> {
+        let user = self.authenticate(username, password).await?;
+
+        self.issue_tokens(&user)
+    }
+
+    // Checks the password without issuing tokens, for clients that send credentials at requests
+    pub async fn authenticate(
+        &self,
+        username: &str,
+        password: &str,
+    ) -> Result<dto::User, anyhow::Error> {
+        let user = self.storage.user.get_by_username(username.trim()).await?;
+
+        // does not tell whether the user exists
+        let user = match user {
+            Some(user) => user,
+            None => return Err(anyhow::anyhow!("invalid username or password")),
+        };
+
+        if !verify_password(password, &user.password_hash).await? {
+            return Err(anyhow::anyhow!("invalid username or password"));
+        }
+
+        Ok(user)
+    }
+
+    pub async fn refresh_token(
+        &self,
+        refresh_token: &str,
+    ) -> Result<TokenRes, anyhow::Error> {
+        let claims = self.decode_token(refresh_token, TokenKind::Refresh)?;
+        let user = self.get_token_user(&claims).await?;
+
+        // issued with the current role of the user
+        self.issue_tokens(&user)
+    }
+
+    pub async fn logout(
+        &self,
+        refresh_token: &str,
+    ) -> Result<(), anyhow::Error> {
+        let claims = self.decode_token(refresh_token, TokenKind::Refresh)?;
+        let user = self.get_token_user(&claims).await?;
+
+        self.storage.user.revoke_tokens(user.id.as_ref().unwrap()).await
+    }
+
+    pub async fn create_user(
+        &self,
+        req: &CreateUserReq,
+    ) -> Result<UserRes, anyhow::Error> {
+        let username = req.username.trim();
+
+        if username.is_empty() || username.chars().count() > MAX_USERNAME_LEN {
+            return Err(anyhow::anyhow!("username should be 1 to {} characters", MAX_USERNAME_LEN));
+        }
+
+        if req.password.chars().count() < MIN_PASSWORD_LEN {
+            return Err(anyhow::anyhow!("password should be at least {} characters", MIN_PASSWORD_LEN));
+        }
+
+        let role = from_user_role(req.role)?;
+
+        let existing_user = self.storage.user.get_by_username(username).await?;
+
+        if existing_user.is_some() {
+            return Err(anyhow::anyhow!("user {} already exists", username));
+        }
+
+        let user = dto::User::new(username, hash_password(&req.password).await?, role);
+
+        self.storage.user.create(&user).await?;
+
+        Ok(UserRes {
+            id: user.id.as_ref().unwrap().to_string(),
+            username: user.username.clone(),
+            role: to_user_role(user.role) as i32,
+            created_timestamp: user.created_timestamp,
+        })
+    }
+
+    pub fn verify_access_token(&self, access_token: &str) -> Result<Claims, anyhow::Error> {
+        self.decode_token(access_token, TokenKind::Access)
+    }
+
+    async fn get_token_user(
+        &self,
+        claims: &Claims,
+    ) -> Result<dto::User, anyhow::Error> {
+        let user_id = ObjectId::parse_str(&claims.sub)?;
+
+        let user = match self.storage.user.get(&user_id).await? {
+            Some(user) => user,
+            None => return Err(anyhow::anyhow!("user of the token does not exist")),
+        };
+
+        if user.token_version != claims.token_version {
+            return Err(anyhow::anyhow!("token is revoked"));
+        }
+
+        Ok(user)
+    }
+
+    fn decode_token(&self, token: &str, kind: TokenKind) -> Result<Claims, anyhow::Error> {
+        // expiration is validated as well
+        let claims = jsonwebtoken::decode::<Claims>(token, &self.decoding_key, &Validation::default())?
+            .claims;
+
+        if claims.kind != kind {
+            return Err(anyhow::anyhow!("token is not {:?} token", kind));
+        }
+
+        Ok(claims)
+    }
+
+    fn issue_tokens(&self, user: &dto::User) -> Result<TokenRes, anyhow::Error> {
+        let timestamp = Utc::now().timestamp();
+
+        let create_claims = |kind: TokenKind, ttl_sec: i64| Claims {
+            sub: user.id.as_ref().unwrap().to_string(),
+            username: user.username.clone(),
+            role: user.role,
+            kind,
+            token_version: user.token_version,
+            iat: timestamp,
+            exp: timestamp + ttl_sec,
+        };
+
+        let access_claims = create_claims(TokenKind::Access, self.access_token_ttl_sec);
+        let refresh_claims = create_claims(TokenKind::Refresh, self.refresh_token_ttl_sec);
+
+        Ok(TokenRes {
+            access_token: jsonwebtoken::encode(&Header::default(), &access_claims, &self.encoding_key)?,
+            refresh_token: jsonwebtoken::encode(&Header::default(), &refresh_claims, &self.encoding_key)?,
+            access_token_expires_at: access_claims.exp,
+            username: user.username.clone(),
+            role: to_user_role(user.role) as i32,
+        })
+    }
+}
@@ -0,0 +1,136 @@
+use std::sync::Arc;
+
+use cirrus_protobuf::api::{AlbumRes, AlbumSummary, ArtistRes, ArtistSummary};
+
+use crate::model::{storage, Storage};
+
+use super::tag::to_audio_tag_res;
+
+fn to_album_summary(album: &storage::AlbumSummary) -> AlbumSummary {
+    AlbumSummary {
+        album: album.album.clone(),
+        album_artist: album.album_artist.clone(),
+        year: album.year.unwrap_or_default(),
+        track_count: album.track_count,
+        disc_count: album.disc_count,
+        duration: album.duration,
+        compilation: album.compilation,
+        artwork_id: album.artwork_id.clone().unwrap_or_default(),
+    }
+}
+
+fn to_artist_summary(artist: &storage::ArtistSummary) -> ArtistSummary {
+    ArtistSummary {
+        name: artist.name.clone(),
+        album_count: artist.album_count,
+        track_count: artist.track_count,
+    }
+}
+
+pub struct AudioBrowse {
+    storage: Arc<Storage>,
+}
+
+impl AudioBrowse {
+    pub fn new(storage: Arc<Storage>) -> Self {
+        Self { storage }
+    }
+
+    pub async fn list_albums(
+        &self,
+        max_item_num: u64,
+        page: u64,
+    ) -> Result<Vec<AlbumSummary>, anyhow::Error> {
+        if max_item_num == 0 || page == 0 {
+            return Err(anyhow::anyhow!("items per page and page should be greater than 0"));
+        }
+
+        let albums = self.storage.audio_tag
+            .get_albums(
+                storage::AlbumQuery::All,
+                Some(max_item_num * (page - 1)),
+                Some(max_item_num as i64),
+            )
+            .await?;
+
+        Ok(albums.iter().map(to_album_summary).collect())
+    }
+
+    pub async fn get_album(
+        &self,
+        album: &str,
+        album_artist: &str,
+    ) -> Result<AlbumRes, anyhow::Error> {
+        let album_summary = self.storage.audio_tag
+            .get_albums(storage::AlbumQuery::Album { album, album_artist }, None, None)
+            .await?;
+
+        let album_summary = match album_summary.first() {
+            Some(album_summary) => to_album_summary(album_summary),
+            None => return Err(anyhow::anyhow!("album '{}' of '{}' does not exist", album, album_artist)),
+        };
+
+        let mut tracks = self.storage.audio_tag
+            .get_album_tracks(album, album_artist)
+            .await?;
+
+        // tracks without numbers follow numbered ones
+        tracks.sort_by_key(|item| (
+            item.disc.unwrap_or(u32::MAX),
+            item.track.unwrap_or(u32::MAX),
+            item.title.clone(),
+        ));
+
+        Ok(AlbumRes {
+            album: Some(album_summary),
+            tracks: tracks.iter().map(to_audio_tag_res).collect(),
+        })
+    }
+
+    pub async fn list_artists(
+        &self,
+        max_item_num: u64,
+        page: u64,
+    ) -> Result<Vec<ArtistSummary>, anyhow::Error> {
+        if max_item_num == 0 || page == 0 {
+            return Err(anyhow::anyhow!("items per page and page should be greater than 0"));
+        }
+
+        let artists = self.storage.audio_tag
+            .get_artists(max_item_num * (page - 1), max_item_num as i64)
+            .await?;
+
+        Ok(artists.iter().map(to_artist_summary).collect())
+    }
+
+    pub async fn get_artist(
+        &self,
+        name: &str,
+    ) -> Result<ArtistRes, anyhow::Error> {
+        let albums = self.storage.audio_tag
+            .get_albums(storage::AlbumQuery::ArtistAlbums(name), None, None)
+            .await?;
+
+        let appearances = self.storage.audio_tag
+            .get_albums(storage::AlbumQuery::ArtistAppearances(name), None, None)
+            .await?;
+
+        let track_count = self.storage.audio_tag
+            .count_artist_tracks(name)
+            .await?;
+
+        if albums.is_empty() && track_count == 0 {
+            return Err(anyhow::anyhow!("artist '{}' does not exist", name));
+        }
+
+        Ok(ArtistRes {
+            artist: Some(ArtistSummary {
+                name: name.to_string(),
+                album_count: albums.len() as u32,
+                track_count: track_count as u32,
+            }),
+            albums: albums.iter().map(to_album_summary).collect(),
+            appearances: appearances.iter().map(to_album_summary).collect(),
+        })
+    }
+}
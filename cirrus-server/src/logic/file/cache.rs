@@ -0,0 +1,745 @@
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use bson::oid::ObjectId;
+use bytes::{Buf, BufMut, BytesMut};
+use cirrus_protobuf::api::AudioCodec;
+use mongodb::bson;
+use walkdir::WalkDir;
+use tracing::{info, warn};
+
+use crate::{settings, util};
+
+use super::{
+    encoding::EncodingProfile,
+    packet::{Packet, Packets},
+};
+
+// bump this when the chunk file layout changes, so that previous chunks are not read
+const CHUNK_FORMAT_VERSION: u32 = 1;
+const CHUNK_FILE_EXTENSION: &'static str = "bin";
+// idx, frame_ts, frame_len, frame_dur, next_pkt_seek_ts and frame size
+const PACKET_HEADER_LEN: usize = 8 * 5 + 4;
+
+// Parameters that change encoded packets of an audio file
+#[derive(Hash)]
+pub struct CacheVariant {
+    pub modified_ts: i64,
+    pub codec: AudioCodec,
+    pub channels: u32,
+    // only lossy codecs depend on the encoding profile
+    pub encoding_profile: Option<EncodingProfile>,
+    pub packet_len: usize,
+    pub sample_rate: usize,
+}
+
+struct CacheIndexEntry {
+    size: u64,
+    last_access: u64,
+}
+
+#[derive(Default)]
+struct CacheIndex {
+    entries: HashMap<PathBuf, CacheIndexEntry>,
+    access_order: BTreeMap<u64, PathBuf>,
+    total_size: u64,
+    access_tick: u64,
+}
+
+impl CacheIndex {
+    fn insert(&mut self, path: PathBuf, size: u64) {
+        self.remove(&path);
+
+        self.access_tick += 1;
+        self.access_order.insert(self.access_tick, path.clone());
+        self.entries.insert(path, CacheIndexEntry {
+            size,
+            last_access: self.access_tick,
+        });
+        self.total_size += size;
+    }
+
+    fn touch(&mut self, path: &Path) -> bool {
+        let entry = match self.entries.get_mut(path) {
+            Some(entry) => entry,
+            None => return false,
+        };
+
+        self.access_order.remove(&entry.last_access);
+        self.access_tick += 1;
+        entry.last_access = self.access_tick;
+        self.access_order.insert(self.access_tick, path.to_path_buf());
+
+        true
+    }
+
+    fn remove(&mut self, path: &Path) -> bool {
+        match self.entries.remove(path) {
+            Some(entry) => {
+                self.access_order.remove(&entry.last_access);
+                self.total_size -= entry.size;
+
+                true
+            },
+            None => false,
+        }
+    }
+
+    // Removes least recently used entries until total size fits, and returns paths of them
+    fn evict(&mut self, max_size: u64) -> Vec<PathBuf> {
+        let mut evicted = Vec::new();
+
+        while self.total_size > max_size {
+            let path = match self.access_order.values().next() {
+                Some(path) => path.clone(),
+                None => break,
+            };
+
+            self.remove(&path);
+            evicted.push(path);
+        }
+
+        evicted
+    }
+}
+
+pub struct TranscodeCache {
+    enabled: bool,
+    cache_dir: PathBuf,
+    max_size: u64,
+    chunk_packets: usize,
+
+    index: Mutex<CacheIndex>,
+    write_seq: AtomicU64,
+}
+
+impl TranscodeCache {
+    pub fn new(settings: &settings::TranscodeCache) -> Result<Self, anyhow::Error> {
+        if settings.chunk_packets == 0 {
+            return Err(anyhow::anyhow!("transcode cache chunk packets should be greater than 0"));
+        }
+
+        let mut cache = Self {
+            enabled: settings.enabled,
+            cache_dir: PathBuf::from(&settings.path),
+            max_size: settings.max_size_mb * 1024 * 1024,
+            chunk_packets: settings.chunk_packets.try_into().unwrap(),
+
+            index: Mutex::new(Default::default()),
+            write_seq: AtomicU64::new(0),
+        };
+
+        if !cache.enabled {
+            return Ok(cache);
+        }
+
+        std::fs::create_dir_all(&cache.cache_dir)?;
+
+        let mut index = Self::load_index(&cache.cache_dir)?;
+        for path in index.evict(cache.max_size) {
+            Self::remove_chunk_file(&path);
+        }
+
+        info!(
+            "transcode cache at {:?} uses {} of {} bytes",
+            cache.cache_dir,
+            index.total_size,
+            cache.max_size
+        );
+
+        cache.index = Mutex::new(index);
+
+        Ok(cache)
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn get_variant_dir(&self, audio_file_id: &ObjectId, variant: &CacheVariant) -> PathBuf {
+        let variant_hash = util::hash::get_hashed_value(
+            &(CHUNK_FORMAT_VERSION, self.chunk_packets, variant)
+        );
+
+        self.cache_dir
+            .join(audio_file_id.to_hex())
+            .join(format!("{:016x}", variant_hash as u64))
+    }
+
+    pub fn load_chunk(&self, variant_dir: &Path, chunk_idx: usize) -> Option<Vec<Packet>> {
+        let chunk_path = Self::get_chunk_path(variant_dir, chunk_idx);
+
+        if !self.index.lock().unwrap().touch(&chunk_path) {
+            return None;
+        }
+
+        let packets = std::fs::read(&chunk_path)
+            .map_err(anyhow::Error::from)
+            .and_then(|chunk| decode_chunk(&chunk));
+
+        match packets {
+            Ok(packets) => Some(packets),
+            Err(err) => {
+                warn!("failed to read transcode cache chunk {:?}: {}", chunk_path, err);
+
+                self.index.lock().unwrap().remove(&chunk_path);
+                Self::remove_chunk_file(&chunk_path);
+
+                None
+            },
+        }
+    }
+
+    pub fn store_chunk(
+        &self,
+        variant_dir: &Path,
+        chunk_idx: usize,
+        packets: &[Packet],
+    ) -> Result<(), anyhow::Error> {
+        let chunk = encode_chunk(packets);
+        let chunk_path = Self::get_chunk_path(variant_dir, chunk_idx);
+
+        // write to a temporary file first, so that readers never see a partially written chunk
+        let write_seq = self.write_seq.fetch_add(1, Ordering::Relaxed);
+        let tmp_chunk_path = variant_dir.join(format!("{}.{}.tmp", chunk_idx, write_seq));
+
+        std::fs::create_dir_all(variant_dir)?;
+        std::fs::write(&tmp_chunk_path, &chunk)?;
+
+        if let Err(err) = std::fs::rename(&tmp_chunk_path, &chunk_path) {
+            let _ = std::fs::remove_file(&tmp_chunk_path);
+
+            return Err(anyhow::anyhow!(err));
+        }
+
+        let evicted = {
+            let mut index = self.index.lock().unwrap();
+            index.insert(chunk_path, chunk.len().try_into().unwrap());
+
+            index.evict(self.max_size)
+        };
+
+        for path in evicted.iter() {
+            Self::remove_chunk_file(path);
+        }
+
+        Ok(())
+    }
+
+    pub fn invalidate_audio_file(&self, audio_file_id: &ObjectId) {
+        if !self.enabled {
+            return;
+        }
+
+        let audio_file_dir = self.cache_dir.join(audio_file_id.to_hex());
+
+        {
+            let mut index = self.index.lock().unwrap();
+            let invalidated_paths: Vec<_> = index.entries
+                .keys()
+                .filter(|path| path.starts_with(&audio_file_dir))
+                .cloned()
+                .collect();
+
+            for path in invalidated_paths.iter() {
+                index.remove(path);
+            }
+        }
+
+        if !audio_file_dir.exists() {
+            return;
+        }
+
+        match std::fs::remove_dir_all(&audio_file_dir) {
+            Ok(_) => info!("invalidated transcode cache of audio file {}", audio_file_id),
+            Err(err) => warn!("failed to invalidate transcode cache of audio file {}: {}", audio_file_id, err),
+        }
+    }
+
+    fn get_chunk_path(variant_dir: &Path, chunk_idx: usize) -> PathBuf {
+        variant_dir.join(format!("{}.{}", chunk_idx, CHUNK_FILE_EXTENSION))
+    }
+
+    // Least recently used order is restored from modified time of chunk files
+    fn load_index(cache_dir: &Path) -> Result<CacheIndex, anyhow::Error> {
+        let mut chunk_files = Vec::new();
+        let mut ignored_count = 0;
+
+        for entry in WalkDir::new(cache_dir)
+            .max_depth(3)
+            .into_iter()
+            .filter_map(|item| item.ok())
+            .filter(|item| item.file_type().is_file())
+        {
+            let path = entry.into_path();
+
+            match get_cache_file_kind(cache_dir, &path) {
+                Some(CacheFileKind::Chunk) => {
+                    let metadata = path.metadata()?;
+                    chunk_files.push((path, metadata.len(), metadata.modified()?));
+                },
+                // temporary file of an interrupted write
+                Some(CacheFileKind::TmpChunk) => {
+                    let _ = std::fs::remove_file(&path);
+                },
+                None => ignored_count += 1,
+            }
+        }
+
+        if ignored_count > 0 {
+            warn!("ignored {} files under transcode cache {:?} that are not chunks", ignored_count, cache_dir);
+        }
+
+        chunk_files.sort_by_key(|(_, _, modified)| *modified);
+
+        let mut index = CacheIndex::default();
+        for (path, size, _) in chunk_files.into_iter() {
+            index.insert(path, size);
+        }
+
+        Ok(index)
+    }
+
+    fn remove_chunk_file(path: &Path) {
+        if let Err(err) = std::fs::remove_file(path) {
+            if err.kind() != std::io::ErrorKind::NotFound {
+                warn!("failed to remove transcode cache chunk {:?}: {}", path, err);
+            }
+        }
+
+        // clean up variant and audio file directories if they become empty
+        for dir in path.ancestors().skip(1).take(2) {
+            if std::fs::remove_dir(dir).is_err() {
+                break;
+            }
+        }
+    }
+}
+
+enum CacheFileKind {
+    Chunk,
+    TmpChunk,
+}
+
+fn is_hex_of_len(s: &str, len: usize) -> bool {
+    s.len() == len && s.bytes().all(|item| item.is_ascii_hexdigit())
+}
+
+fn is_number(s: &str) -> bool {
+    !s.is_empty() && s.bytes().all(|item| item.is_ascii_digit())
+}
+
+// Files of the cache are `<audio file id>/<variant hash>/<chunk idx>.bin` and temporary files of
+// these, and other files under the cache directory are never touched
+fn get_cache_file_kind(cache_dir: &Path, path: &Path) -> Option<CacheFileKind> {
+    let components = path
+        .strip_prefix(cache_dir)
+        .ok()?
+        .iter()
+        .map(|item| item.to_str())
+        .collect::<Option<Vec<_>>>()?;
+
+    let filename = match components.as_slice() {
+        [audio_file_dir, variant_dir, filename] if is_hex_of_len(audio_file_dir, 24) && is_hex_of_len(variant_dir, 16) => *filename,
+        _ => return None,
+    };
+
+    match filename.split('.').collect::<Vec<_>>().as_slice() {
+        [chunk_idx, CHUNK_FILE_EXTENSION] if is_number(chunk_idx) => Some(CacheFileKind::Chunk),
+        [chunk_idx, write_seq, "tmp"] if is_number(chunk_idx) && is_number(write_seq) => Some(CacheFileKind::TmpChunk),
+        _ => None,
+    }
+}
+
+fn encode_chunk(packets: &[Packet]) -> Vec<u8> {
+    let mut buf = BytesMut::new();
+
+    buf.put_u32_le(packets.len().try_into().unwrap());
+
+    for packet in packets.iter() {
+        buf.put_u64_le(packet.idx as u64);
+        buf.put_f64_le(packet.frame_ts);
+        buf.put_u64_le(packet.frame_len as u64);
+        buf.put_f64_le(packet.frame_dur);
+        buf.put_u64_le(packet.next_pkt_seek_ts);
+        buf.put_u32_le(packet.frame.len().try_into().unwrap());
+        buf.put_slice(&packet.frame);
+    }
+
+    buf.to_vec()
+}
+
+fn decode_chunk(mut buf: &[u8]) -> Result<Vec<Packet>, anyhow::Error> {
+    if buf.remaining() < 4 {
+        return Err(anyhow::anyhow!("truncated chunk"));
+    }
+
+    let packet_num = buf.get_u32_le() as usize;
+    let mut packets = Vec::with_capacity(packet_num);
+
+    for _ in 0..packet_num {
+        if buf.remaining() < PACKET_HEADER_LEN {
+            return Err(anyhow::anyhow!("truncated packet header"));
+        }
+
+        let idx = buf.get_u64_le() as usize;
+        let frame_ts = buf.get_f64_le();
+        let frame_len = buf.get_u64_le() as usize;
+        let frame_dur = buf.get_f64_le();
+        let next_pkt_seek_ts = buf.get_u64_le();
+        let frame_size = buf.get_u32_le() as usize;
+
+        if buf.remaining() < frame_size {
+            return Err(anyhow::anyhow!("truncated packet frame"));
+        }
+
+        let frame = buf[..frame_size].to_vec();
+        buf.advance(frame_size);
+
+        packets.push(Packet {
+            idx,
+
+            frame_ts,
+            frame,
+            frame_len,
+            frame_dur,
+
+            next_pkt_seek_ts,
+        });
+    }
+
+    if buf.has_remaining() {
+        return Err(anyhow::anyhow!("unexpected trailing data"));
+    }
+
+    Ok(packets)
+}
+
+pub fn get_modified_timestamp(path: &Path) -> Result<i64, anyhow::Error> {
+    if !path.exists() {
+        return Err(anyhow::anyhow!("audio file {:?} does not exist", path));
+    }
+
+    util::path::get_timestamp(path)
+}
+
+// Serves packets from chunks of the transcode cache, and encodes chunks that are not cached
+pub struct CachedPackets {
+    cache: Arc<TranscodeCache>,
+    variant_dir: PathBuf,
+    create_packets: Box<dyn Fn(usize, usize) -> Result<Packets, anyhow::Error> + Send>,
+
+    chunk_packets: VecDeque<Packet>,
+    next_chunk_idx: usize,
+    next_packet_idx: usize,
+    end_packet_idx: usize,
+    reached_end: bool,
+}
+
+impl CachedPackets {
+    pub fn new(
+        cache: Arc<TranscodeCache>,
+        variant_dir: PathBuf,
+        pkt_start_idx: usize,
+        pkt_num: usize,
+        create_packets: Box<dyn Fn(usize, usize) -> Result<Packets, anyhow::Error> + Send>,
+    ) -> Result<Self, anyhow::Error> {
+        let mut packets = Self {
+            next_chunk_idx: pkt_start_idx / cache.chunk_packets,
+            cache,
+            variant_dir,
+            create_packets,
+
+            chunk_packets: VecDeque::new(),
+            next_packet_idx: pkt_start_idx,
+            end_packet_idx: pkt_start_idx + pkt_num,
+            reached_end: pkt_num == 0,
+        };
+
+        // errors of the first chunk (e.g. seek out of range) are returned to the caller
+        if !packets.reached_end {
+            packets.fill_chunk()?;
+        }
+
+        Ok(packets)
+    }
+
+    fn fill_chunk(&mut self) -> Result<(), anyhow::Error> {
+        let chunk_len = self.cache.chunk_packets;
+        let chunk_idx = self.next_chunk_idx;
+        self.next_chunk_idx += 1;
+
+        let packets = match self.cache.load_chunk(&self.variant_dir, chunk_idx) {
+            Some(packets) => packets,
+            None => {
+                let mut chunk_packets = (self.create_packets)(chunk_idx * chunk_len, chunk_len)?;
+                let packets: Vec<_> = chunk_packets.by_ref().collect();
+
+                // a chunk cut short by an error is served but not cached, and ends the stream
+                if chunk_packets.is_failed() {
+                    self.reached_end = true;
+                } else if let Err(err) = self.cache.store_chunk(&self.variant_dir, chunk_idx, &packets) {
+                    warn!("failed to write transcode cache chunk: {}", err);
+                }
+
+                packets
+            },
+        };
+
+        // only the last chunk of an audio file has fewer packets
+        if packets.len() < chunk_len {
+            self.reached_end = true;
+        }
+
+        self.chunk_packets = packets.into();
+
+        Ok(())
+    }
+}
+
+impl Iterator for CachedPackets {
+    type Item = Packet;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.next_packet_idx >= self.end_packet_idx {
+                return None;
+            }
+
+            if let Some(packet) = self.chunk_packets.pop_front() {
+                if packet.idx < self.next_packet_idx {
+                    continue;
+                }
+
+                if packet.idx >= self.end_packet_idx {
+                    return None;
+                }
+
+                self.next_packet_idx = packet.idx + 1;
+
+                return Some(packet);
+            }
+
+            if self.reached_end {
+                return None;
+            }
+
+            if let Err(err) = self.fill_chunk() {
+                warn!("failed to read packets: {}", err);
+
+                return None;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        path::{Path, PathBuf},
+        sync::{atomic::AtomicU64, Mutex},
+    };
+
+    use bson::oid::ObjectId;
+    use mongodb::bson;
+
+    use super::{
+        decode_chunk, encode_chunk, get_cache_file_kind, CacheFileKind, CacheIndex, Packet,
+        TranscodeCache,
+    };
+
+    fn create_packet(idx: usize, frame_size: usize) -> Packet {
+        Packet {
+            idx,
+
+            frame_ts: idx as f64 * 0.02,
+            frame: (0..frame_size).map(|item| (item + idx) as u8).collect(),
+            frame_len: 960,
+            frame_dur: 0.02,
+
+            next_pkt_seek_ts: (idx as u64 + 1) * 960,
+        }
+    }
+
+    fn assert_packets_eq(actual: &[Packet], expected: &[Packet]) {
+        assert_eq!(actual.len(), expected.len());
+
+        for (actual_packet, expected_packet) in actual.iter().zip(expected.iter()) {
+            assert_eq!(actual_packet.idx, expected_packet.idx);
+            assert_eq!(actual_packet.frame_ts, expected_packet.frame_ts);
+            assert_eq!(actual_packet.frame, expected_packet.frame);
+            assert_eq!(actual_packet.frame_len, expected_packet.frame_len);
+            assert_eq!(actual_packet.frame_dur, expected_packet.frame_dur);
+            assert_eq!(actual_packet.next_pkt_seek_ts, expected_packet.next_pkt_seek_ts);
+        }
+    }
+
+    // A cache under the temporary directory, which is removed when dropped
+    struct TestCache {
+        cache: TranscodeCache,
+    }
+
+    impl TestCache {
+        fn new(max_size: u64) -> Self {
+            let cache_dir = std::env::temp_dir().join(format!("cirrus-cache-test-{}", ObjectId::new().to_hex()));
+            std::fs::create_dir_all(&cache_dir).unwrap();
+
+            Self {
+                cache: TranscodeCache {
+                    enabled: true,
+                    cache_dir,
+                    max_size,
+                    chunk_packets: 4,
+
+                    index: Mutex::new(Default::default()),
+                    write_seq: AtomicU64::new(0),
+                },
+            }
+        }
+
+        fn variant_dir(&self, variant: &str) -> PathBuf {
+            self.cache.cache_dir
+                .join(ObjectId::new().to_hex())
+                .join(variant)
+        }
+    }
+
+    impl Drop for TestCache {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.cache.cache_dir);
+        }
+    }
+
+    #[test]
+    fn encode_and_decode_chunk() {
+        let packets: Vec<_> = (10..14).map(|idx| create_packet(idx, idx * 3)).collect();
+
+        let decoded = decode_chunk(&encode_chunk(&packets)).unwrap();
+        assert_packets_eq(&decoded, &packets);
+
+        // the last chunk of an audio file may be empty
+        assert!(decode_chunk(&encode_chunk(&[])).unwrap().is_empty());
+    }
+
+    #[test]
+    fn reject_malformed_chunk() {
+        let chunk = encode_chunk(&[create_packet(0, 16), create_packet(1, 16)]);
+
+        assert!(decode_chunk(&chunk[..2]).is_err());
+        // header of the second packet is cut
+        assert!(decode_chunk(&chunk[..chunk.len() - 16 - 8]).is_err());
+        // frame of the second packet is cut
+        assert!(decode_chunk(&chunk[..chunk.len() - 1]).is_err());
+
+        let mut trailing_chunk = chunk.clone();
+        trailing_chunk.push(0);
+        assert!(decode_chunk(&trailing_chunk).is_err());
+    }
+
+    #[test]
+    fn evict_least_recently_used() {
+        let mut index = CacheIndex::default();
+
+        index.insert(PathBuf::from("a"), 10);
+        index.insert(PathBuf::from("b"), 10);
+        index.insert(PathBuf::from("c"), 10);
+        assert_eq!(index.total_size, 30);
+
+        assert!(index.touch(Path::new("a")));
+        assert!(!index.touch(Path::new("d")));
+
+        assert_eq!(index.evict(30), Vec::<PathBuf>::new());
+        assert_eq!(index.evict(15), vec![PathBuf::from("b"), PathBuf::from("c")]);
+        assert_eq!(index.total_size, 10);
+
+        assert_eq!(index.evict(0), vec![PathBuf::from("a")]);
+        assert!(index.entries.is_empty());
+        assert!(index.access_order.is_empty());
+    }
+
+    #[test]
+    fn replace_index_entry() {
+        let mut index = CacheIndex::default();
+
+        index.insert(PathBuf::from("a"), 10);
+        index.insert(PathBuf::from("b"), 10);
+        index.insert(PathBuf::from("a"), 5);
+
+        assert_eq!(index.total_size, 15);
+        assert_eq!(index.entries.len(), 2);
+        assert_eq!(index.evict(5), vec![PathBuf::from("b")]);
+    }
+
+    #[test]
+    fn store_and_load_chunk() {
+        let test_cache = TestCache::new(1024 * 1024);
+        let variant_dir = test_cache.variant_dir("0123456789abcdef");
+        let packets: Vec<_> = (4..8).map(|idx| create_packet(idx, 32)).collect();
+
+        assert!(test_cache.cache.load_chunk(&variant_dir, 1).is_none());
+
+        test_cache.cache.store_chunk(&variant_dir, 1, &packets).unwrap();
+
+        assert_packets_eq(&test_cache.cache.load_chunk(&variant_dir, 1).unwrap(), &packets);
+        assert!(test_cache.cache.load_chunk(&variant_dir, 2).is_none());
+    }
+
+    #[test]
+    fn evict_stored_chunks_over_max_size() {
+        let chunk_size = encode_chunk(&[create_packet(0, 100)]).len() as u64;
+        let test_cache = TestCache::new(chunk_size * 2);
+        let variant_dir = test_cache.variant_dir("0123456789abcdef");
+
+        for chunk_idx in 0..2 {
+            test_cache.cache.store_chunk(&variant_dir, chunk_idx, &[create_packet(chunk_idx, 100)]).unwrap();
+        }
+
+        // the first chunk becomes the most recently used one
+        assert!(test_cache.cache.load_chunk(&variant_dir, 0).is_some());
+
+        test_cache.cache.store_chunk(&variant_dir, 2, &[create_packet(2, 100)]).unwrap();
+
+        assert!(test_cache.cache.load_chunk(&variant_dir, 0).is_some());
+        assert!(test_cache.cache.load_chunk(&variant_dir, 1).is_none());
+        assert!(!TranscodeCache::get_chunk_path(&variant_dir, 1).exists());
+        assert!(test_cache.cache.load_chunk(&variant_dir, 2).is_some());
+    }
+
+    #[test]
+    fn remove_corrupted_chunk() {
+        let test_cache = TestCache::new(1024 * 1024);
+        let variant_dir = test_cache.variant_dir("0123456789abcdef");
+
+        test_cache.cache.store_chunk(&variant_dir, 0, &[create_packet(0, 16)]).unwrap();
+
+        let chunk_path = TranscodeCache::get_chunk_path(&variant_dir, 0);
+        std::fs::write(&chunk_path, [1, 2, 3]).unwrap();
+
+        assert!(test_cache.cache.load_chunk(&variant_dir, 0).is_none());
+        assert!(!chunk_path.exists());
+        assert_eq!(test_cache.cache.index.lock().unwrap().total_size, 0);
+    }
+
+    #[test]
+    fn classify_cache_files() {
+        let cache_dir = Path::new("/cache");
+        let variant_dir = cache_dir
+            .join("0123456789abcdef01234567")
+            .join("0123456789abcdef");
+
+        assert!(matches!(get_cache_file_kind(cache_dir, &variant_dir.join("12.bin")), Some(CacheFileKind::Chunk)));
+        assert!(matches!(get_cache_file_kind(cache_dir, &variant_dir.join("12.3.tmp")), Some(CacheFileKind::TmpChunk)));
+
+        assert!(get_cache_file_kind(cache_dir, &variant_dir.join("a.bin")).is_none());
+        assert!(get_cache_file_kind(cache_dir, &variant_dir.join("12.txt")).is_none());
+        assert!(get_cache_file_kind(cache_dir, &cache_dir.join("0123456789abcdef01234567").join("12.bin")).is_none());
+        assert!(get_cache_file_kind(cache_dir, &cache_dir.join("notes").join("0123456789abcdef").join("12.bin")).is_none());
+        assert!(get_cache_file_kind(cache_dir, Path::new("/other/12.bin")).is_none());
+    }
+}
@@ -0,0 +1,276 @@
+use symphonia::core::audio::Channels;
+
+use super::InvalidAudioRequestError;
+
+const MIX_CENTRE_LEVEL: f32 = std::f32::consts::FRAC_1_SQRT_2;
+const MIX_SURROUND_LEVEL: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+// Packets of every codec are mono or stereo, because the Opus encoder in this server supports
+// them only, so multichannel sources are down-mixed
+pub fn resolve_output_channels(
+    source_channels: Option<usize>,
+    requested_channels: u32,
+) -> Result<usize, anyhow::Error> {
+    match requested_channels {
+        0 => match source_channels {
+            Some(1) => Ok(1),
+            _ => Ok(2),
+        },
+        1 | 2 => Ok(requested_channels as usize),
+        _ => Err(InvalidAudioRequestError::new(format!(
+            "unsupported output channels: {}, packets are mono or stereo",
+            requested_channels
+        )).into()),
+    }
+}
+
+// Returns the contribution of a source channel to the (left, right) output channels
+fn get_stereo_coefficient(channel: Channels) -> (f32, f32) {
+    if channel == Channels::FRONT_LEFT {
+        (1., 0.)
+    } else if channel == Channels::FRONT_RIGHT {
+        (0., 1.)
+    } else if channel == Channels::FRONT_CENTRE
+        || channel == Channels::FRONT_CENTRE_HIGH
+        || channel == Channels::TOP_FRONT_CENTRE {
+        (MIX_CENTRE_LEVEL, MIX_CENTRE_LEVEL)
+    } else if channel == Channels::LFE1 || channel == Channels::LFE2 {
+        (0., 0.)
+    } else if channel == Channels::REAR_CENTRE
+        || channel == Channels::TOP_CENTRE
+        || channel == Channels::TOP_REAR_CENTRE {
+        (MIX_SURROUND_LEVEL / 2., MIX_SURROUND_LEVEL / 2.)
+    } else if channel == Channels::FRONT_LEFT_CENTRE
+        || channel == Channels::FRONT_LEFT_WIDE
+        || channel == Channels::FRONT_LEFT_HIGH
+        || channel == Channels::TOP_FRONT_LEFT {
+        (MIX_CENTRE_LEVEL, 0.)
+    } else if channel == Channels::FRONT_RIGHT_CENTRE
+        || channel == Channels::FRONT_RIGHT_WIDE
+        || channel == Channels::FRONT_RIGHT_HIGH
+        || channel == Channels::TOP_FRONT_RIGHT {
+        (0., MIX_CENTRE_LEVEL)
+    } else if channel == Channels::REAR_LEFT
+        || channel == Channels::SIDE_LEFT
+        || channel == Channels::REAR_LEFT_CENTRE
+        || channel == Channels::TOP_REAR_LEFT {
+        (MIX_SURROUND_LEVEL, 0.)
+    } else if channel == Channels::REAR_RIGHT
+        || channel == Channels::SIDE_RIGHT
+        || channel == Channels::REAR_RIGHT_CENTRE
+        || channel == Channels::TOP_REAR_RIGHT {
+        (0., MIX_SURROUND_LEVEL)
+    } else {
+        (0.5, 0.5)
+    }
+}
+
+pub struct ChannelMixer {
+    source_channels: usize,
+    output_channels: usize,
+    // coefficients[output channel][source channel]
+    coefficients: Vec<Vec<f32>>,
+    is_passthrough: bool,
+}
+
+impl ChannelMixer {
+    pub fn new(
+        source_layout: Channels,
+        output_channels: usize,
+    ) -> Result<Self, anyhow::Error> {
+        let source_channels = source_layout.count();
+
+        if source_channels == 0 {
+            return Err(anyhow::anyhow!("source has no channels"));
+        }
+
+        // mono source is up-mixed by duplicating the channel regardless of its position
+        let stereo_coefficients = if source_channels == 1 {
+            vec![(1., 1.)]
+        } else {
+            source_layout
+                .iter()
+                .map(get_stereo_coefficient)
+                .collect::<Vec<_>>()
+        };
+
+        let (left_gain, right_gain) = stereo_coefficients
+            .iter()
+            .fold((0., 0.), |acc, (l, r)| (acc.0 + l, acc.1 + r));
+
+        // normalize to prevent clipping of the down-mixed signal
+        let left_norm = if left_gain > 1. { 1. / left_gain } else { 1. };
+        let right_norm = if right_gain > 1. { 1. / right_gain } else { 1. };
+
+        let left = stereo_coefficients.iter().map(|(l, _)| l * left_norm).collect::<Vec<f32>>();
+        let right = stereo_coefficients.iter().map(|(_, r)| r * right_norm).collect::<Vec<f32>>();
+
+        let coefficients = match output_channels {
+            1 => vec![
+                left.iter().zip(right.iter()).map(|(l, r)| (l + r) / 2.).collect()
+            ],
+            2 => vec![left, right],
+            _ => return Err(anyhow::anyhow!("unsupported output channels: {}", output_channels)),
+        };
+
+        let is_passthrough = source_channels == output_channels
+            && (source_channels == 1 || source_layout == Channels::FRONT_LEFT | Channels::FRONT_RIGHT);
+
+        Ok(Self {
+            source_channels,
+            output_channels,
+            coefficients,
+            is_passthrough,
+        })
+    }
+
+    pub fn mix(&self, input: &[f32], output: &mut Vec<f32>) {
+        if self.is_passthrough {
+            output.extend_from_slice(input);
+            return;
+        }
+
+        output.reserve(input.len() / self.source_channels * self.output_channels);
+
+        for frame in input.chunks_exact(self.source_channels) {
+            for ch_coefficients in self.coefficients.iter() {
+                let sample = frame
+                    .iter()
+                    .zip(ch_coefficients.iter())
+                    .map(|(s, c)| s * c)
+                    .sum();
+
+                output.push(sample);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use symphonia::core::audio::Channels;
+
+    use super::{resolve_output_channels, ChannelMixer, MIX_CENTRE_LEVEL, MIX_SURROUND_LEVEL};
+    use crate::logic::file::InvalidAudioRequestError;
+
+    const EPSILON: f32 = 1e-6;
+
+    fn mix(source_layout: Channels, output_channels: usize, input: &[f32]) -> Vec<f32> {
+        let mixer = ChannelMixer::new(source_layout, output_channels).unwrap();
+        let mut output = Vec::new();
+        mixer.mix(input, &mut output);
+
+        output
+    }
+
+    fn assert_samples_eq(actual: &[f32], expected: &[f32]) {
+        assert_eq!(actual.len(), expected.len(), "{:?} != {:?}", actual, expected);
+
+        for (actual_sample, expected_sample) in actual.iter().zip(expected.iter()) {
+            assert!((actual_sample - expected_sample).abs() < EPSILON, "{:?} != {:?}", actual, expected);
+        }
+    }
+
+    fn surround_5_1() -> Channels {
+        Channels::FRONT_LEFT
+            | Channels::FRONT_RIGHT
+            | Channels::FRONT_CENTRE
+            | Channels::LFE1
+            | Channels::REAR_LEFT
+            | Channels::REAR_RIGHT
+    }
+
+    #[test]
+    fn resolve_output_channels_of_source() {
+        assert_eq!(resolve_output_channels(Some(1), 0).unwrap(), 1);
+        assert_eq!(resolve_output_channels(Some(2), 0).unwrap(), 2);
+        // multichannel and unknown layouts are down-mixed to stereo
+        assert_eq!(resolve_output_channels(Some(6), 0).unwrap(), 2);
+        assert_eq!(resolve_output_channels(None, 0).unwrap(), 2);
+        assert_eq!(resolve_output_channels(Some(6), 1).unwrap(), 1);
+        assert_eq!(resolve_output_channels(Some(1), 2).unwrap(), 2);
+    }
+
+    #[test]
+    fn reject_output_channels_above_stereo() {
+        for requested_channels in [3, 6, 8] {
+            let err = resolve_output_channels(Some(6), requested_channels).unwrap_err();
+
+            assert!(err.is::<InvalidAudioRequestError>());
+        }
+    }
+
+    #[test]
+    fn pass_through_same_layout() {
+        let stereo = [0.1, -0.2, 0.3, -0.4];
+        assert_eq!(mix(Channels::FRONT_LEFT | Channels::FRONT_RIGHT, 2, &stereo), stereo);
+
+        let mono = [0.1, -0.2, 0.3];
+        assert_eq!(mix(Channels::FRONT_CENTRE, 1, &mono), mono);
+    }
+
+    #[test]
+    fn up_mix_mono_to_both_channels() {
+        // a mono source is duplicated regardless of its position
+        for source_layout in [Channels::FRONT_LEFT, Channels::FRONT_CENTRE] {
+            assert_samples_eq(&mix(source_layout, 2, &[0.5, -1.]), &[0.5, 0.5, -1., -1.]);
+        }
+    }
+
+    #[test]
+    fn down_mix_stereo_to_mono() {
+        let output = mix(Channels::FRONT_LEFT | Channels::FRONT_RIGHT, 1, &[1., 0., 0.5, -0.5, 1., 1.]);
+
+        assert_samples_eq(&output, &[0.5, 0., 1.]);
+    }
+
+    #[test]
+    fn down_mix_surround_coefficients() {
+        // gain of each output channel is 1 + centre + surround, which is normalized to 1
+        let norm = 1. / (1. + MIX_CENTRE_LEVEL + MIX_SURROUND_LEVEL);
+
+        // channels are in the order of the layout: FL, FR, FC, LFE, RL, RR
+        let frames = [
+            [1., 0., 0., 0., 0., 0.],
+            [0., 1., 0., 0., 0., 0.],
+            [0., 0., 1., 0., 0., 0.],
+            [0., 0., 0., 1., 0., 0.],
+            [0., 0., 0., 0., 1., 0.],
+            [0., 0., 0., 0., 0., 1.],
+        ];
+        let expected = [
+            [norm, 0.],
+            [0., norm],
+            [MIX_CENTRE_LEVEL * norm, MIX_CENTRE_LEVEL * norm],
+            // LFE is dropped
+            [0., 0.],
+            [MIX_SURROUND_LEVEL * norm, 0.],
+            [0., MIX_SURROUND_LEVEL * norm],
+        ];
+
+        for (frame, expected_frame) in frames.iter().zip(expected.iter()) {
+            assert_samples_eq(&mix(surround_5_1(), 2, frame), expected_frame);
+        }
+    }
+
+    #[test]
+    fn down_mix_surround_without_clipping() {
+        let full_scale = [1.; 6];
+
+        let stereo = mix(surround_5_1(), 2, &full_scale);
+        assert_samples_eq(&stereo, &[1., 1.]);
+
+        let mono = mix(surround_5_1(), 1, &full_scale);
+        assert_samples_eq(&mono, &[1.]);
+
+        let inverted = mix(surround_5_1(), 2, &[-1.; 6]);
+        assert!(inverted.iter().all(|sample| *sample >= -1. - EPSILON));
+    }
+
+    #[test]
+    fn mix_every_frame() {
+        let frames = [[1.; 6], [0.; 6], [-1.; 6]].concat();
+
+        assert_samples_eq(&mix(surround_5_1(), 2, &frames), &[1., 1., 0., 0., -1., -1.]);
+    }
+}
@@ -0,0 +1,213 @@
+use cirrus_protobuf::api::AudioCodec;
+use rubato::Resampler;
+
+use super::{encoding::EncodingProfile, flac::FlacEncoder, InvalidAudioRequestError};
+
+// recommended maximum packet size of the Opus encoder
+const MAX_OPUS_PACKET_BYTES: usize = 4000;
+
+pub fn resolve_codec(codec: i32) -> Result<AudioCodec, anyhow::Error> {
+    match AudioCodec::from_i32(codec) {
+        Some(codec) => Ok(codec),
+        None => Err(InvalidAudioRequestError::new(format!("unknown audio codec: {}", codec)).into()),
+    }
+}
+
+// Lossless packets keep the source sample rate, and have the same duration as Opus packets
+pub fn get_lossless_packet_len(
+    source_sample_rate: u32,
+    packet_len: u32,
+    packet_sample_rate: u32,
+) -> usize {
+    (source_sample_rate as u64 * packet_len as u64 / packet_sample_rate as u64) as usize
+}
+
+// Sources are decoded as 32-bit float, which represents up to 24-bit integer samples exactly,
+// so that sources of more bits are not encoded losslessly
+pub fn get_lossless_bits_per_sample(source_bits_per_sample: Option<u32>) -> Result<u32, anyhow::Error> {
+    match source_bits_per_sample {
+        None => Ok(24),
+        Some(bits_per_sample) if bits_per_sample <= 16 => Ok(16),
+        Some(bits_per_sample) if bits_per_sample <= 24 => Ok(24),
+        Some(bits_per_sample) => Err(anyhow::anyhow!(
+            "lossless codecs support sources up to 24 bits per sample: {}", bits_per_sample
+        )),
+    }
+}
+
+fn convert_to_int_samples(samples: &[f32], bits_per_sample: u32) -> Vec<i32> {
+    let scale = (1i64 << (bits_per_sample - 1)) as f32;
+    let min = -(1i32 << (bits_per_sample - 1));
+    let max = (1i32 << (bits_per_sample - 1)) - 1;
+
+    samples
+        .iter()
+        .map(|sample| ((sample * scale).round() as i32).clamp(min, max))
+        .collect()
+}
+
+pub trait PacketEncoder: Send {
+    fn encode(&mut self, packet_idx: usize, samples: Vec<f32>) -> Result<Vec<u8>, anyhow::Error>;
+}
+
+pub struct OpusPacketEncoder {
+    resampler: rubato::FftFixedOut<f32>,
+    resampler_input_buf: Vec<Vec<f32>>,
+    resampler_output_buf: Vec<Vec<f32>>,
+
+    encoder: audiopus::coder::Encoder,
+    encode_buf: Vec<u8>,
+
+    channels: usize,
+    packet_len: usize,
+}
+
+impl OpusPacketEncoder {
+    pub fn new(
+        source_sample_rate: u32,
+        sample_rate: usize,
+        packet_len: usize,
+        channels: usize,
+        encoding_profile: &EncodingProfile,
+    ) -> Result<Self, anyhow::Error> {
+        let resampler = rubato::FftFixedOut::new(
+            source_sample_rate.try_into().unwrap(),
+            sample_rate,
+            packet_len,
+            2,
+            channels
+        )?;
+
+        let mut resampler_input_buf = resampler.input_buffer_allocate();
+
+        for input_buf_ch in resampler_input_buf.iter_mut() {
+            input_buf_ch.extend(vec![0.; resampler.input_frames_max()]);
+        }
+        let resampler_output_buf = resampler.output_buffer_allocate();
+
+        let encoder = encoding_profile.create_encoder(channels)?;
+
+        Ok(Self {
+            resampler,
+            resampler_input_buf,
+            resampler_output_buf,
+
+            encoder,
+            encode_buf: vec![0; MAX_OPUS_PACKET_BYTES],
+
+            channels,
+            packet_len,
+        })
+    }
+
+    // Number of source frames which are required to create a packet
+    pub fn get_input_frame_len(&self) -> usize {
+        self.resampler.input_frames_max()
+    }
+}
+
+impl PacketEncoder for OpusPacketEncoder {
+    fn encode(&mut self, _packet_idx: usize, samples: Vec<f32>) -> Result<Vec<u8>, anyhow::Error> {
+        let samples = audio::wrap::interleaved(samples.as_slice(), self.channels);
+        let samples_reader = audio::io::Read::new(samples);
+
+        for ch_idx in 0..samples_reader.channels() {
+            let sample_ch_buf = samples_reader
+                .get(ch_idx)
+                .unwrap()
+                .iter()
+                .collect::<Vec<_>>();
+
+            self.resampler_input_buf[ch_idx] = sample_ch_buf;
+        }
+
+        self.resampler.process_into_buffer(
+            &self.resampler_input_buf,
+            &mut self.resampler_output_buf,
+            None
+        )?;
+
+        let mut resampled_output = audio::buf::Interleaved::<f32>::with_topology(
+            self.channels,
+            self.packet_len
+        );
+
+        for ch_idx in 0..self.channels {
+            for (c, s) in resampled_output
+                .get_mut(ch_idx)
+                .unwrap()
+                .iter_mut()
+                .zip(&self.resampler_output_buf[ch_idx])
+            {
+                *c = *s;
+            }
+        }
+
+        let packet_len = self.encoder
+            .encode_float(resampled_output.as_slice(), &mut self.encode_buf)?;
+
+        Ok(self.encode_buf[..packet_len].to_vec())
+    }
+}
+
+pub struct FlacPacketEncoder {
+    encoder: FlacEncoder,
+    bits_per_sample: u32,
+}
+
+impl FlacPacketEncoder {
+    pub fn new(
+        source_sample_rate: u32,
+        channels: usize,
+        bits_per_sample: u32,
+        packet_len: usize,
+    ) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            encoder: FlacEncoder::new(source_sample_rate, channels, bits_per_sample, packet_len)?,
+            bits_per_sample,
+        })
+    }
+
+    // STREAMINFO block which is required to decode packets
+    pub fn get_stream_info(&self) -> Vec<u8> {
+        self.encoder.get_stream_info(0)
+    }
+}
+
+impl PacketEncoder for FlacPacketEncoder {
+    fn encode(&mut self, packet_idx: usize, samples: Vec<f32>) -> Result<Vec<u8>, anyhow::Error> {
+        let samples = convert_to_int_samples(&samples, self.bits_per_sample);
+
+        self.encoder.encode_frame(packet_idx as u64, &samples)
+    }
+}
+
+// Interleaved, little-endian and signed integer samples
+pub struct PcmPacketEncoder {
+    bits_per_sample: u32,
+}
+
+impl PcmPacketEncoder {
+    pub fn new(bits_per_sample: u32) -> Result<Self, anyhow::Error> {
+        if bits_per_sample != 16 && bits_per_sample != 24 {
+            return Err(anyhow::anyhow!("unsupported pcm bits per sample: {}", bits_per_sample));
+        }
+
+        Ok(Self {
+            bits_per_sample,
+        })
+    }
+}
+
+impl PacketEncoder for PcmPacketEncoder {
+    fn encode(&mut self, _packet_idx: usize, samples: Vec<f32>) -> Result<Vec<u8>, anyhow::Error> {
+        let bytes_per_sample = (self.bits_per_sample / 8) as usize;
+
+        let packet = convert_to_int_samples(&samples, self.bits_per_sample)
+            .iter()
+            .flat_map(|sample| sample.to_le_bytes().into_iter().take(bytes_per_sample))
+            .collect();
+
+        Ok(packet)
+    }
+}
@@ -0,0 +1,116 @@
+use audiopus::{coder::Encoder, Bitrate, SampleRate};
+use cirrus_protobuf::api::{
+    AudioEncodingProfile,
+    audio_encoding_profile::{Application, BitrateMode},
+};
+
+use crate::settings;
+
+use super::InvalidAudioRequestError;
+
+// Opus supports complexity of 0 ~ 10
+const MAX_OPUS_COMPLEXITY: u32 = 10;
+
+#[derive(Debug, Clone, Hash)]
+pub struct EncodingProfile {
+    pub bitrate: u32,
+    pub bitrate_mode: BitrateMode,
+    pub complexity: u32,
+    pub application: Application,
+}
+
+impl EncodingProfile {
+    pub fn from_settings(settings: &settings::Encoding) -> Self {
+        Self {
+            bitrate: settings.default_bitrate,
+            bitrate_mode: BitrateMode::Vbr,
+            complexity: std::cmp::min(settings.default_complexity, settings.max_complexity),
+            application: Application::Music,
+        }
+    }
+
+    // Uses the server default if a client does not request a profile
+    pub fn resolve(
+        requested: Option<&AudioEncodingProfile>,
+        settings: &settings::Encoding,
+    ) -> Result<Self, anyhow::Error> {
+        let requested = match requested {
+            Some(requested) => requested,
+            None => return Ok(Self::from_settings(settings)),
+        };
+
+        if !settings.allowed_bitrates.contains(&requested.bitrate) {
+            return Err(InvalidAudioRequestError::new(format!(
+                "bitrate {} is not allowed, allowed bitrates: {:?}",
+                requested.bitrate,
+                settings.allowed_bitrates
+            )).into());
+        }
+
+        let max_complexity = std::cmp::min(settings.max_complexity, MAX_OPUS_COMPLEXITY);
+
+        if requested.complexity > max_complexity {
+            return Err(InvalidAudioRequestError::new(format!(
+                "complexity {} exceeds maximum complexity {}",
+                requested.complexity,
+                max_complexity
+            )).into());
+        }
+
+        let bitrate_mode = match BitrateMode::from_i32(requested.bitrate_mode) {
+            Some(bitrate_mode) => bitrate_mode,
+            None => return Err(InvalidAudioRequestError::new(format!("unknown bitrate mode: {}", requested.bitrate_mode)).into()),
+        };
+
+        let application = match Application::from_i32(requested.application) {
+            Some(application) => application,
+            None => return Err(InvalidAudioRequestError::new(format!("unknown application: {}", requested.application)).into()),
+        };
+
+        Ok(Self {
+            bitrate: requested.bitrate,
+            bitrate_mode,
+            complexity: requested.complexity,
+            application,
+        })
+    }
+
+    pub fn create_encoder(&self, channels: usize) -> Result<Encoder, anyhow::Error> {
+        let encoder_channels = match channels {
+            1 => audiopus::Channels::Mono,
+            2 => audiopus::Channels::Stereo,
+            _ => return Err(anyhow::anyhow!("unsupported encoder channels: {}", channels)),
+        };
+
+        let encoder_application = match self.application {
+            Application::Music => audiopus::Application::Audio,
+            Application::Voice => audiopus::Application::Voip,
+        };
+
+        let mut encoder = Encoder::new(SampleRate::Hz48000, encoder_channels, encoder_application)?;
+
+        encoder.set_bitrate(Bitrate::BitsPerSecond(self.bitrate.try_into()?))?;
+        encoder.set_complexity(self.complexity.try_into()?)?;
+
+        match self.bitrate_mode {
+            BitrateMode::Vbr => {
+                encoder.set_vbr(true)?;
+                encoder.set_vbr_constraint(false)?;
+            },
+            BitrateMode::Cbr => encoder.set_vbr(false)?,
+        }
+
+        Ok(encoder)
+    }
+}
+
+impl From<&EncodingProfile> for AudioEncodingProfile {
+    fn from(profile: &EncodingProfile) -> Self {
+        Self {
+            bitrate: profile.bitrate,
+            bitrate_mode: profile.bitrate_mode as i32,
+            complexity: profile.complexity,
+            application: profile.application as i32,
+        }
+    }
+}
@@ -0,0 +1,564 @@
+// Minimal FLAC frame encoder which encodes each packet as a single frame with fixed predictors
+// and partitioned Rice coded residuals. Frames do not carry a sample rate, so a client requires
+// the STREAMINFO block to decode them.
+
+const MAX_FIXED_ORDER: usize = 4;
+const MAX_PARTITION_ORDER: u32 = 8;
+const MAX_RICE_PARAM: u32 = 30;
+
+const CHANNEL_ASSIGNMENT_LEFT_SIDE: u32 = 0b1000;
+const CHANNEL_ASSIGNMENT_SIDE_RIGHT: u32 = 0b1001;
+const CHANNEL_ASSIGNMENT_MID_SIDE: u32 = 0b1010;
+
+pub struct FlacEncoder {
+    sample_rate: u32,
+    channels: usize,
+    bits_per_sample: u32,
+    block_len: usize,
+}
+
+impl FlacEncoder {
+    pub fn new(
+        sample_rate: u32,
+        channels: usize,
+        bits_per_sample: u32,
+        block_len: usize,
+    ) -> Result<Self, anyhow::Error> {
+        if !(1..=8).contains(&channels) {
+            return Err(anyhow::anyhow!("unsupported flac channels: {}", channels));
+        }
+
+        if get_sample_size_code(bits_per_sample).is_none() {
+            return Err(anyhow::anyhow!("unsupported flac bits per sample: {}", bits_per_sample));
+        }
+
+        if !(16..=u16::MAX as usize).contains(&block_len) {
+            return Err(anyhow::anyhow!("unsupported flac block length: {}", block_len));
+        }
+
+        Ok(Self {
+            sample_rate,
+            channels,
+            bits_per_sample,
+            block_len,
+        })
+    }
+
+    pub fn get_stream_info(&self, n_frames: u64) -> Vec<u8> {
+        let mut writer = BitWriter::default();
+
+        // minimum and maximum block length
+        writer.write(16, self.block_len as u64);
+        writer.write(16, self.block_len as u64);
+        // minimum and maximum frame length are unknown
+        writer.write(24, 0);
+        writer.write(24, 0);
+        writer.write(20, self.sample_rate as u64);
+        writer.write(3, self.channels as u64 - 1);
+        writer.write(5, self.bits_per_sample as u64 - 1);
+        writer.write(36, n_frames);
+        // MD5 signature is unknown
+        writer.write_bytes(&[0; 16]);
+
+        writer.into_bytes()
+    }
+
+    // Encodes interleaved samples to a frame, and the frame number is the index of the packet
+    pub fn encode_frame(
+        &self,
+        frame_idx: u64,
+        samples: &[i32],
+    ) -> Result<Vec<u8>, anyhow::Error> {
+        let block_len = samples.len() / self.channels;
+
+        if block_len == 0 || block_len > self.block_len {
+            return Err(anyhow::anyhow!("invalid flac block length: {}", block_len));
+        }
+
+        let channel_samples = (0..self.channels)
+            .map(|ch_idx| {
+                samples
+                    .iter()
+                    .skip(ch_idx)
+                    .step_by(self.channels)
+                    .map(|sample| *sample as i64)
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        let (channel_assignment, subframes) = self.select_channel_assignment(channel_samples);
+
+        let mut writer = BitWriter::default();
+
+        // sync code and fixed-blocksize stream
+        writer.write(16, 0xfff8);
+        // block size is written as 16-bit at end of header
+        writer.write(4, 0b0111);
+        // sample rate is read from STREAMINFO
+        writer.write(4, 0b0000);
+        writer.write(4, channel_assignment as u64);
+        writer.write(3, get_sample_size_code(self.bits_per_sample).unwrap() as u64);
+        writer.write(1, 0);
+        writer.write_bytes(&encode_utf8_number(frame_idx)?);
+        writer.write(16, block_len as u64 - 1);
+
+        let header_crc = crc8(writer.get_bytes());
+        writer.write(8, header_crc as u64);
+
+        for subframe in subframes.iter() {
+            subframe.write(&mut writer);
+        }
+
+        writer.align();
+
+        let frame_crc = crc16(writer.get_bytes());
+        writer.write(16, frame_crc as u64);
+
+        Ok(writer.into_bytes())
+    }
+
+    fn select_channel_assignment(
+        &self,
+        mut channel_samples: Vec<Vec<i64>>,
+    ) -> (u32, Vec<Subframe>) {
+        let bps = self.bits_per_sample;
+
+        if self.channels != 2 {
+            let subframes = channel_samples
+                .into_iter()
+                .map(|samples| Subframe::new(samples, bps))
+                .collect();
+
+            return (self.channels as u32 - 1, subframes);
+        }
+
+        let right = channel_samples.pop().unwrap();
+        let left = channel_samples.pop().unwrap();
+
+        let side = left.iter().zip(right.iter()).map(|(l, r)| l - r).collect::<Vec<_>>();
+        let mid = left.iter().zip(right.iter()).map(|(l, r)| (l + r) >> 1).collect::<Vec<_>>();
+
+        let left = Subframe::new(left, bps);
+        let right = Subframe::new(right, bps);
+        // side channel requires an extra bit
+        let side = Subframe::new(side, bps + 1);
+        let mid = Subframe::new(mid, bps);
+
+        let candidates = [
+            (1, left.bits + right.bits),
+            (CHANNEL_ASSIGNMENT_LEFT_SIDE, left.bits + side.bits),
+            (CHANNEL_ASSIGNMENT_SIDE_RIGHT, side.bits + right.bits),
+            (CHANNEL_ASSIGNMENT_MID_SIDE, mid.bits + side.bits),
+        ];
+
+        let (channel_assignment, _) = candidates
+            .iter()
+            .min_by_key(|(_, bits)| *bits)
+            .unwrap();
+
+        let subframes = match *channel_assignment {
+            CHANNEL_ASSIGNMENT_LEFT_SIDE => vec![left, side],
+            CHANNEL_ASSIGNMENT_SIDE_RIGHT => vec![side, right],
+            CHANNEL_ASSIGNMENT_MID_SIDE => vec![mid, side],
+            _ => vec![left, right],
+        };
+
+        (*channel_assignment, subframes)
+    }
+}
+
+fn get_sample_size_code(bits_per_sample: u32) -> Option<u32> {
+    match bits_per_sample {
+        8 => Some(0b001),
+        12 => Some(0b010),
+        16 => Some(0b100),
+        20 => Some(0b101),
+        24 => Some(0b110),
+        _ => None,
+    }
+}
+
+fn encode_utf8_number(value: u64) -> Result<Vec<u8>, anyhow::Error> {
+    if value < 0x80 {
+        return Ok(vec![value as u8]);
+    }
+
+    // frame number of fixed-blocksize stream is up to 31 bits
+    let byte_len = match value {
+        0x80..=0x7ff => 2,
+        0x800..=0xffff => 3,
+        0x1_0000..=0x1f_ffff => 4,
+        0x20_0000..=0x3ff_ffff => 5,
+        0x400_0000..=0x7fff_ffff => 6,
+        _ => return Err(anyhow::anyhow!("flac frame number is too large: {}", value)),
+    };
+
+    let mut bytes = vec![0; byte_len];
+
+    for idx in (1..byte_len).rev() {
+        bytes[idx] = 0x80 | ((value >> (6 * (byte_len - 1 - idx))) & 0x3f) as u8;
+    }
+
+    let first_byte_prefix = !(0xffu8 >> byte_len);
+    bytes[0] = first_byte_prefix | (value >> (6 * (byte_len - 1))) as u8;
+
+    Ok(bytes)
+}
+
+fn get_fixed_residual(samples: &[i64], order: usize) -> Vec<i64> {
+    samples
+        .windows(order + 1)
+        .map(|window| {
+            let s = |offset: usize| window[order - offset];
+
+            match order {
+                0 => s(0),
+                1 => s(0) - s(1),
+                2 => s(0) - 2 * s(1) + s(2),
+                3 => s(0) - 3 * s(1) + 3 * s(2) - s(3),
+                _ => s(0) - 4 * s(1) + 6 * s(2) - 4 * s(3) + s(4),
+            }
+        })
+        .collect()
+}
+
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+struct RicePartitions {
+    bits: u64,
+    partition_order: u32,
+    params: Vec<u32>,
+}
+
+fn get_rice_param(sum: u64, len: u64) -> (u32, u64) {
+    (0..=MAX_RICE_PARAM)
+        .map(|param| (param, len * (param as u64 + 1) + (sum >> param)))
+        .min_by_key(|(_, bits)| *bits)
+        .unwrap()
+}
+
+fn find_rice_partitions(residual: &[i64], block_len: usize, predictor_order: usize) -> RicePartitions {
+    let mut best: Option<RicePartitions> = None;
+
+    for partition_order in 0..=MAX_PARTITION_ORDER {
+        let partitions = 1 << partition_order;
+
+        if block_len % partitions != 0 || block_len / partitions <= predictor_order {
+            break;
+        }
+
+        let partition_len = block_len / partitions;
+        let mut bits = 0;
+        let mut params = Vec::with_capacity(partitions);
+        let mut start = 0;
+
+        for partition_idx in 0..partitions {
+            let len = if partition_idx == 0 { partition_len - predictor_order } else { partition_len };
+            let sum = residual[start..start + len].iter().map(|item| zigzag(*item)).sum::<u64>();
+
+            let (param, partition_bits) = get_rice_param(sum, len as u64);
+
+            // 5-bit rice parameter
+            bits += 5 + partition_bits;
+            params.push(param);
+            start += len;
+        }
+
+        if best.as_ref().map_or(true, |item| bits < item.bits) {
+            best = Some(RicePartitions { bits, partition_order, params });
+        }
+    }
+
+    best.unwrap()
+}
+
+enum SubframeKind {
+    Constant,
+    Verbatim,
+    Fixed { order: usize, residual: Vec<i64>, partitions: RicePartitions },
+}
+
+struct Subframe {
+    samples: Vec<i64>,
+    bits_per_sample: u32,
+    kind: SubframeKind,
+    bits: u64,
+}
+
+impl Subframe {
+    // Selects the smallest of constant, verbatim and fixed predictor subframes
+    fn new(samples: Vec<i64>, bits_per_sample: u32) -> Self {
+        let subframe_header_bits = 8;
+
+        if samples.iter().all(|sample| *sample == samples[0]) {
+            return Self {
+                samples,
+                bits_per_sample,
+                kind: SubframeKind::Constant,
+                bits: subframe_header_bits + bits_per_sample as u64,
+            };
+        }
+
+        let mut kind = SubframeKind::Verbatim;
+        let mut bits = subframe_header_bits + bits_per_sample as u64 * samples.len() as u64;
+
+        for order in 0..=std::cmp::min(MAX_FIXED_ORDER, samples.len() - 1) {
+            let residual = get_fixed_residual(&samples, order);
+            let partitions = find_rice_partitions(&residual, samples.len(), order);
+
+            // warm-up samples, coding method and partition order
+            let fixed_bits = subframe_header_bits
+                + bits_per_sample as u64 * order as u64
+                + 2 + 4
+                + partitions.bits;
+
+            if fixed_bits < bits {
+                kind = SubframeKind::Fixed { order, residual, partitions };
+                bits = fixed_bits;
+            }
+        }
+
+        Self {
+            samples,
+            bits_per_sample,
+            kind,
+            bits,
+        }
+    }
+
+    fn write(&self, writer: &mut BitWriter) {
+        match &self.kind {
+            SubframeKind::Constant => {
+                writer.write(8, 0b0000_0000);
+                writer.write_signed(self.bits_per_sample, self.samples[0]);
+            },
+            SubframeKind::Verbatim => {
+                writer.write(8, 0b0000_0010);
+                for sample in self.samples.iter() {
+                    writer.write_signed(self.bits_per_sample, *sample);
+                }
+            },
+            SubframeKind::Fixed { order, residual, partitions } => {
+                writer.write(8, (0b001000 | *order as u64) << 1);
+                for sample in self.samples.iter().take(*order) {
+                    writer.write_signed(self.bits_per_sample, *sample);
+                }
+
+                // partitioned rice coding with 5-bit parameter
+                writer.write(2, 0b01);
+                writer.write(4, partitions.partition_order as u64);
+
+                let partition_len = self.samples.len() >> partitions.partition_order;
+                let mut start = 0;
+
+                for (partition_idx, param) in partitions.params.iter().enumerate() {
+                    let len = if partition_idx == 0 { partition_len - order } else { partition_len };
+
+                    writer.write(5, *param as u64);
+                    for value in residual[start..start + len].iter() {
+                        writer.write_rice(*param, zigzag(*value));
+                    }
+
+                    start += len;
+                }
+            },
+        }
+    }
+}
+
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    acc: u64,
+    acc_len: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, len: u32, value: u64) {
+        let mut remain = len;
+
+        while remain > 0 {
+            let chunk_len = std::cmp::min(remain, 32);
+            remain -= chunk_len;
+
+            let chunk = (value >> remain) & ((1u64 << chunk_len) - 1);
+
+            self.acc = (self.acc << chunk_len) | chunk;
+            self.acc_len += chunk_len;
+
+            while self.acc_len >= 8 {
+                self.acc_len -= 8;
+                self.bytes.push((self.acc >> self.acc_len) as u8);
+            }
+
+            self.acc &= (1u64 << self.acc_len) - 1;
+        }
+    }
+
+    fn write_signed(&mut self, len: u32, value: i64) {
+        self.write(len, value as u64 & ((1u64 << len) - 1));
+    }
+
+    fn write_rice(&mut self, param: u32, value: u64) {
+        let mut quotient = value >> param;
+
+        while quotient >= 32 {
+            self.write(32, 0);
+            quotient -= 32;
+        }
+
+        self.write(quotient as u32 + 1, 1);
+        self.write(param, value & ((1u64 << param) - 1));
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.write(8, *byte as u64);
+        }
+    }
+
+    fn align(&mut self) {
+        if self.acc_len > 0 {
+            self.write(8 - self.acc_len, 0);
+        }
+    }
+
+    // returns written bytes except bits not aligned yet
+    fn get_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    fn into_bytes(mut self) -> Vec<u8> {
+        self.align();
+
+        self.bytes
+    }
+}
+
+fn crc8(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0u8, |crc, byte| {
+        (0..8).fold(crc ^ byte, |crc, _| {
+            if crc & 0x80 != 0 { (crc << 1) ^ 0x07 } else { crc << 1 }
+        })
+    })
+}
+
+fn crc16(bytes: &[u8]) -> u16 {
+    bytes.iter().fold(0u16, |crc, byte| {
+        (0..8).fold(crc ^ ((*byte as u16) << 8), |crc, _| {
+            if crc & 0x8000 != 0 { (crc << 1) ^ 0x8005 } else { crc << 1 }
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use symphonia::core::{
+        audio::SampleBuffer,
+        codecs::{CodecParameters, DecoderOptions, CODEC_TYPE_FLAC},
+        formats::Packet,
+    };
+
+    use super::FlacEncoder;
+
+    const SAMPLE_RATE: u32 = 44100;
+    const BLOCK_LEN: usize = 1024;
+
+    // Encodes interleaved samples to frames of the block length, and decodes them back
+    fn encode_and_decode(samples: &[i32], channels: usize, bits_per_sample: u32) -> Vec<i32> {
+        let encoder = FlacEncoder::new(SAMPLE_RATE, channels, bits_per_sample, BLOCK_LEN).unwrap();
+
+        let mut codec_params = CodecParameters::new();
+        codec_params
+            .for_codec(CODEC_TYPE_FLAC)
+            .with_extra_data(encoder.get_stream_info(0).into_boxed_slice());
+
+        let mut decoder = symphonia::default::get_codecs()
+            .make(&codec_params, &DecoderOptions::default())
+            .unwrap();
+
+        let mut decoded = Vec::with_capacity(samples.len());
+
+        for (frame_idx, frame_samples) in samples.chunks(BLOCK_LEN * channels).enumerate() {
+            let frame = encoder.encode_frame(frame_idx as u64, frame_samples).unwrap();
+            let packet = Packet::new_from_boxed_slice(0, frame_idx as u64, BLOCK_LEN as u64, frame.into_boxed_slice());
+
+            let decoded_frame = decoder.decode(&packet).unwrap();
+            let mut sample_buf = SampleBuffer::<i32>::new(decoded_frame.capacity() as u64, *decoded_frame.spec());
+            sample_buf.copy_interleaved_ref(decoded_frame);
+
+            // decoded samples are scaled to 32-bit
+            decoded.extend(sample_buf.samples().iter().map(|sample| sample >> (32 - bits_per_sample)));
+        }
+
+        decoded
+    }
+
+    fn interleave(channel_samples: &[Vec<i32>]) -> Vec<i32> {
+        (0..channel_samples[0].len())
+            .flat_map(|idx| channel_samples.iter().map(move |samples| samples[idx]))
+            .collect()
+    }
+
+    fn assert_round_trip(channel_samples: &[Vec<i32>], bits_per_sample: u32) {
+        let samples = interleave(channel_samples);
+        let decoded = encode_and_decode(&samples, channel_samples.len(), bits_per_sample);
+
+        assert_eq!(samples, decoded);
+    }
+
+    // last frame is shorter than the block length
+    const SAMPLE_LEN: usize = BLOCK_LEN * 3 + 100;
+
+    fn silence() -> Vec<i32> {
+        vec![0; SAMPLE_LEN]
+    }
+
+    fn square_wave(bits_per_sample: u32, period: usize) -> Vec<i32> {
+        let max = (1 << (bits_per_sample - 1)) - 1;
+        let min = -(1 << (bits_per_sample - 1));
+
+        (0..SAMPLE_LEN)
+            .map(|idx| if idx % period < period / 2 { max } else { min })
+            .collect()
+    }
+
+    fn ramp(bits_per_sample: u32, step: i32) -> Vec<i32> {
+        let min = -(1 << (bits_per_sample - 1));
+        let range = 1i64 << bits_per_sample;
+
+        (0..SAMPLE_LEN)
+            .map(|idx| (min as i64 + (idx as i64 * step as i64).rem_euclid(range)) as i32)
+            .collect()
+    }
+
+    #[test]
+    fn round_trip_silence() {
+        for bits_per_sample in [16, 24] {
+            assert_round_trip(&[silence()], bits_per_sample);
+            assert_round_trip(&[silence(), silence()], bits_per_sample);
+        }
+    }
+
+    #[test]
+    fn round_trip_full_scale_square_wave() {
+        for bits_per_sample in [16, 24] {
+            assert_round_trip(&[square_wave(bits_per_sample, 64)], bits_per_sample);
+            // inverted channels require the side channel at full range
+            assert_round_trip(
+                &[square_wave(bits_per_sample, 64), square_wave(bits_per_sample, 64).iter().map(|sample| -1 - sample).collect()],
+                bits_per_sample,
+            );
+            assert_round_trip(&[square_wave(bits_per_sample, 64), square_wave(bits_per_sample, 7)], bits_per_sample);
+        }
+    }
+
+    #[test]
+    fn round_trip_24_bit_ramp() {
+        assert_round_trip(&[ramp(24, 4099)], 24);
+        assert_round_trip(&[ramp(24, 4099), ramp(24, 1)], 24);
+        assert_round_trip(&[ramp(24, 1), ramp(24, -65537)], 24);
+    }
+}
@@ -1,89 +1,137 @@
+mod cache;
+mod channel;
+mod codec;
+mod encoding;
+mod flac;
+mod ogg;
 mod packet;
 mod sample;
 
 use std::{
-    fs::File, 
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    path::PathBuf,
+    sync::Arc,
 };
 
 use bson::oid::ObjectId;
 
-use cirrus_protobuf::api::AudioMetaRes;
+use cirrus_protobuf::api::{audio_data_req::Start, AudioCodec, AudioEncodingProfile, AudioMetaRes};
 
 use mongodb::bson;
 
-use crate::model::{crud, document};
-use crate::settings::Settings;
+use crate::model::Storage;
+use crate::state::ServerState;
+use crate::util;
 
-use symphonia::core::codecs::CODEC_TYPE_NULL;
-use symphonia::core::formats::FormatOptions;
-use symphonia::core::meta::MetadataOptions;
-use symphonia::core::io::MediaSourceStream;
-use symphonia::core::probe::Hint;
+use self::{
+    cache::{CacheVariant, CachedPackets},
+    codec::FlacPacketEncoder,
+    encoding::EncodingProfile,
+    ogg::OggOpusPages,
+    packet::{Packet, Packets},
+};
 
-use self::packet::Packets;
+pub use self::cache::TranscodeCache;
 
-pub struct AudioFile {
-    crud_audio_file: crud::AudioFile,
+// Parameters of a request that are unknown or not allowed, so that these are not failures of
+// the server
+#[derive(Debug)]
+pub struct InvalidAudioRequestError {
+    message: String,
 }
 
-impl Default for AudioFile {
-    fn default() -> Self {
-        Self { 
-            crud_audio_file: Default::default(),
+impl InvalidAudioRequestError {
+    pub fn new(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
         }
     }
 }
 
+impl std::fmt::Display for InvalidAudioRequestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for InvalidAudioRequestError {}
+
+// Starts are resolved at the sample of packets, which is `packet_sample_rate` of the meta
+fn resolve_start(meta: &AudioMetaRes, start: &Start) -> Result<(u32, u32), anyhow::Error> {
+    let start_sample = match start {
+        Start::StartSec(start_sec) if start_sec.is_finite() && *start_sec >= 0. => {
+            (start_sec * meta.packet_sample_rate as f64).round() as u64
+        },
+        Start::StartSec(start_sec) => return Err(anyhow::anyhow!("invalid start: {} sec", start_sec)),
+        Start::StartSample(start_sample) => *start_sample,
+    };
+
+    let packet_start_idx = start_sample / meta.packet_len as u64;
+    if packet_start_idx >= meta.sp_packets as u64 {
+        return Err(anyhow::anyhow!("start sample {} is out of the audio", start_sample));
+    }
+
+    Ok((packet_start_idx as u32, (start_sample % meta.packet_len as u64) as u32))
+}
+
+pub struct AudioFile {
+    state: Arc<ServerState>,
+    storage: Arc<Storage>,
+    transcode_cache: Arc<TranscodeCache>,
+}
+
 impl AudioFile {
+    pub fn new(
+        state: Arc<ServerState>,
+        storage: Arc<Storage>,
+        transcode_cache: Arc<TranscodeCache>,
+    ) -> Self {
+        Self {
+            state,
+            storage,
+            transcode_cache,
+        }
+    }
+
     pub async fn read_meta(
         &self,
-        db: mongodb::Client,
-        audio_tag_id: &str
+        audio_tag_id: &str,
+        channels: u32,
+        codec: i32,
+        encoding_profile: Option<&AudioEncodingProfile>,
     ) -> Result<AudioMetaRes, anyhow::Error> {        
-        let settings = Settings::get()?;
-
-        let audio_tag_id = ObjectId::parse_str(audio_tag_id).unwrap();
-
-        let audio_file = self.crud_audio_file
-            .single
-            .get(
-                db.clone(),
-                None,
-                Some(
-                    document::audio::query_audio_tag_referer(&audio_tag_id)
-                ),
-            )
-            .await?;
+        let settings = self.state.settings();
+        let codec = codec::resolve_codec(codec)?;
+        let encoding_profile = EncodingProfile::resolve(encoding_profile, &settings.encoding)?;
+
+        let audio_tag_id = ObjectId::parse_str(audio_tag_id)
+            .map_err(|_| InvalidAudioRequestError::new(format!("invalid audio tag id: {}", audio_tag_id)))?;
+
+        let audio_file = self.storage.audio_file.get_by_audio_tag(&audio_tag_id).await?;
             
         let audio_file = match audio_file {
             Some(audio_file) => audio_file,
             None => return Err(anyhow::anyhow!("failed to retrieve audio file information")),
         };
 
-        let file = match File::open(audio_file.get_os_path()) {
-            Ok(file) => file,
-            Err(_) => return Err(anyhow::anyhow!("failed to load file")),
-        };
-
-        let mss = MediaSourceStream::new(Box::new(file), Default::default());
-        let hint = Hint::new();
-
-        let meta_opts: MetadataOptions = Default::default();
-        let fmt_opts: FormatOptions = Default::default();
-
-        let probed = symphonia::default::get_probe().format(&hint, mss, &fmt_opts, &meta_opts).unwrap();
+        let probed = util::audio::probe_file(&audio_file.get_os_path())?;
 
         let format = probed.format;
-        let track = format.tracks()
-            .iter()
-            .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
-            .expect("no supported audio tracks");
+        let track = util::audio::find_audio_track(format.as_ref())?;
 
-        let bit_rate = track.codec_params.bits_per_sample.unwrap();
-        let channels = track.codec_params.channels.unwrap().count();
-        let sample_rate = track.codec_params.sample_rate.unwrap();
-        let content_length = 
-            track.codec_params.n_frames.unwrap() as f64 / sample_rate as f64;
+        // lossy codecs (e.g. MP3, Vorbis) have no bit depth
+        let bit_rate = track.codec_params.bits_per_sample.unwrap_or(0);
+        let orig_channels = track.codec_params.channels.map(|item| item.count());
+        let output_channels = channel::resolve_output_channels(orig_channels, channels)?;
+        let sample_rate = match track.codec_params.sample_rate {
+            Some(sample_rate) => sample_rate,
+            None => return Err(anyhow::anyhow!("unknown sample rate")),
+        };
+        let content_length = match track.codec_params.n_frames {
+            Some(n_frames) => n_frames as f64 / sample_rate as f64,
+            None => return Err(anyhow::anyhow!("unknown content length")),
+        };
 
         let sample_frame_packet_dur = 
             settings.audio_sample_frame_packet.len as f64 
@@ -91,56 +139,245 @@ impl AudioFile {
 
         let sample_frame_packet_num = (content_length / sample_frame_packet_dur).floor() as u32;
 
+        let (packet_sample_rate, packet_len, packet_bits_per_sample, codec_extra_data) = match codec {
+            AudioCodec::Opus => (
+                settings.audio_sample_frame_packet.sample_rate,
+                settings.audio_sample_frame_packet.len as usize,
+                0,
+                Vec::new()
+            ),
+            AudioCodec::Flac | AudioCodec::Pcm => {
+                let packet_len = codec::get_lossless_packet_len(
+                    sample_rate,
+                    settings.audio_sample_frame_packet.len,
+                    settings.audio_sample_frame_packet.sample_rate,
+                );
+                let packet_bits_per_sample = codec::get_lossless_bits_per_sample(
+                    track.codec_params.bits_per_sample
+                )?;
+
+                let codec_extra_data = match codec {
+                    AudioCodec::Flac => FlacPacketEncoder::new(
+                        sample_rate,
+                        output_channels,
+                        packet_bits_per_sample,
+                        packet_len,
+                    )?.get_stream_info(),
+                    _ => Vec::new(),
+                };
+
+                (sample_rate, packet_len, packet_bits_per_sample, codec_extra_data)
+            },
+        };
+
         Ok(AudioMetaRes {
             content_length,
             sp_packets: sample_frame_packet_num,
             packet_dur: sample_frame_packet_dur,
             orig_sample_rate: sample_rate,
             orig_bit_rate: bit_rate,
-            channels: channels.try_into().unwrap(),
+            channels: output_channels.try_into().unwrap(),
+            orig_channels: orig_channels.unwrap_or(output_channels).try_into().unwrap(),
+            encoding_profile: match codec {
+                AudioCodec::Opus => Some(AudioEncodingProfile::from(&encoding_profile)),
+                _ => None,
+            },
+            codec: codec as i32,
+            packet_sample_rate,
+            packet_bits_per_sample,
+            codec_extra_data,
+            packet_len: packet_len.try_into().unwrap(),
         })
     }
 
     pub async fn get_audio_sample_iterator(
         &self,
-        db: mongodb::Client,
         audio_tag_id: &str,
         packet_start_idx: usize,
         packet_num: usize,
-        _channels: u32,
-    ) -> Result<Packets, anyhow::Error> {        
-        let settings = Settings::get()?;
+        channels: u32,
+        codec: i32,
+        encoding_profile: Option<&AudioEncodingProfile>,
+    ) -> Result<Box<dyn Iterator<Item = Packet> + Send>, anyhow::Error> {        
+        let settings = self.state.settings();
+        let codec = codec::resolve_codec(codec)?;
+        let encoding_profile = EncodingProfile::resolve(encoding_profile, &settings.encoding)?;
         
-        let audio_tag_id = ObjectId::parse_str(audio_tag_id).unwrap();
-
-        let audio_file = self.crud_audio_file
-            .single
-            .get(
-                db.clone(),
-                None,
-                Some(
-                    document::audio::query_audio_tag_referer(&audio_tag_id)
-                )
-            ).await?;
+        let audio_tag_id = ObjectId::parse_str(audio_tag_id)
+            .map_err(|_| InvalidAudioRequestError::new(format!("invalid audio tag id: {}", audio_tag_id)))?;
+
+        let audio_file = self.storage.audio_file.get_by_audio_tag(&audio_tag_id).await?;
 
         let audio_file = match audio_file {
             Some(audio_file) => audio_file,
             None => return Err(anyhow::anyhow!("failed to retrieve audio file information")),
         };
 
-        let file = match File::open(audio_file.get_os_path()) {
-            Ok(file) => file,
-            Err(_err) => return Err(anyhow::anyhow!("failed to load file")),
+        let source = audio_file.get_os_path();
+        let packet_len: usize = settings.audio_sample_frame_packet.len.try_into().unwrap();
+        let sample_rate: usize = settings.audio_sample_frame_packet.sample_rate.try_into().unwrap();
+
+        if !self.transcode_cache.is_enabled() {
+            let packets = Packets::new(
+                &source,
+                packet_start_idx,
+                packet_num,
+                packet_len,
+                sample_rate,
+                channels,
+                codec,
+                &encoding_profile,
+            )?;
+
+            return Ok(Box::new(packets));
+        }
+
+        let variant = CacheVariant {
+            modified_ts: cache::get_modified_timestamp(&source)?,
+            codec,
+            channels,
+            encoding_profile: match codec {
+                AudioCodec::Opus => Some(encoding_profile.clone()),
+                _ => None,
+            },
+            packet_len,
+            sample_rate,
         };
+        let variant_dir = self.transcode_cache.get_variant_dir(&audio_file.id.unwrap(), &variant);
 
-        let packets = Packets::new(
-            file,
+        let packets = CachedPackets::new(
+            self.transcode_cache.clone(),
+            variant_dir,
             packet_start_idx,
             packet_num,
-            settings.audio_sample_frame_packet.len.try_into().unwrap(),
-            settings.audio_sample_frame_packet.sample_rate.try_into().unwrap(),
+            Box::new(move |chunk_start_idx, chunk_packet_num| Packets::new(
+                &source,
+                chunk_start_idx,
+                chunk_packet_num,
+                packet_len,
+                sample_rate,
+                channels,
+                codec,
+                &encoding_profile,
+            )),
         )?;
 
-        Ok(packets)
+        Ok(Box::new(packets))
     }
-}
\ No newline at end of file
+
+    // Opus packets from the time in an Ogg container, which players play without the gRPC client
+    pub async fn get_ogg_opus_pages(
+        &self,
+        audio_tag_id: &str,
+        start_sec: f64,
+        encoding_profile: Option<&AudioEncodingProfile>,
+    ) -> Result<Box<dyn Iterator<Item = Vec<u8>> + Send>, anyhow::Error> {
+        let meta = self.read_meta(audio_tag_id, 0, AudioCodec::Opus as i32, encoding_profile).await?;
+        let (packet_start_idx, trim_start_samples) = resolve_start(&meta, &Start::StartSec(start_sec.max(0.)))?;
+
+        let packets = self.get_audio_sample_iterator(
+            audio_tag_id,
+            packet_start_idx as usize,
+            (meta.sp_packets - packet_start_idx) as usize,
+            0,
+            AudioCodec::Opus as i32,
+            encoding_profile,
+        ).await?;
+
+        // the serial identifies the logical stream in chained Ogg files, and any number is valid
+        let mut hasher = DefaultHasher::new();
+        audio_tag_id.hash(&mut hasher);
+
+        Ok(Box::new(OggOpusPages::new(
+            packets,
+            hasher.finish() as u32,
+            meta.channels as u8,
+            meta.orig_sample_rate,
+            meta.packet_len,
+            meta.packet_sample_rate,
+            trim_start_samples,
+        )))
+    }
+
+    // Packet that a stream from the start begins at, and sample frames at the start of the packet
+    // that precede the start
+    pub async fn resolve_start(
+        &self,
+        audio_tag_id: &str,
+        channels: u32,
+        codec: i32,
+        encoding_profile: Option<&AudioEncodingProfile>,
+        start: &Start,
+    ) -> Result<(u32, u32), anyhow::Error> {
+        let meta = self.read_meta(audio_tag_id, channels, codec, encoding_profile).await?;
+
+        resolve_start(&meta, start)
+    }
+
+    // Path of the original file of the audio, which is served as is
+    pub async fn get_source_path(&self, audio_tag_id: &str) -> Result<PathBuf, anyhow::Error> {
+        let audio_tag_id = ObjectId::parse_str(audio_tag_id)?;
+
+        match self.storage.audio_file.get_by_audio_tag(&audio_tag_id).await? {
+            Some(audio_file) => Ok(audio_file.get_os_path()),
+            None => Err(anyhow::anyhow!("audio file of {} does not exist", audio_tag_id)),
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use cirrus_protobuf::api::{audio_data_req::Start, AudioMetaRes};
+
+    use super::resolve_start;
+
+    // 2 seconds of 20ms packets at 48kHz
+    fn create_meta() -> AudioMetaRes {
+        AudioMetaRes {
+            sp_packets: 100,
+            packet_sample_rate: 48000,
+            packet_len: 960,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_resolve_start_sample() {
+        let meta = create_meta();
+
+        assert_eq!(resolve_start(&meta, &Start::StartSample(0)).unwrap(), (0, 0));
+        assert_eq!(resolve_start(&meta, &Start::StartSample(959)).unwrap(), (0, 959));
+        assert_eq!(resolve_start(&meta, &Start::StartSample(960)).unwrap(), (1, 0));
+        assert_eq!(resolve_start(&meta, &Start::StartSample(95_999)).unwrap(), (99, 959));
+        assert!(resolve_start(&meta, &Start::StartSample(96_000)).is_err());
+    }
+
+    #[test]
+    fn test_resolve_start_sec() {
+        let meta = create_meta();
+
+        assert_eq!(resolve_start(&meta, &Start::StartSec(0.)).unwrap(), (0, 0));
+        // 1.005 sec is 48240 samples, 240 samples after the start of the 51st packet
+        assert_eq!(resolve_start(&meta, &Start::StartSec(1.005)).unwrap(), (50, 240));
+        // seconds are rounded to the nearest sample
+        assert_eq!(resolve_start(&meta, &Start::StartSec(0.4 / 48000.)).unwrap(), (0, 0));
+        assert_eq!(resolve_start(&meta, &Start::StartSec(1.6 / 48000.)).unwrap(), (0, 2));
+
+        assert!(resolve_start(&meta, &Start::StartSec(2.)).is_err());
+        assert!(resolve_start(&meta, &Start::StartSec(-1.)).is_err());
+        assert!(resolve_start(&meta, &Start::StartSec(f64::NAN)).is_err());
+        assert!(resolve_start(&meta, &Start::StartSec(f64::INFINITY)).is_err());
+    }
+
+    #[test]
+    fn test_resolve_start_trim() {
+        let meta = create_meta();
+
+        // the packet start and the trimmed samples add up to the requested sample
+        for start_sample in [1, 480, 961, 12_345, 95_040] {
+            let (packet_start_idx, trim_start_samples) = resolve_start(&meta, &Start::StartSample(start_sample)).unwrap();
+
+            assert!(trim_start_samples < meta.packet_len);
+            assert_eq!(packet_start_idx as u64 * meta.packet_len as u64 + trim_start_samples as u64, start_sample);
+        }
+    }
+}
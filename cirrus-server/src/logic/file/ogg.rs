@@ -0,0 +1,217 @@
+use std::collections::VecDeque;
+
+use super::packet::Packet;
+
+// samples that decoders discard at the start, which is the lookahead of the Opus encoder at 48 kHz
+const OPUS_ENCODER_LOOKAHEAD: u64 = 312;
+// granule positions of Ogg Opus are counted at 48 kHz regardless of the encoded sample rate
+const OPUS_GRANULE_SAMPLE_RATE: u64 = 48000;
+const OPUS_VENDOR: &'static str = "cirrus";
+// pages are flushed over this, as libogg does
+const MAX_PAGE_DATA_BYTES: usize = 4096;
+const MAX_PAGE_SEGMENTS: usize = 255;
+
+const HEADER_TYPE_BOS: u8 = 0x02;
+const HEADER_TYPE_EOS: u8 = 0x04;
+
+// CRC-32 of Ogg pages, which has the polynomial 0x04c11db7 without reflection
+const CRC_TABLE: [u32; 256] = create_crc_table();
+
+const fn create_crc_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut idx = 0;
+
+    while idx < 256 {
+        let mut crc = (idx as u32) << 24;
+        let mut bit = 0;
+
+        while bit < 8 {
+            crc = match crc & 0x8000_0000 {
+                0 => crc << 1,
+                _ => (crc << 1) ^ 0x04c1_1db7,
+            };
+            bit += 1;
+        }
+
+        table[idx] = crc;
+        idx += 1;
+    }
+
+    table
+}
+
+fn get_crc(data: &[u8]) -> u32 {
+    data.iter().fold(0, |crc, byte| {
+        (crc << 8) ^ CRC_TABLE[(((crc >> 24) as u8) ^ byte) as usize]
+    })
+}
+
+// Writes Opus packets to pages of a logical Ogg stream (RFC 7845)
+pub struct OggOpusWriter {
+    serial: u32,
+    page_seq: u32,
+    granule_pos: u64,
+
+    page_segments: Vec<u8>,
+    page_data: Vec<u8>,
+}
+
+impl OggOpusWriter {
+    pub fn new(serial: u32) -> Self {
+        Self {
+            serial,
+            page_seq: 0,
+            granule_pos: 0,
+
+            page_segments: Vec::new(),
+            page_data: Vec::new(),
+        }
+    }
+
+    // `OpusHead` and `OpusTags` pages, which precede audio pages. Channels are 1 or 2, which
+    // are of the mapping family 0
+    pub fn write_headers(&mut self, channels: u8, input_sample_rate: u32, pre_skip: u16) -> Vec<u8> {
+        let mut opus_head = Vec::with_capacity(19);
+        opus_head.extend(b"OpusHead");
+        opus_head.push(1);
+        opus_head.push(channels);
+        opus_head.extend(pre_skip.to_le_bytes());
+        opus_head.extend(input_sample_rate.to_le_bytes());
+        // output gain and channel mapping family
+        opus_head.extend(0i16.to_le_bytes());
+        opus_head.push(0);
+
+        let mut opus_tags = Vec::new();
+        opus_tags.extend(b"OpusTags");
+        opus_tags.extend((OPUS_VENDOR.len() as u32).to_le_bytes());
+        opus_tags.extend(OPUS_VENDOR.as_bytes());
+        // no user comments
+        opus_tags.extend(0u32.to_le_bytes());
+
+        let mut pages = self.create_page(&get_lacing_values(opus_head.len()), &opus_head, 0, HEADER_TYPE_BOS);
+        pages.extend(self.create_page(&get_lacing_values(opus_tags.len()), &opus_tags, 0, 0));
+
+        pages
+    }
+
+    // Adds a packet of the frames at 48 kHz, and returns the page that is full before it
+    pub fn write_packet(&mut self, packet: &[u8], frames: u64) -> Option<Vec<u8>> {
+        let lacing_values = get_lacing_values(packet.len());
+
+        let page = match self.page_segments.len() + lacing_values.len() > MAX_PAGE_SEGMENTS
+            || self.page_data.len() >= MAX_PAGE_DATA_BYTES
+        {
+            true => Some(self.flush(0)),
+            false => None,
+        };
+
+        self.page_segments.extend(lacing_values);
+        self.page_data.extend(packet);
+        self.granule_pos += frames;
+
+        page
+    }
+
+    // The last page, which ends the stream
+    pub fn finish(mut self) -> Vec<u8> {
+        self.flush(HEADER_TYPE_EOS)
+    }
+
+    fn flush(&mut self, header_type: u8) -> Vec<u8> {
+        let page_segments = std::mem::take(&mut self.page_segments);
+        let page_data = std::mem::take(&mut self.page_data);
+
+        self.create_page(&page_segments, &page_data, self.granule_pos, header_type)
+    }
+
+    fn create_page(&mut self, segments: &[u8], data: &[u8], granule_pos: u64, header_type: u8) -> Vec<u8> {
+        let mut page = Vec::with_capacity(27 + segments.len() + data.len());
+        page.extend(b"OggS");
+        page.push(0);
+        page.push(header_type);
+        page.extend(granule_pos.to_le_bytes());
+        page.extend(self.serial.to_le_bytes());
+        page.extend(self.page_seq.to_le_bytes());
+        // CRC is calculated with the field set to 0
+        page.extend(0u32.to_le_bytes());
+        page.push(segments.len() as u8);
+        page.extend(segments);
+        page.extend(data);
+
+        let crc = get_crc(&page);
+        page[22..26].copy_from_slice(&crc.to_le_bytes());
+
+        self.page_seq += 1;
+
+        page
+    }
+}
+
+// A packet is split to segments of 255 bytes, and ends with a shorter one
+fn get_lacing_values(packet_len: usize) -> Vec<u8> {
+    let mut lacing_values = vec![255; packet_len / 255];
+    lacing_values.push((packet_len % 255) as u8);
+
+    lacing_values
+}
+
+// Pages of an Ogg Opus stream of Opus packets
+pub struct OggOpusPages {
+    packets: Box<dyn Iterator<Item = Packet> + Send>,
+    writer: Option<OggOpusWriter>,
+    pages: VecDeque<Vec<u8>>,
+    packet_frames: u64,
+}
+
+impl OggOpusPages {
+    pub fn new(
+        packets: Box<dyn Iterator<Item = Packet> + Send>,
+        serial: u32,
+        channels: u8,
+        input_sample_rate: u32,
+        packet_len: u32,
+        packet_sample_rate: u32,
+        trim_start_samples: u32,
+    ) -> Self {
+        // samples of the first packet before the start are skipped with the lookahead, so that
+        // players start at the exact sample
+        let pre_skip = OPUS_ENCODER_LOOKAHEAD
+            + trim_start_samples as u64 * OPUS_GRANULE_SAMPLE_RATE / packet_sample_rate as u64;
+
+        let mut writer = OggOpusWriter::new(serial);
+        let headers = writer.write_headers(channels, input_sample_rate, pre_skip.try_into().unwrap_or(u16::MAX));
+
+        Self {
+            packets,
+            writer: Some(writer),
+            pages: VecDeque::from([headers]),
+            packet_frames: packet_len as u64 * OPUS_GRANULE_SAMPLE_RATE / packet_sample_rate as u64,
+        }
+    }
+}
+
+impl Iterator for OggOpusPages {
+    type Item = Vec<u8>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(page) = self.pages.pop_front() {
+                return Some(page);
+            }
+
+            let writer = self.writer.as_mut()?;
+
+            match self.packets.next() {
+                Some(packet) => {
+                    if let Some(page) = writer.write_packet(&packet.frame, self.packet_frames) {
+                        self.pages.push_back(page);
+                    }
+                },
+                None => {
+                    let page = self.writer.take().unwrap().finish();
+                    self.pages.push_back(page);
+                },
+            }
+        }
+    }
+}
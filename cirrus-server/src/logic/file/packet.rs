@@ -1,157 +1,194 @@
-use std::fs::File;
+use std::{path::Path, time::Instant};
 
-use audio::Buf;
-use rubato::Resampler;
+use cirrus_protobuf::api::AudioCodec;
+use tracing::warn;
 
-use super::sample::SampleFrames;
+use crate::metrics;
 
-const MIN_ENCODER_PRESYNC_PKT_MS: i32 = 80;
+use super::{
+    codec::{self, FlacPacketEncoder, OpusPacketEncoder, PacketEncoder, PcmPacketEncoder},
+    encoding::EncodingProfile,
+    sample::SampleFrames,
+};
+
+// The encoder and the resampler carry state between packets, so packets of this duration
+// before the start are encoded and dropped, and the first packet is encoded as it is in a stream
+// from the beginning
+const ENCODER_PREROLL_MS: f64 = 80.;
 
 pub struct Packets {
     sample_frames: SampleFrames,
 
-    resampler: rubato::FftFixedOut<f32>,
-    resampler_input_buf: Vec<Vec<f32>>,
-    resampler_output_buf: Vec<Vec<f32>>,
-
-    packet_encoder: opus::Encoder,
-    packet_dur_ms: u32,
+    packet_encoder: Box<dyn PacketEncoder>,
 
-    packet_start_idx: usize,
     packet_len: usize,
     packet_dur: f64,
+
+    failed: bool,
 }
 
 impl Packets {
     pub fn new(
-        source: File,
+        source: &Path,
+        pkt_start_idx: usize,
+        pkt_num: usize,
+        pkt_len: usize,
+        sample_rate: usize,
+        channels: u32,
+        codec: AudioCodec,
+        encoding_profile: &EncodingProfile,
+    ) -> Result<Self, anyhow::Error> {
+        match codec {
+            AudioCodec::Opus => Self::new_opus(
+                source,
+                pkt_start_idx,
+                pkt_num,
+                pkt_len,
+                sample_rate,
+                channels,
+                encoding_profile,
+            ),
+            AudioCodec::Flac | AudioCodec::Pcm => Self::new_lossless(
+                source,
+                pkt_start_idx,
+                pkt_num,
+                pkt_len,
+                sample_rate,
+                channels,
+                codec,
+            ),
+        }
+    }
+
+    fn new_opus(
+        source: &Path,
         pkt_start_idx: usize,
         pkt_num: usize,
         pkt_len: usize,
         sample_rate: usize,
+        channels: u32,
+        encoding_profile: &EncodingProfile,
     ) -> Result<Self, anyhow::Error> {
         let packet_dur = pkt_len as f64 / sample_rate as f64;
-        let packet_dur_ms = (packet_dur * 1000 as f64) as u32;
 
-        let seek_start_frame_idx = 
-            if pkt_start_idx > 4
-                { pkt_start_idx - 4 }
-            else
-                { pkt_start_idx };
+        let preroll_pkt_num = std::cmp::min(
+            (ENCODER_PREROLL_MS / (packet_dur * 1000.)).ceil() as usize,
+            pkt_start_idx,
+        );
+        let seek_start_frame_idx = pkt_start_idx - preroll_pkt_num;
 
         let mut sample_frames = SampleFrames::new(
             source,
             seek_start_frame_idx,
             pkt_start_idx + pkt_num-1,
+            channels,
         )?;
 
-        let resampler = rubato::FftFixedOut::new(
-            sample_frames.codec_sample_rate.try_into().unwrap(), 
+        let packet_encoder = OpusPacketEncoder::new(
+            sample_frames.codec_sample_rate,
             sample_rate,
-            pkt_len, 
-            2,
-            2
+            pkt_len,
+            sample_frames.output_channels,
+            encoding_profile,
         )?;
 
-        sample_frames.set_frame_len(resampler.input_frames_max());
+        let input_frame_len = packet_encoder.get_input_frame_len();
 
-        if pkt_start_idx > 4 {
-            let seek_start_idx = pkt_start_idx - 4;
-            let seek_start_ts = seek_start_idx * resampler.input_frames_max();
+        sample_frames.set_frame_len(input_frame_len);
 
-            sample_frames.seek(seek_start_ts.try_into().unwrap())?;
+        // samples are read from the exact frame, regardless of packets of the source
+        if seek_start_frame_idx > 0 {
+            sample_frames.seek((seek_start_frame_idx * input_frame_len).try_into().unwrap())?;
         }
 
-        let mut resampler_input_buf = resampler.input_buffer_allocate();
-
-        for input_buf_ch in resampler_input_buf.iter_mut() {
-            input_buf_ch.extend(vec![0.; resampler.input_frames_max()]);
-        }
-        let resampler_output_buf = resampler.output_buffer_allocate();
-
-        let packet_encoder = opus::Encoder::new(48_000, opus::Channels::Stereo, opus::Application::Audio)?;
-
         let mut packets = Self{
             sample_frames,
 
-            resampler,
-            resampler_input_buf,
-            resampler_output_buf,
-
-            packet_encoder,
-            packet_dur_ms,
+            packet_encoder: Box::new(packet_encoder),
 
-            packet_start_idx: pkt_start_idx,
             packet_len: pkt_len,
             packet_dur,
+
+            failed: false,
         };
 
-        packets.resovle_encoder_frame_sync();
+        packets.preroll_encoder(preroll_pkt_num)?;
 
         Ok(packets)
     }
 
-    fn resovle_encoder_frame_sync(&mut self) {
-        if self.packet_start_idx == 0 {
-            return;
-        }
+    // Lossless packets are independent each other, so the encoder does not require presync
+    fn new_lossless(
+        source: &Path,
+        pkt_start_idx: usize,
+        pkt_num: usize,
+        pkt_len: usize,
+        sample_rate: usize,
+        channels: u32,
+        codec: AudioCodec,
+    ) -> Result<Self, anyhow::Error> {
+        let packet_dur = pkt_len as f64 / sample_rate as f64;
+
+        let mut sample_frames = SampleFrames::new(
+            source,
+            pkt_start_idx,
+            pkt_start_idx + pkt_num-1,
+            channels,
+        )?;
 
-        loop {
-            let frame_idx_delta = self.packet_start_idx as i64 - self.sample_frames.get_curr_frame_idx();
-            if frame_idx_delta as u32 * self.packet_dur_ms <= MIN_ENCODER_PRESYNC_PKT_MS.try_into().unwrap() {
-                break;
-            }
+        let packet_len = codec::get_lossless_packet_len(
+            sample_frames.codec_sample_rate,
+            pkt_len.try_into().unwrap(),
+            sample_rate.try_into().unwrap(),
+        );
 
-            self.sample_frames.next().unwrap().unwrap();
-        }
+        let bits_per_sample = codec::get_lossless_bits_per_sample(sample_frames.codec_bits_per_sample)?;
+
+        let packet_encoder: Box<dyn PacketEncoder> = match codec {
+            AudioCodec::Flac => Box::new(FlacPacketEncoder::new(
+                sample_frames.codec_sample_rate,
+                sample_frames.output_channels,
+                bits_per_sample,
+                packet_len,
+            )?),
+            _ => Box::new(PcmPacketEncoder::new(bits_per_sample)?),
+        };
+
+        sample_frames.set_frame_len(packet_len);
 
-        while self.packet_start_idx -1 > self.sample_frames.get_curr_frame_idx() as usize {
-            let frame = self.sample_frames.next().unwrap().unwrap();
-            self.create_packet(frame.samples);
+        if pkt_start_idx > 0 {
+            sample_frames.seek((pkt_start_idx * packet_len).try_into().unwrap())?;
         }
-    }
 
-    fn create_packet(&mut self, samples: Vec<f32>) -> Vec<u8> {
-        let samples = audio::wrap::interleaved(samples.as_slice(), 2);
-        let samples_reader = audio::io::Read::new(samples);
+        Ok(Self {
+            sample_frames,
 
-        for ch_idx in 0..samples_reader.channels() {
-            let sample_ch_buf = samples_reader
-                .get(ch_idx)
-                .unwrap()
-                .iter()
-                .collect::<Vec<_>>();
+            packet_encoder,
 
-            self.resampler_input_buf[ch_idx] = sample_ch_buf;
-        }
+            packet_len,
+            packet_dur,
 
-        self.resampler.process_into_buffer(
-            &self.resampler_input_buf, 
-            &mut self.resampler_output_buf,
-            None
-        ).unwrap();
+            failed: false,
+        })
+    }
 
-        let mut resampled_output = audio::buf::Interleaved::<f32>::with_topology(
-            2, 
-            self.packet_len
-        );
+    // Whether packets ended early by an error of decoding or encoding
+    pub fn is_failed(&self) -> bool {
+        self.failed
+    }
 
-        for ch_idx in 0..2 {
-            for (c, s) in resampled_output
-                .get_mut(ch_idx)
-                .unwrap()
-                .iter_mut()
-                .zip(&self.resampler_output_buf[ch_idx])
-            {
-                *c = *s;
-            }
-        }
+    // Encodes packets of the preroll, whose outputs are dropped
+    fn preroll_encoder(&mut self, preroll_pkt_num: usize) -> Result<(), anyhow::Error> {
+        for _ in 0..preroll_pkt_num {
+            let frame = match self.sample_frames.next() {
+                Some(frame) => frame?,
+                None => break,
+            };
 
-        let packet = self.packet_encoder
-            .encode_vec_float(resampled_output.as_slice(), 4000)
-            .unwrap();
+            self.packet_encoder.encode(frame.idx, frame.samples)?;
+        }
 
-        packet
+        Ok(())
     }
 }
 
@@ -159,14 +196,33 @@ impl Iterator for Packets {
     type Item = Packet;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let frame = self.sample_frames.next();
-
-        if frame.is_none() {
+        if self.failed {
             return None;
         }
 
-        let frame = frame.unwrap().unwrap();
-        let encoded_frame = self.create_packet(frame.samples);
+        let frame = match self.sample_frames.next()? {
+            Ok(frame) => frame,
+            Err(err) => {
+                warn!("failed to read sample frame: {}", err);
+                self.failed = true;
+
+                return None;
+            },
+        };
+
+        let encode_start = Instant::now();
+        let encoded_frame = match self.packet_encoder.encode(frame.idx, frame.samples) {
+            Ok(encoded_frame) => encoded_frame,
+            Err(err) => {
+                warn!("failed to encode packet {}: {}", frame.idx, err);
+                self.failed = true;
+
+                return None;
+            },
+        };
+
+        metrics::PACKET_ENCODE_DURATION.observe(encode_start.elapsed().as_secs_f64());
+        metrics::ENCODED_PACKETS.inc();
 
         Some(Packet {
             idx: frame.idx,
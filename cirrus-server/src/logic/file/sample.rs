@@ -1,7 +1,11 @@
-use std::fs::File;
+use std::path::Path;
 
 use itertools::Itertools;
-use symphonia::core::{formats::{FormatReader, SeekMode, SeekTo}, io::MediaSourceStream, probe::Hint, codecs::{CODEC_TYPE_NULL, Decoder}, audio::SampleBuffer, errors::Error};
+use symphonia::core::{formats::{FormatReader, SeekMode, SeekTo}, codecs::Decoder, audio::SampleBuffer, errors::Error};
+
+use crate::util;
+
+use super::channel::{self, ChannelMixer};
 
 pub struct SampleFrames {
     media_reader: Box<dyn FormatReader>,
@@ -9,6 +13,9 @@ pub struct SampleFrames {
     track_id: u32,
 
     pub codec_sample_rate: u32,
+    pub codec_bits_per_sample: Option<u32>,
+    pub output_channels: usize,
+    channel_mixer: Option<ChannelMixer>,
     seek_start_frame_idx: usize,
     seek_end_frame_idx: usize,
 
@@ -22,29 +29,31 @@ pub struct SampleFrames {
 
 impl SampleFrames {
     pub fn new(
-        source: File,
+        source: &Path,
         seek_start_frame_idx: usize,
         seek_end_frame_idx: usize,
+        requested_channels: u32,
     ) -> Result<Self, anyhow::Error> {
-        let mss = MediaSourceStream::new(Box::new(source), Default::default());
-        let hint = Hint::new();
-
-        let probe_res = symphonia::default::get_probe()
-            .format(&hint, mss, &Default::default(), &Default::default()).unwrap();
+        let probe_res = util::audio::probe_file(source)?;
 
         let format = probe_res.format;
-        let track = format.tracks()
-            .iter()
-            .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
-            .expect("no supported audio tracks");
+        let track = util::audio::find_audio_track(format.as_ref())?;
 
         let track_id = track.id;
 
-        let codec_sample_rate = track.codec_params.sample_rate.unwrap();
+        let codec_sample_rate = match track.codec_params.sample_rate {
+            Some(sample_rate) => sample_rate,
+            None => return Err(anyhow::anyhow!("unknown sample rate")),
+        };
+        let codec_bits_per_sample = track.codec_params.bits_per_sample;
+
+        let output_channels = channel::resolve_output_channels(
+            track.codec_params.channels.map(|item| item.count()),
+            requested_channels,
+        )?;
 
         let decoder = symphonia::default::get_codecs()
-            .make(&track.codec_params, &Default::default())
-            .expect("unsupported codec");
+            .make(&track.codec_params, &Default::default())?;
 
         Ok(Self {
             media_reader: format,
@@ -52,6 +61,10 @@ impl SampleFrames {
             track_id,
 
             codec_sample_rate,
+            codec_bits_per_sample,
+            output_channels,
+            // created from the layout of the first decoded buffer
+            channel_mixer: None,
             seek_start_frame_idx,
             seek_end_frame_idx,
 
@@ -84,7 +97,7 @@ impl SampleFrames {
     
     pub fn get_curr_frame_idx(&self) -> i64 {
         // should call this function after read samples
-        let remain_frame_len = self.frame_buf.len() / 2;
+        let remain_frame_len = self.frame_buf.len() / self.output_channels;
         let curr_frame_size = (self.curr_frame_start_ts + self.curr_frame_dur) as usize - remain_frame_len;
 
         (curr_frame_size / self.frame_len) as i64 -1
@@ -95,7 +108,7 @@ impl SampleFrames {
     }
 
     fn read_samples(&mut self) -> Result<(), Error> {
-        while self.frame_buf.len() / 2 < self.frame_len {
+        while self.frame_buf.len() / self.output_channels < self.frame_len {
             let mut read_start_offset = 0;
 
             let packet = match self.media_reader.next_packet() {
@@ -124,20 +137,37 @@ impl SampleFrames {
 
             let audio_buf = match self.audio_decoder.decode(&packet) {
                 Ok(buf_ref) => buf_ref,
+                // corrupted packet (e.g. broken MP3 frame) is skipped
+                Err(Error::DecodeError(_)) => continue,
                 Err(err) => {
                     return Err(err);
                 },
             };
 
+            let audio_spec = *audio_buf.spec();
+            let source_channels = audio_spec.channels.count();
+
+            if self.channel_mixer.is_none() {
+                self.channel_mixer = match ChannelMixer::new(audio_spec.channels, self.output_channels) {
+                    Ok(channel_mixer) => Some(channel_mixer),
+                    Err(_err) => return Err(Error::Unsupported("unsupported channel layout")),
+                };
+            }
+
             let mut sample_buf = SampleBuffer::<f32>::new(
                 audio_buf.capacity() as u64, 
-                *audio_buf.spec()
+                audio_spec
             );
 
             sample_buf.copy_interleaved_ref(audio_buf);
-            let (_, frames) = sample_buf.samples().split_at(read_start_offset*2);
 
-            self.frame_buf.extend_from_slice(frames);
+            let read_start_offset = std::cmp::min(read_start_offset * source_channels, sample_buf.samples().len());
+            let (_, frames) = sample_buf.samples().split_at(read_start_offset);
+
+            self.channel_mixer
+                .as_ref()
+                .unwrap()
+                .mix(frames, &mut self.frame_buf);
             
             self.resolved_first_offset = true;
         }
@@ -169,7 +199,7 @@ impl Iterator for SampleFrames {
         }
         
         let samples = self.frame_buf
-            .drain(..self.frame_len*2)
+            .drain(..self.frame_len * self.output_channels)
             .collect_vec();
 
         let next_frame_seek_start_ts =
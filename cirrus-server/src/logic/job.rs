@@ -0,0 +1,407 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use bson::oid::ObjectId;
+use chrono::Utc;
+use cirrus_protobuf::api::{
+    job_event, JobEvent, JobFileError, JobKind, JobRes, JobStatus,
+};
+use tokio::sync::{broadcast, OwnedMutexGuard};
+use tracing::{info, warn};
+
+use crate::{metrics, model::{dto, Storage}};
+
+// only recent errors are kept, and others are counted
+const MAX_JOB_FILE_ERRORS: usize = 100;
+const JOB_EVENT_CAPACITY: usize = 1024;
+const JOB_PERSIST_INTERVAL: Duration = Duration::from_secs(1);
+const JOB_SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+fn to_job_res(job: &dto::Job) -> JobRes {
+    let kind = match job.kind {
+        dto::JobKind::AnalyzeAudioLibrary => JobKind::AnalyzeAudioLibrary,
+        dto::JobKind::RefreshAudioLibrary => JobKind::RefreshAudioLibrary,
+    };
+
+    let status = match job.status {
+        dto::JobStatus::Pending => JobStatus::Pending,
+        dto::JobStatus::Running => JobStatus::Running,
+        dto::JobStatus::Completed => JobStatus::Completed,
+        dto::JobStatus::Failed => JobStatus::Failed,
+        dto::JobStatus::Cancelled => JobStatus::Cancelled,
+    };
+
+    JobRes {
+        id: job.id.as_ref().unwrap().to_string(),
+        kind: kind as i32,
+        status: status as i32,
+        files_scanned: job.files_scanned as u64,
+        tags_written: job.tags_written as u64,
+        error_count: job.error_count as u64,
+        file_errors: job.file_errors
+            .iter()
+            .map(|item| JobFileError {
+                path: item.path.clone(),
+                error: item.error.clone(),
+            })
+            .collect(),
+        message: job.message.clone().unwrap_or_default(),
+        created_timestamp: job.created_timestamp,
+        updated_timestamp: job.updated_timestamp,
+    }
+}
+
+// Jobs write the same libraries and tags, so that a job is not started while another one runs
+#[derive(Debug)]
+pub struct JobRunningError {
+    pub job_id: ObjectId,
+}
+
+impl std::fmt::Display for JobRunningError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "job {} is running, and jobs run one at a time", self.job_id)
+    }
+}
+
+impl std::error::Error for JobRunningError {}
+
+fn parse_job_id(job_id: &str) -> Result<ObjectId, anyhow::Error> {
+    match ObjectId::parse_str(job_id) {
+        Ok(job_id) => Ok(job_id),
+        Err(_) => Err(anyhow::anyhow!("invalid job id: {}", job_id)),
+    }
+}
+
+struct JobState {
+    job: Mutex<dto::Job>,
+    cancelled: AtomicBool,
+    events: broadcast::Sender<JobEvent>,
+}
+
+impl JobState {
+    // Events are sent while the job is locked, so that watchers do not miss events after a snapshot
+    fn update(
+        &self,
+        event_kind: job_event::Kind,
+        path: Option<&Path>,
+        error: Option<String>,
+        update_fn: impl FnOnce(&mut dto::Job),
+    ) {
+        let mut job = self.job.lock().unwrap();
+
+        update_fn(&mut job);
+        job.updated_timestamp = Utc::now().timestamp();
+
+        let _ = self.events.send(JobEvent {
+            kind: event_kind as i32,
+            job: Some(to_job_res(&job)),
+            path: path
+                .map(|item| item.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            error: error.unwrap_or_default(),
+        });
+    }
+
+    fn snapshot(&self) -> dto::Job {
+        self.job.lock().unwrap().clone()
+    }
+}
+
+// Reports progress of a job, and does nothing if the task does not run as a job
+#[derive(Clone, Default)]
+pub struct JobContext {
+    state: Option<Arc<JobState>>,
+}
+
+impl JobContext {
+    pub fn file_scanned(&self, path: &Path) {
+        if let Some(state) = &self.state {
+            state.update(job_event::Kind::FileScanned, Some(path), None, |job| {
+                job.files_scanned += 1;
+            });
+        }
+    }
+
+    pub fn tag_written(&self, path: &Path) {
+        if let Some(state) = &self.state {
+            state.update(job_event::Kind::TagWritten, Some(path), None, |job| {
+                job.tags_written += 1;
+            });
+        }
+    }
+
+    pub fn file_error(&self, path: &Path, err: &anyhow::Error) {
+        warn!("failed to process {:?}: {}", path, err);
+
+        if let Some(state) = &self.state {
+            let error = err.to_string();
+
+            state.update(job_event::Kind::FileError, Some(path), Some(error.clone()), |job| {
+                job.error_count += 1;
+
+                if job.file_errors.len() >= MAX_JOB_FILE_ERRORS {
+                    job.file_errors.remove(0);
+                }
+                job.file_errors.push(dto::JobFileError {
+                    path: path.to_string_lossy().to_string(),
+                    error,
+                });
+            });
+        }
+    }
+
+    pub fn check_cancelled(&self) -> Result<(), anyhow::Error> {
+        match &self.state {
+            Some(state) if state.cancelled.load(Ordering::Relaxed) => Err(anyhow::anyhow!("job is cancelled")),
+            _ => Ok(()),
+        }
+    }
+}
+
+pub struct JobManager {
+    storage: Arc<Storage>,
+    running_jobs: Arc<Mutex<HashMap<ObjectId, Arc<JobState>>>>,
+    // held by a running job, and by changes of the library watcher while they are applied
+    library_lock: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl JobManager {
+    pub fn new(storage: Arc<Storage>) -> Self {
+        Self {
+            storage,
+            running_jobs: Default::default(),
+            library_lock: Default::default(),
+        }
+    }
+
+    // Jobs that were not finished before the server stopped are marked as failed
+    pub async fn recover_interrupted_jobs(
+        &self,
+    ) -> Result<(), anyhow::Error> {
+        let interrupted_jobs = self.storage.job.get_unfinished().await?;
+
+        for mut job in interrupted_jobs.into_iter() {
+            job.status = dto::JobStatus::Failed;
+            job.message = Some("interrupted by server shutdown".to_string());
+            job.updated_timestamp = Utc::now().timestamp();
+
+            self.storage.job.update(&job.id.unwrap(), &job).await?;
+
+            info!("marked interrupted job {} as failed", job.id.unwrap());
+        }
+
+        Ok(())
+    }
+
+    pub async fn spawn_job<F, Fut>(
+        &self,
+        kind: dto::JobKind,
+        job_fn: F,
+    ) -> Result<JobRes, anyhow::Error>
+    where
+        F: FnOnce(JobContext) -> Fut,
+        Fut: Future<Output = Result<(), anyhow::Error>> + Send + 'static,
+    {
+        let job = dto::Job::new(kind);
+        let job_id = job.id.unwrap();
+
+        let (events, _) = broadcast::channel(JOB_EVENT_CAPACITY);
+        let state = Arc::new(JobState {
+            job: Mutex::new(job.clone()),
+            cancelled: AtomicBool::new(false),
+            events,
+        });
+
+        {
+            let mut running_jobs = self.running_jobs.lock().unwrap();
+
+            if let Some(running_job_id) = running_jobs.keys().next() {
+                return Err(JobRunningError { job_id: *running_job_id }.into());
+            }
+
+            running_jobs.insert(job_id, state.clone());
+        }
+
+        if let Err(err) = self.storage.job.create(&job).await {
+            self.running_jobs.lock().unwrap().remove(&job_id);
+
+            return Err(err);
+        }
+
+        let job_future = job_fn(JobContext { state: Some(state.clone()) });
+        let running_jobs = self.running_jobs.clone();
+        let library_lock = self.library_lock.clone();
+        let storage = self.storage.clone();
+
+        tokio::spawn(async move {
+            // waits until changes of the library watcher being applied are synced
+            let _library_guard = library_lock.lock_owned().await;
+
+            info!("start job {} ({:?})", job_id, kind);
+            let job_start = Instant::now();
+
+            state.update(job_event::Kind::Status, None, None, |job| {
+                job.status = dto::JobStatus::Running;
+            });
+
+            tokio::pin!(job_future);
+            let mut persist_interval = tokio::time::interval(JOB_PERSIST_INTERVAL);
+
+            let job_res = loop {
+                tokio::select! {
+                    job_res = &mut job_future => break job_res,
+                    _ = persist_interval.tick() => {
+                        persist_job(&storage, &state.snapshot()).await;
+                    },
+                }
+            };
+
+            let (status, message) = match job_res {
+                Ok(_) => (dto::JobStatus::Completed, None),
+                Err(_) if state.cancelled.load(Ordering::Relaxed) => (dto::JobStatus::Cancelled, None),
+                Err(err) => (dto::JobStatus::Failed, Some(err.to_string())),
+            };
+
+            info!("job {} is finished with status {:?}", job_id, status);
+            metrics::JOB_DURATION
+                .with_label_values(&[&format!("{:?}", kind), &format!("{:?}", status)])
+                .observe(job_start.elapsed().as_secs_f64());
+
+            state.update(job_event::Kind::Status, None, None, |job| {
+                job.status = status;
+                job.message = message;
+            });
+
+            persist_job(&storage, &state.snapshot()).await;
+
+            running_jobs.lock().unwrap().remove(&job_id);
+        });
+
+        Ok(to_job_res(&job))
+    }
+
+    // Waits until the running job is finished, and keeps jobs from starting until the guard is dropped
+    pub async fn lock_library(&self) -> OwnedMutexGuard<()> {
+        self.library_lock.clone().lock_owned().await
+    }
+
+    pub async fn list_jobs(
+        &self,
+        max_item_num: u64,
+        page: u64,
+    ) -> Result<Vec<JobRes>, anyhow::Error> {
+        let jobs = self.storage.job.get_paginated(max_item_num as i64, page).await?;
+
+        let res = jobs
+            .iter()
+            .map(|item| match self.get_running_job(&item.id.unwrap()) {
+                // persisted state of a running job may be outdated
+                Some(state) => to_job_res(&state.snapshot()),
+                None => to_job_res(item),
+            })
+            .collect();
+
+        Ok(res)
+    }
+
+    pub async fn get_job(
+        &self,
+        job_id: &str,
+    ) -> Result<JobRes, anyhow::Error> {
+        let job_id = parse_job_id(job_id)?;
+
+        if let Some(state) = self.get_running_job(&job_id) {
+            return Ok(to_job_res(&state.snapshot()));
+        }
+
+        match self.storage.job.get(&job_id).await? {
+            Some(job) => Ok(to_job_res(&job)),
+            None => Err(anyhow::anyhow!("job {} does not exist", job_id)),
+        }
+    }
+
+    pub async fn cancel_job(
+        &self,
+        job_id: &str,
+    ) -> Result<(), anyhow::Error> {
+        let job_id = parse_job_id(job_id)?;
+
+        match self.get_running_job(&job_id) {
+            // a finished job is kept in running jobs until its state is saved
+            Some(state) if state.snapshot().is_finished() => Err(anyhow::anyhow!("job {} is already finished", job_id)),
+            Some(state) => {
+                state.cancelled.store(true, Ordering::Relaxed);
+
+                Ok(())
+            },
+            None => match self.storage.job.get(&job_id).await? {
+                Some(job) if job.is_finished() => Err(anyhow::anyhow!("job {} is already finished", job_id)),
+                // unfinished jobs which do not run are interrupted, and marked as failed at the next start
+                Some(_) => Err(anyhow::anyhow!("job {} is interrupted", job_id)),
+                None => Err(anyhow::anyhow!("job {} does not exist", job_id)),
+            },
+        }
+    }
+
+    // Returns current state of the job, and a receiver of further events if the job is running
+    pub async fn watch_job(
+        &self,
+        job_id: &str,
+    ) -> Result<(JobRes, Option<broadcast::Receiver<JobEvent>>), anyhow::Error> {
+        let job_id = parse_job_id(job_id)?;
+
+        if let Some(state) = self.get_running_job(&job_id) {
+            let job = state.job.lock().unwrap();
+            let events = state.events.subscribe();
+
+            return Ok((to_job_res(&job), Some(events)));
+        }
+
+        match self.storage.job.get(&job_id).await? {
+            Some(job) => Ok((to_job_res(&job), None)),
+            None => Err(anyhow::anyhow!("job {} does not exist", job_id)),
+        }
+    }
+
+    // Cancels running jobs at shutdown, and waits until they save their state
+    pub async fn cancel_running_jobs(&self, timeout: Duration) {
+        let running_jobs: Vec<_> = self.running_jobs.lock().unwrap().values().cloned().collect();
+        if running_jobs.is_empty() {
+            return;
+        }
+
+        for state in running_jobs.iter() {
+            state.cancelled.store(true, Ordering::Relaxed);
+        }
+
+        info!("cancel {} running jobs", running_jobs.len());
+
+        let wait_jobs = async {
+            while !self.running_jobs.lock().unwrap().is_empty() {
+                tokio::time::sleep(JOB_SHUTDOWN_POLL_INTERVAL).await;
+            }
+        };
+
+        if tokio::time::timeout(timeout, wait_jobs).await.is_err() {
+            warn!("running jobs are not finished in {:?}, and are marked as failed at the next start", timeout);
+        }
+    }
+
+    fn get_running_job(&self, job_id: &ObjectId) -> Option<Arc<JobState>> {
+        self.running_jobs.lock().unwrap().get(job_id).cloned()
+    }
+}
+
+async fn persist_job(storage: &Storage, job: &dto::Job) {
+    if let Err(err) = storage.job.update(&job.id.unwrap(), job).await {
+        warn!("failed to save state of job {}: {}", job.id.unwrap(), err);
+    }
+}
@@ -1,133 +1,139 @@
 use std::{
-    path::{Path, PathBuf}, collections::{HashMap, HashSet},
+    path::{Path, PathBuf}, collections::{HashMap, HashSet}, sync::Arc,
 };
 
 use bson::oid::ObjectId;
 
+use cirrus_protobuf::api::AudioLibraryRes;
 use itertools::Itertools;
 use mongodb::bson;
+use notify::DebouncedEvent;
+use tokio::sync::mpsc;
 use walkdir::{DirEntry, WalkDir};
+use tracing::{debug, info, warn};
 
 use crate::{
     util, 
-    model::{crud, dto::{self, GetPathValue}}
+    model::{dto::{self, GetPathValue}, Storage},
+    state::ServerState,
 };
 
+use super::{JobContext, JobManager, LibraryWatcher, SmartPlaylist, TranscodeCache};
+
 // * path not exist -> return not found
 // * path is added already -> return added already
-fn get_audio_library_entries(path: &Path, audio_types: &Vec<&str>) -> Vec<DirEntry> {
-    let audio_library_entries: Vec<_> = WalkDir::new(path)
+fn get_audio_library_entries(path: &Path, audio_types: &[String]) -> Result<Vec<DirEntry>, anyhow::Error> {
+    let mut audio_library_entries = Vec::new();
+
+    for entry in WalkDir::new(path)
         .into_iter()
-        .filter_map(|item| item.ok()
-            .and_then(|entry| entry.path().is_dir().then(|| entry))
-            .and_then(|entry2| {
-                let mut current_dir = std::fs::read_dir(entry2.path()).unwrap();
-                current_dir.any(|content_entry| {
-                    match content_entry.unwrap().path().extension() {
-                        Some(extension) => return audio_types.contains(&extension.to_str().unwrap()),
-                        None => false,
-                    }
-                }).then(|| entry2)
-            })
-        )
-        .collect();
+        .filter_map(|item| item.ok())
+        .filter(|item| item.path().is_dir())
+    {
+        let mut has_audio_files = false;
+
+        for content_entry in std::fs::read_dir(entry.path())? {
+            if util::audio::is_audio_type(&content_entry?.path(), audio_types) {
+                has_audio_files = true;
+                break;
+            }
+        }
 
-    audio_library_entries
+        if has_audio_files {
+            audio_library_entries.push(entry);
+        }
+    }
+
+    Ok(audio_library_entries)
 }
 
-fn get_audio_file_paths(current_path: &Path, audio_types: &Vec<&str>) -> Vec<PathBuf> {
-    let audio_file_dir = std::fs::read_dir(current_path).unwrap();
+fn get_audio_file_paths(current_path: &Path, audio_types: &[String]) -> Result<Vec<PathBuf>, anyhow::Error> {
+    let audio_file_dir = std::fs::read_dir(current_path)?;
     let audio_file_paths: Vec<_> = audio_file_dir
         .into_iter()
         .filter_map(|item| item.ok()
             .and_then(|entry| entry.path().is_file().then(|| entry.path()))
-            .and_then(|pathbuf| {
-                match pathbuf.extension() {
-                    Some(file_extension) => {
-                        let file_extension = file_extension.to_str().unwrap();
-                        return audio_types.contains(&file_extension).then(|| pathbuf)
-                    },
-                    None => None,
-                }
-            })
+            .and_then(|pathbuf| util::audio::is_audio_type(&pathbuf, audio_types).then(|| pathbuf))
         )
         .collect();
     
-    audio_file_paths
+    Ok(audio_file_paths)
 }
 
 pub struct AudioLibrary {
-    crud_audio_lib: crud::AudioLibrary,
-    crud_audio_lib_root: crud::AudioLibraryRoot,
-    crud_audio_file: crud::AudioFile,
-    crud_audio_tag: crud::AudioTag,
+    state: Arc<ServerState>,
+    storage: Arc<Storage>,
+    transcode_cache: Arc<TranscodeCache>,
+    library_watcher: Arc<LibraryWatcher>,
+    smart_playlist: Arc<SmartPlaylist>,
 }
 
-impl Default for AudioLibrary {
-    fn default() -> Self {
+impl AudioLibrary {
+    pub fn new(
+        state: Arc<ServerState>,
+        storage: Arc<Storage>,
+        transcode_cache: Arc<TranscodeCache>,
+        library_watcher: Arc<LibraryWatcher>,
+        smart_playlist: Arc<SmartPlaylist>,
+    ) -> Self {
         Self { 
-            crud_audio_lib: Default::default(),
-            crud_audio_lib_root: Default::default(),
-            crud_audio_file: Default::default(),
-            crud_audio_tag: Default::default(),
+            state,
+            storage,
+            transcode_cache,
+            library_watcher,
+            smart_playlist,
         }
     }
-}
 
-impl AudioLibrary {
     pub async fn add_audio_library(
         &self,
-        db: mongodb::Client,
         library_root: &Path
     ) -> Result<String, anyhow::Error> {
         if !library_root.exists() {
-            return Err(anyhow::anyhow!("library {} does not exists", library_root.to_str().unwrap()))
+            return Err(anyhow::anyhow!("library {:?} does not exists", library_root))
         }
 
-        if self.crud_audio_lib_root.path.check_exists_by_path(db.clone(), library_root).await? {
+        if self.storage.library_root.check_exists_by_path(library_root).await? {
             return Err(anyhow::anyhow!("path '{:?}' already exists", library_root))
         }
 
-        let audio_types = vec!["aiff"];
+        let audio_types = self.state.settings().audio_library.audio_types.clone();
 
-        let audio_library_entries = get_audio_library_entries(library_root, &audio_types);
-        let audio_file_docs = audio_library_entries
-            .iter()
-            .map(|item| get_audio_file_paths(item.path(), &audio_types))
-            .flat_map(|item| item)
-            .map(|item| dto::AudioFile::new(&item))
-            .collect::<Vec<_>>();
+        let audio_library_entries = get_audio_library_entries(library_root, &audio_types)?;
+
+        let mut audio_file_docs = Vec::new();
+        for audio_library_entry in audio_library_entries.iter() {
+            for audio_file_path in get_audio_file_paths(audio_library_entry.path(), &audio_types)?.iter() {
+                audio_file_docs.push(dto::AudioFile::new(audio_file_path)?);
+            }
+        }
         
         let library_docs = audio_library_entries
             .iter()
             .map(|item| dto::AudioLibrary::new(&item.path()))
-            .collect::<Vec<_>>();
-
-        let create_lib_root_res = match self.crud_audio_lib_root.single.create(
-                db.clone(), 
-                &dto::AudioLibrary::new(&library_root)
-            ).await {
-                Ok(res) => res,
-                Err(err) => return Err(anyhow::anyhow!(err)),
-        };
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let library_root_doc = dto::AudioLibrary::new(&library_root)?;
+        self.storage.library_root.create(&library_root_doc).await?;
 
         if !library_docs.is_empty() {
-            self.crud_audio_lib.many.create_many(db.clone(), library_docs).await?;
+            self.storage.library.create_many(library_docs).await?;
         }
         
         if !audio_file_docs.is_empty() {
-            self.crud_audio_file.many.create_many(db.clone(), audio_file_docs).await?;
+            self.storage.audio_file.create_many(audio_file_docs).await?;
         }
 
-        Ok(format!("{:?}", create_lib_root_res.inserted_id))
+        self.library_watcher.watch(library_root)?;
+
+        Ok(library_root_doc.id.unwrap().to_string())
     }
 
     pub async fn remove_audio_library(
         &self,
-        db: mongodb::Client,
         path: &Path
     ) -> Result<String, anyhow::Error> {
-        if !self.crud_audio_lib_root.path.check_exists_by_path(db.clone(), path).await? {
+        if !self.storage.library_root.check_exists_by_path(path).await? {
             return Err(anyhow::anyhow!("path '{:?}' not exists", path))
         }
 
@@ -135,16 +141,12 @@ impl AudioLibrary {
         let mut delete_file_count = 0;
         let mut delete_library_count = 0;
 
-        let delete_audio_libs = self.crud_audio_lib.path.get_by_path(
-                db.clone(), 
-                path
-            ).await?;
+        let delete_audio_libs = self.storage.library.get_by_path(path).await?;
         
         for audio_lib in delete_audio_libs.iter() {
-            let audio_files = self.crud_audio_file.path.get_by_materialized_path(
-                    db.clone(), 
-                    &audio_lib.get_mat_path_val()
-                ).await?;
+            let audio_files = self.storage.audio_file
+                .get_by_materialized_path(&audio_lib.get_mat_path_val())
+                .await?;
 
             let (delete_file_ids, delete_tag_ids): (Vec<ObjectId>, Vec<Option<ObjectId>>)  = audio_files
                 .into_iter()
@@ -159,106 +161,93 @@ impl AudioLibrary {
                 .filter_map(|item| item)
                 .collect_vec();
 
-            let delete_audio_tag_res = self.crud_audio_tag
-                .many
-                .delete_many(
-                    db.clone(), 
-                    &delete_tag_ids
-                ).await?;
-
-            delete_tag_count += delete_audio_tag_res.deleted_count;
-
-            let delete_audio_file_res = self.crud_audio_file
-                .many
-                .delete_many(
-                    db.clone(), 
-                    &delete_file_ids
-                ).await?;
+            delete_tag_count += self.storage.audio_tag.delete_many(&delete_tag_ids).await?;
 
-            delete_file_count += delete_audio_file_res.deleted_count;
-
-            let delete_lib_res = self.crud_audio_lib
-                .single
-                .delete(
-                    db.clone(), 
-                    &audio_lib.id.unwrap()
-                ).await?;
+            for delete_file_id in delete_file_ids.iter() {
+                self.transcode_cache.invalidate_audio_file(delete_file_id);
+            }
 
-            delete_library_count += delete_lib_res.deleted_count;
+            delete_file_count += self.storage.audio_file.delete_many(&delete_file_ids).await?;
+            delete_library_count += self.storage.library.delete(&audio_lib.id.unwrap()).await?;
         }
 
-        self.crud_audio_lib_root
-            .path
-            .delete_by_path(
-                db.clone(), 
-                path
-            ).await?;
+        self.storage.library_root.delete_by_path(path).await?;
+
+        self.library_watcher.unwatch(path)?;
+        self.evaluate_smart_playlists().await;
 
         Ok(format!("deleted tag count: {}, deleted file count: {}, deleted library count: {}", delete_tag_count, delete_file_count, delete_library_count))
     }
 
     pub async fn analyze_audio_library(
         &self,
-        db: mongodb::Client,
+        job: &JobContext,
     ) -> Result<(), anyhow::Error> {
-        let audio_libs = self.crud_audio_lib_root
-            .many
-            .get_all(db.clone())
-            .await?;
+        let audio_libs = self.storage.library_root.get_all().await?;
 
         for audio_lib in audio_libs.into_iter() {
-            let audio_files = self.crud_audio_file
-                .path
-                .get_by_materialized_path(
-                    db.clone(), 
-                    &audio_lib.get_mat_path_val()
-                ).await?;
+            let audio_files = self.storage.audio_file
+                .get_by_materialized_path(&audio_lib.get_mat_path_val())
+                .await?;
 
+            // files of a directory are analyzed in a row, so that its sidecar artwork is read once
             let mut audio_files = audio_files
                 .into_iter()
                 .filter(|item| item.audio_tag_refer.is_none())
+                .sorted_by(|a, b| a.parent_path.cmp(&b.parent_path))
                 .collect_vec();
+            let mut sidecar_artwork = dto::SidecarArtwork::default();
 
             for audio_file in audio_files.iter_mut() {
-                let audio_tag = dto::AudioTag::new(
+                job.check_cancelled()?;
+
+                let audio_file_path = audio_file.get_os_path();
+                job.file_scanned(&audio_file_path);
+
+                // a broken file does not stop analyzing others
+                let mut audio_tag = match dto::AudioTag::new(
                         None,
                         &util::path::materialized_to_path(&audio_file.parent_path), 
-                        &audio_file.filename
-                    )?;
-
-                self.crud_audio_tag.single.create(db.clone(), &audio_tag).await?;
+                        &audio_file.filename,
+                        &mut sidecar_artwork
+                    ) {
+                        Ok(audio_tag) => audio_tag,
+                        Err(err) => {
+                            job.file_error(&audio_file_path, &err);
+                            continue;
+                        },
+                    };
+
+                self.save_artwork(&mut audio_tag).await?;
+                self.storage.audio_tag.create(&audio_tag).await?;
 
                 audio_file.audio_tag_refer = audio_tag.id.clone();
 
-                let update_res = self.crud_audio_file
-                    .single
-                    .update(
-                        db.clone(), 
-                        &audio_file.id.unwrap(), 
-                        audio_file
-                    ).await?;
+                self.storage.audio_file.update(&audio_file.id.unwrap(), audio_file).await?;
 
-                println!("ur: {:?}", update_res);
+                job.tag_written(&audio_file_path);
             }
         }
 
+        self.evaluate_smart_playlists().await;
+
         Ok(())
     }
 
     pub async fn refresh_audio_library(
         &self,
-        db: mongodb::Client,
+        job: &JobContext,
     ) -> Result<(), anyhow::Error> {
-        let audio_lib_roots = self.crud_audio_lib_root.many.get_all(db.clone()).await?;
-        let audio_types = vec!["aiff"];
+        let audio_types = self.state.settings().audio_library.audio_types.clone();
+
+        let audio_lib_roots = self.storage.library_root.get_all().await?;
 
         for audio_lib_root in audio_lib_roots.iter() {
-            let audio_libs = self.crud_audio_lib
-                .path
-                .get_by_materialized_path(
-                    db.clone(), 
-                    &audio_lib_root.get_mat_path_val()
-                ).await?;
+            job.check_cancelled()?;
+
+            let audio_libs = self.storage.library
+                .get_by_materialized_path(&audio_lib_root.get_mat_path_val())
+                .await?;
 
             let audio_libraries: HashMap<_, _> = audio_libs.iter()
                 .map(|item| (item.os_path.clone(), item))
@@ -267,93 +256,91 @@ impl AudioLibrary {
             let local_audio_library_entreis = get_audio_library_entries(
                 Path::new(&audio_lib_root.os_path), 
                 &audio_types
-            );
+            )?;
 
             let audio_libraries_keys: HashSet<_> = audio_libraries
                 .iter()
                 .map(|(k, _)| util::path::replace_with_common_separator(k))
                 .collect();
-            let local_audio_libraries_keys: HashSet<_> = local_audio_library_entreis
+            let local_audio_libraries_keys = local_audio_library_entreis
                 .iter()
-                .map(|item| {
-                    util::path::replace_with_common_separator(item.path().to_str().unwrap())
+                .map(|item| match item.path().to_str() {
+                    Some(path) => Ok(util::path::replace_with_common_separator(path)),
+                    None => Err(anyhow::anyhow!("path {:?} is not valid unicode", item.path())),
                 })
-                .collect();
+                .collect::<Result<HashSet<_>, _>>()?;
 
             let new_library_pathstrs: HashSet<_> = local_audio_libraries_keys.difference(&audio_libraries_keys).cloned().collect();
             let deleted_library_pathstrs: HashSet<_> = audio_libraries_keys.difference(&local_audio_libraries_keys).cloned().collect();
             let managed_library_pathstrs: HashSet<_> = audio_libraries_keys.difference(&deleted_library_pathstrs).collect();
-            let updated_local_libraries: Vec<_> = managed_library_pathstrs.into_iter()
-                .filter_map(|item| audio_libraries.get(item.as_str())
-                    .and_then(|audio_library| audio_library.check_modified().then(|| audio_library)))
-                .collect();
+            let mut updated_local_libraries = Vec::new();
+            for managed_library_pathstr in managed_library_pathstrs.into_iter() {
+                if let Some(audio_library) = audio_libraries.get(managed_library_pathstr.as_str()) {
+                    if audio_library.check_modified()? {
+                        updated_local_libraries.push(*audio_library);
+                    }
+                }
+            }
 
-            println!("nl: {:?}, dl: {:?}, ull: {:?}", new_library_pathstrs, deleted_library_pathstrs, updated_local_libraries);
+            debug!("new libraries: {:?}, deleted libraries: {:?}, updated libraries: {:?}", new_library_pathstrs, deleted_library_pathstrs, updated_local_libraries);
 
             if !new_library_pathstrs.is_empty() {
-                let new_audio_file_docs: Vec<_> = new_library_pathstrs
-                    .iter()
-                    .map(|item| get_audio_file_paths(Path::new(item), &audio_types))
-                    .flat_map(|item| item)
-                    .map(|item| dto::AudioFile::new(&item))
-                    .collect();
+                let mut new_audio_file_docs = Vec::new();
+                for new_library_pathstr in new_library_pathstrs.iter() {
+                    for audio_file_path in get_audio_file_paths(Path::new(new_library_pathstr), &audio_types)?.iter() {
+                        job.file_scanned(audio_file_path);
+
+                        match dto::AudioFile::new(audio_file_path) {
+                            Ok(audio_file) => new_audio_file_docs.push(audio_file),
+                            Err(err) => job.file_error(audio_file_path, &err),
+                        }
+                    }
+                }
 
-                let new_library_docs: Vec<_> = new_library_pathstrs
+                let new_library_docs = new_library_pathstrs
                     .iter()
                     .map(|item| dto::AudioLibrary::new(Path::new(&item)))
-                    .collect();
+                    .collect::<Result<Vec<_>, _>>()?;
 
-                self.crud_audio_lib.many.create_many(db.clone(), new_library_docs).await?;
+                self.storage.library.create_many(new_library_docs).await?;
 
-                self.crud_audio_file.many.create_many(db.clone(), new_audio_file_docs).await?;
+                self.storage.audio_file.create_many(new_audio_file_docs).await?;
     
             }
 
             if !deleted_library_pathstrs.is_empty() {
                 for deleted_library_pathstr in deleted_library_pathstrs.iter() {
-                    println!("sync delete audio library: {:?}", deleted_library_pathstr);
+                    debug!("sync delete audio library: {:?}", deleted_library_pathstr);
                     let deleted_audio_lib_path = Path::new(deleted_library_pathstr);
 
-                    let audio_files = self.crud_audio_file.path.get_by_path(db.clone(), deleted_audio_lib_path).await?;
+                    let audio_files = self.storage.audio_file.get_by_path(deleted_audio_lib_path).await?;
                     let delete_audio_tag_ids: Vec<_> = audio_files.iter()
                         .filter_map(|item| item.audio_tag_refer)
                         .collect();
 
-                    let _delete_audio_tag_res = self.crud_audio_tag
-                        .many
-                        .delete_many(
-                            db.clone(), 
-                            &delete_audio_tag_ids
-                        ).await?;
-
-                    let _delete_audio_file_res = self.crud_audio_file
-                        .many
-                        .delete_many(
-                            db.clone(), 
-                            &audio_files.iter().map(|item| item.id.unwrap()).collect_vec()
-                        ).await?;
+                    self.storage.audio_tag.delete_many(&delete_audio_tag_ids).await?;
+
+                    for audio_file in audio_files.iter() {
+                        self.transcode_cache.invalidate_audio_file(&audio_file.id.unwrap());
+                    }
+
+                    self.storage.audio_file
+                        .delete_many(&audio_files.iter().map(|item| item.id.unwrap()).collect_vec())
+                        .await?;
             
-                    let _delete_audio_lib_res = self.crud_audio_lib
-                        .path
-                        .delete_by_path(
-                            db.clone(), 
-                            deleted_audio_lib_path,
-                        ).await?;
+                    self.storage.library.delete_by_path(deleted_audio_lib_path).await?;
                 }
             }
 
             if !updated_local_libraries.is_empty() {
-                println!("sync updated local libraries: {:?}", updated_local_libraries);
+                debug!("sync updated local libraries: {:?}", updated_local_libraries);
                 
                 for updated_local_library in updated_local_libraries.into_iter() {
+                    job.check_cancelled()?;
+
                     let local_library_path = Path::new(&updated_local_library.os_path);
 
-                    let audio_files = self.crud_audio_file
-                        .path
-                        .get_by_path(
-                            db.clone(), 
-                            local_library_path
-                        ).await?;
+                    let audio_files = self.storage.audio_file.get_by_path(local_library_path).await?;
 
                     let audio_filenames: HashSet<_> = audio_files
                         .iter()
@@ -367,7 +354,7 @@ impl AudioLibrary {
                     let local_audio_file_paths = get_audio_file_paths(
                         local_library_path, 
                         &audio_types
-                    );
+                    )?;
 
                     let local_audio_filenames: HashSet<_> = local_audio_file_paths
                         .iter()
@@ -382,20 +369,45 @@ impl AudioLibrary {
 
                     let mut updated_audio_files: Vec<dto::AudioFile> = vec![];
                     let mut updated_audio_tags: Vec<dto::AudioTag> = vec![];
+                    let mut updated_audio_tag_paths: Vec<PathBuf> = vec![];
+                    let mut sidecar_artwork = dto::SidecarArtwork::default();
 
                     for managed_audio_filename in managed_audio_filenames.iter() {
                         let mut audio_file = audio_files.remove(managed_audio_filename).unwrap();
-                        if audio_file.check_modified() {
+                        let audio_file_path = audio_file.get_os_path();
+                        job.file_scanned(&audio_file_path);
+
+                        let is_modified = match audio_file.check_modified() {
+                            Ok(is_modified) => is_modified,
+                            Err(err) => {
+                                job.file_error(&audio_file_path, &err);
+                                continue;
+                            },
+                        };
+
+                        if is_modified {
+                            if let Err(err) = audio_file.update_modified_timestamp() {
+                                job.file_error(&audio_file_path, &err);
+                                continue;
+                            }
+
                             match audio_file.audio_tag_refer {
                                 Some(audio_tag_id) => {
                                     let parent_path = util::path::materialized_to_path(&audio_file.get_mat_path_val());
-                                    let updated_audio_tag = dto::AudioTag::new(Some(audio_tag_id), &parent_path, &audio_file.filename)?;
-                                    updated_audio_tags.push(updated_audio_tag);
+                                    match dto::AudioTag::new(Some(audio_tag_id), &parent_path, &audio_file.filename, &mut sidecar_artwork) {
+                                        Ok(mut updated_audio_tag) => {
+                                            self.save_artwork(&mut updated_audio_tag).await?;
+
+                                            updated_audio_tags.push(updated_audio_tag);
+                                            updated_audio_tag_paths.push(audio_file_path);
+                                        },
+                                        Err(err) => job.file_error(&audio_file_path, &err),
+                                    }
                                 },
                                 None => (),
                             }
 
-                            audio_file.update_modified_timestamp();
+                            self.transcode_cache.invalidate_audio_file(&audio_file.id.unwrap());
 
                             updated_audio_files.push(audio_file);
                         }
@@ -403,11 +415,18 @@ impl AudioLibrary {
 
                     let new_audio_file_docs: Vec<_> = new_audio_filenames
                         .iter()
-                        .map(|item| {
-                            let mut target_path = local_library_path.clone().to_path_buf();
+                        .filter_map(|item| {
+                            let mut target_path = local_library_path.to_path_buf();
                             target_path.push(item);
+                            job.file_scanned(&target_path);
 
-                            dto::AudioFile::new(&target_path)
+                            match dto::AudioFile::new(&target_path) {
+                                Ok(audio_file) => Some(audio_file),
+                                Err(err) => {
+                                    job.file_error(&target_path, &err);
+                                    None
+                                },
+                            }
                         })
                         .collect();
 
@@ -417,12 +436,7 @@ impl AudioLibrary {
                         .collect();
 
                     if !new_audio_file_docs.is_empty() {
-                        self.crud_audio_file
-                            .many
-                            .create_many(
-                                db.clone(), 
-                                new_audio_file_docs
-                            ).await?;
+                        self.storage.audio_file.create_many(new_audio_file_docs).await?;
                     }
 
                     if !delete_audio_file_docs.is_empty() {
@@ -431,50 +445,441 @@ impl AudioLibrary {
                             .filter_map(|item| item.audio_tag_refer)
                             .collect();
 
-                        self.crud_audio_tag
-                            .many
-                            .delete_many(
-                                db.clone(), 
-                                &deleted_audio_tag_ids
-                            ).await?;
-
-                        self.crud_audio_file
-                            .many
-                            .delete_many(
-                                db.clone(), 
-                                &delete_audio_file_docs.iter().map(|item| item.id.unwrap()).collect_vec()
-                            ).await?;
+                        for delete_audio_file_doc in delete_audio_file_docs.iter() {
+                            self.transcode_cache.invalidate_audio_file(&delete_audio_file_doc.id.unwrap());
+                        }
+
+                        self.storage.audio_tag.delete_many(&deleted_audio_tag_ids).await?;
+
+                        self.storage.audio_file
+                            .delete_many(&delete_audio_file_docs.iter().map(|item| item.id.unwrap()).collect_vec())
+                            .await?;
                     }
 
                     if !updated_audio_files.is_empty() {
-                        self.crud_audio_file
-                            .many
-                            .update_many(
-                                db.clone(), 
-                                &updated_audio_files
-                            ).await;
+                        self.storage.audio_file.update_many(&updated_audio_files).await;
                     }
 
                     if !updated_audio_tags.is_empty() {
-                        self.crud_audio_tag
-                            .many
-                            .update_many(
-                                db.clone(), 
-                                &updated_audio_tags
-                            ).await;
+                        let update_results = self.storage.audio_tag.update_many(&updated_audio_tags).await;
+
+                        for (update_res, audio_file_path) in update_results.iter().zip(updated_audio_tag_paths.iter()) {
+                            match update_res {
+                                Ok(_) => job.tag_written(audio_file_path),
+                                Err(err) => job.file_error(audio_file_path, err),
+                            }
+                        }
                     }
 
-                    let modified_ts = util::path::get_timestamp(&local_library_path);
-                    let _update_local_library_res = self.crud_audio_lib
-                        .path
-                        .update_modified_timestamp(
-                            db.clone(), 
-                            &updated_local_library, 
-                            modified_ts
-                        ).await?;
+                    let modified_ts = util::path::get_timestamp(&local_library_path)?;
+                    self.storage.library
+                        .update_modified_timestamp(&updated_local_library, modified_ts)
+                        .await?;
+                }
+            }
+
+        }
+
+        self.evaluate_smart_playlists().await;
+
+        Ok(())
+    }
+
+    pub async fn set_audio_library_watch(
+        &self,
+        path: &Path,
+        enabled: bool,
+    ) -> Result<(), anyhow::Error> {
+        let audio_lib_root = self.storage.library_root
+            .get_by_exact_materialized_path(&util::path::path_to_materialized(path)?)
+            .await?;
+
+        let mut audio_lib_root = match audio_lib_root {
+            Some(audio_lib_root) => audio_lib_root,
+            None => return Err(anyhow::anyhow!("path '{:?}' not exists", path)),
+        };
+
+        audio_lib_root.watch = enabled;
+
+        self.storage.library_root.update(&audio_lib_root.id.unwrap(), &audio_lib_root).await?;
+
+        if enabled {
+            self.library_watcher.watch(Path::new(&audio_lib_root.os_path))?;
+        } else {
+            self.library_watcher.unwatch(Path::new(&audio_lib_root.os_path))?;
+        }
+
+        Ok(())
+    }
+
+    pub async fn list_audio_libraries(
+        &self,
+        max_item_num: u64,
+        page: u64,
+    ) -> Result<Vec<AudioLibraryRes>, anyhow::Error> {
+        let audio_lib_roots = self.storage.library_root
+            .get_paginated(max_item_num as i64, page)
+            .await?;
+
+        let res = audio_lib_roots
+            .into_iter()
+            .map(|item| AudioLibraryRes {
+                id: item.id.unwrap().to_string(),
+                path: item.os_path,
+                modified_timestamp: item.modified_timestamp,
+                watch: item.watch,
+            })
+            .collect();
+
+        Ok(res)
+    }
+
+    // Watches library roots, and applies file system changes of them until the watcher stops
+    pub async fn sync_library_events(
+        self: Arc<Self>,
+        mut events: mpsc::Receiver<DebouncedEvent>,
+        job_manager: Arc<JobManager>,
+    ) -> Result<(), anyhow::Error> {
+        let audio_lib_roots = self.storage.library_root.get_all().await?;
+
+        for audio_lib_root in audio_lib_roots.iter().filter(|item| item.watch) {
+            if let Err(err) = self.library_watcher.watch(Path::new(&audio_lib_root.os_path)) {
+                warn!("failed to watch library root {}: {}", audio_lib_root.os_path, err);
+            }
+        }
+
+        while let Some(event) = events.recv().await {
+            // events are held while a job runs, as per-file changes would race with it
+            let _library_guard = job_manager.lock_library().await;
+            let mut rescan = false;
+
+            // smart playlists are evaluated once for a burst of changes
+            for event in std::iter::once(event).chain(std::iter::from_fn(|| events.try_recv().ok())) {
+                match event {
+                    DebouncedEvent::Rescan => rescan = true,
+                    event => if let Err(err) = self.apply_library_event(event).await {
+                        warn!("failed to sync library change: {}", err);
+                    },
                 }
             }
 
+            if rescan {
+                self.clone().rescan_audio_libraries(&job_manager).await;
+            }
+
+            self.evaluate_smart_playlists().await;
+        }
+
+        Ok(())
+    }
+
+    // Events are dropped, so that whole libraries are synced by a job, which refreshes and then
+    // analyzes. It is skipped while another job runs, as it would race with the job
+    async fn rescan_audio_libraries(
+        self: Arc<Self>,
+        job_manager: &JobManager,
+    ) {
+        let audio_library = self;
+
+        let spawn_res = job_manager.spawn_job(
+            dto::JobKind::RefreshAudioLibrary,
+            move |job| async move {
+                audio_library.refresh_audio_library(&job).await?;
+                audio_library.analyze_audio_library(&job).await
+            }
+        ).await;
+
+        match spawn_res {
+            Ok(job) => info!("rescan audio libraries as job {}", job.id),
+            Err(err) => warn!("skipped rescan of audio libraries, which should be refreshed and analyzed later: {}", err),
+        }
+    }
+
+    async fn apply_library_event(
+        &self,
+        event: DebouncedEvent,
+    ) -> Result<(), anyhow::Error> {
+        let audio_types = self.state.settings().audio_library.audio_types.clone();
+
+        match event {
+            DebouncedEvent::Create(path) |
+            DebouncedEvent::Write(path) |
+            DebouncedEvent::Chmod(path) |
+            DebouncedEvent::Remove(path) => {
+                if self.library_watcher.is_watched(&path) {
+                    self.sync_path(&path, &audio_types).await?;
+                }
+            },
+            DebouncedEvent::Rename(src_path, dest_path) => {
+                match (self.library_watcher.is_watched(&src_path), self.library_watcher.is_watched(&dest_path)) {
+                    (true, true) => {
+                        if !self.rename_audio_file(&src_path, &dest_path, &audio_types).await? {
+                            self.sync_path(&src_path, &audio_types).await?;
+                            self.sync_path(&dest_path, &audio_types).await?;
+                        }
+                    },
+                    // moved out of watched libraries
+                    (true, false) => self.remove_synced_path(&src_path, &audio_types).await?,
+                    (false, true) => self.sync_path(&dest_path, &audio_types).await?,
+                    (false, false) => (),
+                }
+            },
+            // handled by `sync_library_events`, as it runs as a job
+            DebouncedEvent::Rescan => (),
+            DebouncedEvent::Error(err, path) => {
+                warn!("library watcher error at {:?}: {}", path, err);
+            },
+            DebouncedEvent::NoticeWrite(_) | DebouncedEvent::NoticeRemove(_) => (),
+        }
+
+        Ok(())
+    }
+
+    async fn sync_path(
+        &self,
+        path: &Path,
+        audio_types: &[String],
+    ) -> Result<(), anyhow::Error> {
+        if path.is_dir() {
+            // a directory may be moved into the library with its contents
+            for audio_library_entry in get_audio_library_entries(path, audio_types)?.iter() {
+                let mut sidecar_artwork = dto::SidecarArtwork::default();
+
+                for audio_file_path in get_audio_file_paths(audio_library_entry.path(), audio_types)?.iter() {
+                    self.sync_audio_file(audio_file_path, &mut sidecar_artwork).await?;
+                }
+
+                self.sync_library_doc(audio_library_entry.path(), audio_types).await?;
+            }
+        } else if path.is_file() {
+            if !util::audio::is_audio_type(path, audio_types) {
+                return Ok(());
+            }
+
+            self.sync_audio_file(path, &mut dto::SidecarArtwork::default()).await?;
+            self.sync_library_doc(path.parent().unwrap(), audio_types).await?;
+        } else {
+            self.remove_synced_path(path, audio_types).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn get_audio_file_by_path(
+        &self,
+        path: &Path,
+    ) -> Result<Option<dto::AudioFile>, anyhow::Error> {
+        let (parent_path, filename) = match (path.parent(), path.file_name().and_then(|item| item.to_str())) {
+            (Some(parent_path), Some(filename)) => (parent_path, filename),
+            _ => return Ok(None),
+        };
+
+        let audio_file = self.storage.audio_file
+            .get_by_filename(&util::path::path_to_materialized(parent_path)?, filename)
+            .await?;
+
+        Ok(audio_file)
+    }
+
+    async fn evaluate_smart_playlists(&self) {
+        if let Err(err) = self.smart_playlist.evaluate_all().await {
+            warn!("failed to evaluate smart playlists: {}", err);
+        }
+    }
+
+    // Stores artwork read with the tag, unless the same artwork is stored already
+    async fn save_artwork(
+        &self,
+        audio_tag: &mut dto::AudioTag,
+    ) -> Result<(), anyhow::Error> {
+        let artwork = match audio_tag.artwork.take() {
+            Some(artwork) => artwork,
+            None => return Ok(()),
+        };
+
+        if self.storage.artwork.get_artwork(&artwork.id).await?.is_none() {
+            self.storage.artwork.create(&artwork).await?;
+        }
+
+        Ok(())
+    }
+
+    // Creates or updates the audio file and its tag
+    async fn sync_audio_file(
+        &self,
+        path: &Path,
+        sidecar_artwork: &mut dto::SidecarArtwork,
+    ) -> Result<(), anyhow::Error> {
+        let (mut audio_file, is_new_audio_file) = match self.get_audio_file_by_path(path).await? {
+            Some(audio_file) => {
+                if !audio_file.check_modified()? {
+                    return Ok(());
+                }
+
+                (audio_file, false)
+            },
+            None => (dto::AudioFile::new(path)?, true),
+        };
+
+        audio_file.update_modified_timestamp()?;
+        self.transcode_cache.invalidate_audio_file(&audio_file.id.unwrap());
+
+        let mut audio_tag = dto::AudioTag::new(
+            audio_file.audio_tag_refer,
+            &util::path::materialized_to_path(&audio_file.parent_path),
+            &audio_file.filename,
+            sidecar_artwork
+        )?;
+
+        self.save_artwork(&mut audio_tag).await?;
+
+        match audio_file.audio_tag_refer {
+            Some(audio_tag_id) => {
+                self.storage.audio_tag.update(&audio_tag_id, &audio_tag).await?;
+            },
+            None => {
+                self.storage.audio_tag.create(&audio_tag).await?;
+                audio_file.audio_tag_refer = audio_tag.id;
+            },
+        }
+
+        if is_new_audio_file {
+            self.storage.audio_file.create(&audio_file).await?;
+
+            info!("synced new audio file {:?}", path);
+        } else {
+            self.storage.audio_file.update(&audio_file.id.unwrap(), &audio_file).await?;
+
+            info!("synced modified audio file {:?}", path);
+        }
+
+        Ok(())
+    }
+
+    async fn rename_audio_file(
+        &self,
+        src_path: &Path,
+        dest_path: &Path,
+        audio_types: &[String],
+    ) -> Result<bool, anyhow::Error> {
+        if !dest_path.is_file() || !util::audio::is_audio_type(dest_path, audio_types) {
+            return Ok(false);
+        }
+
+        let mut audio_file = match self.get_audio_file_by_path(src_path).await? {
+            Some(audio_file) => audio_file,
+            None => return Ok(false),
+        };
+
+        // the renamed file replaces an existing one
+        if let Some(replaced_audio_file) = self.get_audio_file_by_path(dest_path).await? {
+            self.delete_audio_files(&[replaced_audio_file]).await?;
+        }
+
+        let (src_parent_path, dest_parent_path, dest_filename) = match (
+            src_path.parent(),
+            dest_path.parent(),
+            dest_path.file_name().and_then(|item| item.to_str()),
+        ) {
+            (Some(src_parent_path), Some(dest_parent_path), Some(dest_filename)) => (src_parent_path, dest_parent_path, dest_filename),
+            _ => return Ok(false),
+        };
+
+        audio_file.parent_path = util::path::path_to_materialized(dest_parent_path)?;
+        audio_file.filename = dest_filename.to_owned();
+        audio_file.update_modified_timestamp()?;
+
+        self.storage.audio_file.update(&audio_file.id.unwrap(), &audio_file).await?;
+
+        self.sync_library_doc(src_parent_path, audio_types).await?;
+        if src_parent_path != dest_parent_path {
+            self.sync_library_doc(dest_parent_path, audio_types).await?;
+        }
+
+        info!("synced renamed audio file {:?} -> {:?}", src_path, dest_path);
+
+        Ok(true)
+    }
+
+    async fn remove_synced_path(
+        &self,
+        path: &Path,
+        audio_types: &[String],
+    ) -> Result<(), anyhow::Error> {
+        if let Some(audio_file) = self.get_audio_file_by_path(path).await? {
+            self.delete_audio_files(&[audio_file]).await?;
+            self.sync_library_doc(path.parent().unwrap(), audio_types).await?;
+
+            info!("synced removed audio file {:?}", path);
+
+            return Ok(());
+        }
+
+        // removed directory, which may contain libraries
+        let audio_files = self.storage.audio_file.get_by_path(path).await?;
+
+        if !audio_files.is_empty() {
+            self.delete_audio_files(&audio_files).await?;
+        }
+
+        let deleted_lib_count = self.storage.library.delete_by_path(path).await?;
+
+        if deleted_lib_count > 0 {
+            info!("synced removed directory {:?}", path);
+        }
+
+        Ok(())
+    }
+
+    async fn delete_audio_files(
+        &self,
+        audio_files: &[dto::AudioFile],
+    ) -> Result<(), anyhow::Error> {
+        let delete_audio_tag_ids = audio_files
+            .iter()
+            .filter_map(|item| item.audio_tag_refer)
+            .collect_vec();
+
+        let delete_audio_file_ids = audio_files
+            .iter()
+            .map(|item| item.id.unwrap())
+            .collect_vec();
+
+        self.storage.audio_tag.delete_many(&delete_audio_tag_ids).await?;
+        self.storage.audio_file.delete_many(&delete_audio_file_ids).await?;
+
+        for audio_file_id in delete_audio_file_ids.iter() {
+            self.transcode_cache.invalidate_audio_file(audio_file_id);
+        }
+
+        Ok(())
+    }
+
+    // Creates, updates or removes the library document, whether the directory has audio files
+    async fn sync_library_doc(
+        &self,
+        path: &Path,
+        audio_types: &[String],
+    ) -> Result<(), anyhow::Error> {
+        let has_audio_files = path.is_dir() && !get_audio_file_paths(path, audio_types)?.is_empty();
+
+        let audio_lib = self.storage.library
+            .get_by_exact_materialized_path(&util::path::path_to_materialized(path)?)
+            .await?;
+
+        match (audio_lib, has_audio_files) {
+            (Some(mut audio_lib), true) => {
+                audio_lib.modified_timestamp = util::path::get_timestamp(path)?;
+
+                self.storage.library.update(&audio_lib.id.unwrap(), &audio_lib).await?;
+            },
+            (Some(audio_lib), false) => {
+                self.storage.library.delete(&audio_lib.id.unwrap()).await?;
+            },
+            (None, true) => {
+                self.storage.library.create(&dto::AudioLibrary::new(path)?).await?;
+            },
+            (None, false) => (),
         }
 
         Ok(())
@@ -1,7 +1,23 @@
+mod artwork;
+mod auth;
+mod browse;
 mod file;
+mod job;
 mod library;
+mod play_history;
+mod playlist;
+mod smart_playlist;
 mod tag;
+mod watcher;
 
-pub use file::AudioFile;
+pub use artwork::{Artwork, ArtworkError};
+pub use auth::{Auth, Claims};
+pub use browse::AudioBrowse;
+pub use file::{AudioFile, InvalidAudioRequestError, TranscodeCache};
+pub use job::{JobContext, JobManager, JobRunningError};
 pub use library::AudioLibrary;
-pub use tag::AudioTag;
\ No newline at end of file
+pub use play_history::PlayHistory;
+pub use playlist::{Playlist, PlaylistPermissionError};
+pub use smart_playlist::SmartPlaylist;
+pub use tag::AudioTag;
+pub use watcher::LibraryWatcher;
\ No newline at end of file
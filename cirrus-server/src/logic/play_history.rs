@@ -0,0 +1,462 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::Arc,
+};
+
+use bson::oid::ObjectId;
+use chrono::Utc;
+use cirrus_protobuf::api::{
+    play_history_item, playback_report_req, ListensExportReq, ListensExportRes, PlayHistoryItem,
+    PlayHistoryReq, PlayStat, PlayStatsRes, PlaybackReportReq,
+};
+use serde_json::json;
+use tokio::sync::Mutex;
+
+use crate::{
+    model::{dto, storage::PlayHistoryQuery, Storage},
+    state::ServerState,
+};
+
+use super::tag::to_audio_tag_res;
+
+const MAX_PLAY_HISTORY_ITEMS_PER_PAGE: u64 = 500;
+// streamed packets of an audio are counted as a playback until it is idle over this
+const STREAMED_PLAYBACK_IDLE_SEC: i64 = 30 * 60;
+const LISTENS_SUBMISSION_CLIENT: &'static str = "cirrus";
+
+fn parse_audio_tag_id(audio_tag_id: &str) -> Result<ObjectId, anyhow::Error> {
+    match ObjectId::parse_str(audio_tag_id) {
+        Ok(audio_tag_id) => Ok(audio_tag_id),
+        Err(_) => Err(anyhow::anyhow!("invalid audio tag id: {}", audio_tag_id)),
+    }
+}
+
+fn to_play_source(source: dto::PlaySource) -> play_history_item::Source {
+    match source {
+        dto::PlaySource::Client => play_history_item::Source::Client,
+        dto::PlaySource::Server => play_history_item::Source::Server,
+    }
+}
+
+// Streamed playbacks are of a user at a remote address
+type StreamedPlaybackKey = (Option<ObjectId>, IpAddr, ObjectId);
+
+// Packets of an audio streamed to a remote address, which is the fallback of clients that do
+// not report playback
+struct StreamedPlayback {
+    playback_id: String,
+    duration_ms: Option<u32>,
+    streamed_packets: u64,
+    // the client reports playback of the audio, so that streamed packets are not counted
+    reported: bool,
+    counted: bool,
+    updated_timestamp: i64,
+}
+
+impl StreamedPlayback {
+    fn new(duration_ms: Option<u32>, timestamp: i64) -> Self {
+        Self {
+            playback_id: format!("server-{}", ObjectId::new()),
+            duration_ms,
+            streamed_packets: 0,
+            reported: false,
+            counted: false,
+            updated_timestamp: timestamp,
+        }
+    }
+}
+
+pub struct PlayHistory {
+    state: Arc<ServerState>,
+    storage: Arc<Storage>,
+    streamed_playbacks: Mutex<HashMap<StreamedPlaybackKey, StreamedPlayback>>,
+}
+
+impl PlayHistory {
+    pub fn new(state: Arc<ServerState>, storage: Arc<Storage>) -> Self {
+        Self {
+            state,
+            storage,
+            streamed_playbacks: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Plays are of the user, which is not set if authentication is disabled
+    pub async fn report_playback(
+        &self,
+        user_id: Option<&ObjectId>,
+        remote_ip: Option<IpAddr>,
+        req: &PlaybackReportReq,
+    ) -> Result<(), anyhow::Error> {
+        let audio_tag_id = parse_audio_tag_id(&req.audio_tag_id)?;
+        let playback_id = req.playback_id.trim();
+
+        if playback_id.is_empty() {
+            return Err(anyhow::anyhow!("playback id should not be empty"));
+        }
+
+        let event = match playback_report_req::Event::from_i32(req.event) {
+            Some(event) => event,
+            None => return Err(anyhow::anyhow!("unknown playback event: {}", req.event)),
+        };
+
+        if let Some(remote_ip) = remote_ip {
+            self.set_streamed_playback_reported((user_id.copied(), remote_ip, audio_tag_id)).await?;
+        }
+
+        let play_event = self.storage.play_event.get_playback(user_id, playback_id).await?;
+
+        match play_event {
+            Some(play_event) if play_event.audio_tag_id != audio_tag_id => {
+                return Err(anyhow::anyhow!("playback {} is of another audio", playback_id));
+            },
+            Some(_) => (),
+            // a playback may be reported from the half, if the client misses the start
+            None => {
+                let play_event = dto::PlayEvent::new(
+                    user_id.copied(),
+                    audio_tag_id,
+                    playback_id,
+                    dto::PlaySource::Client,
+                    Utc::now().timestamp()
+                );
+
+                self.storage.play_event.create(&play_event).await?;
+            },
+        }
+
+        match event {
+            playback_report_req::Event::Started => {
+                self.storage.play_event
+                    .update_playback(user_id, playback_id, req.position_ms, false)
+                    .await?;
+            },
+            playback_report_req::Event::Halfway | playback_report_req::Event::Finished => {
+                self.count_playback(
+                    user_id,
+                    playback_id,
+                    &audio_tag_id,
+                    req.position_ms,
+                    event == playback_report_req::Event::Finished
+                ).await?;
+            },
+        }
+
+        Ok(())
+    }
+
+    // Clients that report plays in other ways (e.g. Subsonic scrobbles) tell the audio they
+    // start playing, so that its streamed packets are not counted
+    pub async fn set_now_playing(
+        &self,
+        user_id: Option<&ObjectId>,
+        remote_ip: IpAddr,
+        audio_tag_id: &str,
+    ) -> Result<(), anyhow::Error> {
+        let audio_tag_id = parse_audio_tag_id(audio_tag_id)?;
+
+        self.set_streamed_playback_reported((user_id.copied(), remote_ip, audio_tag_id)).await
+    }
+
+    // Counts streamed packets of GetData, and a playback is counted as a play when half of the
+    // audio is streamed without reports of the client
+    pub async fn add_streamed_packets(
+        &self,
+        user_id: Option<&ObjectId>,
+        remote_ip: IpAddr,
+        audio_tag_id: &str,
+        packet_start_idx: u32,
+        packets: u64,
+    ) -> Result<(), anyhow::Error> {
+        if packets == 0 {
+            return Ok(());
+        }
+
+        let settings = self.state.settings();
+        let packet_dur_ms = settings.audio_sample_frame_packet.len as f64 * 1000.
+            / settings.audio_sample_frame_packet.sample_rate as f64;
+
+        let audio_tag_id = parse_audio_tag_id(audio_tag_id)?;
+        // read before locking, as other streams and reports wait for the lock
+        let duration_ms = self.get_duration_ms(&audio_tag_id).await?;
+        let timestamp = Utc::now().timestamp();
+        let key = (user_id.copied(), remote_ip, audio_tag_id);
+
+        let mut streamed_playbacks = self.streamed_playbacks.lock().await;
+        streamed_playbacks.retain(|_, item| timestamp - item.updated_timestamp < STREAMED_PLAYBACK_IDLE_SEC);
+
+        // streaming from the start again after a counted play is another playback
+        let replayed = packet_start_idx == 0 && streamed_playbacks
+            .get(&key)
+            .map_or(false, |item| item.counted && !item.reported);
+
+        if replayed {
+            streamed_playbacks.remove(&key);
+        }
+
+        let streamed_playback = streamed_playbacks
+            .entry(key)
+            .or_insert_with(|| StreamedPlayback::new(duration_ms, timestamp));
+        streamed_playback.streamed_packets += packets;
+        streamed_playback.updated_timestamp = timestamp;
+
+        if streamed_playback.reported || streamed_playback.counted {
+            return Ok(());
+        }
+
+        let duration_ms = match streamed_playback.duration_ms {
+            Some(duration_ms) if duration_ms > 0 => duration_ms as f64,
+            _ => return Ok(()),
+        };
+
+        let streamed_ms = streamed_playback.streamed_packets as f64 * packet_dur_ms;
+        if streamed_ms < duration_ms / 2. {
+            return Ok(());
+        }
+
+        streamed_playback.counted = true;
+
+        let mut play_event = dto::PlayEvent::new(
+            user_id.copied(),
+            audio_tag_id,
+            &streamed_playback.playback_id,
+            dto::PlaySource::Server,
+            timestamp
+        );
+        play_event.listened_at = Some(timestamp);
+        play_event.finished = streamed_ms >= duration_ms;
+        play_event.position_ms = streamed_ms as u64;
+
+        drop(streamed_playbacks);
+
+        self.storage.play_event.create(&play_event).await?;
+        self.storage.play_stat.increase_play_count(user_id, &audio_tag_id, timestamp).await?;
+
+        Ok(())
+    }
+
+    pub async fn get_play_stats(
+        &self,
+        user_id: Option<&ObjectId>,
+        audio_tag_ids: &[String],
+    ) -> Result<PlayStatsRes, anyhow::Error> {
+        let audio_tag_ids = audio_tag_ids
+            .iter()
+            .map(|item| parse_audio_tag_id(item))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let play_stats = self.storage.play_stat.get_by_audio_tags(user_id, &audio_tag_ids).await?;
+
+        let play_stats: HashMap<_, _> = play_stats
+            .into_iter()
+            .map(|item| (item.audio_tag_id, item))
+            .collect();
+
+        let stats = audio_tag_ids
+            .iter()
+            .map(|audio_tag_id| match play_stats.get(audio_tag_id) {
+                Some(play_stat) => PlayStat {
+                    audio_tag_id: audio_tag_id.to_string(),
+                    play_count: play_stat.play_count.max(0) as u64,
+                    last_played_timestamp: play_stat.last_played_timestamp,
+                },
+                None => PlayStat {
+                    audio_tag_id: audio_tag_id.to_string(),
+                    play_count: 0,
+                    last_played_timestamp: 0,
+                },
+            })
+            .collect();
+
+        Ok(PlayStatsRes { stats })
+    }
+
+    pub async fn list_play_history(
+        &self,
+        user_id: Option<&ObjectId>,
+        req: &PlayHistoryReq,
+    ) -> Result<Vec<PlayHistoryItem>, anyhow::Error> {
+        if req.items_per_page == 0 || req.items_per_page > MAX_PLAY_HISTORY_ITEMS_PER_PAGE {
+            return Err(anyhow::anyhow!(
+                "items per page should be between 1 and {}", MAX_PLAY_HISTORY_ITEMS_PER_PAGE
+            ));
+        }
+
+        if req.page == 0 {
+            return Err(anyhow::anyhow!("page should be greater than 0"));
+        }
+
+        let audio_tag_id = match req.audio_tag_id.is_empty() {
+            true => None,
+            false => Some(parse_audio_tag_id(&req.audio_tag_id)?),
+        };
+
+        let query = PlayHistoryQuery {
+            user_id,
+            since: req.since,
+            until: req.until,
+            audio_tag_id: audio_tag_id.as_ref(),
+        };
+
+        let play_events = self.storage.play_event
+            .get_history(
+                &query,
+                false,
+                Some(req.items_per_page * (req.page - 1)),
+                Some(req.items_per_page as i64)
+            ).await?;
+
+        let audio_tags = self.get_audio_tags(&play_events).await?;
+
+        let res = play_events
+            .iter()
+            .map(|item| PlayHistoryItem {
+                id: item.id.as_ref().unwrap().to_string(),
+                audio_tag_id: item.audio_tag_id.to_string(),
+                audio_tag: audio_tags.get(&item.audio_tag_id).map(to_audio_tag_res),
+                source: to_play_source(item.source) as i32,
+                listened_at: item.listened_at.unwrap_or_default(),
+            })
+            .collect();
+
+        Ok(res)
+    }
+
+    // Exports plays as a ListenBrainz import payload, and plays of removed audio or audio without
+    // artist or title are left out
+    pub async fn export_listens(
+        &self,
+        user_id: Option<&ObjectId>,
+        req: &ListensExportReq,
+    ) -> Result<ListensExportRes, anyhow::Error> {
+        let query = PlayHistoryQuery {
+            user_id,
+            since: req.since,
+            until: req.until,
+            audio_tag_id: None,
+        };
+
+        let play_events = self.storage.play_event.get_history(&query, true, None, None).await?;
+
+        let audio_tags = self.get_audio_tags(&play_events).await?;
+
+        let listens: Vec<_> = play_events
+            .iter()
+            .filter_map(|item| {
+                let audio_tag = audio_tags.get(&item.audio_tag_id)?;
+
+                let mut additional_info = json!({
+                    "submission_client": LISTENS_SUBMISSION_CLIENT,
+                });
+
+                if let Some(duration) = audio_tag.duration {
+                    additional_info["duration_ms"] = json!(duration);
+                }
+
+                if let Some(track) = audio_tag.track {
+                    additional_info["tracknumber"] = json!(track);
+                }
+
+                let mut track_metadata = json!({
+                    "artist_name": audio_tag.artist.as_ref()?,
+                    "track_name": audio_tag.title.as_ref()?,
+                    "additional_info": additional_info,
+                });
+
+                if let Some(album) = &audio_tag.album {
+                    track_metadata["release_name"] = json!(album);
+                }
+
+                Some(json!({
+                    "listened_at": item.listened_at?,
+                    "track_metadata": track_metadata,
+                }))
+            })
+            .collect();
+
+        let listen_count = listens.len() as u64;
+        let content = json!({
+            "listen_type": "import",
+            "payload": listens,
+        });
+
+        Ok(ListensExportRes {
+            content: serde_json::to_string_pretty(&content)?,
+            listen_count,
+        })
+    }
+
+    async fn set_streamed_playback_reported(
+        &self,
+        key: StreamedPlaybackKey,
+    ) -> Result<(), anyhow::Error> {
+        let duration_ms = self.get_duration_ms(&key.2).await?;
+        let timestamp = Utc::now().timestamp();
+        let mut streamed_playbacks = self.streamed_playbacks.lock().await;
+
+        let streamed_playback = streamed_playbacks
+            .entry(key)
+            .or_insert_with(|| StreamedPlayback::new(duration_ms, timestamp));
+        streamed_playback.reported = true;
+        streamed_playback.updated_timestamp = timestamp;
+
+        Ok(())
+    }
+
+    async fn count_playback(
+        &self,
+        user_id: Option<&ObjectId>,
+        playback_id: &str,
+        audio_tag_id: &ObjectId,
+        position_ms: u64,
+        finished: bool,
+    ) -> Result<(), anyhow::Error> {
+        let timestamp = Utc::now().timestamp();
+
+        // only the first of halfway and finished events counts the playback
+        let counted = self.storage.play_event
+            .count_playback(user_id, playback_id, position_ms, finished, timestamp)
+            .await?;
+
+        if counted {
+            self.storage.play_stat.increase_play_count(user_id, audio_tag_id, timestamp).await?;
+        } else {
+            self.storage.play_event
+                .update_playback(user_id, playback_id, position_ms, finished)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn get_duration_ms(
+        &self,
+        audio_tag_id: &ObjectId,
+    ) -> Result<Option<u32>, anyhow::Error> {
+        let audio_tag = self.storage.audio_tag.get(audio_tag_id).await?;
+
+        Ok(audio_tag.and_then(|item| item.duration))
+    }
+
+    async fn get_audio_tags(
+        &self,
+        play_events: &[dto::PlayEvent],
+    ) -> Result<HashMap<ObjectId, dto::AudioTag>, anyhow::Error> {
+        let mut audio_tag_ids: Vec<_> = play_events
+            .iter()
+            .map(|item| item.audio_tag_id)
+            .collect();
+        audio_tag_ids.sort();
+        audio_tag_ids.dedup();
+
+        let audio_tags = self.storage.audio_tag.get_many(&audio_tag_ids).await?;
+
+        let audio_tags = audio_tags
+            .into_iter()
+            .filter_map(|item| Some((item.id?, item)))
+            .collect();
+
+        Ok(audio_tags)
+    }
+}
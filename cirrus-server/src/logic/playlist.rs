@@ -0,0 +1,616 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+    sync::Arc,
+};
+
+use bson::oid::ObjectId;
+use chrono::Utc;
+use cirrus_protobuf::api::{PlaylistEntryRes, PlaylistRes};
+use tokio::sync::Mutex;
+use tracing::info;
+
+use crate::{
+    model::{dto, Storage},
+    util,
+};
+
+use super::{tag::to_audio_tag_res, Claims};
+
+const M3U8_HEADER: &'static str = "#EXTM3U";
+const M3U8_PLAYLIST_NAME_TAG: &'static str = "#PLAYLIST:";
+const DEFAULT_IMPORTED_PLAYLIST_NAME: &'static str = "Imported playlist";
+
+fn parse_object_id(id: &str, kind: &str) -> Result<ObjectId, anyhow::Error> {
+    match ObjectId::parse_str(id) {
+        Ok(id) => Ok(id),
+        Err(_) => Err(anyhow::anyhow!("invalid {} id: {}", kind, id)),
+    }
+}
+
+// A playlist is read or edited by a user who is not allowed to
+#[derive(Debug)]
+pub struct PlaylistPermissionError {
+    pub playlist_id: ObjectId,
+}
+
+impl std::fmt::Display for PlaylistPermissionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "playlist {} is owned by another user", self.playlist_id)
+    }
+}
+
+impl std::error::Error for PlaylistPermissionError {}
+
+// Claims are not set if auth is disabled, and playlists are accessible to all requests then
+fn get_owner_id(claims: Option<&Claims>) -> Result<Option<ObjectId>, anyhow::Error> {
+    claims
+        .map(|claims| parse_object_id(&claims.sub, "user"))
+        .transpose()
+}
+
+fn is_editable_by(playlist: &dto::Playlist, claims: &Claims) -> bool {
+    claims.role == dto::UserRole::Admin
+        || playlist.owner_id.is_some_and(|owner_id| owner_id.to_hex() == claims.sub)
+}
+
+// Playlists without an owner are created while auth is disabled, so that these are read by all
+// users as shared ones
+fn check_readable(playlist: &dto::Playlist, claims: Option<&Claims>) -> Result<(), anyhow::Error> {
+    match claims {
+        Some(claims) if !playlist.shared
+            && playlist.owner_id.is_some()
+            && !is_editable_by(playlist, claims) => Err(PlaylistPermissionError {
+                playlist_id: playlist.id.unwrap(),
+            }.into()),
+        _ => Ok(()),
+    }
+}
+
+fn check_editable(playlist: &dto::Playlist, claims: Option<&Claims>) -> Result<(), anyhow::Error> {
+    match claims {
+        Some(claims) if !is_editable_by(playlist, claims) => Err(PlaylistPermissionError {
+            playlist_id: playlist.id.unwrap(),
+        }.into()),
+        _ => Ok(()),
+    }
+}
+
+fn check_playlist_name(name: &str) -> Result<String, anyhow::Error> {
+    let name = name.trim();
+
+    if name.is_empty() {
+        return Err(anyhow::anyhow!("playlist name should not be empty"));
+    }
+
+    Ok(name.to_string())
+}
+
+// Name and entry paths of a M3U8 playlist, and a name of the request takes precedence over the
+// one in the playlist
+fn parse_m3u8<'a>(content: &'a str, name: &'a str) -> (&'a str, Vec<&'a str>) {
+    let content = content.trim_start_matches('\u{feff}');
+
+    let name = if !name.trim().is_empty() {
+        name
+    } else {
+        content
+            .lines()
+            .find_map(|item| item.trim().strip_prefix(M3U8_PLAYLIST_NAME_TAG))
+            .filter(|item| !item.trim().is_empty())
+            .unwrap_or(DEFAULT_IMPORTED_PLAYLIST_NAME)
+    };
+
+    let paths = content
+        .lines()
+        .map(|item| item.trim())
+        .filter(|item| !item.is_empty() && !item.starts_with('#'))
+        .collect();
+
+    (name, paths)
+}
+
+fn write_m3u8(name: &str, entries: &[(Option<&dto::AudioTag>, &Path)]) -> String {
+    let mut lines = vec![
+        M3U8_HEADER.to_string(),
+        format!("{}{}", M3U8_PLAYLIST_NAME_TAG, name),
+    ];
+
+    for (audio_tag, audio_file_path) in entries.iter() {
+        if let Some(audio_tag) = audio_tag {
+            let duration = audio_tag.duration
+                .map(|item| ((item + 500) / 1000) as i64)
+                .unwrap_or(-1);
+
+            let title = match (&audio_tag.artist, &audio_tag.title) {
+                (Some(artist), Some(title)) => format!("{} - {}", artist, title),
+                (None, Some(title)) => title.clone(),
+                _ => String::new(),
+            };
+
+            lines.push(format!("#EXTINF:{},{}", duration, title));
+        }
+
+        lines.push(audio_file_path.to_string_lossy().to_string());
+    }
+
+    lines.push(String::new());
+
+    lines.join("\n")
+}
+
+// Materialized parent path and filename of an entry path, which is an absolute path of an
+// audio file
+fn get_entry_path_keys(path: &Path) -> Result<Option<(String, &str)>, anyhow::Error> {
+    match (path.parent(), path.file_name().and_then(|item| item.to_str())) {
+        (Some(parent_path), Some(filename)) if path.is_absolute() => Ok(Some((util::path::path_to_materialized(parent_path)?, filename))),
+        _ => Ok(None),
+    }
+}
+
+pub struct Playlist {
+    storage: Arc<Storage>,
+    // edits read and write the whole playlist, so that these are serialized
+    edit_lock: Mutex<()>,
+}
+
+impl Playlist {
+    pub fn new(storage: Arc<Storage>) -> Self {
+        Self {
+            storage,
+            edit_lock: Mutex::new(()),
+        }
+    }
+
+    // Lists own and shared playlists of the user
+    pub async fn list_playlists(
+        &self,
+        claims: Option<&Claims>,
+        max_item_num: u64,
+        page: u64,
+    ) -> Result<Vec<PlaylistRes>, anyhow::Error> {
+        let playlists = match get_owner_id(claims)? {
+            Some(owner_id) => self.storage.playlist.get_visible_paginated(&owner_id, max_item_num as i64, page).await?,
+            None => self.storage.playlist.get_paginated(max_item_num as i64, page).await?,
+        };
+
+        let res = playlists
+            .iter()
+            .map(|item| PlaylistRes {
+                id: item.id.as_ref().unwrap().to_string(),
+                name: item.name.clone(),
+                entry_count: item.entries.len() as u64,
+                entries: Vec::new(),
+                created_timestamp: item.created_timestamp,
+                updated_timestamp: item.updated_timestamp,
+                owner_id: item.owner_id.map(|owner_id| owner_id.to_hex()).unwrap_or_default(),
+                shared: item.shared,
+            })
+            .collect();
+
+        Ok(res)
+    }
+
+    pub async fn get_playlist(
+        &self,
+        claims: Option<&Claims>,
+        playlist_id: &str,
+    ) -> Result<PlaylistRes, anyhow::Error> {
+        let playlist = self.get_playlist_doc(playlist_id).await?;
+        check_readable(&playlist, claims)?;
+
+        self.to_playlist_res(&playlist).await
+    }
+
+    pub async fn create_playlist(
+        &self,
+        claims: Option<&Claims>,
+        name: &str,
+        shared: bool,
+    ) -> Result<PlaylistRes, anyhow::Error> {
+        let playlist = dto::Playlist::new(&check_playlist_name(name)?, get_owner_id(claims)?, shared);
+
+        self.storage.playlist.create(&playlist).await?;
+
+        self.to_playlist_res(&playlist).await
+    }
+
+    pub async fn rename_playlist(
+        &self,
+        claims: Option<&Claims>,
+        playlist_id: &str,
+        name: &str,
+    ) -> Result<PlaylistRes, anyhow::Error> {
+        let name = check_playlist_name(name)?;
+
+        self.edit_playlist(claims, playlist_id, |playlist| {
+            playlist.name = name;
+
+            Ok(())
+        }).await
+    }
+
+    pub async fn set_playlist_shared(
+        &self,
+        claims: Option<&Claims>,
+        playlist_id: &str,
+        shared: bool,
+    ) -> Result<PlaylistRes, anyhow::Error> {
+        self.edit_playlist(claims, playlist_id, |playlist| {
+            playlist.shared = shared;
+
+            Ok(())
+        }).await
+    }
+
+    pub async fn delete_playlist(
+        &self,
+        claims: Option<&Claims>,
+        playlist_id: &str,
+    ) -> Result<(), anyhow::Error> {
+        let _edit_lock = self.edit_lock.lock().await;
+
+        let playlist = self.get_playlist_doc(playlist_id).await?;
+        check_editable(&playlist, claims)?;
+
+        let playlist_id = playlist.id.unwrap();
+
+        if self.storage.playlist.delete(&playlist_id).await? == 0 {
+            return Err(anyhow::anyhow!("playlist {} does not exist", playlist_id));
+        }
+
+        Ok(())
+    }
+
+    pub async fn insert_playlist_entries(
+        &self,
+        claims: Option<&Claims>,
+        playlist_id: &str,
+        audio_tag_ids: &[String],
+        position: i64,
+    ) -> Result<PlaylistRes, anyhow::Error> {
+        let audio_tag_ids = audio_tag_ids
+            .iter()
+            .map(|item| parse_object_id(item, "audio tag"))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let unique_audio_tag_ids = audio_tag_ids.iter().cloned().collect::<HashSet<_>>();
+        let audio_tags = self.storage.audio_tag
+            .get_many(&unique_audio_tag_ids.iter().cloned().collect::<Vec<_>>())
+            .await?;
+
+        if audio_tags.len() != unique_audio_tag_ids.len() {
+            let found_ids = audio_tags.iter().filter_map(|item| item.id).collect::<HashSet<_>>();
+            let missing_ids = unique_audio_tag_ids.difference(&found_ids).collect::<Vec<_>>();
+
+            return Err(anyhow::anyhow!("audio tags do not exist: {:?}", missing_ids));
+        }
+
+        self.edit_playlist(claims, playlist_id, |playlist| {
+            let position = match usize::try_from(position) {
+                Ok(position) => position.min(playlist.entries.len()),
+                Err(_) => playlist.entries.len(),
+            };
+
+            let entries = audio_tag_ids.into_iter().map(dto::PlaylistEntry::new);
+            playlist.entries.splice(position..position, entries);
+
+            Ok(())
+        }).await
+    }
+
+    pub async fn move_playlist_entry(
+        &self,
+        claims: Option<&Claims>,
+        playlist_id: &str,
+        entry_id: &str,
+        position: u64,
+    ) -> Result<PlaylistRes, anyhow::Error> {
+        let entry_id = parse_object_id(entry_id, "playlist entry")?;
+
+        self.edit_playlist(claims, playlist_id, |playlist| {
+            let entry_idx = match playlist.entries.iter().position(|item| item.entry_id == entry_id) {
+                Some(entry_idx) => entry_idx,
+                None => return Err(anyhow::anyhow!("playlist entry {} does not exist", entry_id)),
+            };
+
+            let entry = playlist.entries.remove(entry_idx);
+            let position = (position as usize).min(playlist.entries.len());
+
+            playlist.entries.insert(position, entry);
+
+            Ok(())
+        }).await
+    }
+
+    pub async fn remove_playlist_entries(
+        &self,
+        claims: Option<&Claims>,
+        playlist_id: &str,
+        entry_ids: &[String],
+    ) -> Result<PlaylistRes, anyhow::Error> {
+        let entry_ids = entry_ids
+            .iter()
+            .map(|item| parse_object_id(item, "playlist entry"))
+            .collect::<Result<HashSet<_>, _>>()?;
+
+        self.edit_playlist(claims, playlist_id, |playlist| {
+            let entry_count = playlist.entries.len();
+            playlist.entries.retain(|item| !entry_ids.contains(&item.entry_id));
+
+            if entry_count - playlist.entries.len() != entry_ids.len() {
+                return Err(anyhow::anyhow!("some of playlist entries do not exist"));
+            }
+
+            Ok(())
+        }).await
+    }
+
+    // Entries of which audio file is removed are left out
+    pub async fn export_playlist_m3u8(
+        &self,
+        claims: Option<&Claims>,
+        playlist_id: &str,
+    ) -> Result<String, anyhow::Error> {
+        let playlist = self.get_playlist_doc(playlist_id).await?;
+        check_readable(&playlist, claims)?;
+        let audio_tag_ids = playlist.entries
+            .iter()
+            .map(|item| item.audio_tag_id)
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect::<Vec<_>>();
+
+        let audio_tags = self.get_audio_tags(&audio_tag_ids).await?;
+
+        let audio_file_paths = self.storage.audio_file
+            .get_many_by_audio_tags(&audio_tag_ids)
+            .await?
+            .into_iter()
+            .filter_map(|item| Some((item.audio_tag_refer?, item.get_os_path())))
+            .collect::<HashMap<_, _>>();
+
+        let entries = playlist.entries
+            .iter()
+            .filter_map(|item| Some((
+                audio_tags.get(&item.audio_tag_id),
+                audio_file_paths.get(&item.audio_tag_id)?.as_path(),
+            )))
+            .collect::<Vec<_>>();
+
+        Ok(write_m3u8(&playlist.name, &entries))
+    }
+
+    // Paths should be absolute paths of analyzed audio files in libraries
+    pub async fn import_playlist_m3u8(
+        &self,
+        claims: Option<&Claims>,
+        name: &str,
+        content: &str,
+        shared: bool,
+    ) -> Result<(PlaylistRes, Vec<String>), anyhow::Error> {
+        let (name, paths) = parse_m3u8(content, name);
+
+        let mut playlist = dto::Playlist::new(&check_playlist_name(name)?, get_owner_id(claims)?, shared);
+        let mut unmatched_paths = Vec::new();
+
+        for path in paths {
+            match self.get_audio_tag_id_by_path(Path::new(path)).await? {
+                Some(audio_tag_id) => playlist.entries.push(dto::PlaylistEntry::new(audio_tag_id)),
+                None => unmatched_paths.push(path.to_string()),
+            }
+        }
+
+        self.storage.playlist.create(&playlist).await?;
+
+        info!(
+            "imported playlist '{}' with {} entries, {} paths are unmatched",
+            playlist.name,
+            playlist.entries.len(),
+            unmatched_paths.len()
+        );
+
+        Ok((self.to_playlist_res(&playlist).await?, unmatched_paths))
+    }
+
+    async fn get_playlist_doc(
+        &self,
+        playlist_id: &str,
+    ) -> Result<dto::Playlist, anyhow::Error> {
+        let playlist_id = parse_object_id(playlist_id, "playlist")?;
+
+        match self.storage.playlist.get(&playlist_id).await? {
+            Some(playlist) => Ok(playlist),
+            None => Err(anyhow::anyhow!("playlist {} does not exist", playlist_id)),
+        }
+    }
+
+    async fn edit_playlist<F>(
+        &self,
+        claims: Option<&Claims>,
+        playlist_id: &str,
+        edit_fn: F,
+    ) -> Result<PlaylistRes, anyhow::Error>
+    where
+        F: FnOnce(&mut dto::Playlist) -> Result<(), anyhow::Error>,
+    {
+        let playlist = {
+            let _edit_lock = self.edit_lock.lock().await;
+
+            let mut playlist = self.get_playlist_doc(playlist_id).await?;
+            check_editable(&playlist, claims)?;
+
+            edit_fn(&mut playlist)?;
+            playlist.updated_timestamp = Utc::now().timestamp();
+
+            self.storage.playlist.update(&playlist.id.unwrap(), &playlist).await?;
+
+            playlist
+        };
+
+        self.to_playlist_res(&playlist).await
+    }
+
+    async fn get_audio_tags(
+        &self,
+        audio_tag_ids: &[ObjectId],
+    ) -> Result<HashMap<ObjectId, dto::AudioTag>, anyhow::Error> {
+        if audio_tag_ids.is_empty() {
+            return Ok(HashMap::new());
+        }
+
+        let audio_tags = self.storage.audio_tag
+            .get_many(audio_tag_ids)
+            .await?
+            .into_iter()
+            .filter_map(|item| Some((item.id?, item)))
+            .collect();
+
+        Ok(audio_tags)
+    }
+
+    async fn get_audio_tag_id_by_path(
+        &self,
+        path: &Path,
+    ) -> Result<Option<ObjectId>, anyhow::Error> {
+        let (parent_path, filename) = match get_entry_path_keys(path)? {
+            Some(keys) => keys,
+            None => return Ok(None),
+        };
+
+        let audio_file = self.storage.audio_file
+            .get_by_filename(&parent_path, filename)
+            .await?;
+
+        Ok(audio_file.and_then(|item| item.audio_tag_refer))
+    }
+
+    async fn to_playlist_res(
+        &self,
+        playlist: &dto::Playlist,
+    ) -> Result<PlaylistRes, anyhow::Error> {
+        let audio_tag_ids = playlist.entries
+            .iter()
+            .map(|item| item.audio_tag_id)
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect::<Vec<_>>();
+
+        let audio_tags = self.get_audio_tags(&audio_tag_ids).await?;
+
+        Ok(PlaylistRes {
+            id: playlist.id.as_ref().unwrap().to_string(),
+            name: playlist.name.clone(),
+            entry_count: playlist.entries.len() as u64,
+            entries: playlist.entries
+                .iter()
+                .map(|item| PlaylistEntryRes {
+                    entry_id: item.entry_id.to_string(),
+                    audio_tag_id: item.audio_tag_id.to_string(),
+                    audio_tag: audio_tags.get(&item.audio_tag_id).map(to_audio_tag_res),
+                })
+                .collect(),
+            created_timestamp: playlist.created_timestamp,
+            updated_timestamp: playlist.updated_timestamp,
+            owner_id: playlist.owner_id.map(|owner_id| owner_id.to_hex()).unwrap_or_default(),
+            shared: playlist.shared,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use crate::{model::dto, util};
+
+    use super::{get_entry_path_keys, parse_m3u8, write_m3u8, DEFAULT_IMPORTED_PLAYLIST_NAME};
+
+    fn create_audio_tag(artist: Option<&str>, title: Option<&str>, duration: Option<u32>) -> dto::AudioTag {
+        dto::AudioTag {
+            artist: artist.map(|item| item.to_string()),
+            title: title.map(|item| item.to_string()),
+            duration,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn write_entries() {
+        let first_tag = create_audio_tag(Some("Artist"), Some("Title"), Some(181_499));
+        let second_tag = create_audio_tag(None, Some("Untitled artist"), Some(500));
+        let third_tag = create_audio_tag(Some("Artist"), None, None);
+
+        let content = write_m3u8("Mix", &[
+            (Some(&first_tag), Path::new("/music/a/01.flac")),
+            (Some(&second_tag), Path::new("/music/a/02.flac")),
+            (Some(&third_tag), Path::new("/music/b/03.flac")),
+            (None, Path::new("/music/b/04.flac")),
+        ]);
+
+        assert_eq!(content, [
+            "#EXTM3U",
+            "#PLAYLIST:Mix",
+            "#EXTINF:181,Artist - Title",
+            "/music/a/01.flac",
+            "#EXTINF:1,Untitled artist",
+            "/music/a/02.flac",
+            "#EXTINF:-1,",
+            "/music/b/03.flac",
+            "/music/b/04.flac",
+            "",
+        ].join("\n"));
+    }
+
+    #[test]
+    fn parse_entries() {
+        let content = "\u{feff}#EXTM3U\r\n#PLAYLIST:Mix\r\n#EXTINF:181,Artist - Title\r\n  /music/a/01.flac  \r\n\r\n# comment\r\n/music/b/02.flac\r\n";
+
+        let (name, paths) = parse_m3u8(content, "");
+
+        assert_eq!(name, "Mix");
+        assert_eq!(paths, vec!["/music/a/01.flac", "/music/b/02.flac"]);
+    }
+
+    #[test]
+    fn parse_playlist_name() {
+        let content = "#EXTM3U\n#PLAYLIST:Mix\n/music/a/01.flac\n";
+
+        assert_eq!(parse_m3u8(content, "Requested").0, "Requested");
+        assert_eq!(parse_m3u8(content, "  ").0, "Mix");
+        assert_eq!(parse_m3u8("#EXTM3U\n#PLAYLIST: \n", "").0, DEFAULT_IMPORTED_PLAYLIST_NAME);
+        assert_eq!(parse_m3u8("/music/a/01.flac\n", "").0, DEFAULT_IMPORTED_PLAYLIST_NAME);
+    }
+
+    #[test]
+    fn read_written_entries() {
+        let audio_tag = create_audio_tag(Some("Artist"), Some("Title"), Some(1_000));
+        let entry_paths = [Path::new("/music/a/01.flac"), Path::new("/music/b/02.flac")];
+
+        let content = write_m3u8("Mix", &[(Some(&audio_tag), entry_paths[0]), (None, entry_paths[1])]);
+        let (name, paths) = parse_m3u8(&content, "");
+
+        assert_eq!(name, "Mix");
+        assert_eq!(paths.iter().map(Path::new).collect::<Vec<_>>(), entry_paths);
+    }
+
+    #[test]
+    fn map_entry_path_to_audio_file() {
+        let path = Path::new("/music/Artist/Album/01 Title.flac");
+        let (parent_path, filename) = get_entry_path_keys(path).unwrap().unwrap();
+
+        assert_eq!(parent_path, ",,music,Artist,Album,");
+        assert_eq!(filename, "01 Title.flac");
+
+        // the path is restored as exported paths of audio files
+        let restored_path = PathBuf::from(util::path::materialized_to_path(&parent_path)).join(filename);
+        assert_eq!(restored_path, path);
+    }
+
+    #[test]
+    fn skip_relative_entry_path() {
+        assert!(get_entry_path_keys(Path::new("Album/01 Title.flac")).unwrap().is_none());
+        assert!(get_entry_path_keys(Path::new("01 Title.flac")).unwrap().is_none());
+        assert!(get_entry_path_keys(Path::new("/")).unwrap().is_none());
+    }
+}
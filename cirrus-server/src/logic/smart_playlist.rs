@@ -0,0 +1,482 @@
+use std::{collections::HashMap, sync::Arc};
+
+use bson::oid::ObjectId;
+use chrono::Utc;
+use cirrus_protobuf::api::{
+    smart_playlist_order, smart_rule, text_match_rule, AddedWithinRule, GenreInRule,
+    SaveSmartPlaylistReq, SmartPlaylistOrder, SmartPlaylistRes, SmartRule, SmartRuleGroup,
+    TextMatchRule, YearBetweenRule,
+};
+use tokio::sync::Mutex;
+
+use crate::model::{dto, Storage};
+
+use super::tag::to_audio_tag_res;
+
+const MAX_SMART_RULE_DEPTH: usize = 8;
+const MAX_SMART_PLAYLIST_LIMIT: u32 = 10_000;
+// time relative rules (e.g. added within days) are re-evaluated at reading after this
+const TIME_RELATIVE_EVALUATION_INTERVAL_SEC: i64 = 60 * 60;
+
+fn parse_smart_playlist_id(smart_playlist_id: &str) -> Result<ObjectId, anyhow::Error> {
+    match ObjectId::parse_str(smart_playlist_id) {
+        Ok(smart_playlist_id) => Ok(smart_playlist_id),
+        Err(_) => Err(anyhow::anyhow!("invalid smart playlist id: {}", smart_playlist_id)),
+    }
+}
+
+fn from_smart_rule(rule: &SmartRule) -> Result<dto::SmartRule, anyhow::Error> {
+    let rule = match &rule.rule {
+        Some(rule) => rule,
+        None => return Err(anyhow::anyhow!("smart rule is not set")),
+    };
+
+    let rule = match rule {
+        smart_rule::Rule::All(group) => dto::SmartRule::All {
+            rules: group.rules.iter().map(from_smart_rule).collect::<Result<_, _>>()?,
+        },
+        smart_rule::Rule::Any(group) => dto::SmartRule::Any {
+            rules: group.rules.iter().map(from_smart_rule).collect::<Result<_, _>>()?,
+        },
+        smart_rule::Rule::GenreIn(genre_in) => dto::SmartRule::GenreIn {
+            genres: genre_in.genres.clone(),
+        },
+        smart_rule::Rule::YearBetween(year_between) => dto::SmartRule::YearBetween {
+            min: (year_between.min != 0).then(|| year_between.min),
+            max: (year_between.max != 0).then(|| year_between.max),
+        },
+        smart_rule::Rule::TextMatch(text_match) => {
+            let field = match text_match_rule::Field::from_i32(text_match.field) {
+                Some(text_match_rule::Field::Artist) => dto::SmartRuleTextField::Artist,
+                Some(text_match_rule::Field::AlbumArtist) => dto::SmartRuleTextField::AlbumArtist,
+                Some(text_match_rule::Field::Album) => dto::SmartRuleTextField::Album,
+                Some(text_match_rule::Field::Title) => dto::SmartRuleTextField::Title,
+                Some(text_match_rule::Field::Genre) => dto::SmartRuleTextField::Genre,
+                None => return Err(anyhow::anyhow!("unknown text match field: {}", text_match.field)),
+            };
+
+            if text_match.pattern.is_empty() {
+                return Err(anyhow::anyhow!("text match pattern should not be empty"));
+            }
+
+            dto::SmartRule::TextMatch {
+                field,
+                pattern: text_match.pattern.clone(),
+            }
+        },
+        smart_rule::Rule::AddedWithin(added_within) => dto::SmartRule::AddedWithin {
+            days: added_within.days,
+        },
+    };
+
+    Ok(rule)
+}
+
+fn to_smart_rule(rule: &dto::SmartRule) -> SmartRule {
+    let rule = match rule {
+        dto::SmartRule::All { rules } => smart_rule::Rule::All(SmartRuleGroup {
+            rules: rules.iter().map(to_smart_rule).collect(),
+        }),
+        dto::SmartRule::Any { rules } => smart_rule::Rule::Any(SmartRuleGroup {
+            rules: rules.iter().map(to_smart_rule).collect(),
+        }),
+        dto::SmartRule::GenreIn { genres } => smart_rule::Rule::GenreIn(GenreInRule {
+            genres: genres.clone(),
+        }),
+        dto::SmartRule::YearBetween { min, max } => smart_rule::Rule::YearBetween(YearBetweenRule {
+            min: min.unwrap_or_default(),
+            max: max.unwrap_or_default(),
+        }),
+        dto::SmartRule::TextMatch { field, pattern } => {
+            let field = match field {
+                dto::SmartRuleTextField::Artist => text_match_rule::Field::Artist,
+                dto::SmartRuleTextField::AlbumArtist => text_match_rule::Field::AlbumArtist,
+                dto::SmartRuleTextField::Album => text_match_rule::Field::Album,
+                dto::SmartRuleTextField::Title => text_match_rule::Field::Title,
+                dto::SmartRuleTextField::Genre => text_match_rule::Field::Genre,
+            };
+
+            smart_rule::Rule::TextMatch(TextMatchRule {
+                field: field as i32,
+                pattern: pattern.clone(),
+            })
+        },
+        dto::SmartRule::AddedWithin { days } => smart_rule::Rule::AddedWithin(AddedWithinRule {
+            days: *days,
+        }),
+    };
+
+    SmartRule {
+        rule: Some(rule),
+    }
+}
+
+fn from_smart_playlist_order(order: &Option<SmartPlaylistOrder>) -> Result<dto::SmartPlaylistOrder, anyhow::Error> {
+    let order = order.clone().unwrap_or_default();
+
+    let field = match smart_playlist_order::Field::from_i32(order.field) {
+        Some(smart_playlist_order::Field::Added) => dto::SmartPlaylistOrderField::Added,
+        Some(smart_playlist_order::Field::Title) => dto::SmartPlaylistOrderField::Title,
+        Some(smart_playlist_order::Field::Artist) => dto::SmartPlaylistOrderField::Artist,
+        Some(smart_playlist_order::Field::Album) => dto::SmartPlaylistOrderField::Album,
+        Some(smart_playlist_order::Field::Year) => dto::SmartPlaylistOrderField::Year,
+        Some(smart_playlist_order::Field::Duration) => dto::SmartPlaylistOrderField::Duration,
+        None => return Err(anyhow::anyhow!("unknown order field: {}", order.field)),
+    };
+
+    Ok(dto::SmartPlaylistOrder {
+        field,
+        descending: order.descending,
+    })
+}
+
+fn to_smart_playlist_order(order: &dto::SmartPlaylistOrder) -> SmartPlaylistOrder {
+    let field = match order.field {
+        dto::SmartPlaylistOrderField::Added => smart_playlist_order::Field::Added,
+        dto::SmartPlaylistOrderField::Title => smart_playlist_order::Field::Title,
+        dto::SmartPlaylistOrderField::Artist => smart_playlist_order::Field::Artist,
+        dto::SmartPlaylistOrderField::Album => smart_playlist_order::Field::Album,
+        dto::SmartPlaylistOrderField::Year => smart_playlist_order::Field::Year,
+        dto::SmartPlaylistOrderField::Duration => smart_playlist_order::Field::Duration,
+    };
+
+    SmartPlaylistOrder {
+        field: field as i32,
+        descending: order.descending,
+    }
+}
+
+struct SmartPlaylistDefinition {
+    name: String,
+    rule: dto::SmartRule,
+    order: dto::SmartPlaylistOrder,
+    limit: Option<u32>,
+}
+
+fn parse_smart_playlist_definition(req: &SaveSmartPlaylistReq) -> Result<SmartPlaylistDefinition, anyhow::Error> {
+    let name = req.name.trim();
+    if name.is_empty() {
+        return Err(anyhow::anyhow!("smart playlist name should not be empty"));
+    }
+
+    let rule = match &req.rule {
+        Some(rule) => from_smart_rule(rule)?,
+        None => return Err(anyhow::anyhow!("smart rule is not set")),
+    };
+
+    if rule.get_depth() > MAX_SMART_RULE_DEPTH {
+        return Err(anyhow::anyhow!("smart rule is nested deeper than {}", MAX_SMART_RULE_DEPTH));
+    }
+
+    if req.limit > MAX_SMART_PLAYLIST_LIMIT {
+        return Err(anyhow::anyhow!("limit should not be greater than {}", MAX_SMART_PLAYLIST_LIMIT));
+    }
+
+    Ok(SmartPlaylistDefinition {
+        name: name.to_string(),
+        rule,
+        order: from_smart_playlist_order(&req.order)?,
+        limit: (req.limit != 0).then(|| req.limit),
+    })
+}
+
+pub struct SmartPlaylist {
+    storage: Arc<Storage>,
+    // evaluations and edits write the whole smart playlist, so that these are serialized
+    edit_lock: Mutex<()>,
+}
+
+impl SmartPlaylist {
+    pub fn new(storage: Arc<Storage>) -> Self {
+        Self {
+            storage,
+            edit_lock: Mutex::new(()),
+        }
+    }
+
+    pub async fn list_smart_playlists(
+        &self,
+        max_item_num: u64,
+        page: u64,
+    ) -> Result<Vec<SmartPlaylistRes>, anyhow::Error> {
+        let smart_playlists = self.storage.smart_playlist.get_paginated(max_item_num as i64, page).await?;
+
+        let res = smart_playlists
+            .iter()
+            .map(|item| self.to_smart_playlist_res(item, &HashMap::new()))
+            .collect();
+
+        Ok(res)
+    }
+
+    pub async fn get_smart_playlist(
+        &self,
+        smart_playlist_id: &str,
+    ) -> Result<SmartPlaylistRes, anyhow::Error> {
+        let smart_playlist_id = parse_smart_playlist_id(smart_playlist_id)?;
+        let mut smart_playlist = self.get_smart_playlist_doc(&smart_playlist_id).await?;
+
+        let evaluated_elapsed = Utc::now().timestamp() - smart_playlist.evaluated_timestamp;
+        if smart_playlist.rule.is_time_relative() && evaluated_elapsed > TIME_RELATIVE_EVALUATION_INTERVAL_SEC {
+            let _edit_lock = self.edit_lock.lock().await;
+
+            smart_playlist = self.get_smart_playlist_doc(&smart_playlist_id).await?;
+            self.evaluate(&mut smart_playlist).await?;
+        }
+
+        let audio_tags = self.storage.audio_tag
+            .get_many(&smart_playlist.audio_tag_ids)
+            .await?
+            .into_iter()
+            .filter_map(|item| Some((item.id?, item)))
+            .collect();
+
+        Ok(self.to_smart_playlist_res(&smart_playlist, &audio_tags))
+    }
+
+    pub async fn create_smart_playlist(
+        &self,
+        req: &SaveSmartPlaylistReq,
+    ) -> Result<SmartPlaylistRes, anyhow::Error> {
+        let definition = parse_smart_playlist_definition(req)?;
+
+        let mut smart_playlist = dto::SmartPlaylist::new(
+            &definition.name,
+            definition.rule,
+            definition.order,
+            definition.limit,
+        );
+
+        self.storage.smart_playlist.create(&smart_playlist).await?;
+
+        {
+            let _edit_lock = self.edit_lock.lock().await;
+            self.evaluate(&mut smart_playlist).await?;
+        }
+
+        self.get_smart_playlist(&smart_playlist.id.unwrap().to_string()).await
+    }
+
+    pub async fn update_smart_playlist(
+        &self,
+        req: &SaveSmartPlaylistReq,
+    ) -> Result<SmartPlaylistRes, anyhow::Error> {
+        let smart_playlist_id = parse_smart_playlist_id(&req.smart_playlist_id)?;
+        let definition = parse_smart_playlist_definition(req)?;
+
+        {
+            let _edit_lock = self.edit_lock.lock().await;
+
+            let mut smart_playlist = self.get_smart_playlist_doc(&smart_playlist_id).await?;
+            smart_playlist.name = definition.name;
+            smart_playlist.rule = definition.rule;
+            smart_playlist.order = definition.order;
+            smart_playlist.limit = definition.limit;
+            smart_playlist.updated_timestamp = Utc::now().timestamp();
+
+            self.evaluate(&mut smart_playlist).await?;
+        }
+
+        self.get_smart_playlist(&smart_playlist_id.to_string()).await
+    }
+
+    pub async fn delete_smart_playlist(
+        &self,
+        smart_playlist_id: &str,
+    ) -> Result<(), anyhow::Error> {
+        let smart_playlist_id = parse_smart_playlist_id(smart_playlist_id)?;
+
+        if self.storage.smart_playlist.delete(&smart_playlist_id).await? == 0 {
+            return Err(anyhow::anyhow!("smart playlist {} does not exist", smart_playlist_id));
+        }
+
+        Ok(())
+    }
+
+    // Called when audio tags of libraries are changed
+    pub async fn evaluate_all(&self) -> Result<(), anyhow::Error> {
+        let _edit_lock = self.edit_lock.lock().await;
+
+        let smart_playlists = self.storage.smart_playlist.get_all().await?;
+
+        for mut smart_playlist in smart_playlists.into_iter() {
+            self.evaluate(&mut smart_playlist).await?;
+        }
+
+        Ok(())
+    }
+
+    // Should be called with the edit lock
+    async fn evaluate(
+        &self,
+        smart_playlist: &mut dto::SmartPlaylist,
+    ) -> Result<(), anyhow::Error> {
+        smart_playlist.audio_tag_ids = self.storage.audio_tag
+            .evaluate_smart_rule(
+                &smart_playlist.rule,
+                &smart_playlist.order,
+                smart_playlist.limit
+            ).await?;
+        smart_playlist.evaluated_timestamp = Utc::now().timestamp();
+
+        self.storage.smart_playlist
+            .update(&smart_playlist.id.unwrap(), smart_playlist)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn get_smart_playlist_doc(
+        &self,
+        smart_playlist_id: &ObjectId,
+    ) -> Result<dto::SmartPlaylist, anyhow::Error> {
+        match self.storage.smart_playlist.get(smart_playlist_id).await? {
+            Some(smart_playlist) => Ok(smart_playlist),
+            None => Err(anyhow::anyhow!("smart playlist {} does not exist", smart_playlist_id)),
+        }
+    }
+
+    fn to_smart_playlist_res(
+        &self,
+        smart_playlist: &dto::SmartPlaylist,
+        audio_tags: &HashMap<ObjectId, dto::AudioTag>,
+    ) -> SmartPlaylistRes {
+        SmartPlaylistRes {
+            id: smart_playlist.id.as_ref().unwrap().to_string(),
+            name: smart_playlist.name.clone(),
+            rule: Some(to_smart_rule(&smart_playlist.rule)),
+            order: Some(to_smart_playlist_order(&smart_playlist.order)),
+            limit: smart_playlist.limit.unwrap_or_default(),
+            audio_tag_count: smart_playlist.audio_tag_ids.len() as u64,
+            // tags removed after the evaluation are left out
+            audio_tags: smart_playlist.audio_tag_ids
+                .iter()
+                .filter_map(|item| audio_tags.get(item))
+                .map(to_audio_tag_res)
+                .collect(),
+            evaluated_timestamp: smart_playlist.evaluated_timestamp,
+            created_timestamp: smart_playlist.created_timestamp,
+            updated_timestamp: smart_playlist.updated_timestamp,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cirrus_protobuf::api::{
+        smart_playlist_order, smart_rule, text_match_rule, AddedWithinRule, GenreInRule,
+        SaveSmartPlaylistReq, SmartPlaylistOrder, SmartRule, SmartRuleGroup, TextMatchRule,
+        YearBetweenRule,
+    };
+
+    use crate::model::dto;
+
+    use super::{
+        from_smart_rule, parse_smart_playlist_definition, to_smart_rule, MAX_SMART_PLAYLIST_LIMIT,
+        MAX_SMART_RULE_DEPTH,
+    };
+
+    fn create_rule(rule: smart_rule::Rule) -> SmartRule {
+        SmartRule { rule: Some(rule) }
+    }
+
+    fn create_req(rule: SmartRule) -> SaveSmartPlaylistReq {
+        SaveSmartPlaylistReq {
+            name: " Recent jazz ".to_string(),
+            rule: Some(rule),
+            ..Default::default()
+        }
+    }
+
+    fn nest_rule(depth: usize) -> SmartRule {
+        (1..depth).fold(
+            create_rule(smart_rule::Rule::AddedWithin(AddedWithinRule { days: 1 })),
+            |rule, _| create_rule(smart_rule::Rule::All(SmartRuleGroup { rules: vec![rule] })),
+        )
+    }
+
+    #[test]
+    fn translate_rules() {
+        let rule = create_rule(smart_rule::Rule::Any(SmartRuleGroup {
+            rules: vec![
+                create_rule(smart_rule::Rule::GenreIn(GenreInRule { genres: vec!["Jazz".to_string()] })),
+                create_rule(smart_rule::Rule::YearBetween(YearBetweenRule { min: 1960, max: 0 })),
+                create_rule(smart_rule::Rule::TextMatch(TextMatchRule {
+                    field: text_match_rule::Field::AlbumArtist as i32,
+                    pattern: "Miles*".to_string(),
+                })),
+                create_rule(smart_rule::Rule::AddedWithin(AddedWithinRule { days: 30 })),
+            ],
+        }));
+
+        let expected = dto::SmartRule::Any {
+            rules: vec![
+                dto::SmartRule::GenreIn { genres: vec!["Jazz".to_string()] },
+                // zero is an open bound
+                dto::SmartRule::YearBetween { min: Some(1960), max: None },
+                dto::SmartRule::TextMatch { field: dto::SmartRuleTextField::AlbumArtist, pattern: "Miles*".to_string() },
+                dto::SmartRule::AddedWithin { days: 30 },
+            ],
+        };
+
+        let translated = from_smart_rule(&rule).unwrap();
+        assert_eq!(translated, expected);
+        assert_eq!(to_smart_rule(&translated), rule);
+    }
+
+    #[test]
+    fn reject_invalid_rules() {
+        assert!(from_smart_rule(&SmartRule { rule: None }).is_err());
+        assert!(from_smart_rule(&create_rule(smart_rule::Rule::TextMatch(TextMatchRule {
+            field: text_match_rule::Field::Title as i32,
+            pattern: String::new(),
+        }))).is_err());
+        assert!(from_smart_rule(&create_rule(smart_rule::Rule::TextMatch(TextMatchRule {
+            field: 100,
+            pattern: "a".to_string(),
+        }))).is_err());
+        // an unset rule in a group
+        assert!(from_smart_rule(&create_rule(smart_rule::Rule::All(SmartRuleGroup {
+            rules: vec![SmartRule { rule: None }],
+        }))).is_err());
+    }
+
+    #[test]
+    fn parse_definition() {
+        let mut req = create_req(nest_rule(2));
+        req.order = Some(SmartPlaylistOrder {
+            field: smart_playlist_order::Field::Year as i32,
+            descending: true,
+        });
+        req.limit = 50;
+
+        let definition = parse_smart_playlist_definition(&req).unwrap();
+        assert_eq!(definition.name, "Recent jazz");
+        assert_eq!(definition.rule.get_depth(), 2);
+        assert_eq!(definition.order, dto::SmartPlaylistOrder { field: dto::SmartPlaylistOrderField::Year, descending: true });
+        assert_eq!(definition.limit, Some(50));
+
+        // unset limit and order
+        let definition = parse_smart_playlist_definition(&create_req(nest_rule(1))).unwrap();
+        assert_eq!(definition.order.field, dto::SmartPlaylistOrderField::Added);
+        assert_eq!(definition.limit, None);
+    }
+
+    #[test]
+    fn reject_invalid_definitions() {
+        assert!(parse_smart_playlist_definition(&create_req(nest_rule(MAX_SMART_RULE_DEPTH))).is_ok());
+        assert!(parse_smart_playlist_definition(&create_req(nest_rule(MAX_SMART_RULE_DEPTH + 1))).is_err());
+
+        let mut req = create_req(nest_rule(1));
+        req.limit = MAX_SMART_PLAYLIST_LIMIT + 1;
+        assert!(parse_smart_playlist_definition(&req).is_err());
+
+        let mut req = create_req(nest_rule(1));
+        req.name = "  ".to_string();
+        assert!(parse_smart_playlist_definition(&req).is_err());
+
+        let mut req = create_req(nest_rule(1));
+        req.rule = None;
+        assert!(parse_smart_playlist_definition(&req).is_err());
+    }
+}
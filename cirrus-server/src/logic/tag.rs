@@ -1,44 +1,169 @@
-use cirrus_protobuf::api::AudioTagRes;
+use std::sync::Arc;
 
-use crate::model::crud;
+use bson::oid::ObjectId;
+use cirrus_protobuf::api::{AudioTagRes, AudioTagSearchHit, AudioTagSearchReq, AudioTagSearchRes, FacetCount};
+use mongodb::bson;
 
-pub struct AudioTag {
-    crud_audio_tag: crud::AudioTag,
-}
+use crate::model::{document, dto, Storage};
 
-impl Default for AudioTag {
-    fn default() -> Self {
-        Self { 
-            crud_audio_tag: Default::default(),
-        }
+const MAX_SEARCH_ITEMS_PER_PAGE: u64 = 500;
+
+pub fn to_audio_tag_res(audio_tag: &dto::AudioTag) -> AudioTagRes {
+    AudioTagRes {
+        id: audio_tag.id.as_ref().unwrap().to_string(),
+        artist: audio_tag.artist.clone().unwrap_or_default(),
+        genre: audio_tag.genre.clone().unwrap_or_default(),
+        title: audio_tag.title.clone().unwrap_or_default(),
+        album: audio_tag.album.clone().unwrap_or_default(),
+        album_artist: audio_tag.album_artist.clone().unwrap_or_default(),
+        year: audio_tag.year.unwrap_or_default(),
+        duration: audio_tag.duration.unwrap_or_default(),
+        disc: audio_tag.disc.unwrap_or_default(),
+        track: audio_tag.track.unwrap_or_default(),
+        artwork_id: audio_tag.artwork_id.clone().unwrap_or_default(),
     }
 }
 
+pub struct AudioTag {
+    storage: Arc<Storage>,
+}
+
 impl AudioTag {
+    pub fn new(storage: Arc<Storage>) -> Self {
+        Self { storage }
+    }
+
     pub async fn list_audio_tags(
         &self,
-        db: mongodb::Client,
         max_item_num: u64,
         page: u64,
     ) -> Result<Vec<AudioTagRes>, anyhow::Error> {
-        let get_all_res = self.crud_audio_tag
-            .page
-            .get_paginated(
-                db.clone(), 
-                max_item_num as i64, 
-                page
-            ).await?;
+        let get_all_res = self.storage.audio_tag
+            .get_paginated(max_item_num as i64, page)
+            .await?;
 
         let res = get_all_res
             .iter()
-            .map(|item| AudioTagRes {
-                id: item.id.as_ref().unwrap().to_string(),
-                artist: item.artist.as_ref().unwrap().to_string(),
-                genre: item.genre.as_ref().unwrap().to_string(),
-                title: item.title.as_ref().unwrap().to_string(),
-            })
+            .map(to_audio_tag_res)
             .collect::<Vec<_>>();
 
         Ok(res)
     }
-}
\ No newline at end of file
+
+    pub async fn get_audio_tag(
+        &self,
+        audio_tag_id: &str,
+    ) -> Result<AudioTagRes, anyhow::Error> {
+        let audio_tag_oid = match ObjectId::parse_str(audio_tag_id) {
+            Ok(audio_tag_oid) => audio_tag_oid,
+            Err(_) => return Err(anyhow::anyhow!("invalid audio tag id: {}", audio_tag_id)),
+        };
+
+        match self.storage.audio_tag.get(&audio_tag_oid).await? {
+            Some(audio_tag) => Ok(to_audio_tag_res(&audio_tag)),
+            None => Err(anyhow::anyhow!("audio tag {} does not exist", audio_tag_id)),
+        }
+    }
+
+    // Checks pages and ranges of a request, and 0 of a range bound is not set
+    pub fn check_search_req(req: &AudioTagSearchReq) -> Result<(), anyhow::Error> {
+        if req.items_per_page == 0 || req.items_per_page > MAX_SEARCH_ITEMS_PER_PAGE {
+            return Err(anyhow::anyhow!(
+                "items per page should be between 1 and {}",
+                MAX_SEARCH_ITEMS_PER_PAGE
+            ));
+        }
+        if req.page == 0 {
+            return Err(anyhow::anyhow!("page starts from 1"));
+        }
+        if req.year_min != 0 && req.year_max != 0 && req.year_min > req.year_max {
+            return Err(anyhow::anyhow!("year min {} is greater than year max {}", req.year_min, req.year_max));
+        }
+        if req.duration_min != 0 && req.duration_max != 0 && req.duration_min > req.duration_max {
+            return Err(anyhow::anyhow!(
+                "duration min {} is greater than duration max {}",
+                req.duration_min,
+                req.duration_max
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub async fn search_audio_tags(
+        &self,
+        req: &AudioTagSearchReq,
+    ) -> Result<AudioTagSearchRes, anyhow::Error> {
+        Self::check_search_req(req)?;
+
+        let filter = document::tag::SearchFilter {
+            query: &req.query,
+            genres: &req.genres,
+            year_range: (
+                (req.year_min != 0).then(|| req.year_min),
+                (req.year_max != 0).then(|| req.year_max),
+            ),
+            duration_range: (
+                (req.duration_min != 0).then(|| req.duration_min),
+                (req.duration_max != 0).then(|| req.duration_max),
+            ),
+        };
+
+        let search_res = self.storage.audio_tag
+            .search(
+                &filter,
+                (req.page - 1) * req.items_per_page,
+                req.items_per_page as i64,
+            )
+            .await?;
+
+        let hits = search_res.hits
+            .iter()
+            .map(|(audio_tag, score)| AudioTagSearchHit {
+                audio_tag: Some(to_audio_tag_res(audio_tag)),
+                score: *score,
+            })
+            .collect();
+
+        let genres = search_res.genres
+            .into_iter()
+            .map(|(value, count)| FacetCount { value, count })
+            .collect();
+
+        let years = search_res.years
+            .into_iter()
+            .map(|(value, count)| FacetCount { value: value.to_string(), count })
+            .collect();
+
+        Ok(AudioTagSearchRes {
+            hits,
+            total: search_res.total,
+            genres,
+            years,
+        })
+    }
+
+    // Searches by a query only, from an offset that is not aligned to pages
+    pub async fn search_audio_tags_from(
+        &self,
+        query: &str,
+        skip: u64,
+        limit: u64,
+    ) -> Result<Vec<AudioTagRes>, anyhow::Error> {
+        let filter = document::tag::SearchFilter {
+            query,
+            genres: &[],
+            year_range: (None, None),
+            duration_range: (None, None),
+        };
+
+        let search_res = self.storage.audio_tag
+            .search(&filter, skip, limit.min(MAX_SEARCH_ITEMS_PER_PAGE) as i64)
+            .await?;
+
+        Ok(search_res.hits
+            .iter()
+            .map(|(audio_tag, _)| to_audio_tag_res(audio_tag))
+            .collect())
+    }
+}
@@ -0,0 +1,101 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::Duration,
+};
+
+use notify::{DebouncedEvent, RecommendedWatcher, RecursiveMode, Watcher};
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+use crate::settings;
+
+pub struct LibraryWatcher {
+    watcher: Option<Mutex<RecommendedWatcher>>,
+    watched_roots: Mutex<HashSet<PathBuf>>,
+}
+
+impl LibraryWatcher {
+    // Debounced events of watched library roots are sent to the returned receiver
+    pub fn new(
+        settings: &settings::LibraryWatcher
+    ) -> Result<(Self, mpsc::Receiver<DebouncedEvent>), anyhow::Error> {
+        let (tx, rx) = mpsc::channel(1024);
+
+        if !settings.enabled {
+            info!("library watcher is disabled");
+
+            return Ok((Self { watcher: None, watched_roots: Default::default() }, rx));
+        }
+
+        let (watcher_tx, watcher_rx) = std::sync::mpsc::channel();
+        let watcher = notify::watcher(watcher_tx, Duration::from_millis(settings.debounce_ms))?;
+
+        // notify sends events to a std channel, so they are forwarded from a dedicated thread
+        std::thread::spawn(move || {
+            while let Ok(event) = watcher_rx.recv() {
+                if tx.blocking_send(event).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok((
+            Self {
+                watcher: Some(Mutex::new(watcher)),
+                watched_roots: Default::default(),
+            },
+            rx
+        ))
+    }
+
+    pub fn watch(&self, library_root: &Path) -> Result<(), anyhow::Error> {
+        let watcher = match &self.watcher {
+            Some(watcher) => watcher,
+            None => return Ok(()),
+        };
+
+        let mut watched_roots = self.watched_roots.lock().unwrap();
+        if watched_roots.contains(library_root) {
+            return Ok(());
+        }
+
+        watcher.lock().unwrap().watch(library_root, RecursiveMode::Recursive)?;
+        watched_roots.insert(library_root.to_path_buf());
+
+        info!("watch library root {:?}", library_root);
+
+        Ok(())
+    }
+
+    pub fn unwatch(&self, library_root: &Path) -> Result<(), anyhow::Error> {
+        let watcher = match &self.watcher {
+            Some(watcher) => watcher,
+            None => return Ok(()),
+        };
+
+        let mut watched_roots = self.watched_roots.lock().unwrap();
+        if !watched_roots.remove(library_root) {
+            return Ok(());
+        }
+
+        // the root may be removed from the file system already
+        if let Err(err) = watcher.lock().unwrap().unwatch(library_root) {
+            warn!("failed to unwatch library root {:?}: {}", library_root, err);
+        }
+
+        info!("unwatch library root {:?}", library_root);
+
+        Ok(())
+    }
+
+    // Events may arrive after a root is unwatched, and these are ignored
+    pub fn is_watched(&self, path: &Path) -> bool {
+        self.watched_roots
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|library_root| path.starts_with(library_root))
+    }
+}
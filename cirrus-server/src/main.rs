@@ -1,21 +1,79 @@
+mod health;
 mod logic;
+mod logging;
+mod metrics;
 mod model;
+mod rest;
 mod service;
 mod util;
 mod settings;
+mod state;
+mod stream;
+mod subsonic;
 
-// use notify::{Watcher, RecursiveMode, watcher};
-use tonic::transport::{Server as TonicServer, Identity, ServerTlsConfig};
+use std::{path::{Path, PathBuf}, sync::Arc, time::Duration};
+
+use clap::{Parser, Subcommand};
+use tonic::{
+    codegen::InterceptedService,
+    transport::{Server as TonicServer, Identity, ServerTlsConfig},
+};
+use tracing::{error, info, warn};
 
 use cirrus_protobuf::{
+    artwork_svc_server::ArtworkSvcServer,
+    audio_browse_svc_server::AudioBrowseSvcServer,
     audio_data_svc_server::AudioDataSvcServer,
     audio_library_svc_server::AudioLibrarySvcServer,
     audio_tag_svc_server::AudioTagSvcServer,
+    auth_svc_server::AuthSvcServer,
+    job_svc_server::JobSvcServer,
+    play_history_svc_server::PlayHistorySvcServer,
+    playlist_svc_server::PlaylistSvcServer,
+    smart_playlist_svc_server::SmartPlaylistSvcServer,
 };
-use settings::Settings;
+use model::dto::UserRole;
+use settings::{Settings, StorageBackend};
+use state::ServerState;
+
+#[derive(Parser)]
+#[command(version, about = "Cirrus server")]
+struct Cli {
+    /// Path of server.toml [default: configs/cirrus/server.toml]
+    #[arg(long, global = true, env = "CIRRUS_CONFIG")]
+    config: Option<PathBuf>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Copy all data to the other storage backend
+    MigrateStorage {
+        /// mongodb or sqlite
+        source: String,
+        /// mongodb or sqlite
+        target: String,
+    },
+    /// Manage the config
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigCommand {
+    /// Load the config with environment variables, and check its values
+    Check,
+}
+
+async fn serve_grpc_service(config_path: PathBuf) -> Result<(), anyhow::Error> {
+    info!("config: {:?}", config_path);
 
-async fn serve_grpc_service() -> Result<(), anyhow::Error> {
-    let settings = Settings::get()?;
+    let state = Arc::new(ServerState::new(config_path)?);
+    let settings = state.settings();
 
     let server_listen_address = format!(
         "{}:{}", 
@@ -23,11 +81,12 @@ async fn serve_grpc_service() -> Result<(), anyhow::Error> {
         settings.server.listen_port
     );
 
-    println!("info: listen address: {}", server_listen_address);
-    println!("info: use tls: {}", settings.server.tls);
+    info!("listen address: {}", server_listen_address);
+    info!("use tls: {}", settings.server.tls);
 
     let addr = server_listen_address.parse().unwrap();
-    let mut tonic_server = TonicServer::builder();
+    // browsers call gRPC-Web and the REST API with HTTP/1.1
+    let mut tonic_server = TonicServer::builder().accept_http1(settings.web.enabled);
 
     if settings.server.tls {
         let cert = tokio::fs::read(&settings.server.cert_path).await?;
@@ -36,26 +95,253 @@ async fn serve_grpc_service() -> Result<(), anyhow::Error> {
         let identity = Identity::from_pem(cert, key);
         tonic_server = tonic_server.tls_config(ServerTlsConfig::new().identity(identity))?;
 
-        println!("info: loaded TLS identity successfully");
+        info!("loaded TLS identity successfully");
     }
 
-    println!("info: start grpc service");
+    info!("storage backend: {:?}", settings.storage.backend);
+    let storage = Arc::new(model::storage::create_storage(settings.storage.backend, &settings).await?);
+
+    let auth = Arc::new(logic::Auth::new(&settings.auth, storage.clone())?);
+    auth.create_initial_admin(&settings.auth).await?;
+
+    if !auth.is_enabled() {
+        warn!("auth is disabled, and anyone who can reach the server can call services");
+    }
+
+    let listener = service::AuthInterceptor::new(auth.clone(), UserRole::Listener);
+    let admin = service::AuthInterceptor::new(auth.clone(), UserRole::Admin);
+
+    let transcode_cache = Arc::new(logic::TranscodeCache::new(&settings.transcode_cache)?);
+    let artwork = logic::Artwork::new(&settings.artwork, storage.clone())?;
+
+    let job_manager = Arc::new(logic::JobManager::new(storage.clone()));
+    if let Err(err) = job_manager.recover_interrupted_jobs().await {
+        warn!("failed to recover interrupted jobs: {}", err);
+    }
+
+    let (library_watcher, library_events) = logic::LibraryWatcher::new(&settings.library_watcher)?;
+    let library_watcher = Arc::new(library_watcher);
+
+    let smart_playlist = Arc::new(logic::SmartPlaylist::new(storage.clone()));
+    let play_history = Arc::new(logic::PlayHistory::new(state.clone(), storage.clone()));
+
+    let library_sync = Arc::new(logic::AudioLibrary::new(state.clone(), storage.clone(), transcode_cache.clone(), library_watcher.clone(), smart_playlist.clone()));
+    let library_sync_job_manager = job_manager.clone();
 
-    tonic_server
-        .add_service(AudioDataSvcServer::new(service::AudioDataSvcImpl::default()))
-        .add_service(AudioLibrarySvcServer::new(service::AudioLibrarySvcImpl::default()))
-        .add_service(AudioTagSvcServer::new(service::AudioTagSvcImpl::default()))
-        .serve(addr)
-        .await?;
+    tokio::spawn(async move {
+        if let Err(err) = library_sync.sync_library_events(library_events, library_sync_job_manager).await {
+            error!("library sync is stopped: {}", err);
+        }
+    });
+
+    if settings.metrics.enabled {
+        let metrics_addr = format!("{}:{}", settings.metrics.listen_address, settings.metrics.listen_port).parse()?;
+        let metrics_storage = storage.clone();
+
+        tokio::spawn(async move {
+            if let Err(err) = metrics::serve(metrics_addr, metrics_storage).await {
+                error!("metrics endpoint is stopped: {}", err);
+            }
+        });
+    }
+
+    if settings.subsonic.enabled {
+        let subsonic_addr = format!("{}:{}", settings.subsonic.listen_address, settings.subsonic.listen_port).parse()?;
+        let subsonic = Arc::new(subsonic::Subsonic::new(
+            state.clone(),
+            storage.clone(),
+            auth.clone(),
+            transcode_cache.clone(),
+            logic::Artwork::new(&settings.artwork, storage.clone())?,
+            play_history.clone(),
+        ));
+
+        tokio::spawn(async move {
+            if let Err(err) = subsonic::serve(subsonic_addr, subsonic).await {
+                error!("subsonic api is stopped: {}", err);
+            }
+        });
+    }
+
+    if let Err(err) = state.watch_settings() {
+        warn!("failed to watch settings, changes require a restart: {}", err);
+    }
+
+    let (mut health_reporter, health_service) = tonic_health::server::health_reporter();
+    let health_watcher = tokio::spawn(health::watch_db_health(health_reporter.clone(), storage.clone()));
+
+    let reflection_service = tonic_reflection::server::Builder::configure()
+        .register_encoded_file_descriptor_set(cirrus_protobuf::FILE_DESCRIPTOR_SET)
+        .build()?;
+
+    // gRPC-Web requests of browsers are refused by origin if it is disabled
+    let grpc_web = match settings.web.enabled {
+        true if settings.web.cors_allowed_origins.iter().any(|item| item == "*") => tonic_web::config().allow_all_origins(),
+        true => tonic_web::config().allow_origins(settings.web.cors_allowed_origins.clone()),
+        false => tonic_web::config().allow_origins(Vec::<String>::new()),
+    };
+
+    info!("serve grpc-web, rest api and http streams: {}", settings.web.enabled);
+
+    // shared with the REST API
+    let audio_data_svc = Arc::new(service::AudioDataSvcImpl::new(state.clone(), storage.clone(), transcode_cache.clone(), play_history.clone()));
+    let audio_library_svc = Arc::new(service::AudioLibrarySvcImpl::new(state.clone(), storage.clone(), transcode_cache.clone(), library_watcher, job_manager.clone(), smart_playlist.clone()));
+    let audio_tag_svc = Arc::new(service::AudioTagSvcImpl::new(storage.clone()));
+
+    let rest_api = match settings.web.enabled {
+        true => Some(rest::RestApi::new(audio_tag_svc.clone(), audio_library_svc.clone(), audio_data_svc.clone(), listener.clone(), admin.clone())
+            .into_service(&settings.web.cors_allowed_origins)),
+        false => None,
+    };
+
+    let http_stream = match settings.web.enabled {
+        true => Some(stream::HttpStream::new(state.clone(), storage.clone(), transcode_cache, listener.clone())
+            .into_service(&settings.web.cors_allowed_origins)),
+        false => None,
+    };
+
+    let (shutdown_tx, mut shutdown_rx) = tokio::sync::watch::channel(false);
+    let shutdown_timeout = Duration::from_secs(settings.server.shutdown_timeout_sec);
+
+    info!("start grpc service");
+
+    let server = tonic_server
+        .layer(metrics::RpcMetricsLayer)
+        .add_service(health_service)
+        .add_service(reflection_service)
+        .add_service(grpc_web.enable(AuthSvcServer::new(service::AuthSvcImpl::new(auth))))
+        .add_service(grpc_web.enable(InterceptedService::new(AudioDataSvcServer::from_arc(audio_data_svc), listener.clone())))
+        .add_service(grpc_web.enable(InterceptedService::new(AudioLibrarySvcServer::from_arc(audio_library_svc), admin.clone())))
+        .add_service(grpc_web.enable(InterceptedService::new(AudioTagSvcServer::from_arc(audio_tag_svc), listener.clone())))
+        .add_service(grpc_web.enable(AudioBrowseSvcServer::with_interceptor(service::AudioBrowseSvcImpl::new(storage.clone()), listener.clone())))
+        .add_service(grpc_web.enable(ArtworkSvcServer::with_interceptor(service::ArtworkSvcImpl::new(artwork), listener.clone())))
+        .add_service(grpc_web.enable(PlaylistSvcServer::with_interceptor(service::PlaylistSvcImpl::new(storage), listener.clone())))
+        .add_service(grpc_web.enable(SmartPlaylistSvcServer::with_interceptor(service::SmartPlaylistSvcImpl::new(smart_playlist), listener.clone())))
+        .add_service(grpc_web.enable(PlayHistorySvcServer::with_interceptor(service::PlayHistorySvcImpl::new(play_history), listener)))
+        .add_service(grpc_web.enable(JobSvcServer::with_interceptor(service::JobSvcImpl::new(job_manager.clone()), admin)))
+        .add_optional_service(rest_api)
+        .add_optional_service(http_stream)
+        // new connections and streams are refused after the signal, and in-flight ones are kept
+        .serve_with_shutdown(addr, async move {
+            let _ = shutdown_rx.changed().await;
+        });
+    tokio::pin!(server);
+
+    tokio::select! {
+        res = &mut server => return Ok(res?),
+        _ = wait_shutdown_signal() => (),
+    }
+
+    info!("shutdown, wait in-flight streams for {:?}", shutdown_timeout);
+
+    health_watcher.abort();
+    health::set_not_serving(&mut health_reporter).await;
+    let _ = shutdown_tx.send(true);
+
+    let (server_res, _) = tokio::join!(
+        tokio::time::timeout(shutdown_timeout, &mut server),
+        job_manager.cancel_running_jobs(shutdown_timeout),
+    );
+
+    match server_res {
+        Ok(res) => res?,
+        Err(_) => warn!("in-flight streams are not finished in {:?}, and are dropped", shutdown_timeout),
+    }
+
+    info!("stopped grpc service");
+
+    Ok(())
+}
+
+// SIGTERM is sent by container orchestrators, and Ctrl-C at terminals
+async fn wait_shutdown_signal() {
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => { signal.recv().await; },
+            Err(err) => {
+                warn!("failed to listen SIGTERM: {}", err);
+                std::future::pending::<()>().await;
+            },
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => (),
+        _ = terminate => (),
+    }
+}
+
+// Copies all data between storage backends, so that the backend can be changed without
+// analyzing libraries again
+async fn migrate_storage(config_path: &Path, source: &str, target: &str) -> Result<(), anyhow::Error> {
+    let settings = Settings::load(config_path)?;
+    let source_backend: StorageBackend = source.parse()?;
+    let target_backend: StorageBackend = target.parse()?;
+
+    if source_backend == target_backend {
+        return Err(anyhow::anyhow!("source and target storage backends are the same"));
+    }
+
+    info!("migrate storage from {} to {}", source, target);
+
+    let source_storage = model::storage::create_storage(source_backend, &settings).await?;
+    let target_storage = model::storage::create_storage(target_backend, &settings).await?;
+
+    model::storage::migrate(&source_storage, &target_storage).await?;
+
+    info!("migrated storage, set storage.backend to \"{}\" to use it", target);
+
+    Ok(())
+}
+
+// Checks the config without connecting to the database or starting services
+async fn check_config(config_path: &Path) -> Result<(), anyhow::Error> {
+    let settings = Settings::load(config_path)?;
+    let mut errors = settings.validate();
+
+    if let Err(err) = logic::Auth::check_settings(&settings.auth) {
+        errors.push(format!("auth: {}", err));
+    }
+
+    if settings.storage.backend == StorageBackend::MongoDB {
+        if let Err(err) = mongodb::options::ClientOptions::parse(&settings.mongodb.address).await {
+            errors.push(format!("mongodb: {}", err));
+        }
+    }
+
+    if !errors.is_empty() {
+        for error in errors.iter() {
+            error!("{}", error);
+        }
+
+        return Err(anyhow::anyhow!("config {:?} has {} errors", config_path, errors.len()));
+    }
+
+    info!("config {:?} is valid", config_path);
 
     Ok(())
 }
 
 #[tokio::main]
 async fn main() -> Result<(), anyhow::Error> {
-    println!("Cirrus v0.3.0");
+    let cli = Cli::parse();
+    let config_path = cli.config.unwrap_or_else(Settings::get_default_path);
+
+    // the config is loaded again by the command, which reports its error
+    let log_settings = Settings::load(&config_path).ok().map(|item| item.log);
+    let _log_guard = logging::init(log_settings.as_ref())?;
 
-    serve_grpc_service().await?;
+    info!("Cirrus v{}", env!("CARGO_PKG_VERSION"));
+
+    match cli.command {
+        None => serve_grpc_service(config_path).await?,
+        Some(Command::MigrateStorage { source, target }) => migrate_storage(&config_path, &source, &target).await?,
+        Some(Command::Config { command: ConfigCommand::Check }) => check_config(&config_path).await?,
+    }
 
     Ok(())
 }
\ No newline at end of file
@@ -0,0 +1,260 @@
+use std::{
+    convert::Infallible,
+    future::Future,
+    net::SocketAddr,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Instant,
+};
+
+use hyper::{
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, StatusCode,
+};
+use mongodb::event::command::{CommandEventHandler, CommandFailedEvent};
+use once_cell::sync::Lazy;
+use prometheus::{
+    register_histogram, register_histogram_vec, register_int_counter, register_int_counter_vec,
+    register_int_gauge, register_int_gauge_vec, Encoder, Histogram, HistogramVec, IntCounter,
+    IntCounterVec, IntGauge, IntGaugeVec, TextEncoder,
+};
+use tower::{Layer, Service};
+use tracing::{info, warn};
+
+use crate::model::Storage;
+
+const METRICS_PATH: &'static str = "/metrics";
+// paths of methods that do not exist are recorded as this, so that labels are bounded
+const UNKNOWN_METHOD: &'static str = "unknown";
+// REST requests and HTTP streams are served at the same address, and their paths have ids
+const HTTP_METHOD: &'static str = "http";
+const GRPC_STATUS_UNIMPLEMENTED: &'static str = "12";
+
+// Metrics are recorded even if the endpoint is disabled, as updating them is cheap
+
+pub static RPC_REQUESTS: Lazy<IntCounterVec> = Lazy::new(|| register_int_counter_vec!(
+    "cirrus_rpc_requests_total",
+    "gRPC requests by method and status code, and other HTTP requests as method \"http\" by HTTP status",
+    &["method", "code"]
+).unwrap());
+
+pub static RPC_DURATION: Lazy<HistogramVec> = Lazy::new(|| register_histogram_vec!(
+    "cirrus_rpc_duration_seconds",
+    "Time until the response of gRPC requests starts, by method",
+    &["method"]
+).unwrap());
+
+pub static ACTIVE_DATA_STREAMS: Lazy<IntGauge> = Lazy::new(|| register_int_gauge!(
+    "cirrus_active_data_streams",
+    "GetData streams that are sending packets"
+).unwrap());
+
+pub static ENCODED_PACKETS: Lazy<IntCounter> = Lazy::new(|| register_int_counter!(
+    "cirrus_encoded_packets_total",
+    "Packets encoded for streams, which are not served from the transcode cache"
+).unwrap());
+
+pub static PACKET_ENCODE_DURATION: Lazy<Histogram> = Lazy::new(|| register_histogram!(
+    "cirrus_packet_encode_duration_seconds",
+    "Time to encode a packet",
+    vec![0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05]
+).unwrap());
+
+pub static LIBRARY_DOCUMENTS: Lazy<IntGaugeVec> = Lazy::new(|| register_int_gauge_vec!(
+    "cirrus_library_documents",
+    "Documents in the metadata storage by collection",
+    &["collection"]
+).unwrap());
+
+pub static JOB_DURATION: Lazy<HistogramVec> = Lazy::new(|| register_histogram_vec!(
+    "cirrus_job_duration_seconds",
+    "Duration of library jobs by kind and status",
+    &["kind", "status"],
+    vec![1.0, 5.0, 15.0, 60.0, 300.0, 900.0, 1800.0, 3600.0, 7200.0]
+).unwrap());
+
+pub static DB_ERRORS: Lazy<IntCounterVec> = Lazy::new(|| register_int_counter_vec!(
+    "cirrus_db_errors_total",
+    "Failed database operations by backend",
+    &["backend"]
+).unwrap());
+
+// Counts failed commands of the MongoDB client
+pub struct DbCommandMetrics;
+
+impl CommandEventHandler for DbCommandMetrics {
+    fn handle_command_failed_event(&self, _event: CommandFailedEvent) {
+        DB_ERRORS.with_label_values(&["mongodb"]).inc();
+    }
+}
+
+// Records counts and latencies of gRPC requests. Streaming methods are measured until the
+// response starts
+#[derive(Clone, Default)]
+pub struct RpcMetricsLayer;
+
+impl<S> Layer<S> for RpcMetricsLayer {
+    type Service = RpcMetrics<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RpcMetrics { inner }
+    }
+}
+
+#[derive(Clone)]
+pub struct RpcMetrics<S> {
+    inner: S,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for RpcMetrics<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    ReqBody: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<ReqBody>) -> Self::Future {
+        // the service that is ready is used, and its clone is kept for the next request
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let method = request.uri().path().to_string();
+        let is_grpc = is_grpc_request(&request);
+
+        Box::pin(async move {
+            let start = Instant::now();
+            let res = inner.call(request).await;
+
+            // the status is at headers if the request fails before the response starts,
+            // and at trailers otherwise
+            let code = match &res {
+                Ok(res) if is_grpc => res.headers()
+                    .get("grpc-status")
+                    .and_then(|item| item.to_str().ok())
+                    .unwrap_or("0")
+                    .to_string(),
+                Ok(res) => res.status().as_u16().to_string(),
+                Err(_) => "error".to_string(),
+            };
+
+            let method = match code.as_str() {
+                _ if !is_grpc => HTTP_METHOD,
+                GRPC_STATUS_UNIMPLEMENTED => UNKNOWN_METHOD,
+                _ => method.as_str(),
+            };
+
+            RPC_REQUESTS.with_label_values(&[method, &code]).inc();
+            RPC_DURATION.with_label_values(&[method]).observe(start.elapsed().as_secs_f64());
+
+            res
+        })
+    }
+}
+
+// gRPC and gRPC-Web requests, of which paths are methods
+fn is_grpc_request<B>(request: &Request<B>) -> bool {
+    request.headers()
+        .get(hyper::header::CONTENT_TYPE)
+        .and_then(|item| item.to_str().ok())
+        .map_or(false, |item| item.starts_with("application/grpc"))
+}
+
+// Serves metrics in the Prometheus text format at `/metrics`
+pub async fn serve(addr: SocketAddr, storage: Arc<Storage>) -> Result<(), anyhow::Error> {
+    let make_service = make_service_fn(move |_| {
+        let storage = storage.clone();
+
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| handle_request(request, storage.clone())))
+        }
+    });
+
+    let server = hyper::Server::try_bind(&addr)?.serve(make_service);
+
+    info!("serve metrics at http://{}{}", addr, METRICS_PATH);
+
+    server.await?;
+
+    Ok(())
+}
+
+async fn handle_request(
+    request: Request<Body>,
+    storage: Arc<Storage>,
+) -> Result<Response<Body>, Infallible> {
+    if request.method() != Method::GET || request.uri().path() != METRICS_PATH {
+        return Ok(create_response(StatusCode::NOT_FOUND, Body::empty()));
+    }
+
+    update_library_documents(&storage).await;
+
+    let encoder = TextEncoder::new();
+    let mut buf = Vec::new();
+
+    if let Err(err) = encoder.encode(&prometheus::gather(), &mut buf) {
+        warn!("failed to encode metrics: {}", err);
+
+        return Ok(create_response(StatusCode::INTERNAL_SERVER_ERROR, Body::empty()));
+    }
+
+    let mut res = create_response(StatusCode::OK, Body::from(buf));
+    res.headers_mut().insert(
+        hyper::header::CONTENT_TYPE,
+        hyper::header::HeaderValue::from_static("text/plain; version=0.0.4"),
+    );
+
+    Ok(res)
+}
+
+fn create_response(status: StatusCode, body: Body) -> Response<Body> {
+    let mut res = Response::new(body);
+    *res.status_mut() = status;
+
+    res
+}
+
+// Library sizes are counted at scrapes, as documents are changed by scans and the watcher
+async fn update_library_documents(storage: &Storage) {
+    let counts = [
+        ("library_roots", storage.library_root.count().await),
+        ("libraries", storage.library.count().await),
+        ("audio_files", storage.audio_file.count().await),
+        ("audio_tags", storage.audio_tag.count().await),
+    ];
+
+    for (collection, count) in counts {
+        match count {
+            Ok(count) => LIBRARY_DOCUMENTS.with_label_values(&[collection]).set(count as i64),
+            Err(err) => warn!("failed to count {}: {}", collection, err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_request(content_type: Option<&str>) -> Request<()> {
+        let mut builder = Request::builder().uri("/cirrus.api.AudioTagSvc/ListAudioTags");
+        if let Some(content_type) = content_type {
+            builder = builder.header(hyper::header::CONTENT_TYPE, content_type);
+        }
+
+        builder.body(()).unwrap()
+    }
+
+    #[test]
+    fn test_is_grpc_request() {
+        assert!(is_grpc_request(&create_request(Some("application/grpc"))));
+        assert!(is_grpc_request(&create_request(Some("application/grpc-web+proto"))));
+        assert!(!is_grpc_request(&create_request(Some("application/json"))));
+        assert!(!is_grpc_request(&create_request(None)));
+    }
+}
@@ -0,0 +1,12 @@
+use crate::{
+    model::{GetCollection, dto}
+};
+
+// Collection of the MongoDB storage, which is queried through `model::Storage`
+pub struct Artwork;
+
+impl GetCollection<dto::Artwork> for Artwork {
+    fn get_collection(db: mongodb::Client) -> mongodb::Collection<dto::Artwork> {
+        db.database("cirrus").collection::<dto::Artwork>("artworks")
+    }
+}
@@ -1,13 +1,26 @@
+use bson::doc;
+use mongodb::{bson, IndexModel};
+
 use crate::{
     model::{GetCollection, dto}
 };
 
-use super::{CrudMany, PathOperation, CrudSingle};
+// Collection of the MongoDB storage, which is queried through `model::Storage`
+pub struct AudioFile;
+
+impl AudioFile {
+    // smart playlist rules join audio files of tags for the added time
+    pub async fn create_indexes(db: mongodb::Client) -> Result<(), anyhow::Error> {
+        let index = IndexModel::builder()
+            .keys(doc! { "audio_tag_refer": 1 })
+            .build();
 
-pub struct AudioFile {
-    pub single: CrudSingle<dto::AudioFile>,
-    pub many: CrudMany<dto::AudioFile>,
-    pub path: PathOperation<dto::AudioFile>,
+        Self::get_collection(db)
+            .create_index(index, None)
+            .await?;
+
+        Ok(())
+    }
 }
 
 impl GetCollection<dto::AudioFile> for AudioFile {
@@ -15,13 +28,3 @@ impl GetCollection<dto::AudioFile> for AudioFile {
         db.database("cirrus").collection::<dto::AudioFile>("audio")
     }
 }
-
-impl Default for AudioFile {
-    fn default() -> Self {
-        Self { 
-            single: CrudSingle::new(Self::get_collection), 
-            many: CrudMany::new(Self::get_collection), 
-            path: PathOperation::new(Self::get_collection)
-        }
-    }
-}
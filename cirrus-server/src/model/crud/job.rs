@@ -0,0 +1,12 @@
+use crate::{
+    model::{GetCollection, dto}
+};
+
+// Collection of the MongoDB storage, which is queried through `model::Storage`
+pub struct Job;
+
+impl GetCollection<dto::Job> for Job {
+    fn get_collection(db: mongodb::Client) -> mongodb::Collection<dto::Job> {
+        db.database("cirrus").collection::<dto::Job>("jobs")
+    }
+}
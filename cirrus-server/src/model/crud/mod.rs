@@ -1,17 +1,40 @@
 use std::path::Path;
 
-use bson::{oid::ObjectId, Document};
+use bson::{doc, oid::ObjectId, Bson, Document};
 use futures::stream::TryStreamExt;
 use mongodb::results::{InsertOneResult, InsertManyResult, DeleteResult, UpdateResult};
 
+mod artwork;
 mod file;
+mod job;
 mod library;
+mod play_history;
+mod playlist;
+mod smart_playlist;
 mod tag;
+mod user;
 
+pub use artwork::Artwork;
 pub use library::{AudioLibraryRoot, AudioLibrary};
 pub use file::AudioFile;
+pub use job::Job;
+pub use play_history::{PlayEvent, PlayStat};
+pub use playlist::Playlist;
+pub use smart_playlist::SmartPlaylist;
 use serde::{Serialize, de::DeserializeOwned};
 pub use tag::AudioTag;
+pub use user::User;
+
+// Creates indexes that queries rely on, and existing indexes are left as is
+pub async fn create_indexes(db: mongodb::Client) -> Result<(), anyhow::Error> {
+    AudioFile::create_indexes(db.clone()).await?;
+    AudioTag::create_indexes(db.clone()).await?;
+    PlayEvent::create_indexes(db.clone()).await?;
+    PlayStat::create_indexes(db.clone()).await?;
+    User::create_indexes(db.clone()).await?;
+
+    Ok(())
+}
 
 use crate::util;
 
@@ -24,7 +47,7 @@ pub struct Pagination<T> {
 }
 
 impl<T> Pagination<T> {
-    fn new(col_fn: fn(mongodb::Client) -> mongodb::Collection<T>) -> Self {
+    pub(crate) fn new(col_fn: fn(mongodb::Client) -> mongodb::Collection<T>) -> Self {
         Self {
             _object: None,
             col_fn,
@@ -41,6 +64,16 @@ where
         db: mongodb::Client,
         limit: i64,
         page: u64
+    ) -> Result<Vec<T>, anyhow::Error> {
+        self.get_paginated_by_query(db, None, limit, page).await
+    }
+
+    pub async fn get_paginated_by_query(
+        &self,
+        db: mongodb::Client,
+        query: Option<Document>,
+        limit: i64,
+        page: u64
     ) -> Result<Vec<T>, anyhow::Error> {
         let options = mongodb::options::FindOptions::builder()
             .limit(limit)
@@ -48,7 +81,7 @@ where
             .build();
 
         let find_res = (self.col_fn)(db)
-            .find(None, options)
+            .find(query, options)
             .await?;
 
         let found_docs = find_res
@@ -60,13 +93,48 @@ where
     }
 }
 
+pub struct Aggregation<T> {
+    _object: Option<T>,
+    col_fn: fn(mongodb::Client) -> mongodb::Collection<T>,
+}
+
+impl<T> Aggregation<T> {
+    pub(crate) fn new(col_fn: fn(mongodb::Client) -> mongodb::Collection<T>) -> Self {
+        Self {
+            _object: None,
+            col_fn,
+        }
+    }
+}
+
+impl<T> Aggregation<T> 
+where
+    T: Serialize + DeserializeOwned + Sync + Send + Unpin
+{
+    pub async fn aggregate(
+        &self,
+        db: mongodb::Client,
+        pipeline: Vec<Document>,
+    ) -> Result<Vec<Document>, anyhow::Error> {
+        let aggregate_res = (self.col_fn)(db)
+            .aggregate(pipeline, None)
+            .await?;
+
+        let docs = aggregate_res
+            .try_collect()
+            .await?;
+
+        Ok(docs)
+    }
+}
+
 pub struct CrudMany<T> {
     _object: Option<T>,
     col_fn: fn(mongodb::Client) -> mongodb::Collection<T>,
 }
 
 impl<T> CrudMany<T> {
-    fn new(col_fn: fn(mongodb::Client) -> mongodb::Collection<T>) -> Self {
+    pub(crate) fn new(col_fn: fn(mongodb::Client) -> mongodb::Collection<T>) -> Self {
         Self {
             _object: None,
             col_fn,
@@ -132,13 +200,53 @@ where
 
         Ok(found_docs)
     }
+
+    pub async fn count(
+        &self,
+        db: mongodb::Client,
+        query: Document,
+    ) -> Result<u64, anyhow::Error> {
+        let count = (self.col_fn)(db)
+            .count_documents(query, None)
+            .await?;
+
+        Ok(count)
+    }
     
     pub async fn update_many(
         &self,
         db: mongodb::Client,
-        docs: &Vec<T>
+        docs: &[T]
     ) -> Vec<Result<UpdateResult, anyhow::Error>> {
-        todo!()
+        let mut update_results = Vec::with_capacity(docs.len());
+
+        for doc in docs.iter() {
+            let update_res = self.update_one_by_doc_id(db.clone(), doc).await;
+            update_results.push(update_res);
+        }
+
+        update_results
+    }
+
+    async fn update_one_by_doc_id(
+        &self,
+        db: mongodb::Client,
+        doc: &T
+    ) -> Result<UpdateResult, anyhow::Error> {
+        let mut doc = mongodb::bson::to_document(doc)?;
+        let id = match doc.remove("_id") {
+            Some(Bson::ObjectId(id)) => id,
+            _ => return Err(anyhow::anyhow!("object id is not set")),
+        };
+
+        let update_res = (self.col_fn)(db)
+            .update_one(
+                document::query_single_id(&id), 
+                doc! { "$set": doc }, 
+                None
+            ).await?;
+
+        Ok(update_res)
     }
 
     pub async fn delete_many(
@@ -164,7 +272,7 @@ pub struct CrudSingle<T> {
 }
 
 impl<T> CrudSingle<T> {
-    fn new(col_fn: fn(mongodb::Client) -> mongodb::Collection<T>) -> Self {
+    pub(crate) fn new(col_fn: fn(mongodb::Client) -> mongodb::Collection<T>) -> Self {
         Self {
             _object: None,
             col_fn,
@@ -253,7 +361,7 @@ pub struct PathOperation<T> {
 }
 
 impl<T> PathOperation<T> {
-    fn new(col_fn: fn(mongodb::Client) -> mongodb::Collection<T>) -> Self {
+    pub(crate) fn new(col_fn: fn(mongodb::Client) -> mongodb::Collection<T>) -> Self {
         Self {
             _object: None,
             col_fn,
@@ -270,7 +378,7 @@ where
         db: mongodb::Client,
         path: &Path
     ) -> Result<Vec<T>, anyhow::Error> {
-        let path = util::path::path_to_materialized(path);
+        let path = util::path::path_to_materialized(path)?;
         let filter = document::path::query_path(T::get_mat_path_key(), &path);
 
         let find_res = (self.col_fn)(db)
@@ -307,7 +415,7 @@ where
         db: mongodb::Client,
         path: &Path
     ) -> Result<DeleteResult, anyhow::Error> {
-        let mat_path = util::path::path_to_materialized(path);
+        let mat_path = util::path::path_to_materialized(path)?;
         let filter = document::path::query_path(T::get_mat_path_key(), &mat_path);
 
         let delete_res = (self.col_fn)(db)
@@ -339,7 +447,7 @@ where
         db: mongodb::Client,
         path: &Path
     ) -> Result<bool, anyhow::Error> {
-        let path = util::path::path_to_materialized(path);
+        let path = util::path::path_to_materialized(path)?;
         let filter = document::path::query_path(T::get_mat_path_key(), &path);
 
         let find_res = (self.col_fn)(db)
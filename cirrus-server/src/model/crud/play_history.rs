@@ -0,0 +1,65 @@
+use bson::doc;
+use mongodb::{bson, options::IndexOptions, IndexModel};
+
+use crate::{
+    model::{GetCollection, dto}
+};
+
+// Collections of the MongoDB storage, which are queried through `model::Storage`
+pub struct PlayEvent;
+
+impl PlayEvent {
+    pub async fn create_indexes(db: mongodb::Client) -> Result<(), anyhow::Error> {
+        // events of a playback are applied to a single document
+        let playback_index = IndexModel::builder()
+            .keys(doc! { "user_id": 1, "playback_id": 1 })
+            .options(IndexOptions::builder().unique(true).build())
+            .build();
+
+        let history_indexes = [
+            doc! { "user_id": 1, "listened_at": -1 },
+            doc! { "user_id": 1, "audio_tag_id": 1, "listened_at": -1 },
+        ]
+        .into_iter()
+        .map(|keys| IndexModel::builder().keys(keys).build());
+
+        let mut indexes = vec![playback_index];
+        indexes.extend(history_indexes);
+
+        Self::get_collection(db)
+            .create_indexes(indexes, None)
+            .await?;
+
+        Ok(())
+    }
+}
+
+impl GetCollection<dto::PlayEvent> for PlayEvent {
+    fn get_collection(db: mongodb::Client) -> mongodb::Collection<dto::PlayEvent> {
+        db.database("cirrus").collection::<dto::PlayEvent>("play_events")
+    }
+}
+
+pub struct PlayStat;
+
+impl PlayStat {
+    pub async fn create_indexes(db: mongodb::Client) -> Result<(), anyhow::Error> {
+        // stats of an audio by a user are increased in a single document
+        let stat_index = IndexModel::builder()
+            .keys(doc! { "user_id": 1, "audio_tag_id": 1 })
+            .options(IndexOptions::builder().unique(true).build())
+            .build();
+
+        Self::get_collection(db)
+            .create_index(stat_index, None)
+            .await?;
+
+        Ok(())
+    }
+}
+
+impl GetCollection<dto::PlayStat> for PlayStat {
+    fn get_collection(db: mongodb::Client) -> mongodb::Collection<dto::PlayStat> {
+        db.database("cirrus").collection::<dto::PlayStat>("play_stats")
+    }
+}
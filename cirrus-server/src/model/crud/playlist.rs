@@ -0,0 +1,12 @@
+use crate::{
+    model::{GetCollection, dto}
+};
+
+// Collection of the MongoDB storage, which is queried through `model::Storage`
+pub struct Playlist;
+
+impl GetCollection<dto::Playlist> for Playlist {
+    fn get_collection(db: mongodb::Client) -> mongodb::Collection<dto::Playlist> {
+        db.database("cirrus").collection::<dto::Playlist>("playlists")
+    }
+}
@@ -0,0 +1,12 @@
+use crate::{
+    model::{GetCollection, dto}
+};
+
+// Collection of the MongoDB storage, which is queried through `model::Storage`
+pub struct SmartPlaylist;
+
+impl GetCollection<dto::SmartPlaylist> for SmartPlaylist {
+    fn get_collection(db: mongodb::Client) -> mongodb::Collection<dto::SmartPlaylist> {
+        db.database("cirrus").collection::<dto::SmartPlaylist>("smart_playlists")
+    }
+}
@@ -1,14 +1,51 @@
+use bson::doc;
+use mongodb::{bson, options::IndexOptions, IndexModel};
+
 use crate::{
     model::{GetCollection, dto}
 };
 
-use super::{CrudMany, CrudSingle, PathOperation, Pagination};
+// Queries of tags are run through `model::Storage`, and this creates indexes of them
+pub struct AudioTag;
+
+impl AudioTag {
+    pub async fn create_indexes(db: mongodb::Client) -> Result<(), anyhow::Error> {
+        // text index (version 3) ignores case and diacritics, and "none" language disables stemming
+        // and stop words, as titles and names are written in various languages
+        let text_index = IndexModel::builder()
+            .keys(doc! {
+                "title": "text",
+                "artist": "text",
+                "album": "text",
+                "album_artist": "text",
+            })
+            .options(
+                IndexOptions::builder()
+                    .name("audio_tag_text_search".to_string())
+                    .default_language("none".to_string())
+                    .weights(doc! {
+                        "title": 10,
+                        "artist": 5,
+                        "album": 3,
+                        "album_artist": 3,
+                    })
+                    .build()
+            )
+            .build();
+
+        let filter_indexes = ["genre", "year", "duration"]
+            .iter()
+            .map(|key| IndexModel::builder().keys(doc! { *key: 1 }).build());
+
+        let mut indexes = vec![text_index];
+        indexes.extend(filter_indexes);
+
+        Self::get_collection(db)
+            .create_indexes(indexes, None)
+            .await?;
 
-pub struct AudioTag {
-    pub single: CrudSingle<dto::AudioTag>,
-    pub many: CrudMany<dto::AudioTag>,
-    pub path: PathOperation<dto::AudioTag>,
-    pub page: Pagination<dto::AudioTag>,
+        Ok(())
+    }
 }
 
 impl GetCollection<dto::AudioTag> for AudioTag {
@@ -17,14 +54,3 @@ impl GetCollection<dto::AudioTag> for AudioTag {
             .collection::<dto::AudioTag>("audio_tag")
     }
 }
-
-impl Default for AudioTag {
-    fn default() -> Self {
-        Self { 
-            single: CrudSingle::new(Self::get_collection), 
-            many: CrudMany::new(Self::get_collection), 
-            path: PathOperation::new(Self::get_collection),
-            page: Pagination::new(Self::get_collection),
-        }
-    }
-}
@@ -0,0 +1,30 @@
+use bson::doc;
+use mongodb::{bson, options::IndexOptions, IndexModel};
+
+use crate::{
+    model::{GetCollection, dto}
+};
+
+// Collection of the MongoDB storage, which is queried through `model::Storage`
+pub struct User;
+
+impl User {
+    pub async fn create_indexes(db: mongodb::Client) -> Result<(), anyhow::Error> {
+        let username_index = IndexModel::builder()
+            .keys(doc! { "username": 1 })
+            .options(IndexOptions::builder().unique(true).build())
+            .build();
+
+        Self::get_collection(db)
+            .create_index(username_index, None)
+            .await?;
+
+        Ok(())
+    }
+}
+
+impl GetCollection<dto::User> for User {
+    fn get_collection(db: mongodb::Client) -> mongodb::Collection<dto::User> {
+        db.database("cirrus").collection::<dto::User>("users")
+    }
+}
@@ -0,0 +1,7 @@
+use bson::{Document, doc};
+
+pub fn query_artwork(artwork_id: &str) -> Document {
+    doc! {
+        "_id": artwork_id
+    }
+}
@@ -10,4 +10,18 @@ pub fn query_audio_tag_referer(ref_id: &ObjectId) -> Document {
     doc! {
         "audio_tag_refer": ref_id
     }
-}
\ No newline at end of file
+}
+pub fn query_many_audio_tag_referer(ref_ids: &Vec<ObjectId>) -> Document {
+    doc! {
+        "audio_tag_refer": {
+            "$in": ref_ids
+        }
+    }
+}
+
+pub fn query_audio_file(parent_path: &str, filename: &str) -> Document {
+    doc! {
+        "parent_path": parent_path,
+        "filename": filename,
+    }
+}
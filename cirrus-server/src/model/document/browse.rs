@@ -0,0 +1,164 @@
+use bson::{Bson, Document, doc};
+
+// Tracks without album artist are grouped by their artist
+fn album_artist_expr() -> Bson {
+    Bson::Document(doc! {
+        "$ifNull": ["$album_artist", "$artist"]
+    })
+}
+
+pub fn query_album_tracks(album: &str, album_artist: &str) -> Document {
+    doc! {
+        "album": album,
+        "$or": [
+            { "album_artist": album_artist },
+            { "album_artist": null, "artist": album_artist },
+        ]
+    }
+}
+
+pub fn query_artist_albums(artist: &str) -> Document {
+    doc! {
+        "album": { "$ne": null },
+        "$or": [
+            { "album_artist": artist },
+            { "album_artist": null, "artist": artist },
+        ]
+    }
+}
+
+// Albums of other album artists that have tracks of the artist
+pub fn query_artist_appearances(artist: &str) -> Document {
+    doc! {
+        "album": { "$ne": null },
+        "artist": artist,
+        "album_artist": { "$nin": [null, artist] },
+    }
+}
+
+pub fn query_artist_tracks(artist: &str) -> Document {
+    doc! {
+        "artist": artist
+    }
+}
+
+// Results of `album`, `album_artist`, `year`, `track_count`, `disc_count`, `duration`, `artwork_id`
+// and `compilation`
+pub fn create_album_pipeline(query: Document, skip: Option<u64>, limit: Option<i64>) -> Vec<Document> {
+    let mut pipeline = vec![
+        doc! {
+            "$match": { "$and": [query, { "album": { "$ne": null } }] }
+        },
+        doc! {
+            "$group": {
+                "_id": { "album": "$album", "album_artist": album_artist_expr() },
+                "year": { "$max": "$year" },
+                "track_count": { "$sum": 1 },
+                "discs": { "$addToSet": "$disc" },
+                "duration": { "$sum": "$duration" },
+                "artists": { "$addToSet": "$artist" },
+                "artwork_id": { "$max": "$artwork_id" },
+            }
+        },
+        doc! {
+            "$project": {
+                "_id": 0,
+                "album": "$_id.album",
+                "album_artist": "$_id.album_artist",
+                "year": 1,
+                "track_count": 1,
+                "disc_count": { "$size": { "$setDifference": ["$discs", [null]] } },
+                "duration": 1,
+                "artwork_id": 1,
+                // tracks of a compilation have various artists under an album artist
+                "compilation": {
+                    "$gt": [{ "$size": { "$setDifference": ["$artists", [null]] } }, 1]
+                },
+            }
+        },
+        doc! {
+            "$sort": { "album_artist": 1, "year": 1, "album": 1 }
+        },
+    ];
+
+    if let Some(skip) = skip {
+        pipeline.push(doc! { "$skip": skip as i64 });
+    }
+    if let Some(limit) = limit {
+        pipeline.push(doc! { "$limit": limit });
+    }
+
+    pipeline
+}
+
+// Album keys (`_id.album` and `_id.album_artist`) of matched tracks
+pub fn create_album_key_pipeline(query: Document) -> Vec<Document> {
+    vec![
+        doc! {
+            "$match": query
+        },
+        doc! {
+            "$group": {
+                "_id": { "album": "$album", "album_artist": album_artist_expr() }
+            }
+        },
+    ]
+}
+
+// Artists are names of track artists and album artists.
+// Results of `name`, `album_count` and `track_count`
+pub fn create_artist_pipeline(skip: u64, limit: i64) -> Vec<Document> {
+    vec![
+        doc! {
+            "$project": {
+                "artist": 1,
+                "album": 1,
+                "album_artist": album_artist_expr(),
+            }
+        },
+        doc! {
+            "$project": {
+                "artist": 1,
+                "album": 1,
+                "album_artist": 1,
+                "names": { "$setUnion": [["$artist"], ["$album_artist"]] },
+            }
+        },
+        doc! {
+            "$unwind": "$names"
+        },
+        doc! {
+            "$match": { "names": { "$ne": null } }
+        },
+        doc! {
+            "$group": {
+                "_id": "$names",
+                "track_count": {
+                    "$sum": { "$cond": [{ "$eq": ["$artist", "$names"] }, 1, 0] }
+                },
+                "albums": {
+                    "$addToSet": {
+                        "$cond": [{ "$eq": ["$album_artist", "$names"] }, "$album", null]
+                    }
+                },
+            }
+        },
+        doc! {
+            "$sort": { "_id": 1 }
+        },
+        doc! {
+            "$skip": skip as i64
+        },
+        doc! {
+            "$limit": limit
+        },
+        doc! {
+            "$project": {
+                "_id": 0,
+                "name": "$_id",
+                "track_count": 1,
+                "album_count": { "$size": { "$setDifference": ["$albums", [null]] } },
+            }
+        },
+    ]
+}
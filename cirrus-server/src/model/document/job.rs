@@ -0,0 +1,7 @@
+use bson::{Document, doc};
+
+pub fn query_unfinished_jobs() -> Document {
+    doc! {
+        "status": { "$in": ["pending", "running"] }
+    }
+}
@@ -1,9 +1,17 @@
 use bson::{Document, doc, oid::ObjectId};
 use serde::Serialize;
 
+pub mod artwork;
 pub mod path;
 pub mod time;
 pub mod audio;
+pub mod job;
+pub mod tag;
+pub mod smart_playlist;
+pub mod browse;
+pub mod play_history;
+pub mod user;
+pub mod playlist;
 
 pub fn query_single_id(id: &ObjectId) -> Document {
     doc! {
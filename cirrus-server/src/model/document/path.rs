@@ -1,7 +1,20 @@
 use bson::{Document, doc};
 
+use crate::util;
+
+// Paths under the path, queried as a range rather than a regex, so that characters of paths are
+// not read as patterns
 pub fn query_path(key: &str, path: &str) -> Document {
     doc! {
-        key: { "$regex": format!("^{}", path) }
+        key: {
+            "$gte": path,
+            "$lt": util::path::get_materialized_upper_bound(path),
+        }
+    }
+}
+
+pub fn query_exact_path(key: &str, path: &str) -> Document {
+    doc! {
+        key: path
     }
-}
\ No newline at end of file
+}
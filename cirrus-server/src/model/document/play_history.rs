@@ -0,0 +1,97 @@
+use bson::{oid::ObjectId, Document, doc};
+
+// playbacks and stats without a user are of the server, where authentication is disabled
+pub fn query_playback(user_id: Option<&ObjectId>, playback_id: &str) -> Document {
+    doc! {
+        "user_id": user_id,
+        "playback_id": playback_id,
+    }
+}
+
+// matches the playback only if it is not counted as a play yet
+pub fn query_uncounted_playback(user_id: Option<&ObjectId>, playback_id: &str) -> Document {
+    doc! {
+        "user_id": user_id,
+        "playback_id": playback_id,
+        "listened_at": null,
+    }
+}
+
+pub fn query_play_stat(user_id: Option<&ObjectId>, audio_tag_id: &ObjectId) -> Document {
+    doc! {
+        "user_id": user_id,
+        "audio_tag_id": audio_tag_id,
+    }
+}
+
+pub fn query_play_stats(user_id: Option<&ObjectId>, audio_tag_ids: &[ObjectId]) -> Document {
+    doc! {
+        "user_id": user_id,
+        "audio_tag_id": { "$in": audio_tag_ids },
+    }
+}
+
+pub fn create_playback_update(position_ms: u64, finished: bool, listened_at: Option<i64>) -> Document {
+    let mut update = doc! {
+        "position_ms": position_ms as i64,
+    };
+
+    if finished {
+        update.insert("finished", true);
+    }
+
+    if let Some(listened_at) = listened_at {
+        update.insert("listened_at", listened_at);
+    }
+
+    doc! {
+        "$set": update
+    }
+}
+
+pub fn create_play_stat_update(timestamp: i64) -> Document {
+    doc! {
+        "$inc": { "play_count": 1_i64 },
+        "$max": { "last_played_timestamp": timestamp },
+    }
+}
+
+// counted plays of the user within the range, and 0 of `since` and `until` is unbounded
+pub fn query_play_history(
+    user_id: Option<&ObjectId>,
+    since: i64,
+    until: i64,
+    audio_tag_id: Option<&ObjectId>,
+) -> Document {
+    let mut listened_at = doc! {
+        "$ne": null
+    };
+
+    if since > 0 {
+        listened_at.insert("$gte", since);
+    }
+
+    if until > 0 {
+        listened_at.insert("$lte", until);
+    }
+
+    let mut query = doc! {
+        "user_id": user_id,
+        "listened_at": listened_at,
+    };
+
+    if let Some(audio_tag_id) = audio_tag_id {
+        query.insert("audio_tag_id", audio_tag_id);
+    }
+
+    query
+}
+
+pub fn sort_play_history(ascending: bool) -> Document {
+    let order = if ascending { 1 } else { -1 };
+
+    doc! {
+        "listened_at": order,
+        "_id": order,
+    }
+}
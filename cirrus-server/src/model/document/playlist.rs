@@ -0,0 +1,12 @@
+use bson::{oid::ObjectId, Document, doc};
+
+// playlists of the user, shared ones and ones without an owner
+pub fn query_visible_playlists(owner_id: &ObjectId) -> Document {
+    doc! {
+        "$or": [
+            { "owner_id": owner_id },
+            { "shared": true },
+            { "owner_id": null },
+        ]
+    }
+}
@@ -0,0 +1,212 @@
+use bson::{Bson, Document, doc};
+use chrono::Utc;
+
+use crate::model::dto::{SmartPlaylistOrder, SmartPlaylistOrderField, SmartRule};
+
+// `*` matches any characters and `?` matches a character, and other characters are matched as is
+fn glob_to_regex(pattern: &str) -> String {
+    let mut regex = String::from("^");
+
+    for c in pattern.chars() {
+        match c {
+            '*' => regex.push_str(".*"),
+            '?' => regex.push('.'),
+            c if "\\^$.|+()[]{}".contains(c) => {
+                regex.push('\\');
+                regex.push(c);
+            },
+            c => regex.push(c),
+        }
+    }
+
+    regex.push('$');
+    regex
+}
+
+pub fn query_smart_rule(rule: &SmartRule) -> Document {
+    match rule {
+        // $and and $or do not accept an empty array
+        SmartRule::All { rules } if rules.is_empty() => doc! {},
+        SmartRule::Any { rules } if rules.is_empty() => doc! { "$expr": false },
+        SmartRule::All { rules } => doc! {
+            "$and": rules.iter().map(query_smart_rule).collect::<Vec<_>>()
+        },
+        SmartRule::Any { rules } => doc! {
+            "$or": rules.iter().map(query_smart_rule).collect::<Vec<_>>()
+        },
+        SmartRule::GenreIn { genres } => doc! {
+            "genre": { "$in": genres }
+        },
+        SmartRule::YearBetween { min, max } => {
+            let mut range = doc! { "$ne": Bson::Null };
+
+            if let Some(min) = min {
+                range.insert("$gte", *min);
+            }
+            if let Some(max) = max {
+                range.insert("$lte", *max);
+            }
+
+            doc! { "year": range }
+        },
+        SmartRule::TextMatch { field, pattern } => doc! {
+            field.get_key(): {
+                "$regex": glob_to_regex(pattern),
+                "$options": "i",
+            }
+        },
+        SmartRule::AddedWithin { days } => {
+            let timestamp = Utc::now().timestamp() - *days as i64 * 24 * 60 * 60;
+
+            doc! {
+                "added_timestamp": { "$gte": timestamp }
+            }
+        },
+    }
+}
+
+// Joins audio files of tags, which keep the added time. Files which are added by older versions do
+// not have it, and the creation time of the id is used instead, as `dto::AudioFile`
+pub fn create_added_timestamp_stages() -> Vec<Document> {
+    vec![
+        doc! {
+            "$lookup": {
+                "from": "audio",
+                "localField": "_id",
+                "foreignField": "audio_tag_refer",
+                "as": "audio_file",
+            }
+        },
+        doc! {
+            "$addFields": {
+                "added_timestamp": {
+                    "$ifNull": [
+                        { "$arrayElemAt": ["$audio_file.added_timestamp", 0] },
+                        { "$divide": [{ "$toLong": { "$toDate": { "$arrayElemAt": ["$audio_file._id", 0] } } }, 1000] },
+                    ]
+                }
+            }
+        },
+    ]
+}
+
+// ties are ordered by the ids of tags, and the added order requires `create_added_timestamp_stages`
+pub fn sort_smart_playlist(order: &SmartPlaylistOrder) -> Document {
+    let direction = if order.descending { -1 } else { 1 };
+
+    match order.field {
+        SmartPlaylistOrderField::Added => doc! { "added_timestamp": direction, "_id": 1 },
+        SmartPlaylistOrderField::Title => doc! { "title": direction, "_id": 1 },
+        SmartPlaylistOrderField::Artist => doc! { "artist": direction, "album": 1, "disc": 1, "track": 1, "_id": 1 },
+        SmartPlaylistOrderField::Album => doc! { "album": direction, "disc": 1, "track": 1, "_id": 1 },
+        SmartPlaylistOrderField::Year => doc! { "year": direction, "_id": 1 },
+        SmartPlaylistOrderField::Duration => doc! { "duration": direction, "_id": 1 },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bson::{Document, doc};
+    use chrono::Utc;
+
+    use crate::model::dto::{SmartPlaylistOrder, SmartPlaylistOrderField, SmartRule, SmartRuleTextField};
+
+    use super::{glob_to_regex, query_smart_rule, sort_smart_playlist};
+
+    #[test]
+    fn translate_glob_to_regex() {
+        assert_eq!(glob_to_regex("live*"), "^live.*$");
+        assert_eq!(glob_to_regex("?at"), "^.at$");
+        // special characters of regex are matched as is
+        assert_eq!(glob_to_regex("a.b (c)+[d]"), "^a\\.b \\(c\\)\\+\\[d\\]$");
+        assert_eq!(glob_to_regex("$^|{}\\"), "^\\$\\^\\|\\{\\}\\\\$");
+    }
+
+    #[test]
+    fn query_leaf_rules() {
+        assert_eq!(
+            query_smart_rule(&SmartRule::GenreIn { genres: vec!["Jazz".to_string(), "Rock".to_string()] }),
+            doc! { "genre": { "$in": ["Jazz", "Rock"] } }
+        );
+        assert_eq!(
+            query_smart_rule(&SmartRule::TextMatch { field: SmartRuleTextField::AlbumArtist, pattern: "The *".to_string() }),
+            doc! { "album_artist": { "$regex": "^The .*$", "$options": "i" } }
+        );
+    }
+
+    #[test]
+    fn query_year_range() {
+        assert_eq!(
+            query_smart_rule(&SmartRule::YearBetween { min: Some(1990), max: Some(1999) }),
+            doc! { "year": { "$ne": null, "$gte": 1990, "$lte": 1999 } }
+        );
+        assert_eq!(
+            query_smart_rule(&SmartRule::YearBetween { min: None, max: Some(1999) }),
+            doc! { "year": { "$ne": null, "$lte": 1999 } }
+        );
+        // tags without a year are not matched by an open range
+        assert_eq!(
+            query_smart_rule(&SmartRule::YearBetween { min: None, max: None }),
+            doc! { "year": { "$ne": null } }
+        );
+    }
+
+    #[test]
+    fn query_added_within() {
+        let now = Utc::now().timestamp();
+        let query = query_smart_rule(&SmartRule::AddedWithin { days: 7 });
+
+        let timestamp = query
+            .get_document("added_timestamp").unwrap()
+            .get_i64("$gte").unwrap();
+
+        assert!((now - 7 * 24 * 60 * 60 - timestamp).abs() <= 1);
+    }
+
+    #[test]
+    fn query_rule_groups() {
+        let rule = SmartRule::All {
+            rules: vec![
+                SmartRule::GenreIn { genres: vec!["Jazz".to_string()] },
+                SmartRule::Any {
+                    rules: vec![
+                        SmartRule::YearBetween { min: Some(1960), max: None },
+                        SmartRule::TextMatch { field: SmartRuleTextField::Artist, pattern: "Miles*".to_string() },
+                    ],
+                },
+            ],
+        };
+
+        assert_eq!(query_smart_rule(&rule), doc! {
+            "$and": [
+                { "genre": { "$in": ["Jazz"] } },
+                {
+                    "$or": [
+                        { "year": { "$ne": null, "$gte": 1960 } },
+                        { "artist": { "$regex": "^Miles.*$", "$options": "i" } },
+                    ]
+                },
+            ]
+        });
+    }
+
+    #[test]
+    fn query_empty_rule_groups() {
+        // an empty `all` matches every tag, and an empty `any` matches none
+        assert_eq!(query_smart_rule(&SmartRule::All { rules: vec![] }), Document::new());
+        assert_eq!(query_smart_rule(&SmartRule::Any { rules: vec![] }), doc! { "$expr": false });
+        assert_eq!(
+            query_smart_rule(&SmartRule::Any { rules: vec![SmartRule::All { rules: vec![] }] }),
+            doc! { "$or": [{}] }
+        );
+    }
+
+    #[test]
+    fn sort_with_tie_breaker() {
+        let order = SmartPlaylistOrder { field: SmartPlaylistOrderField::Year, descending: true };
+        assert_eq!(sort_smart_playlist(&order), doc! { "year": -1, "_id": 1 });
+
+        let order = SmartPlaylistOrder { field: SmartPlaylistOrderField::Artist, descending: false };
+        assert_eq!(sort_smart_playlist(&order), doc! { "artist": 1, "album": 1, "disc": 1, "track": 1, "_id": 1 });
+    }
+}
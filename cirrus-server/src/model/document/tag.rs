@@ -0,0 +1,105 @@
+use bson::{Bson, Document, doc};
+
+// facets with many values (e.g. genres) return only the most frequent ones
+const MAX_FACET_VALUES: i64 = 50;
+
+pub struct SearchFilter<'a> {
+    pub query: &'a str,
+    pub genres: &'a [String],
+    pub year_range: (Option<i32>, Option<i32>),
+    pub duration_range: (Option<u32>, Option<u32>),
+}
+
+fn create_range_filter(key: &str, min: Option<Bson>, max: Option<Bson>) -> Option<Document> {
+    let mut range = Document::new();
+
+    if let Some(min) = min {
+        range.insert("$gte", min);
+    }
+    if let Some(max) = max {
+        range.insert("$lte", max);
+    }
+
+    (!range.is_empty()).then(|| doc! { key: range })
+}
+
+fn create_match_stage(filters: &[&Option<Document>]) -> Document {
+    let mut query = Document::new();
+
+    for filter in filters.iter().filter_map(|item| item.as_ref()) {
+        query.extend(filter.clone());
+    }
+
+    doc! {
+        "$match": query
+    }
+}
+
+// Results of a single document: `hits`, `total`, `genres` and `years`
+pub fn create_search_pipeline(filter: &SearchFilter, skip: u64, limit: i64) -> Vec<Document> {
+    let has_query = !filter.query.trim().is_empty();
+    let mut pipeline = Vec::new();
+
+    // text search should be the first stage
+    if has_query {
+        pipeline.push(doc! {
+            "$match": { "$text": { "$search": filter.query } }
+        });
+        pipeline.push(doc! {
+            "$addFields": { "score": { "$meta": "textScore" } }
+        });
+    } else {
+        pipeline.push(doc! {
+            "$addFields": { "score": 0.0 }
+        });
+    }
+
+    let genre_filter = (!filter.genres.is_empty())
+        .then(|| doc! { "genre": { "$in": filter.genres } });
+    let year_filter = create_range_filter(
+        "year",
+        filter.year_range.0.map(Bson::from),
+        filter.year_range.1.map(Bson::from),
+    );
+    let duration_filter = create_range_filter(
+        "duration",
+        filter.duration_range.0.map(Bson::from),
+        filter.duration_range.1.map(Bson::from),
+    );
+
+    let hits_sort = if has_query {
+        doc! { "score": -1, "_id": 1 }
+    } else {
+        doc! { "artist": 1, "album": 1, "disc": 1, "track": 1, "_id": 1 }
+    };
+
+    pipeline.push(doc! {
+        "$facet": {
+            "hits": [
+                create_match_stage(&[&genre_filter, &year_filter, &duration_filter]),
+                { "$sort": hits_sort },
+                { "$skip": skip as i64 },
+                { "$limit": limit },
+            ],
+            "total": [
+                create_match_stage(&[&genre_filter, &year_filter, &duration_filter]),
+                { "$count": "count" },
+            ],
+            "genres": [
+                create_match_stage(&[&year_filter, &duration_filter]),
+                { "$group": { "_id": "$genre", "count": { "$sum": 1 } } },
+                { "$match": { "_id": { "$ne": null } } },
+                { "$sort": { "count": -1, "_id": 1 } },
+                { "$limit": MAX_FACET_VALUES },
+            ],
+            "years": [
+                create_match_stage(&[&genre_filter, &duration_filter]),
+                { "$group": { "_id": "$year", "count": { "$sum": 1 } } },
+                { "$match": { "_id": { "$ne": null } } },
+                { "$sort": { "_id": -1 } },
+            ],
+        }
+    });
+
+    pipeline
+}
@@ -0,0 +1,13 @@
+use bson::{Document, doc};
+
+pub fn query_username(username: &str) -> Document {
+    doc! {
+        "username": username
+    }
+}
+
+pub fn create_revoke_tokens_update() -> Document {
+    doc! {
+        "$inc": { "token_version": 1_i64 }
+    }
+}
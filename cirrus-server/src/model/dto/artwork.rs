@@ -0,0 +1,107 @@
+use std::{fs, io::Cursor, path::{Path, PathBuf}};
+
+use image::ImageFormat;
+use mongodb::bson::{spec::BinarySubtype, Binary};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::warn;
+
+// names of artwork files placed beside audio files, compared without case
+const SIDECAR_ARTWORK_NAMES: [&'static str; 4] = ["cover", "folder", "front", "album"];
+const SIDECAR_ARTWORK_EXTENSIONS: [&'static str; 3] = ["jpg", "jpeg", "png"];
+// artwork is stored in a single document, which is limited to 16MB
+const MAX_ARTWORK_SIZE: usize = 15 * 1024 * 1024;
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Artwork {
+    // SHA-256 of the image data, so that the same artwork of tracks is stored once
+    #[serde(rename = "_id")]
+    pub id: String,
+    pub mime_type: String,
+    pub width: u32,
+    pub height: u32,
+    pub data: Binary,
+}
+
+impl Artwork {
+    pub fn new(data: Vec<u8>) -> Result<Self, anyhow::Error> {
+        if data.len() > MAX_ARTWORK_SIZE {
+            return Err(anyhow::anyhow!("artwork is larger than {} bytes", MAX_ARTWORK_SIZE));
+        }
+
+        let format = image::guess_format(&data)?;
+        let mime_type = match format {
+            ImageFormat::Jpeg => "image/jpeg",
+            ImageFormat::Png => "image/png",
+            _ => return Err(anyhow::anyhow!("unsupported artwork format: {:?}", format)),
+        };
+
+        let (width, height) = image::io::Reader::with_format(Cursor::new(&data), format)
+            .into_dimensions()?;
+
+        let id = format!("{:x}", Sha256::digest(&data));
+
+        Ok(Self {
+            id,
+            mime_type: mime_type.to_string(),
+            width,
+            height,
+            data: Binary {
+                subtype: BinarySubtype::Generic,
+                bytes: data,
+            },
+        })
+    }
+
+    // Reads artwork file (e.g. `cover.jpg`, `folder.png`) in the directory of audio files
+    pub fn read_sidecar(audio_dir_path: &Path) -> Option<Self> {
+        let entries = fs::read_dir(audio_dir_path).ok()?;
+
+        let mut sidecar_paths = entries
+            .filter_map(|item| item.ok())
+            .map(|item| item.path())
+            .filter_map(|path| {
+                let stem = path.file_stem()?.to_str()?.to_lowercase();
+                let extension = path.extension()?.to_str()?.to_lowercase();
+
+                let name_priority = SIDECAR_ARTWORK_NAMES.iter().position(|item| *item == stem)?;
+                SIDECAR_ARTWORK_EXTENSIONS.contains(&extension.as_str()).then(|| (name_priority, path))
+            })
+            .collect::<Vec<_>>();
+
+        sidecar_paths.sort();
+
+        sidecar_paths
+            .into_iter()
+            .find_map(|(_, path)| {
+                let data = fs::read(&path).ok()?;
+
+                match Self::new(data) {
+                    Ok(artwork) => Some(artwork),
+                    Err(err) => {
+                        warn!("failed to read artwork {:?}: {}", path, err);
+                        None
+                    },
+                }
+            })
+    }
+}
+
+// Sidecar artwork of the last directory, so that a directory is read once if its audio files are
+// read in a row
+#[derive(Default)]
+pub struct SidecarArtwork {
+    audio_dir_path: Option<PathBuf>,
+    artwork: Option<Artwork>,
+}
+
+impl SidecarArtwork {
+    pub fn get(&mut self, audio_dir_path: &Path) -> Option<Artwork> {
+        if self.audio_dir_path.as_deref() != Some(audio_dir_path) {
+            self.artwork = Artwork::read_sidecar(audio_dir_path);
+            self.audio_dir_path = Some(audio_dir_path.to_path_buf());
+        }
+
+        self.artwork.clone()
+    }
+}
@@ -7,9 +7,13 @@ use aiff::reader::AiffReader;
 use chrono::{DateTime, Utc, TimeZone};
 use mongodb::bson::oid::ObjectId;
 use serde::{Deserialize, Serialize};
+use symphonia::core::meta::{StandardTagKey, StandardVisualKey, Tag, Visual};
+use tracing::warn;
 
 use crate::util;
 
+use super::{Artwork, SidecarArtwork};
+
 // pub type PathProp<'a> = (&'a str, &'a str);
 
 // pub trait GetMaterializedPath {
@@ -31,27 +35,36 @@ pub struct AudioLibrary {
     pub materialized_path: String,
     pub os_path: String,
     pub modified_timestamp: i64,
+    // changes under a library root are synced by the file system watcher
+    #[serde(default = "default_watch")]
+    pub watch: bool,
+}
+
+fn default_watch() -> bool {
+    true
 }
 
 impl AudioLibrary {
-    pub fn new(path: &Path) -> Self {
-        let os_path = util::path::replace_with_common_separator(path.to_str().unwrap());
-        let materialized_path = util::path::path_to_materialized(&path);
+    pub fn new(path: &Path) -> Result<Self, anyhow::Error> {
+        let materialized_path = util::path::path_to_materialized(&path)?;
+        // the path is valid unicode, as it is materialized above
+        let os_path = util::path::replace_with_common_separator(&path.to_string_lossy());
 
-        let modified_timestamp = util::path::get_timestamp(&path);
+        let modified_timestamp = util::path::get_timestamp(&path)?;
         
-        Self {
+        Ok(Self {
             id: Some(mongodb::bson::oid::ObjectId::new()),
             materialized_path,
             os_path: os_path,
             modified_timestamp,
-        }
+            watch: default_watch(),
+        })
     }
 
-    pub fn check_modified(&self) -> bool {
-        let local_timestamp = util::path::get_timestamp(Path::new(&self.os_path));
+    pub fn check_modified(&self) -> Result<bool, anyhow::Error> {
+        let local_timestamp = util::path::get_timestamp(Path::new(&self.os_path))?;
 
-        local_timestamp != self.modified_timestamp
+        Ok(local_timestamp != self.modified_timestamp)
     }
 }
 
@@ -82,30 +95,42 @@ pub struct AudioFile {
     pub parent_path: String,
     pub filename: String,
     pub audio_tag_refer: Option<ObjectId>,
+    // time when the file is first seen, and it is kept when the file is renamed
+    #[serde(default)]
+    pub added_timestamp: Option<i64>,
 }
 
 impl AudioFile {
-    pub fn new(path: &Path) -> Self {
-        let parent_path = path.parent().unwrap();
-        let parent_path = util::path::path_to_materialized(&parent_path);
-
-        let modified_timestamp = util::path::get_timestamp(path);
+    pub fn new(path: &Path) -> Result<Self, anyhow::Error> {
+        let (parent_path, filename) = match (path.parent(), path.file_name().and_then(|item| item.to_str())) {
+            (Some(parent_path), Some(filename)) => (parent_path, filename),
+            _ => return Err(anyhow::anyhow!("path {:?} is not a valid audio file path", path)),
+        };
+        let parent_path = util::path::path_to_materialized(&parent_path)?;
 
-        let filename = path.file_name().unwrap().to_str().unwrap().to_string();
+        let modified_timestamp = util::path::get_timestamp(path)?;
 
-        Self {
+        Ok(Self {
             id: Some(mongodb::bson::oid::ObjectId::new()),
             modified_timestamp,
             parent_path,
-            filename,
+            filename: filename.to_string(),
             audio_tag_refer: None,
-        }
+            added_timestamp: Some(Utc::now().timestamp()),
+        })
+    }
+
+    // Files which are added by older versions do not have the added time, and the creation time
+    // of the id is used instead
+    pub fn get_added_timestamp(&self) -> Option<i64> {
+        self.added_timestamp
+            .or_else(|| self.id.map(|id| id.timestamp().timestamp_millis() / 1000))
     }
 
-    pub fn check_modified(&self) -> bool {
-        let local_timestamp = util::path::get_timestamp(&self.get_os_path());
+    pub fn check_modified(&self) -> Result<bool, anyhow::Error> {
+        let local_timestamp = util::path::get_timestamp(&self.get_os_path())?;
 
-        local_timestamp != self.modified_timestamp
+        Ok(local_timestamp != self.modified_timestamp)
     }
 
     pub fn get_os_path(&self) -> PathBuf {
@@ -115,10 +140,12 @@ impl AudioFile {
         parent_path.join(&self.filename)
     }
 
-    pub fn update_modified_timestamp(&mut self) {
+    pub fn update_modified_timestamp(&mut self) -> Result<(), anyhow::Error> {
         let path = self.get_os_path();
         
-        self.modified_timestamp = util::path::get_timestamp(&path);
+        self.modified_timestamp = util::path::get_timestamp(&path)?;
+
+        Ok(())
     }
 }
 
@@ -161,6 +188,11 @@ pub struct AudioTag {
     pub total_tracks: Option<u32>,
     pub track: Option<u32>,
     pub year: Option<i32>,
+    pub artwork_id: Option<String>,
+
+    // stored in its own collection, and referred by `artwork_id`
+    #[serde(skip)]
+    pub artwork: Option<Artwork>,
 }
 
 impl AudioTag {
@@ -168,122 +200,269 @@ impl AudioTag {
         id: Option<ObjectId>,
         parent_path: &str,
         filename: &str,
+        sidecar_artwork: &mut SidecarArtwork,
     ) -> Result<Self, anyhow::Error> {
         let mut audio_file_path = Path::new(parent_path).to_path_buf();
         audio_file_path.push(filename);
 
-        let audio_file = File::open(audio_file_path).unwrap();
-        let mut aiff = AiffReader::new(audio_file);
-        // aiff.read().unwrap();
-        aiff.parse().unwrap();
-
-        // let id3v2 = aiff.read_chunk::<aiff::chunks::ID3v2Chunk>(true, false, aiff::ids::AIFF).unwrap();
-
         // let id = Some(ObjectId::new());
         let id = match id {
             Some(id) => Some(id),
             None => Some(ObjectId::new())
         };
 
+        let is_aiff = match audio_file_path.extension().and_then(|item| item.to_str()) {
+            Some(extension) => extension.eq_ignore_ascii_case("aiff") || extension.eq_ignore_ascii_case("aif"),
+            None => false,
+        };
+
+        // AIFF stores ID3v2 tag in its own chunk, which is not exposed by the symphonia reader
+        let audio_tag = if is_aiff {
+            Self::read_aiff_id3v2(id, &audio_file_path)?
+        } else {
+            Self::read_probed_metadata(id, &audio_file_path)?
+        };
+
+        let has_tags = audio_tag.is_some();
+        let mut audio_tag = match audio_tag {
+            Some(mut audio_tag) => {
+                if audio_tag.title.is_none() {
+                    audio_tag.title = Some(filename.to_owned());
+                }
+
+                audio_tag
+            },
+            None => Self {
+                id,
+                property_hash: None,
+                title: Some(filename.to_owned()),
+                ..Default::default()
+            },
+        };
+
+        // embedded artwork takes precedence over a sidecar file
+        if audio_tag.artwork.is_none() {
+            audio_tag.artwork = sidecar_artwork.get(Path::new(parent_path));
+        }
+        audio_tag.artwork_id = audio_tag.artwork.as_ref().map(|item| item.id.clone());
+
+        if has_tags {
+            audio_tag.property_hash = Some(util::hash::get_hashed_value(&audio_tag));
+        }
+
+        Ok(audio_tag)
+    }
+
+    fn read_embedded_artwork(audio_file_path: &Path, data: Vec<u8>) -> Option<Artwork> {
+        match Artwork::new(data) {
+            Ok(artwork) => Some(artwork),
+            Err(err) => {
+                warn!("failed to read embedded artwork of {:?}: {}", audio_file_path, err);
+                None
+            },
+        }
+    }
+
+    fn read_aiff_id3v2(
+        id: Option<ObjectId>,
+        audio_file_path: &Path,
+    ) -> Result<Option<Self>, anyhow::Error> {
+        let audio_file = File::open(audio_file_path)?;
+        let mut aiff = AiffReader::new(audio_file);
+        // aiff.read().unwrap();
+        aiff.parse().unwrap();
+
+        // let id3v2 = aiff.read_chunk::<aiff::chunks::ID3v2Chunk>(true, false, aiff::ids::AIFF).unwrap();
+
         let mut id_id3v2 = aiff::ids::ID3.to_vec();
         id_id3v2.push(0);
 
-        let _audio_metadata = if let Some(id3v2) = aiff.read_chunk::<aiff::chunks::ID3v2Chunk>(true, false, &id_id3v2) {
-            let date_recorded = match id3v2.tag.date_recorded() {
-                Some(datetime) => {
-                    let month = datetime.month.unwrap_or_else(|| 1u8);
-                    let day = datetime.day.unwrap_or_else(|| 1u8);
-                    let hour = datetime.hour.unwrap_or_else(|| 0u8);
-                    let minute = datetime.minute.unwrap_or_else(|| 0u8);
-                    let second = datetime.second.unwrap_or_else(|| 0u8);
+        let id3v2 = match aiff.read_chunk::<aiff::chunks::ID3v2Chunk>(true, false, &id_id3v2) {
+            Some(id3v2) => id3v2,
+            None => return Ok(None),
+        };
 
-                    Some(Utc.ymd(datetime.year, month.into(), day.into()).and_hms(hour.into(), minute.into(), second.into()))
-                },
-                None => None,
-            };
+        let date_recorded = match id3v2.tag.date_recorded() {
+            Some(datetime) => {
+                let month = datetime.month.unwrap_or_else(|| 1u8);
+                let day = datetime.day.unwrap_or_else(|| 1u8);
+                let hour = datetime.hour.unwrap_or_else(|| 0u8);
+                let minute = datetime.minute.unwrap_or_else(|| 0u8);
+                let second = datetime.second.unwrap_or_else(|| 0u8);
+
+                Some(Utc.ymd(datetime.year, month.into(), day.into()).and_hms(hour.into(), minute.into(), second.into()))
+            },
+            None => None,
+        };
 
-            let date_released = match id3v2.tag.date_released() {
-                Some(datetime) => {
-                    let month = datetime.month.unwrap_or_else(|| 1u8);
-                    let day = datetime.day.unwrap_or_else(|| 1u8);
-                    let hour = datetime.hour.unwrap_or_else(|| 0u8);
-                    let minute = datetime.minute.unwrap_or_else(|| 0u8);
-                    let second = datetime.second.unwrap_or_else(|| 0u8);
+        let date_released = match id3v2.tag.date_released() {
+            Some(datetime) => {
+                let month = datetime.month.unwrap_or_else(|| 1u8);
+                let day = datetime.day.unwrap_or_else(|| 1u8);
+                let hour = datetime.hour.unwrap_or_else(|| 0u8);
+                let minute = datetime.minute.unwrap_or_else(|| 0u8);
+                let second = datetime.second.unwrap_or_else(|| 0u8);
+
+                Some(Utc.ymd(datetime.year, month.into(), day.into()).and_hms(hour.into(), minute.into(), second.into()))
+            },
+            None => None,
+        };
 
-                    Some(Utc.ymd(datetime.year, month.into(), day.into()).and_hms(hour.into(), minute.into(), second.into()))
-                },
-                None => None,
-            };
+        let artwork = id3v2.tag.pictures()
+            .next()
+            .and_then(|item| Self::read_embedded_artwork(audio_file_path, item.data.to_owned()));
+
+        Ok(Some(Self {
+            id,
+            property_hash: None,
+            artist: id3v2.tag.artist().map(|item| item.to_owned()),
+            album: id3v2.tag.album().map(|item| item.to_owned()),
+            album_artist: id3v2.tag.album_artist().map(|item| item.to_owned()),
+            date_recorded,
+            date_released,
+            disc: id3v2.tag.disc(),
+            duration: id3v2.tag.duration(),
+            genre: id3v2.tag.genre().map(|item| item.to_owned()),
+            artwork,
+            // set after sidecar artwork is resolved
+            artwork_id: None,
+            title: id3v2.tag.title().map(|item| item.to_owned()),
+            total_discs: id3v2.tag.total_discs(),
+            total_tracks: id3v2.tag.total_tracks(),
+            track: id3v2.tag.track(),
+            year: id3v2.tag.year(),
+        }))
+    }
 
-            // let pictures: Vec<_> = id3v2.tag.pictures()
-            //     .into_iter()
-            //     .map(|item| document::audio::AudioFileMetadataPicture {
-            //         description: item.description.clone(),
-            //         mime_type: item.mime_type.clone(),
-            //         picture_type: item.picture_type.to_string(),
-            //         data: item.data.to_owned(),
-            //     })
-            //     .collect();
-
-            let artist = match id3v2.tag.artist() {
-                Some(item) => Some(item.to_owned()),
-                None => None,
-            };
+    // Reads tags found by probing the container: Vorbis comments (FLAC, Ogg), 
+    // ID3v1/ID3v2 (MP3), MP4 atoms (ALAC, AAC) and RIFF INFO (WAV)
+    fn read_probed_metadata(
+        id: Option<ObjectId>,
+        audio_file_path: &Path,
+    ) -> Result<Option<Self>, anyhow::Error> {
+        let mut probed = util::audio::probe_file(audio_file_path)?;
+
+        let mut tags: Vec<Tag> = Vec::new();
+        let mut visuals: Vec<Visual> = Vec::new();
+
+        // tags placed in front of the container (e.g. ID3v2 of MP3) 
+        if let Some(metadata) = probed.metadata.get() {
+            if let Some(revision) = metadata.current() {
+                tags.extend_from_slice(revision.tags());
+                visuals.extend_from_slice(revision.visuals());
+            }
+        }
 
-            let album = match id3v2.tag.album() {
-                Some(item) => Some(item.to_owned()),
-                None => None,
-            };
+        // tags of the container itself
+        if let Some(revision) = probed.format.metadata().current() {
+            tags.extend_from_slice(revision.tags());
+            visuals.extend_from_slice(revision.visuals());
+        }
 
-            let album_artist = match id3v2.tag.album_artist() {
-                Some(item) => Some(item.to_owned()),
-                None => None,
-            };
+        let artwork = visuals
+            .iter()
+            .find(|item| item.usage == Some(StandardVisualKey::FrontCover))
+            .or_else(|| visuals.first())
+            .and_then(|item| Self::read_embedded_artwork(audio_file_path, item.data.to_vec()));
 
-            let genre = match id3v2.tag.genre() {
-                Some(item) => Some(item.to_owned()),
-                None => None,
-            };
+        let duration = {
+            let track = util::audio::find_audio_track(probed.format.as_ref())?;
 
-            let title = match id3v2.tag.title() {
-                Some(item) => Some(item.to_owned()),
-                None => None,
-            };
+            match (track.codec_params.n_frames, track.codec_params.sample_rate) {
+                (Some(n_frames), Some(sample_rate)) => Some((n_frames * 1000 / sample_rate as u64) as u32),
+                _ => None,
+            }
+        };
 
-            let mut audio_tag = Self {
-                id,
-                property_hash: None,
-                artist: artist,
-                album: album,
-                album_artist: album_artist,
-                date_recorded,
-                date_released,
-                disc: id3v2.tag.disc(),
-                duration: id3v2.tag.duration(),
-                genre: genre,
-                // pictures: pictures,
-                title: title,
-                total_discs: id3v2.tag.total_discs(),
-                total_tracks: id3v2.tag.total_tracks(),
-                track: id3v2.tag.track(),
-                year: id3v2.tag.year(),
+        let mut audio_tag = Self {
+            id,
+            duration,
+            artwork,
+            ..Default::default()
+        };
+
+        for tag in tags.iter() {
+            let std_key = match tag.std_key {
+                Some(std_key) => std_key,
+                None => continue,
             };
 
-            audio_tag.property_hash = Some(util::hash::get_hashed_value(&audio_tag));
+            let value = tag.value.to_string();
+            let value = value.trim();
+
+            if value.is_empty() {
+                continue;
+            }
+
+            match std_key {
+                StandardTagKey::TrackTitle => audio_tag.title = Some(value.to_owned()),
+                StandardTagKey::Artist => audio_tag.artist = Some(value.to_owned()),
+                StandardTagKey::Album => audio_tag.album = Some(value.to_owned()),
+                StandardTagKey::AlbumArtist => audio_tag.album_artist = Some(value.to_owned()),
+                StandardTagKey::Genre => audio_tag.genre = Some(value.to_owned()),
+                StandardTagKey::Date => {
+                    audio_tag.date_recorded = parse_tag_date(value);
+                    audio_tag.year = parse_tag_year(value).or(audio_tag.year);
+                },
+                StandardTagKey::ReleaseDate => {
+                    audio_tag.date_released = parse_tag_date(value);
+                },
+                StandardTagKey::OriginalDate => {
+                    if audio_tag.year.is_none() {
+                        audio_tag.year = parse_tag_year(value);
+                    }
+                },
+                StandardTagKey::TrackNumber => {
+                    let (track, total_tracks) = parse_tag_number_pair(value);
 
-            return Ok(audio_tag)
+                    audio_tag.track = track;
+                    audio_tag.total_tracks = total_tracks.or(audio_tag.total_tracks);
+                },
+                StandardTagKey::TrackTotal => audio_tag.total_tracks = value.parse().ok(),
+                StandardTagKey::DiscNumber => {
+                    let (disc, total_discs) = parse_tag_number_pair(value);
 
-        } else {
-            return Ok(Self {
-                id,
-                property_hash: None,
-                title: Some(filename.clone().to_owned()),
-                ..Default::default()
-            });
-        };
+                    audio_tag.disc = disc;
+                    audio_tag.total_discs = total_discs.or(audio_tag.total_discs);
+                },
+                StandardTagKey::DiscTotal => audio_tag.total_discs = value.parse().ok(),
+                _ => (),
+            }
+        }
+
+        Ok(Some(audio_tag))
     }
 }
 
+// e.g. "2001", "2001-07", "2001-07-15", "2001-07-15T10:00:00"
+fn parse_tag_date(value: &str) -> Option<DateTime<Utc>> {
+    let date = value.split(|c| c == 'T' || c == ' ').next()?;
+    let mut date_parts = date.split('-');
+
+    let year = date_parts.next()?.parse::<i32>().ok()?;
+    let month = date_parts.next().and_then(|item| item.parse::<u32>().ok()).unwrap_or(1);
+    let day = date_parts.next().and_then(|item| item.parse::<u32>().ok()).unwrap_or(1);
+
+    Utc.ymd_opt(year, month, day)
+        .single()
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+}
+
+fn parse_tag_year(value: &str) -> Option<i32> {
+    value.get(..4)
+        .and_then(|item| item.parse::<i32>().ok())
+}
+
+// e.g. "3", "3/12"
+fn parse_tag_number_pair(value: &str) -> (Option<u32>, Option<u32>) {
+    let mut parts = value.split('/');
+
+    let number = parts.next().and_then(|item| item.trim().parse().ok());
+    let total = parts.next().and_then(|item| item.trim().parse().ok());
+
+    (number, total)
+}
+
 impl Hash for AudioTag {
     fn hash<H: Hasher>(&self, state: &mut H) {
         self.artist.hash(state);
@@ -292,5 +471,6 @@ impl Hash for AudioTag {
         self.date_released.hash(state);
         self.title.hash(state);
         self.year.hash(state);
+        self.artwork_id.hash(state);
     }
 }
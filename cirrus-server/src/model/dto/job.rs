@@ -0,0 +1,71 @@
+use chrono::Utc;
+use mongodb::bson::oid::ObjectId;
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum JobKind {
+    AnalyzeAudioLibrary,
+    RefreshAudioLibrary,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Pending,
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct JobFileError {
+    pub path: String,
+    pub error: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Job {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub kind: JobKind,
+    pub status: JobStatus,
+
+    pub files_scanned: i64,
+    pub tags_written: i64,
+    pub error_count: i64,
+    pub file_errors: Vec<JobFileError>,
+    pub message: Option<String>,
+
+    pub created_timestamp: i64,
+    pub updated_timestamp: i64,
+}
+
+impl Job {
+    pub fn new(kind: JobKind) -> Self {
+        let timestamp = Utc::now().timestamp();
+
+        Self {
+            id: Some(ObjectId::new()),
+            kind,
+            status: JobStatus::Pending,
+
+            files_scanned: 0,
+            tags_written: 0,
+            error_count: 0,
+            file_errors: Vec::new(),
+            message: None,
+
+            created_timestamp: timestamp,
+            updated_timestamp: timestamp,
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        match self.status {
+            JobStatus::Pending | JobStatus::Running => false,
+            JobStatus::Completed | JobStatus::Failed | JobStatus::Cancelled => true,
+        }
+    }
+}
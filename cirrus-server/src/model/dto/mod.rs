@@ -1,3 +1,17 @@
+mod artwork;
 mod audio;
+mod job;
+mod play_history;
+mod playlist;
+mod smart_playlist;
+mod user;
 
-pub use self::audio::{AudioFile, AudioLibrary, AudioTag, GetPathKey, GetPathValue};
\ No newline at end of file
+pub use self::artwork::{Artwork, SidecarArtwork};
+pub use self::audio::{AudioFile, AudioLibrary, AudioTag, GetPathKey, GetPathValue};
+pub use self::job::{Job, JobFileError, JobKind, JobStatus};
+pub use self::play_history::{PlayEvent, PlaySource, PlayStat};
+pub use self::playlist::{Playlist, PlaylistEntry};
+pub use self::smart_playlist::{
+    SmartPlaylist, SmartPlaylistOrder, SmartPlaylistOrderField, SmartRule, SmartRuleTextField,
+};
+pub use self::user::{User, UserRole};
\ No newline at end of file
@@ -0,0 +1,65 @@
+use mongodb::bson::oid::ObjectId;
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PlaySource {
+    // reported by the client
+    Client,
+    // counted from streamed packets
+    Server,
+}
+
+// A playback of an audio, and it is counted as a play once it passes the half or finishes
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct PlayEvent {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    // not set if authentication is disabled
+    #[serde(default)]
+    pub user_id: Option<ObjectId>,
+    pub audio_tag_id: ObjectId,
+    pub playback_id: String,
+    pub source: PlaySource,
+
+    pub started_timestamp: i64,
+    // set when the playback is counted as a play
+    pub listened_at: Option<i64>,
+    pub finished: bool,
+    pub position_ms: u64,
+}
+
+impl PlayEvent {
+    pub fn new(
+        user_id: Option<ObjectId>,
+        audio_tag_id: ObjectId,
+        playback_id: &str,
+        source: PlaySource,
+        timestamp: i64,
+    ) -> Self {
+        Self {
+            id: Some(ObjectId::new()),
+            user_id,
+            audio_tag_id,
+            playback_id: playback_id.to_string(),
+            source,
+
+            started_timestamp: timestamp,
+            listened_at: None,
+            finished: false,
+            position_ms: 0,
+        }
+    }
+}
+
+// Play count of an audio by a user, which is increased as play events are counted
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct PlayStat {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    #[serde(default)]
+    pub user_id: Option<ObjectId>,
+    pub audio_tag_id: ObjectId,
+    pub play_count: i64,
+    pub last_played_timestamp: i64,
+}
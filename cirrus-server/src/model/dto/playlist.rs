@@ -0,0 +1,53 @@
+use chrono::Utc;
+use mongodb::bson::oid::ObjectId;
+use serde::{Deserialize, Serialize};
+
+// An entry has its own id, so that the same audio can be placed in a playlist more than once
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct PlaylistEntry {
+    pub entry_id: ObjectId,
+    pub audio_tag_id: ObjectId,
+}
+
+impl PlaylistEntry {
+    pub fn new(audio_tag_id: ObjectId) -> Self {
+        Self {
+            entry_id: ObjectId::new(),
+            audio_tag_id,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Playlist {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub name: String,
+    // in playing order
+    pub entries: Vec<PlaylistEntry>,
+    // id of the user, and not set if the playlist is created while auth is disabled
+    #[serde(default)]
+    pub owner_id: Option<ObjectId>,
+    #[serde(default)]
+    pub shared: bool,
+
+    pub created_timestamp: i64,
+    pub updated_timestamp: i64,
+}
+
+impl Playlist {
+    pub fn new(name: &str, owner_id: Option<ObjectId>, shared: bool) -> Self {
+        let timestamp = Utc::now().timestamp();
+
+        Self {
+            id: Some(ObjectId::new()),
+            name: name.to_string(),
+            entries: Vec::new(),
+            owner_id,
+            shared,
+
+            created_timestamp: timestamp,
+            updated_timestamp: timestamp,
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use chrono::Utc;
+use mongodb::bson::oid::ObjectId;
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SmartRuleTextField {
+    Artist,
+    AlbumArtist,
+    Album,
+    Title,
+    Genre,
+}
+
+impl SmartRuleTextField {
+    pub fn get_key(&self) -> &'static str {
+        match self {
+            SmartRuleTextField::Artist => "artist",
+            SmartRuleTextField::AlbumArtist => "album_artist",
+            SmartRuleTextField::Album => "album",
+            SmartRuleTextField::Title => "title",
+            SmartRuleTextField::Genre => "genre",
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SmartRule {
+    All { rules: Vec<SmartRule> },
+    Any { rules: Vec<SmartRule> },
+    GenreIn { genres: Vec<String> },
+    YearBetween { min: Option<i32>, max: Option<i32> },
+    TextMatch { field: SmartRuleTextField, pattern: String },
+    AddedWithin { days: u32 },
+}
+
+impl SmartRule {
+    pub fn get_depth(&self) -> usize {
+        match self {
+            SmartRule::All { rules } | SmartRule::Any { rules } => {
+                1 + rules.iter().map(|item| item.get_depth()).max().unwrap_or(0)
+            },
+            _ => 1,
+        }
+    }
+
+    // Results of these rules change as time goes, without changes of the library
+    pub fn is_time_relative(&self) -> bool {
+        match self {
+            SmartRule::All { rules } | SmartRule::Any { rules } => rules.iter().any(|item| item.is_time_relative()),
+            SmartRule::AddedWithin { .. } => true,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SmartPlaylistOrderField {
+    Added,
+    Title,
+    Artist,
+    Album,
+    Year,
+    Duration,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SmartPlaylistOrder {
+    pub field: SmartPlaylistOrderField,
+    pub descending: bool,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct SmartPlaylist {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub name: String,
+    pub rule: SmartRule,
+    pub order: SmartPlaylistOrder,
+    pub limit: Option<u32>,
+
+    // results of the last evaluation in order
+    pub audio_tag_ids: Vec<ObjectId>,
+    pub evaluated_timestamp: i64,
+
+    pub created_timestamp: i64,
+    pub updated_timestamp: i64,
+}
+
+impl SmartPlaylist {
+    pub fn new(
+        name: &str,
+        rule: SmartRule,
+        order: SmartPlaylistOrder,
+        limit: Option<u32>,
+    ) -> Self {
+        let timestamp = Utc::now().timestamp();
+
+        Self {
+            id: Some(ObjectId::new()),
+            name: name.to_string(),
+            rule,
+            order,
+            limit,
+
+            audio_tag_ids: Vec::new(),
+            evaluated_timestamp: 0,
+
+            created_timestamp: timestamp,
+            updated_timestamp: timestamp,
+        }
+    }
+}
@@ -0,0 +1,37 @@
+use chrono::Utc;
+use mongodb::bson::oid::ObjectId;
+use serde::{Deserialize, Serialize};
+
+// Roles are ordered by privileges, and a role has privileges of lower ones
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum UserRole {
+    Listener,
+    Admin,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct User {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub username: String,
+    // argon2 hash in PHC string format
+    pub password_hash: String,
+    pub role: UserRole,
+    // refresh tokens of an older version are revoked
+    pub token_version: i64,
+    pub created_timestamp: i64,
+}
+
+impl User {
+    pub fn new(username: &str, password_hash: String, role: UserRole) -> Self {
+        Self {
+            id: Some(ObjectId::new()),
+            username: username.to_string(),
+            password_hash,
+            role,
+            token_version: 0,
+            created_timestamp: Utc::now().timestamp(),
+        }
+    }
+}
@@ -1,14 +1,19 @@
 pub mod dto;
 pub mod document;
 pub mod crud;
+pub mod storage;
+
+use std::sync::Arc;
 
 use mongodb::{Client, options::ClientOptions};
 
-use crate::settings::Settings;
+use crate::{metrics, settings};
+
+pub use storage::Storage;
 
-pub async fn create_db_client() -> Result<mongodb::Client, anyhow::Error> {
-    let settings = Settings::get()?;
-    let client_options = ClientOptions::parse(settings.mongodb.address).await?;
+pub async fn create_db_client(settings: &settings::MongoDB) -> Result<mongodb::Client, anyhow::Error> {
+    let mut client_options = ClientOptions::parse(&settings.address).await?;
+    client_options.command_event_handler = Some(Arc::new(metrics::DbCommandMetrics));
 
     let client = Client::with_options(client_options)?;
 
@@ -0,0 +1,326 @@
+mod mongo;
+mod sqlite;
+
+use std::path::Path;
+
+use async_trait::async_trait;
+use bson::oid::ObjectId;
+use tracing::{info, warn};
+
+use crate::settings::{Settings, StorageBackend};
+
+use super::{crud, document::tag::SearchFilter, dto};
+
+const MIGRATION_BATCH_SIZE: i64 = 1000;
+
+#[async_trait]
+pub trait DocumentStorage<T: Send + Sync>: Send + Sync {
+    async fn create(&self, doc: &T) -> Result<(), anyhow::Error>;
+
+    async fn create_many(&self, docs: Vec<T>) -> Result<(), anyhow::Error>;
+
+    async fn get(&self, id: &ObjectId) -> Result<Option<T>, anyhow::Error>;
+
+    async fn get_all(&self) -> Result<Vec<T>, anyhow::Error>;
+
+    async fn get_many(&self, ids: &[ObjectId]) -> Result<Vec<T>, anyhow::Error>;
+
+    async fn get_paginated(&self, limit: i64, page: u64) -> Result<Vec<T>, anyhow::Error>;
+
+    async fn count(&self) -> Result<u64, anyhow::Error>;
+
+    async fn update(&self, id: &ObjectId, doc: &T) -> Result<(), anyhow::Error>;
+
+    // Results are in the order of docs, so that a failed document can be told
+    async fn update_many(&self, docs: &[T]) -> Vec<Result<(), anyhow::Error>>;
+
+    async fn delete(&self, id: &ObjectId) -> Result<u64, anyhow::Error>;
+
+    async fn delete_many(&self, ids: &[ObjectId]) -> Result<u64, anyhow::Error>;
+}
+
+// Queries by materialized path, which match documents under the path as well
+#[async_trait]
+pub trait PathStorage<T: Send + Sync>: DocumentStorage<T> {
+    async fn get_by_path(&self, path: &Path) -> Result<Vec<T>, anyhow::Error>;
+
+    async fn get_by_materialized_path(&self, path: &str) -> Result<Vec<T>, anyhow::Error>;
+
+    async fn get_by_exact_materialized_path(&self, path: &str) -> Result<Option<T>, anyhow::Error>;
+
+    async fn delete_by_path(&self, path: &Path) -> Result<u64, anyhow::Error>;
+
+    async fn update_modified_timestamp(&self, doc: &T, timestamp: i64) -> Result<(), anyhow::Error>;
+
+    async fn check_exists_by_path(&self, path: &Path) -> Result<bool, anyhow::Error>;
+}
+
+#[async_trait]
+pub trait AudioFileStorage: PathStorage<dto::AudioFile> {
+    async fn get_by_audio_tag(&self, audio_tag_id: &ObjectId) -> Result<Option<dto::AudioFile>, anyhow::Error>;
+
+    async fn get_many_by_audio_tags(&self, audio_tag_ids: &[ObjectId]) -> Result<Vec<dto::AudioFile>, anyhow::Error>;
+
+    async fn get_by_filename(&self, parent_path: &str, filename: &str) -> Result<Option<dto::AudioFile>, anyhow::Error>;
+}
+
+pub struct TagSearchRes {
+    // tags and their scores in order
+    pub hits: Vec<(dto::AudioTag, f64)>,
+    pub total: u64,
+    pub genres: Vec<(String, u64)>,
+    pub years: Vec<(i32, u64)>,
+}
+
+// Albums are grouped by the album and the album artist, which is the artist of tracks without it
+pub enum AlbumQuery<'a> {
+    All,
+    Album { album: &'a str, album_artist: &'a str },
+    ArtistAlbums(&'a str),
+    // whole albums of other album artists that have tracks of the artist
+    ArtistAppearances(&'a str),
+}
+
+pub struct AlbumSummary {
+    pub album: String,
+    pub album_artist: String,
+    pub year: Option<i32>,
+    pub track_count: u32,
+    pub disc_count: u32,
+    pub duration: u64,
+    pub artwork_id: Option<String>,
+    // tracks of a compilation have various artists under an album artist
+    pub compilation: bool,
+}
+
+pub struct ArtistSummary {
+    pub name: String,
+    pub album_count: u32,
+    pub track_count: u32,
+}
+
+// Search, browse and smart playlist queries of tags
+#[async_trait]
+pub trait AudioTagStorage: DocumentStorage<dto::AudioTag> {
+    async fn search(&self, filter: &SearchFilter<'_>, skip: u64, limit: i64) -> Result<TagSearchRes, anyhow::Error>;
+
+    // Results are ordered by the album artist, year and album
+    async fn get_albums(
+        &self,
+        query: AlbumQuery<'_>,
+        skip: Option<u64>,
+        limit: Option<i64>,
+    ) -> Result<Vec<AlbumSummary>, anyhow::Error>;
+
+    async fn get_album_tracks(&self, album: &str, album_artist: &str) -> Result<Vec<dto::AudioTag>, anyhow::Error>;
+
+    // Artists are names of track artists and album artists, ordered by the name
+    async fn get_artists(&self, skip: u64, limit: i64) -> Result<Vec<ArtistSummary>, anyhow::Error>;
+
+    async fn count_artist_tracks(&self, artist: &str) -> Result<u64, anyhow::Error>;
+
+    // Returns ids of audio tags that match the rule in order
+    async fn evaluate_smart_rule(
+        &self,
+        rule: &dto::SmartRule,
+        order: &dto::SmartPlaylistOrder,
+        limit: Option<u32>,
+    ) -> Result<Vec<ObjectId>, anyhow::Error>;
+}
+
+#[async_trait]
+pub trait ArtworkStorage: DocumentStorage<dto::Artwork> {
+    // Artworks are identified by the hash of their data instead of an object id
+    async fn get_artwork(&self, artwork_id: &str) -> Result<Option<dto::Artwork>, anyhow::Error>;
+}
+
+#[async_trait]
+pub trait PlaylistStorage: DocumentStorage<dto::Playlist> {
+    // Playlists of the owner, shared ones and ones without an owner
+    async fn get_visible_paginated(
+        &self,
+        owner_id: &ObjectId,
+        limit: i64,
+        page: u64,
+    ) -> Result<Vec<dto::Playlist>, anyhow::Error>;
+}
+
+// Counted plays of the user within the range, and 0 of `since` and `until` is unbounded. Plays
+// without a user are of the server, where authentication is disabled
+pub struct PlayHistoryQuery<'a> {
+    pub user_id: Option<&'a ObjectId>,
+    pub since: i64,
+    pub until: i64,
+    pub audio_tag_id: Option<&'a ObjectId>,
+}
+
+// Events of a playback are applied to a single document of the user and the playback id
+#[async_trait]
+pub trait PlayEventStorage: DocumentStorage<dto::PlayEvent> {
+    async fn get_playback(
+        &self,
+        user_id: Option<&ObjectId>,
+        playback_id: &str,
+    ) -> Result<Option<dto::PlayEvent>, anyhow::Error>;
+
+    // Counts the playback as a play at `listened_at`, and returns true only if it is not counted
+    // yet
+    async fn count_playback(
+        &self,
+        user_id: Option<&ObjectId>,
+        playback_id: &str,
+        position_ms: u64,
+        finished: bool,
+        listened_at: i64,
+    ) -> Result<bool, anyhow::Error>;
+
+    async fn update_playback(
+        &self,
+        user_id: Option<&ObjectId>,
+        playback_id: &str,
+        position_ms: u64,
+        finished: bool,
+    ) -> Result<(), anyhow::Error>;
+
+    // Results are ordered by the listened time
+    async fn get_history(
+        &self,
+        query: &PlayHistoryQuery<'_>,
+        ascending: bool,
+        skip: Option<u64>,
+        limit: Option<i64>,
+    ) -> Result<Vec<dto::PlayEvent>, anyhow::Error>;
+}
+
+#[async_trait]
+pub trait PlayStatStorage: DocumentStorage<dto::PlayStat> {
+    // Creates the stat of the user and the audio at the first play
+    async fn increase_play_count(
+        &self,
+        user_id: Option<&ObjectId>,
+        audio_tag_id: &ObjectId,
+        timestamp: i64,
+    ) -> Result<(), anyhow::Error>;
+
+    async fn get_by_audio_tags(
+        &self,
+        user_id: Option<&ObjectId>,
+        audio_tag_ids: &[ObjectId],
+    ) -> Result<Vec<dto::PlayStat>, anyhow::Error>;
+}
+
+#[async_trait]
+pub trait UserStorage: DocumentStorage<dto::User> {
+    async fn get_by_username(&self, username: &str) -> Result<Option<dto::User>, anyhow::Error>;
+
+    // Increases the token version, so that issued tokens are revoked
+    async fn revoke_tokens(&self, id: &ObjectId) -> Result<(), anyhow::Error>;
+}
+
+#[async_trait]
+pub trait JobStorage: DocumentStorage<dto::Job> {
+    // Pending and running jobs
+    async fn get_unfinished(&self) -> Result<Vec<dto::Job>, anyhow::Error>;
+}
+
+// All documents of the server, which are stored at the backend selected by `storage.backend`
+pub struct Storage {
+    pub library_root: Box<dyn PathStorage<dto::AudioLibrary>>,
+    pub library: Box<dyn PathStorage<dto::AudioLibrary>>,
+    pub audio_file: Box<dyn AudioFileStorage>,
+    pub audio_tag: Box<dyn AudioTagStorage>,
+    pub artwork: Box<dyn ArtworkStorage>,
+    pub playlist: Box<dyn PlaylistStorage>,
+    pub smart_playlist: Box<dyn DocumentStorage<dto::SmartPlaylist>>,
+    pub play_event: Box<dyn PlayEventStorage>,
+    pub play_stat: Box<dyn PlayStatStorage>,
+    pub user: Box<dyn UserStorage>,
+    pub job: Box<dyn JobStorage>,
+}
+
+// MongoDB is connected only if it is the backend
+pub async fn create_storage(
+    backend: StorageBackend,
+    settings: &Settings,
+) -> Result<Storage, anyhow::Error> {
+    let storage = match backend {
+        StorageBackend::MongoDB => {
+            let db = super::create_db_client(&settings.mongodb).await?;
+
+            if let Err(err) = crud::create_indexes(db.clone()).await {
+                warn!("failed to create indexes: {}", err);
+            }
+
+            mongo::create_storage(db)
+        },
+        StorageBackend::Sqlite => sqlite::create_storage(Path::new(&settings.storage.sqlite_path))?,
+    };
+
+    Ok(storage)
+}
+
+// Copies all documents to the target storage, which should be empty. Ids are kept, so that
+// files, playlists and plays keep referring their tags
+pub async fn migrate(source: &Storage, target: &Storage) -> Result<(), anyhow::Error> {
+    let target_counts = [
+        target.library_root.count().await?,
+        target.library.count().await?,
+        target.audio_file.count().await?,
+        target.audio_tag.count().await?,
+        target.artwork.count().await?,
+        target.playlist.count().await?,
+        target.smart_playlist.count().await?,
+        target.play_event.count().await?,
+        target.play_stat.count().await?,
+        target.user.count().await?,
+        target.job.count().await?,
+    ];
+
+    if target_counts.iter().any(|item| *item > 0) {
+        return Err(anyhow::anyhow!("target storage is not empty"));
+    }
+
+    copy_documents("library roots", source.library_root.as_ref(), target.library_root.as_ref()).await?;
+    copy_documents("libraries", source.library.as_ref(), target.library.as_ref()).await?;
+    copy_documents("audio files", source.audio_file.as_ref(), target.audio_file.as_ref()).await?;
+    copy_documents("audio tags", source.audio_tag.as_ref(), target.audio_tag.as_ref()).await?;
+    copy_documents("artworks", source.artwork.as_ref(), target.artwork.as_ref()).await?;
+    copy_documents("playlists", source.playlist.as_ref(), target.playlist.as_ref()).await?;
+    copy_documents("smart playlists", source.smart_playlist.as_ref(), target.smart_playlist.as_ref()).await?;
+    copy_documents("play events", source.play_event.as_ref(), target.play_event.as_ref()).await?;
+    copy_documents("play stats", source.play_stat.as_ref(), target.play_stat.as_ref()).await?;
+    copy_documents("users", source.user.as_ref(), target.user.as_ref()).await?;
+    copy_documents("jobs", source.job.as_ref(), target.job.as_ref()).await?;
+
+    Ok(())
+}
+
+async fn copy_documents<T, S, D>(
+    name: &str,
+    source: &S,
+    target: &D,
+) -> Result<(), anyhow::Error>
+where
+    T: Send + Sync,
+    S: DocumentStorage<T> + ?Sized,
+    D: DocumentStorage<T> + ?Sized,
+{
+    let mut copied_count = 0;
+    let mut page = 1;
+
+    loop {
+        let docs = source.get_paginated(MIGRATION_BATCH_SIZE, page).await?;
+        if docs.is_empty() {
+            break;
+        }
+
+        copied_count += docs.len();
+        target.create_many(docs).await?;
+
+        page += 1;
+    }
+
+    info!("migrated {} {}", copied_count, name);
+
+    Ok(())
+}
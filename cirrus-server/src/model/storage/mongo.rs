@@ -0,0 +1,572 @@
+use std::path::Path;
+
+use async_trait::async_trait;
+use bson::{doc, oid::ObjectId, Bson, Document};
+use futures::stream::TryStreamExt;
+use mongodb::options::{FindOptions, UpdateOptions};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::model::{
+    crud::{self, Aggregation, CrudMany, CrudSingle, Pagination, PathOperation},
+    document::{self, tag::SearchFilter},
+    dto::{self, GetPathKey, GetPathValue},
+    GetCollection,
+};
+
+use super::{
+    AlbumQuery, AlbumSummary, ArtistSummary, ArtworkStorage, AudioFileStorage, AudioTagStorage, DocumentStorage,
+    JobStorage, PathStorage, PlayEventStorage, PlayHistoryQuery, PlayStatStorage, PlaylistStorage, Storage,
+    TagSearchRes, UserStorage,
+};
+
+// numeric results of aggregation are either 32 or 64 bit integers, or doubles
+fn get_number(doc: &Document, key: &str) -> Option<i64> {
+    match doc.get(key) {
+        Some(Bson::Int32(value)) => Some(*value as i64),
+        Some(Bson::Int64(value)) => Some(*value),
+        Some(Bson::Double(value)) => Some(*value as i64),
+        _ => None,
+    }
+}
+
+fn get_facet_docs<'a>(facets: &'a Document, key: &str) -> impl Iterator<Item = &'a Document> {
+    facets
+        .get_array(key)
+        .map(|items| items.as_slice())
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|item| item.as_document())
+}
+
+fn to_album_summary(doc: &Document) -> AlbumSummary {
+    AlbumSummary {
+        album: doc.get_str("album").unwrap_or_default().to_string(),
+        album_artist: doc.get_str("album_artist").unwrap_or_default().to_string(),
+        year: get_number(doc, "year").map(|item| item as i32),
+        track_count: get_number(doc, "track_count").unwrap_or_default() as u32,
+        disc_count: get_number(doc, "disc_count").unwrap_or_default() as u32,
+        duration: get_number(doc, "duration").unwrap_or_default() as u64,
+        artwork_id: doc.get_str("artwork_id").ok().map(|item| item.to_string()),
+        compilation: doc.get_bool("compilation").unwrap_or_default(),
+    }
+}
+
+fn to_artist_summary(doc: &Document) -> ArtistSummary {
+    ArtistSummary {
+        name: doc.get_str("name").unwrap_or_default().to_string(),
+        album_count: get_number(doc, "album_count").unwrap_or_default() as u32,
+        track_count: get_number(doc, "track_count").unwrap_or_default() as u32,
+    }
+}
+
+pub struct MongoCollection<T> {
+    db: mongodb::Client,
+    col_fn: fn(mongodb::Client) -> mongodb::Collection<T>,
+    single: CrudSingle<T>,
+    many: CrudMany<T>,
+    path: PathOperation<T>,
+    page: Pagination<T>,
+    aggregation: Aggregation<T>,
+}
+
+impl<T> MongoCollection<T> {
+    fn new(db: mongodb::Client, col_fn: fn(mongodb::Client) -> mongodb::Collection<T>) -> Self {
+        Self {
+            db,
+            col_fn,
+            single: CrudSingle::new(col_fn),
+            many: CrudMany::new(col_fn),
+            path: PathOperation::new(col_fn),
+            page: Pagination::new(col_fn),
+            aggregation: Aggregation::new(col_fn),
+        }
+    }
+
+    // for updates that are not covered by crud operations
+    fn get_collection(&self) -> mongodb::Collection<T> {
+        (self.col_fn)(self.db.clone())
+    }
+}
+
+#[async_trait]
+impl<T> DocumentStorage<T> for MongoCollection<T>
+where
+    T: Serialize + DeserializeOwned + Sync + Send + Unpin,
+{
+    async fn create(&self, doc: &T) -> Result<(), anyhow::Error> {
+        self.single.create(self.db.clone(), doc).await?;
+
+        Ok(())
+    }
+
+    async fn create_many(&self, docs: Vec<T>) -> Result<(), anyhow::Error> {
+        self.many.create_many(self.db.clone(), docs).await?;
+
+        Ok(())
+    }
+
+    async fn get(&self, id: &ObjectId) -> Result<Option<T>, anyhow::Error> {
+        self.single.get(self.db.clone(), Some(id), None).await
+    }
+
+    async fn get_all(&self) -> Result<Vec<T>, anyhow::Error> {
+        self.many.get_all(self.db.clone()).await
+    }
+
+    async fn get_many(&self, ids: &[ObjectId]) -> Result<Vec<T>, anyhow::Error> {
+        self.many.get_many(self.db.clone(), Some(&ids.to_vec()), None).await
+    }
+
+    async fn get_paginated(&self, limit: i64, page: u64) -> Result<Vec<T>, anyhow::Error> {
+        self.page.get_paginated(self.db.clone(), limit, page).await
+    }
+
+    async fn count(&self) -> Result<u64, anyhow::Error> {
+        self.many.count(self.db.clone(), bson::doc! {}).await
+    }
+
+    async fn update(&self, id: &ObjectId, doc: &T) -> Result<(), anyhow::Error> {
+        self.single.update(self.db.clone(), id, doc).await?;
+
+        Ok(())
+    }
+
+    async fn update_many(&self, docs: &[T]) -> Vec<Result<(), anyhow::Error>> {
+        self.many
+            .update_many(self.db.clone(), docs)
+            .await
+            .into_iter()
+            .map(|item| item.map(|_| ()))
+            .collect()
+    }
+
+    async fn delete(&self, id: &ObjectId) -> Result<u64, anyhow::Error> {
+        let delete_res = self.single.delete(self.db.clone(), id).await?;
+
+        Ok(delete_res.deleted_count)
+    }
+
+    async fn delete_many(&self, ids: &[ObjectId]) -> Result<u64, anyhow::Error> {
+        let delete_res = self.many.delete_many(self.db.clone(), &ids.to_vec()).await?;
+
+        Ok(delete_res.deleted_count)
+    }
+}
+
+#[async_trait]
+impl<T> PathStorage<T> for MongoCollection<T>
+where
+    T: Serialize + DeserializeOwned + Sync + Send + Unpin + GetPathKey + GetPathValue,
+{
+    async fn get_by_path(&self, path: &Path) -> Result<Vec<T>, anyhow::Error> {
+        self.path.get_by_path(self.db.clone(), path).await
+    }
+
+    async fn get_by_materialized_path(&self, path: &str) -> Result<Vec<T>, anyhow::Error> {
+        self.path.get_by_materialized_path(self.db.clone(), path).await
+    }
+
+    async fn get_by_exact_materialized_path(&self, path: &str) -> Result<Option<T>, anyhow::Error> {
+        self.single
+            .get(
+                self.db.clone(),
+                None,
+                Some(document::path::query_exact_path(T::get_mat_path_key(), path))
+            ).await
+    }
+
+    async fn delete_by_path(&self, path: &Path) -> Result<u64, anyhow::Error> {
+        let delete_res = self.path.delete_by_path(self.db.clone(), path).await?;
+
+        Ok(delete_res.deleted_count)
+    }
+
+    async fn update_modified_timestamp(&self, doc: &T, timestamp: i64) -> Result<(), anyhow::Error> {
+        self.path.update_modified_timestamp(self.db.clone(), doc, timestamp).await?;
+
+        Ok(())
+    }
+
+    async fn check_exists_by_path(&self, path: &Path) -> Result<bool, anyhow::Error> {
+        self.path.check_exists_by_path(self.db.clone(), path).await
+    }
+}
+
+#[async_trait]
+impl AudioFileStorage for MongoCollection<dto::AudioFile> {
+    async fn get_by_audio_tag(&self, audio_tag_id: &ObjectId) -> Result<Option<dto::AudioFile>, anyhow::Error> {
+        self.single
+            .get(
+                self.db.clone(),
+                None,
+                Some(document::audio::query_audio_tag_referer(audio_tag_id))
+            ).await
+    }
+
+    async fn get_many_by_audio_tags(&self, audio_tag_ids: &[ObjectId]) -> Result<Vec<dto::AudioFile>, anyhow::Error> {
+        self.many
+            .get_many(
+                self.db.clone(),
+                None,
+                Some(document::audio::query_many_audio_tag_referer(&audio_tag_ids.to_vec()))
+            ).await
+    }
+
+    async fn get_by_filename(&self, parent_path: &str, filename: &str) -> Result<Option<dto::AudioFile>, anyhow::Error> {
+        self.single
+            .get(
+                self.db.clone(),
+                None,
+                Some(document::audio::query_audio_file(parent_path, filename))
+            ).await
+    }
+}
+
+#[async_trait]
+impl AudioTagStorage for MongoCollection<dto::AudioTag> {
+    async fn search(&self, filter: &SearchFilter<'_>, skip: u64, limit: i64) -> Result<TagSearchRes, anyhow::Error> {
+        let aggregate_res = self.aggregation
+            .aggregate(self.db.clone(), document::tag::create_search_pipeline(filter, skip, limit))
+            .await?;
+
+        let mut search_res = TagSearchRes {
+            hits: Vec::new(),
+            total: 0,
+            genres: Vec::new(),
+            years: Vec::new(),
+        };
+
+        // facet stage always outputs a single document
+        let facets = match aggregate_res.first() {
+            Some(facets) => facets,
+            None => return Ok(search_res),
+        };
+
+        for hit_doc in get_facet_docs(facets, "hits") {
+            let score = hit_doc.get_f64("score").unwrap_or_default();
+            let audio_tag: dto::AudioTag = bson::from_document(hit_doc.clone())?;
+
+            search_res.hits.push((audio_tag, score));
+        }
+
+        search_res.total = get_facet_docs(facets, "total")
+            .next()
+            .and_then(|item| get_number(item, "count"))
+            .unwrap_or_default() as u64;
+
+        search_res.genres = get_facet_docs(facets, "genres")
+            .filter_map(|item| Some((
+                item.get_str("_id").ok()?.to_string(),
+                get_number(item, "count").unwrap_or_default() as u64,
+            )))
+            .collect();
+
+        search_res.years = get_facet_docs(facets, "years")
+            .filter_map(|item| Some((
+                item.get_i32("_id").ok()?,
+                get_number(item, "count").unwrap_or_default() as u64,
+            )))
+            .collect();
+
+        Ok(search_res)
+    }
+
+    async fn get_albums(
+        &self,
+        query: AlbumQuery<'_>,
+        skip: Option<u64>,
+        limit: Option<i64>,
+    ) -> Result<Vec<AlbumSummary>, anyhow::Error> {
+        let query = match query {
+            AlbumQuery::All => doc! {},
+            AlbumQuery::Album { album, album_artist } => document::browse::query_album_tracks(album, album_artist),
+            AlbumQuery::ArtistAlbums(artist) => document::browse::query_artist_albums(artist),
+            // summaries of appearances count all tracks of albums, not only ones of the artist
+            AlbumQuery::ArtistAppearances(artist) => {
+                let appearance_keys = self.aggregation
+                    .aggregate(
+                        self.db.clone(),
+                        document::browse::create_album_key_pipeline(document::browse::query_artist_appearances(artist))
+                    )
+                    .await?;
+
+                let appearance_queries = appearance_keys
+                    .iter()
+                    .filter_map(|item| item.get_document("_id").ok())
+                    .filter_map(|item| Some(document::browse::query_album_tracks(
+                        item.get_str("album").ok()?,
+                        item.get_str("album_artist").ok()?,
+                    )))
+                    .collect::<Vec<_>>();
+
+                if appearance_queries.is_empty() {
+                    return Ok(Vec::new());
+                }
+
+                doc! { "$or": appearance_queries }
+            },
+        };
+
+        let albums = self.aggregation
+            .aggregate(self.db.clone(), document::browse::create_album_pipeline(query, skip, limit))
+            .await?;
+
+        Ok(albums.iter().map(to_album_summary).collect())
+    }
+
+    async fn get_album_tracks(&self, album: &str, album_artist: &str) -> Result<Vec<dto::AudioTag>, anyhow::Error> {
+        self.many
+            .get_many(
+                self.db.clone(),
+                None,
+                Some(document::browse::query_album_tracks(album, album_artist))
+            ).await
+    }
+
+    async fn get_artists(&self, skip: u64, limit: i64) -> Result<Vec<ArtistSummary>, anyhow::Error> {
+        let artists = self.aggregation
+            .aggregate(self.db.clone(), document::browse::create_artist_pipeline(skip, limit))
+            .await?;
+
+        Ok(artists.iter().map(to_artist_summary).collect())
+    }
+
+    async fn count_artist_tracks(&self, artist: &str) -> Result<u64, anyhow::Error> {
+        self.many
+            .count(self.db.clone(), document::browse::query_artist_tracks(artist))
+            .await
+    }
+
+    async fn evaluate_smart_rule(
+        &self,
+        rule: &dto::SmartRule,
+        order: &dto::SmartPlaylistOrder,
+        limit: Option<u32>,
+    ) -> Result<Vec<ObjectId>, anyhow::Error> {
+        let mut pipeline = Vec::new();
+
+        // the added time is kept at audio files
+        if rule.is_time_relative() || order.field == dto::SmartPlaylistOrderField::Added {
+            pipeline.extend(document::smart_playlist::create_added_timestamp_stages());
+        }
+
+        pipeline.push(doc! { "$match": document::smart_playlist::query_smart_rule(rule) });
+        pipeline.push(doc! { "$sort": document::smart_playlist::sort_smart_playlist(order) });
+
+        if let Some(limit) = limit {
+            pipeline.push(doc! { "$limit": limit as i64 });
+        }
+
+        pipeline.push(doc! { "$project": { "_id": 1 } });
+
+        let found_docs = self.aggregation
+            .aggregate(self.db.clone(), pipeline)
+            .await?;
+
+        let ids = found_docs
+            .iter()
+            .filter_map(|item| item.get_object_id("_id").ok())
+            .collect();
+
+        Ok(ids)
+    }
+}
+
+#[async_trait]
+impl ArtworkStorage for MongoCollection<dto::Artwork> {
+    async fn get_artwork(&self, artwork_id: &str) -> Result<Option<dto::Artwork>, anyhow::Error> {
+        self.single
+            .get(
+                self.db.clone(),
+                None,
+                Some(document::artwork::query_artwork(artwork_id))
+            ).await
+    }
+}
+
+#[async_trait]
+impl PlaylistStorage for MongoCollection<dto::Playlist> {
+    async fn get_visible_paginated(
+        &self,
+        owner_id: &ObjectId,
+        limit: i64,
+        page: u64,
+    ) -> Result<Vec<dto::Playlist>, anyhow::Error> {
+        self.page
+            .get_paginated_by_query(
+                self.db.clone(),
+                Some(document::playlist::query_visible_playlists(owner_id)),
+                limit,
+                page
+            ).await
+    }
+}
+
+#[async_trait]
+impl PlayEventStorage for MongoCollection<dto::PlayEvent> {
+    async fn get_playback(
+        &self,
+        user_id: Option<&ObjectId>,
+        playback_id: &str,
+    ) -> Result<Option<dto::PlayEvent>, anyhow::Error> {
+        self.single
+            .get(
+                self.db.clone(),
+                None,
+                Some(document::play_history::query_playback(user_id, playback_id))
+            ).await
+    }
+
+    async fn count_playback(
+        &self,
+        user_id: Option<&ObjectId>,
+        playback_id: &str,
+        position_ms: u64,
+        finished: bool,
+        listened_at: i64,
+    ) -> Result<bool, anyhow::Error> {
+        let updated = self.get_collection()
+            .find_one_and_update(
+                document::play_history::query_uncounted_playback(user_id, playback_id),
+                document::play_history::create_playback_update(position_ms, finished, Some(listened_at)),
+                None
+            )
+            .await?;
+
+        Ok(updated.is_some())
+    }
+
+    async fn update_playback(
+        &self,
+        user_id: Option<&ObjectId>,
+        playback_id: &str,
+        position_ms: u64,
+        finished: bool,
+    ) -> Result<(), anyhow::Error> {
+        self.get_collection()
+            .update_one(
+                document::play_history::query_playback(user_id, playback_id),
+                document::play_history::create_playback_update(position_ms, finished, None),
+                None
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn get_history(
+        &self,
+        query: &PlayHistoryQuery<'_>,
+        ascending: bool,
+        skip: Option<u64>,
+        limit: Option<i64>,
+    ) -> Result<Vec<dto::PlayEvent>, anyhow::Error> {
+        let options = FindOptions::builder()
+            .sort(document::play_history::sort_play_history(ascending))
+            .limit(limit)
+            .skip(skip)
+            .build();
+
+        let find_res = self.get_collection()
+            .find(
+                document::play_history::query_play_history(query.user_id, query.since, query.until, query.audio_tag_id),
+                options
+            )
+            .await?;
+
+        let found_docs = find_res
+            .try_collect()
+            .await?;
+
+        Ok(found_docs)
+    }
+}
+
+#[async_trait]
+impl PlayStatStorage for MongoCollection<dto::PlayStat> {
+    async fn increase_play_count(
+        &self,
+        user_id: Option<&ObjectId>,
+        audio_tag_id: &ObjectId,
+        timestamp: i64,
+    ) -> Result<(), anyhow::Error> {
+        let options = UpdateOptions::builder()
+            .upsert(true)
+            .build();
+
+        self.get_collection()
+            .update_one(
+                document::play_history::query_play_stat(user_id, audio_tag_id),
+                document::play_history::create_play_stat_update(timestamp),
+                options
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn get_by_audio_tags(
+        &self,
+        user_id: Option<&ObjectId>,
+        audio_tag_ids: &[ObjectId],
+    ) -> Result<Vec<dto::PlayStat>, anyhow::Error> {
+        self.many
+            .get_many(
+                self.db.clone(),
+                None,
+                Some(document::play_history::query_play_stats(user_id, audio_tag_ids))
+            ).await
+    }
+}
+
+#[async_trait]
+impl UserStorage for MongoCollection<dto::User> {
+    async fn get_by_username(&self, username: &str) -> Result<Option<dto::User>, anyhow::Error> {
+        self.single
+            .get(
+                self.db.clone(),
+                None,
+                Some(document::user::query_username(username))
+            ).await
+    }
+
+    async fn revoke_tokens(&self, id: &ObjectId) -> Result<(), anyhow::Error> {
+        self.get_collection()
+            .update_one(
+                document::query_single_id(id),
+                document::user::create_revoke_tokens_update(),
+                None
+            )
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl JobStorage for MongoCollection<dto::Job> {
+    async fn get_unfinished(&self) -> Result<Vec<dto::Job>, anyhow::Error> {
+        self.many
+            .get_many(
+                self.db.clone(),
+                None,
+                Some(document::job::query_unfinished_jobs())
+            ).await
+    }
+}
+
+pub fn create_storage(db: mongodb::Client) -> Storage {
+    Storage {
+        library_root: Box::new(MongoCollection::new(db.clone(), crud::AudioLibraryRoot::get_collection)),
+        library: Box::new(MongoCollection::new(db.clone(), crud::AudioLibrary::get_collection)),
+        audio_file: Box::new(MongoCollection::new(db.clone(), crud::AudioFile::get_collection)),
+        audio_tag: Box::new(MongoCollection::new(db.clone(), crud::AudioTag::get_collection)),
+        artwork: Box::new(MongoCollection::new(db.clone(), crud::Artwork::get_collection)),
+        playlist: Box::new(MongoCollection::new(db.clone(), crud::Playlist::get_collection)),
+        smart_playlist: Box::new(MongoCollection::new(db.clone(), crud::SmartPlaylist::get_collection)),
+        play_event: Box::new(MongoCollection::new(db.clone(), crud::PlayEvent::get_collection)),
+        play_stat: Box::new(MongoCollection::new(db.clone(), crud::PlayStat::get_collection)),
+        user: Box::new(MongoCollection::new(db.clone(), crud::User::get_collection)),
+        job: Box::new(MongoCollection::new(db, crud::Job::get_collection)),
+    }
+}
@@ -0,0 +1,1621 @@
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+use bson::{oid::ObjectId, Document};
+use chrono::Utc;
+use rusqlite::{params, params_from_iter, types::Value, Connection, OptionalExtension};
+use serde::{de::DeserializeOwned, Serialize};
+use tracing::info;
+
+use crate::{
+    metrics,
+    model::{
+        document::tag::SearchFilter,
+        dto::{self, GetPathValue, SmartPlaylistOrderField, SmartRule},
+    },
+    util,
+};
+
+use super::{
+    AlbumQuery, AlbumSummary, ArtistSummary, ArtworkStorage, AudioFileStorage, AudioTagStorage, DocumentStorage,
+    JobStorage, PathStorage, PlayEventStorage, PlayHistoryQuery, PlayStatStorage, PlaylistStorage, Storage,
+    TagSearchRes, UserStorage,
+};
+
+// SQLite limits the number of variables in a statement
+const MAX_QUERY_IDS: usize = 500;
+// same as the facet limit of MongoDB
+const MAX_FACET_VALUES: i64 = 50;
+
+const AUDIO_FILE_TABLE: &'static str = "audio";
+const AUDIO_TAG_TABLE: &'static str = "audio_tag";
+
+// Tracks without album artist are grouped by their artist, and indexes of albums are created on
+// this expression
+const ALBUM_ARTIST_EXPR: &'static str = "IFNULL(album_artist, artist)";
+
+// Search index of titles and names, which is synced with tags by triggers. The tokenizer ignores
+// case and diacritics, as the text index of MongoDB
+const AUDIO_TAG_SCHEMA_SQL: &'static str = "
+    CREATE INDEX IF NOT EXISTS audio_tag_artist ON audio_tag (artist);
+    CREATE INDEX IF NOT EXISTS audio_tag_album ON audio_tag (album, IFNULL(album_artist, artist));
+    CREATE INDEX IF NOT EXISTS audio_tag_album_artist ON audio_tag (IFNULL(album_artist, artist));
+    CREATE INDEX IF NOT EXISTS audio_tag_genre ON audio_tag (genre);
+    CREATE INDEX IF NOT EXISTS audio_tag_year ON audio_tag (year);
+    CREATE INDEX IF NOT EXISTS audio_tag_duration ON audio_tag (duration);
+
+    CREATE VIRTUAL TABLE IF NOT EXISTS audio_tag_search USING fts5 (
+        title, artist, album, album_artist,
+        content = 'audio_tag',
+        content_rowid = 'seq'
+    );
+
+    CREATE TRIGGER IF NOT EXISTS audio_tag_search_insert AFTER INSERT ON audio_tag BEGIN
+        INSERT INTO audio_tag_search (rowid, title, artist, album, album_artist)
+        VALUES (new.seq, new.title, new.artist, new.album, new.album_artist);
+    END;
+    CREATE TRIGGER IF NOT EXISTS audio_tag_search_delete AFTER DELETE ON audio_tag BEGIN
+        INSERT INTO audio_tag_search (audio_tag_search, rowid, title, artist, album, album_artist)
+        VALUES ('delete', old.seq, old.title, old.artist, old.album, old.album_artist);
+    END;
+    CREATE TRIGGER IF NOT EXISTS audio_tag_search_update AFTER UPDATE ON audio_tag BEGIN
+        INSERT INTO audio_tag_search (audio_tag_search, rowid, title, artist, album, album_artist)
+        VALUES ('delete', old.seq, old.title, old.artist, old.album, old.album_artist);
+        INSERT INTO audio_tag_search (rowid, title, artist, album, album_artist)
+        VALUES (new.seq, new.title, new.artist, new.album, new.album_artist);
+    END;";
+
+// weights of title, artist, album and album artist, as the text index of MongoDB
+const SEARCH_SCORE_EXPR: &'static str = "-bm25(audio_tag_search, 10.0, 5.0, 3.0, 3.0)";
+
+// Plays and stats are unique by the user, which is an empty string for plays of the server
+const PLAY_EVENT_SCHEMA_SQL: &'static str = "
+    CREATE UNIQUE INDEX IF NOT EXISTS play_events_playback ON play_events (user_id, playback_id);
+    CREATE INDEX IF NOT EXISTS play_events_history ON play_events (user_id, listened_at);
+    CREATE INDEX IF NOT EXISTS play_events_audio_tag ON play_events (user_id, audio_tag_id, listened_at);";
+
+const PLAY_STAT_SCHEMA_SQL: &'static str = "
+    CREATE UNIQUE INDEX IF NOT EXISTS play_stats_audio_tag ON play_stats (user_id, audio_tag_id);";
+
+// Documents are stored as BSON, and fields that are queried are copied to columns
+trait Columns {
+    const COLUMNS: &'static [&'static str];
+
+    fn get_row_id(&self) -> Option<String>;
+
+    fn get_column_values(&self) -> Vec<Value>;
+}
+
+fn to_id_value(id: Option<&ObjectId>) -> Value {
+    id.map(|item| item.to_hex()).into()
+}
+
+fn to_user_key(user_id: Option<&ObjectId>) -> String {
+    user_id.map(|item| item.to_hex()).unwrap_or_default()
+}
+
+impl Columns for dto::AudioLibrary {
+    const COLUMNS: &'static [&'static str] = &["path"];
+
+    fn get_row_id(&self) -> Option<String> {
+        self.id.map(|item| item.to_hex())
+    }
+
+    fn get_column_values(&self) -> Vec<Value> {
+        vec![self.materialized_path.clone().into()]
+    }
+}
+
+impl Columns for dto::AudioFile {
+    const COLUMNS: &'static [&'static str] = &["path", "filename", "audio_tag_refer", "added_timestamp"];
+
+    fn get_row_id(&self) -> Option<String> {
+        self.id.map(|item| item.to_hex())
+    }
+
+    fn get_column_values(&self) -> Vec<Value> {
+        vec![
+            self.parent_path.clone().into(),
+            self.filename.clone().into(),
+            to_id_value(self.audio_tag_refer.as_ref()),
+            self.get_added_timestamp().into(),
+        ]
+    }
+}
+
+impl Columns for dto::AudioTag {
+    const COLUMNS: &'static [&'static str] = &[
+        "title", "artist", "album", "album_artist", "genre", "year", "duration", "disc", "track", "artwork_id",
+    ];
+
+    fn get_row_id(&self) -> Option<String> {
+        self.id.map(|item| item.to_hex())
+    }
+
+    fn get_column_values(&self) -> Vec<Value> {
+        vec![
+            self.title.clone().into(),
+            self.artist.clone().into(),
+            self.album.clone().into(),
+            self.album_artist.clone().into(),
+            self.genre.clone().into(),
+            self.year.into(),
+            self.duration.into(),
+            self.disc.into(),
+            self.track.into(),
+            self.artwork_id.clone().into(),
+        ]
+    }
+}
+
+impl Columns for dto::Artwork {
+    const COLUMNS: &'static [&'static str] = &[];
+
+    fn get_row_id(&self) -> Option<String> {
+        Some(self.id.clone())
+    }
+
+    fn get_column_values(&self) -> Vec<Value> {
+        Vec::new()
+    }
+}
+
+impl Columns for dto::Playlist {
+    const COLUMNS: &'static [&'static str] = &["owner_id", "shared"];
+
+    fn get_row_id(&self) -> Option<String> {
+        self.id.map(|item| item.to_hex())
+    }
+
+    fn get_column_values(&self) -> Vec<Value> {
+        vec![to_id_value(self.owner_id.as_ref()), self.shared.into()]
+    }
+}
+
+impl Columns for dto::SmartPlaylist {
+    const COLUMNS: &'static [&'static str] = &[];
+
+    fn get_row_id(&self) -> Option<String> {
+        self.id.map(|item| item.to_hex())
+    }
+
+    fn get_column_values(&self) -> Vec<Value> {
+        Vec::new()
+    }
+}
+
+impl Columns for dto::PlayEvent {
+    const COLUMNS: &'static [&'static str] = &["user_id", "playback_id", "audio_tag_id", "listened_at"];
+
+    fn get_row_id(&self) -> Option<String> {
+        self.id.map(|item| item.to_hex())
+    }
+
+    fn get_column_values(&self) -> Vec<Value> {
+        vec![
+            to_user_key(self.user_id.as_ref()).into(),
+            self.playback_id.clone().into(),
+            self.audio_tag_id.to_hex().into(),
+            self.listened_at.into(),
+        ]
+    }
+}
+
+impl Columns for dto::PlayStat {
+    const COLUMNS: &'static [&'static str] = &["user_id", "audio_tag_id"];
+
+    fn get_row_id(&self) -> Option<String> {
+        self.id.map(|item| item.to_hex())
+    }
+
+    fn get_column_values(&self) -> Vec<Value> {
+        vec![to_user_key(self.user_id.as_ref()).into(), self.audio_tag_id.to_hex().into()]
+    }
+}
+
+impl Columns for dto::User {
+    const COLUMNS: &'static [&'static str] = &["username"];
+
+    fn get_row_id(&self) -> Option<String> {
+        self.id.map(|item| item.to_hex())
+    }
+
+    fn get_column_values(&self) -> Vec<Value> {
+        vec![self.username.clone().into()]
+    }
+}
+
+impl Columns for dto::Job {
+    const COLUMNS: &'static [&'static str] = &["finished"];
+
+    fn get_row_id(&self) -> Option<String> {
+        self.id.map(|item| item.to_hex())
+    }
+
+    fn get_column_values(&self) -> Vec<Value> {
+        vec![self.is_finished().into()]
+    }
+}
+
+// `seq` is the rowid which the search index refers, and unlike an implicit rowid it is kept by
+// VACUUM
+fn create_table_sql(table: &str, columns: &[&str]) -> String {
+    let columns = columns
+        .iter()
+        .map(|item| format!("{}, ", item))
+        .collect::<String>();
+
+    format!(
+        "CREATE TABLE IF NOT EXISTS {table} (seq INTEGER PRIMARY KEY, id TEXT NOT NULL UNIQUE, {columns}doc BLOB NOT NULL);",
+        table = table,
+        columns = columns
+    )
+}
+
+fn create_table<T: Columns>(conn: &Connection, table: &str, schema_sql: &str) -> Result<(), anyhow::Error> {
+    conn.execute_batch(&create_table_sql(table, T::COLUMNS))?;
+    conn.execute_batch(schema_sql)?;
+
+    Ok(())
+}
+
+struct Row {
+    id: String,
+    columns: Vec<Value>,
+    doc: Vec<u8>,
+}
+
+impl Row {
+    fn new<T: Serialize + Columns>(doc: &T) -> Result<Self, anyhow::Error> {
+        let id = match doc.get_row_id() {
+            Some(id) => id,
+            None => return Err(anyhow::anyhow!("object id is not set")),
+        };
+
+        Ok(Self {
+            id,
+            columns: doc.get_column_values(),
+            doc: bson::to_vec(doc)?,
+        })
+    }
+}
+
+fn insert_row<T: Columns>(conn: &Connection, table: &str, row: &Row) -> Result<(), anyhow::Error> {
+    let mut columns = vec!["id"];
+    columns.extend(T::COLUMNS);
+    columns.push("doc");
+
+    let sql = format!(
+        "INSERT INTO {} ({}) VALUES ({})",
+        table,
+        columns.join(", "),
+        vec!["?"; columns.len()].join(", ")
+    );
+
+    let values = std::iter::once(Value::Text(row.id.clone()))
+        .chain(row.columns.iter().cloned())
+        .chain(std::iter::once(Value::Blob(row.doc.clone())));
+
+    conn.execute(&sql, params_from_iter(values))?;
+
+    Ok(())
+}
+
+fn update_row<T: Columns>(conn: &Connection, table: &str, row: &Row) -> Result<(), anyhow::Error> {
+    let assignments = T::COLUMNS
+        .iter()
+        .chain(["doc"].iter())
+        .map(|item| format!("{} = ?", item))
+        .collect::<Vec<_>>();
+
+    let sql = format!("UPDATE {} SET {} WHERE id = ?", table, assignments.join(", "));
+
+    let values = row.columns
+        .iter()
+        .cloned()
+        .chain([Value::Blob(row.doc.clone()), Value::Text(row.id.clone())]);
+
+    conn.execute(&sql, params_from_iter(values))?;
+
+    Ok(())
+}
+
+fn query_docs<T: DeserializeOwned>(
+    conn: &Connection,
+    sql: &str,
+    params: impl rusqlite::Params,
+) -> Result<Vec<T>, anyhow::Error> {
+    let mut stmt = conn.prepare(sql)?;
+    let docs = stmt
+        .query_map(params, |row| row.get::<_, Vec<u8>>(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let docs = docs
+        .iter()
+        .map(|item| bson::from_slice(item))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(docs)
+}
+
+fn query_doc<T: DeserializeOwned>(
+    conn: &Connection,
+    sql: &str,
+    params: impl rusqlite::Params,
+) -> Result<Option<T>, anyhow::Error> {
+    let doc = conn
+        .query_row(sql, params, |row| row.get::<_, Vec<u8>>(0))
+        .optional()?;
+
+    match doc {
+        Some(doc) => Ok(Some(bson::from_slice(&doc)?)),
+        None => Ok(None),
+    }
+}
+
+// Reads and writes the first document matched by the condition in a transaction, and returns
+// whether it is matched
+fn update_doc<T, F>(
+    conn: &mut Connection,
+    table: &str,
+    condition: &str,
+    params: impl rusqlite::Params,
+    update_fn: F,
+) -> Result<bool, anyhow::Error>
+where
+    T: Serialize + DeserializeOwned + Columns,
+    F: FnOnce(&mut T),
+{
+    let tx = conn.transaction()?;
+
+    let mut doc: T = match query_doc(&tx, &format!("SELECT doc FROM {} WHERE {} LIMIT 1", table, condition), params)? {
+        Some(doc) => doc,
+        None => return Ok(false),
+    };
+
+    update_fn(&mut doc);
+    update_row::<T>(&tx, table, &Row::new(&doc)?)?;
+
+    tx.commit()?;
+
+    Ok(true)
+}
+
+// Conditions of a WHERE clause, and their parameters in order
+#[derive(Clone, Default)]
+struct SqlFilter {
+    conditions: Vec<String>,
+    params: Vec<Value>,
+}
+
+impl SqlFilter {
+    fn add(&mut self, condition: String, params: Vec<Value>) {
+        self.conditions.push(condition);
+        self.params.extend(params);
+    }
+
+    fn add_range(&mut self, column: &str, range: (Option<i64>, Option<i64>)) {
+        if let Some(min) = range.0 {
+            self.add(format!("{} >= ?", column), vec![min.into()]);
+        }
+        if let Some(max) = range.1 {
+            self.add(format!("{} <= ?", column), vec![max.into()]);
+        }
+    }
+
+    fn and(&self, other: &SqlFilter) -> SqlFilter {
+        let mut filter = self.clone();
+        filter.conditions.extend(other.conditions.iter().cloned());
+        filter.params.extend(other.params.iter().cloned());
+
+        filter
+    }
+
+    fn to_where_clause(&self) -> String {
+        match self.conditions.is_empty() {
+            true => String::new(),
+            false => format!("WHERE {}", self.conditions.join(" AND ")),
+        }
+    }
+}
+
+fn create_placeholders(count: usize) -> String {
+    vec!["?"; count].join(", ")
+}
+
+// Terms of the query, which are split as the tokenizer of the search index does, and tags which
+// have any of these are matched
+fn create_search_match(query: &str) -> Option<String> {
+    let terms = query
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|item| !item.is_empty())
+        .map(|item| format!("\"{}\"", item))
+        .collect::<Vec<_>>();
+
+    (!terms.is_empty()).then(|| terms.join(" OR "))
+}
+
+// `*` matches any characters and `?` matches a character, and other characters are matched as is.
+// LIKE of SQLite ignores case of ASCII letters only, unlike the regex of MongoDB
+fn glob_to_like(pattern: &str) -> String {
+    let mut like = String::new();
+
+    for c in pattern.chars() {
+        match c {
+            '*' => like.push('%'),
+            '?' => like.push('_'),
+            '%' | '_' | '\\' => {
+                like.push('\\');
+                like.push(c);
+            },
+            c => like.push(c),
+        }
+    }
+
+    like
+}
+
+fn query_smart_rule(rule: &SmartRule, now: i64) -> (String, Vec<Value>) {
+    match rule {
+        SmartRule::All { rules } if rules.is_empty() => ("1".to_string(), Vec::new()),
+        SmartRule::Any { rules } if rules.is_empty() => ("0".to_string(), Vec::new()),
+        SmartRule::All { rules } | SmartRule::Any { rules } => {
+            let operator = if matches!(rule, SmartRule::All { .. }) { " AND " } else { " OR " };
+            let (conditions, params): (Vec<_>, Vec<_>) = rules
+                .iter()
+                .map(|item| query_smart_rule(item, now))
+                .unzip();
+
+            (format!("({})", conditions.join(operator)), params.concat())
+        },
+        SmartRule::GenreIn { genres } if genres.is_empty() => ("0".to_string(), Vec::new()),
+        SmartRule::GenreIn { genres } => (
+            format!("genre IN ({})", create_placeholders(genres.len())),
+            genres.iter().map(|item| item.clone().into()).collect(),
+        ),
+        SmartRule::YearBetween { min, max } => {
+            let mut filter = SqlFilter::default();
+            filter.add("year IS NOT NULL".to_string(), Vec::new());
+            filter.add_range("year", (min.map(i64::from), max.map(i64::from)));
+
+            (format!("({})", filter.conditions.join(" AND ")), filter.params)
+        },
+        SmartRule::TextMatch { field, pattern } => (
+            format!("{} LIKE ? ESCAPE '\\'", field.get_key()),
+            vec![glob_to_like(pattern).into()],
+        ),
+        SmartRule::AddedWithin { days } => (
+            "added_timestamp >= ?".to_string(),
+            vec![(now - *days as i64 * 24 * 60 * 60).into()],
+        ),
+    }
+}
+
+// ties are ordered by the ids of tags, as `document::smart_playlist::sort_smart_playlist`
+fn sort_smart_playlist(order: &dto::SmartPlaylistOrder) -> String {
+    let direction = if order.descending { "DESC" } else { "ASC" };
+
+    match order.field {
+        SmartPlaylistOrderField::Added => format!("added_timestamp {}, id", direction),
+        SmartPlaylistOrderField::Title => format!("title {}, id", direction),
+        SmartPlaylistOrderField::Artist => format!("artist {}, album, disc, track, id", direction),
+        SmartPlaylistOrderField::Album => format!("album {}, disc, track, id", direction),
+        SmartPlaylistOrderField::Year => format!("year {}, id", direction),
+        SmartPlaylistOrderField::Duration => format!("duration {}, id", direction),
+    }
+}
+
+pub struct SqliteTable<T> {
+    _object: Option<T>,
+    conn: Arc<Mutex<Connection>>,
+    table: &'static str,
+}
+
+impl<T> SqliteTable<T> {
+    fn new(conn: Arc<Mutex<Connection>>, table: &'static str) -> Self {
+        Self {
+            _object: None,
+            conn,
+            table,
+        }
+    }
+
+    // Queries run on the blocking thread pool, as rusqlite blocks while waiting for the file
+    async fn run<F, R>(&self, f: F) -> Result<R, anyhow::Error>
+    where
+        F: FnOnce(&mut Connection, &'static str) -> Result<R, anyhow::Error> + Send + 'static,
+        R: Send + 'static,
+    {
+        let conn = self.conn.clone();
+        let table = self.table;
+
+        let res = tokio::task::spawn_blocking(move || {
+            let mut conn = match conn.lock() {
+                Ok(conn) => conn,
+                Err(_) => return Err(anyhow::anyhow!("sqlite connection is poisoned")),
+            };
+
+            f(&mut conn, table)
+        }).await?;
+
+        if res.is_err() {
+            metrics::DB_ERRORS.with_label_values(&["sqlite"]).inc();
+        }
+
+        res
+    }
+
+    async fn get_docs_by_ids(&self, column: &'static str, ids: &[ObjectId]) -> Result<Vec<T>, anyhow::Error>
+    where
+        T: DeserializeOwned + Send + 'static,
+    {
+        let ids = ids.iter().map(|item| item.to_hex()).collect::<Vec<_>>();
+
+        self.run(move |conn, table| {
+            let mut docs = Vec::with_capacity(ids.len());
+
+            for chunk in ids.chunks(MAX_QUERY_IDS) {
+                let sql = format!("SELECT doc FROM {} WHERE {} IN ({})", table, column, create_placeholders(chunk.len()));
+
+                docs.extend(query_docs(conn, &sql, params_from_iter(chunk.iter()))?);
+            }
+
+            Ok(docs)
+        }).await
+    }
+}
+
+#[async_trait]
+impl<T> DocumentStorage<T> for SqliteTable<T>
+where
+    T: Serialize + DeserializeOwned + Columns + Sync + Send + 'static,
+{
+    async fn create(&self, doc: &T) -> Result<(), anyhow::Error> {
+        let row = Row::new(doc)?;
+
+        self.run(move |conn, table| insert_row::<T>(conn, table, &row)).await
+    }
+
+    async fn create_many(&self, docs: Vec<T>) -> Result<(), anyhow::Error> {
+        let rows = docs
+            .iter()
+            .map(Row::new)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        self.run(move |conn, table| {
+            let tx = conn.transaction()?;
+
+            for row in rows.iter() {
+                insert_row::<T>(&tx, table, row)?;
+            }
+
+            tx.commit()?;
+
+            Ok(())
+        }).await
+    }
+
+    async fn get(&self, id: &ObjectId) -> Result<Option<T>, anyhow::Error> {
+        let id = id.to_hex();
+
+        self.run(move |conn, table| {
+            query_doc(conn, &format!("SELECT doc FROM {} WHERE id = ?1", table), params![id])
+        }).await
+    }
+
+    async fn get_all(&self) -> Result<Vec<T>, anyhow::Error> {
+        self.run(|conn, table| {
+            query_docs(conn, &format!("SELECT doc FROM {}", table), [])
+        }).await
+    }
+
+    async fn get_many(&self, ids: &[ObjectId]) -> Result<Vec<T>, anyhow::Error> {
+        self.get_docs_by_ids("id", ids).await
+    }
+
+    async fn get_paginated(&self, limit: i64, page: u64) -> Result<Vec<T>, anyhow::Error> {
+        let offset = limit * (page as i64 - 1);
+
+        self.run(move |conn, table| {
+            query_docs(
+                conn,
+                &format!("SELECT doc FROM {} ORDER BY seq LIMIT ?1 OFFSET ?2", table),
+                params![limit, offset]
+            )
+        }).await
+    }
+
+    async fn count(&self) -> Result<u64, anyhow::Error> {
+        self.run(|conn, table| {
+            let count: i64 = conn.query_row(&format!("SELECT COUNT(*) FROM {}", table), [], |row| row.get(0))?;
+
+            Ok(count as u64)
+        }).await
+    }
+
+    async fn update(&self, id: &ObjectId, doc: &T) -> Result<(), anyhow::Error> {
+        let mut row = Row::new(doc)?;
+        row.id = id.to_hex();
+
+        self.run(move |conn, table| update_row::<T>(conn, table, &row)).await
+    }
+
+    async fn update_many(&self, docs: &[T]) -> Vec<Result<(), anyhow::Error>> {
+        let mut update_results = Vec::with_capacity(docs.len());
+
+        for doc in docs.iter() {
+            let update_res = match Row::new(doc) {
+                Ok(row) => self.run(move |conn, table| update_row::<T>(conn, table, &row)).await,
+                Err(err) => Err(err),
+            };
+
+            update_results.push(update_res);
+        }
+
+        update_results
+    }
+
+    async fn delete(&self, id: &ObjectId) -> Result<u64, anyhow::Error> {
+        let id = id.to_hex();
+
+        self.run(move |conn, table| {
+            let deleted_count = conn.execute(&format!("DELETE FROM {} WHERE id = ?1", table), params![id])?;
+
+            Ok(deleted_count as u64)
+        }).await
+    }
+
+    async fn delete_many(&self, ids: &[ObjectId]) -> Result<u64, anyhow::Error> {
+        let ids = ids.iter().map(|item| item.to_hex()).collect::<Vec<_>>();
+
+        self.run(move |conn, table| {
+            let tx = conn.transaction()?;
+            let mut deleted_count = 0;
+
+            for chunk in ids.chunks(MAX_QUERY_IDS) {
+                let sql = format!("DELETE FROM {} WHERE id IN ({})", table, create_placeholders(chunk.len()));
+
+                deleted_count += tx.execute(&sql, params_from_iter(chunk.iter()))?;
+            }
+
+            tx.commit()?;
+
+            Ok(deleted_count as u64)
+        }).await
+    }
+}
+
+#[async_trait]
+impl<T> PathStorage<T> for SqliteTable<T>
+where
+    T: Serialize + DeserializeOwned + Columns + Sync + Send + 'static + GetPathValue,
+{
+    async fn get_by_path(&self, path: &Path) -> Result<Vec<T>, anyhow::Error> {
+        self.get_by_materialized_path(&util::path::path_to_materialized(path)?).await
+    }
+
+    async fn get_by_materialized_path(&self, path: &str) -> Result<Vec<T>, anyhow::Error> {
+        let path = path.to_string();
+
+        self.run(move |conn, table| {
+            query_docs(
+                conn,
+                &format!("SELECT doc FROM {} WHERE path >= ?1 AND path < ?2", table),
+                params![path, util::path::get_materialized_upper_bound(&path)]
+            )
+        }).await
+    }
+
+    async fn get_by_exact_materialized_path(&self, path: &str) -> Result<Option<T>, anyhow::Error> {
+        let path = path.to_string();
+
+        self.run(move |conn, table| {
+            query_doc(conn, &format!("SELECT doc FROM {} WHERE path = ?1 LIMIT 1", table), params![path])
+        }).await
+    }
+
+    async fn delete_by_path(&self, path: &Path) -> Result<u64, anyhow::Error> {
+        let path = util::path::path_to_materialized(path)?;
+
+        self.run(move |conn, table| {
+            let deleted_count = conn.execute(
+                &format!("DELETE FROM {} WHERE path >= ?1 AND path < ?2", table),
+                params![path, util::path::get_materialized_upper_bound(&path)]
+            )?;
+
+            Ok(deleted_count as u64)
+        }).await
+    }
+
+    async fn update_modified_timestamp(&self, doc: &T, timestamp: i64) -> Result<(), anyhow::Error> {
+        let path = doc.get_mat_path_val().to_string();
+
+        self.run(move |conn, table| {
+            let tx = conn.transaction()?;
+
+            let row = tx
+                .query_row(
+                    &format!("SELECT id, doc FROM {} WHERE path >= ?1 AND path < ?2 ORDER BY path LIMIT 1", table),
+                    params![path, util::path::get_materialized_upper_bound(&path)],
+                    |row| Ok((row.get::<_, String>(0)?, row.get::<_, Vec<u8>>(1)?))
+                )
+                .optional()?;
+
+            // the modified time is not a column
+            if let Some((id, doc)) = row {
+                let mut doc: Document = bson::from_slice(&doc)?;
+                doc.insert("modified_timestamp", timestamp);
+
+                tx.execute(
+                    &format!("UPDATE {} SET doc = ?2 WHERE id = ?1", table),
+                    params![id, bson::to_vec(&doc)?]
+                )?;
+            }
+
+            tx.commit()?;
+
+            Ok(())
+        }).await
+    }
+
+    async fn check_exists_by_path(&self, path: &Path) -> Result<bool, anyhow::Error> {
+        let path = util::path::path_to_materialized(path)?;
+
+        self.run(move |conn, table| {
+            let exists = conn
+                .query_row(
+                    &format!("SELECT 1 FROM {} WHERE path >= ?1 AND path < ?2 LIMIT 1", table),
+                    params![path, util::path::get_materialized_upper_bound(&path)],
+                    |_row| Ok(())
+                )
+                .optional()?;
+
+            Ok(exists.is_some())
+        }).await
+    }
+}
+
+#[async_trait]
+impl AudioFileStorage for SqliteTable<dto::AudioFile> {
+    async fn get_by_audio_tag(&self, audio_tag_id: &ObjectId) -> Result<Option<dto::AudioFile>, anyhow::Error> {
+        let audio_tag_id = audio_tag_id.to_hex();
+
+        self.run(move |conn, table| {
+            query_doc(
+                conn,
+                &format!("SELECT doc FROM {} WHERE audio_tag_refer = ?1 LIMIT 1", table),
+                params![audio_tag_id]
+            )
+        }).await
+    }
+
+    async fn get_many_by_audio_tags(&self, audio_tag_ids: &[ObjectId]) -> Result<Vec<dto::AudioFile>, anyhow::Error> {
+        self.get_docs_by_ids("audio_tag_refer", audio_tag_ids).await
+    }
+
+    async fn get_by_filename(&self, parent_path: &str, filename: &str) -> Result<Option<dto::AudioFile>, anyhow::Error> {
+        let parent_path = parent_path.to_string();
+        let filename = filename.to_string();
+
+        self.run(move |conn, table| {
+            query_doc(
+                conn,
+                &format!("SELECT doc FROM {} WHERE path = ?1 AND filename = ?2 LIMIT 1", table),
+                params![parent_path, filename]
+            )
+        }).await
+    }
+}
+
+#[async_trait]
+impl AudioTagStorage for SqliteTable<dto::AudioTag> {
+    async fn search(&self, filter: &SearchFilter<'_>, skip: u64, limit: i64) -> Result<TagSearchRes, anyhow::Error> {
+        let search_match = create_search_match(filter.query);
+
+        // tags matched by the query and the duration, and the facet of genres or years is not
+        // filtered by itself
+        let mut matched_filter = SqlFilter::default();
+        matched_filter.add_range(
+            "duration",
+            (filter.duration_range.0.map(i64::from), filter.duration_range.1.map(i64::from))
+        );
+
+        let mut genre_filter = SqlFilter::default();
+        if !filter.genres.is_empty() {
+            genre_filter.add(
+                format!("genre IN ({})", create_placeholders(filter.genres.len())),
+                filter.genres.iter().map(|item| item.clone().into()).collect()
+            );
+        }
+
+        let mut year_filter = SqlFilter::default();
+        year_filter.add_range("year", (filter.year_range.0.map(i64::from), filter.year_range.1.map(i64::from)));
+
+        let hits_order = match search_match {
+            Some(_) => "score DESC, id",
+            None => "artist, album, disc, track, id",
+        };
+
+        self.run(move |conn, table| {
+            let (source, mut source_params) = match search_match {
+                Some(search_match) => (
+                    format!(
+                        "{table} JOIN (SELECT rowid, {score} AS score FROM audio_tag_search WHERE audio_tag_search MATCH ?) AS search \
+                        ON search.rowid = {table}.seq",
+                        table = table,
+                        score = SEARCH_SCORE_EXPR
+                    ),
+                    vec![Value::Text(search_match)],
+                ),
+                None => (format!("{table} JOIN (SELECT 0.0 AS score) AS search", table = table), Vec::new()),
+            };
+            source_params.extend(matched_filter.params.iter().cloned());
+
+            let matched = format!(
+                "WITH matched AS (SELECT {table}.*, search.score AS score FROM {source} {where_clause})",
+                table = table,
+                source = source,
+                where_clause = matched_filter.to_where_clause()
+            );
+
+            let query_values = |filter: &SqlFilter, extra_params: Vec<Value>| {
+                source_params
+                    .iter()
+                    .cloned()
+                    .chain(filter.params.iter().cloned())
+                    .chain(extra_params)
+                    .collect::<Vec<_>>()
+            };
+
+            let hits_filter = genre_filter.and(&year_filter);
+
+            let mut stmt = conn.prepare(&format!(
+                "{} SELECT doc, score FROM matched {} ORDER BY {} LIMIT ? OFFSET ?",
+                matched,
+                hits_filter.to_where_clause(),
+                hits_order
+            ))?;
+            let hits = stmt
+                .query_map(
+                    params_from_iter(query_values(&hits_filter, vec![limit.max(0).into(), (skip as i64).into()])),
+                    |row| Ok((row.get::<_, Vec<u8>>(0)?, row.get::<_, f64>(1)?))
+                )?
+                .collect::<Result<Vec<_>, _>>()?
+                .into_iter()
+                .map(|(doc, score)| Ok((bson::from_slice(&doc)?, score)))
+                .collect::<Result<Vec<_>, anyhow::Error>>()?;
+
+            let total: i64 = conn.query_row(
+                &format!("{} SELECT COUNT(*) FROM matched {}", matched, hits_filter.to_where_clause()),
+                params_from_iter(query_values(&hits_filter, Vec::new())),
+                |row| row.get(0)
+            )?;
+
+            let mut genre_facet_filter = year_filter.clone();
+            genre_facet_filter.add("genre IS NOT NULL".to_string(), Vec::new());
+
+            let mut stmt = conn.prepare(&format!(
+                "{} SELECT genre, COUNT(*) AS count FROM matched {} GROUP BY genre ORDER BY count DESC, genre LIMIT ?",
+                matched,
+                genre_facet_filter.to_where_clause()
+            ))?;
+            let genres = stmt
+                .query_map(
+                    params_from_iter(query_values(&genre_facet_filter, vec![MAX_FACET_VALUES.into()])),
+                    |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)? as u64))
+                )?
+                .collect::<Result<Vec<_>, _>>()?;
+
+            let mut year_facet_filter = genre_filter.clone();
+            year_facet_filter.add("year IS NOT NULL".to_string(), Vec::new());
+
+            let mut stmt = conn.prepare(&format!(
+                "{} SELECT year, COUNT(*) FROM matched {} GROUP BY year ORDER BY year DESC",
+                matched,
+                year_facet_filter.to_where_clause()
+            ))?;
+            let years = stmt
+                .query_map(
+                    params_from_iter(query_values(&year_facet_filter, Vec::new())),
+                    |row| Ok((row.get::<_, i32>(0)?, row.get::<_, i64>(1)? as u64))
+                )?
+                .collect::<Result<Vec<_>, _>>()?;
+
+            Ok(TagSearchRes {
+                hits,
+                total: total as u64,
+                genres,
+                years,
+            })
+        }).await
+    }
+
+    async fn get_albums(
+        &self,
+        album_query: AlbumQuery<'_>,
+        skip: Option<u64>,
+        limit: Option<i64>,
+    ) -> Result<Vec<AlbumSummary>, anyhow::Error> {
+        let mut filter = SqlFilter::default();
+        filter.add("album IS NOT NULL".to_string(), Vec::new());
+
+        match album_query {
+            AlbumQuery::All => (),
+            AlbumQuery::Album { album, album_artist } => filter.add(
+                format!("album = ? AND {} = ?", ALBUM_ARTIST_EXPR),
+                vec![album.to_string().into(), album_artist.to_string().into()]
+            ),
+            AlbumQuery::ArtistAlbums(artist) => filter.add(
+                format!("{} = ?", ALBUM_ARTIST_EXPR),
+                vec![artist.to_string().into()]
+            ),
+            // summaries of appearances count all tracks of albums, not only ones of the artist
+            AlbumQuery::ArtistAppearances(artist) => filter.add(
+                format!(
+                    "(album, {}) IN (\
+                        SELECT album, album_artist FROM {} \
+                        WHERE artist = ? AND album IS NOT NULL AND album_artist IS NOT NULL AND album_artist != ?\
+                    )",
+                    ALBUM_ARTIST_EXPR,
+                    self.table
+                ),
+                vec![artist.to_string().into(), artist.to_string().into()]
+            ),
+        }
+
+        filter.params.push(limit.unwrap_or(-1).into());
+        filter.params.push((skip.unwrap_or_default() as i64).into());
+
+        self.run(move |conn, table| {
+            let mut stmt = conn.prepare(&format!(
+                "SELECT album, {album_artist} AS group_album_artist, MAX(year), COUNT(*), COUNT(DISTINCT disc), \
+                    IFNULL(SUM(duration), 0), MAX(artwork_id), COUNT(DISTINCT artist) > 1 \
+                FROM {table} {where_clause} \
+                GROUP BY album, group_album_artist \
+                ORDER BY group_album_artist, MAX(year), album \
+                LIMIT ? OFFSET ?",
+                album_artist = ALBUM_ARTIST_EXPR,
+                table = table,
+                where_clause = filter.to_where_clause()
+            ))?;
+
+            let albums = stmt
+                .query_map(params_from_iter(filter.params.iter()), |row| Ok(AlbumSummary {
+                    album: row.get(0)?,
+                    album_artist: row.get::<_, Option<String>>(1)?.unwrap_or_default(),
+                    year: row.get(2)?,
+                    track_count: row.get(3)?,
+                    disc_count: row.get(4)?,
+                    duration: row.get::<_, i64>(5)? as u64,
+                    artwork_id: row.get(6)?,
+                    // tracks of a compilation have various artists under an album artist
+                    compilation: row.get(7)?,
+                }))?
+                .collect::<Result<Vec<_>, _>>()?;
+
+            Ok(albums)
+        }).await
+    }
+
+    async fn get_album_tracks(&self, album: &str, album_artist: &str) -> Result<Vec<dto::AudioTag>, anyhow::Error> {
+        let album = album.to_string();
+        let album_artist = album_artist.to_string();
+
+        self.run(move |conn, table| {
+            query_docs(
+                conn,
+                &format!("SELECT doc FROM {} WHERE album = ?1 AND {} = ?2", table, ALBUM_ARTIST_EXPR),
+                params![album, album_artist]
+            )
+        }).await
+    }
+
+    async fn get_artists(&self, skip: u64, limit: i64) -> Result<Vec<ArtistSummary>, anyhow::Error> {
+        self.run(move |conn, table| {
+            let mut stmt = conn.prepare(&format!(
+                "SELECT name, \
+                    (SELECT COUNT(DISTINCT album) FROM {table} WHERE {album_artist} = name), \
+                    (SELECT COUNT(*) FROM {table} WHERE artist = name) \
+                FROM (\
+                    SELECT artist AS name FROM {table} WHERE artist IS NOT NULL \
+                    UNION \
+                    SELECT {album_artist} FROM {table} WHERE {album_artist} IS NOT NULL\
+                ) \
+                ORDER BY name \
+                LIMIT ?1 OFFSET ?2",
+                table = table,
+                album_artist = ALBUM_ARTIST_EXPR
+            ))?;
+
+            let artists = stmt
+                .query_map(params![limit.max(0), skip as i64], |row| Ok(ArtistSummary {
+                    name: row.get(0)?,
+                    album_count: row.get(1)?,
+                    track_count: row.get(2)?,
+                }))?
+                .collect::<Result<Vec<_>, _>>()?;
+
+            Ok(artists)
+        }).await
+    }
+
+    async fn count_artist_tracks(&self, artist: &str) -> Result<u64, anyhow::Error> {
+        let artist = artist.to_string();
+
+        self.run(move |conn, table| {
+            let count: i64 = conn.query_row(
+                &format!("SELECT COUNT(*) FROM {} WHERE artist = ?1", table),
+                params![artist],
+                |row| row.get(0)
+            )?;
+
+            Ok(count as u64)
+        }).await
+    }
+
+    async fn evaluate_smart_rule(
+        &self,
+        rule: &dto::SmartRule,
+        order: &dto::SmartPlaylistOrder,
+        limit: Option<u32>,
+    ) -> Result<Vec<ObjectId>, anyhow::Error> {
+        let (condition, mut params) = query_smart_rule(rule, Utc::now().timestamp());
+        params.push(limit.map_or(-1, i64::from).into());
+
+        let is_added_required = rule.is_time_relative() || order.field == SmartPlaylistOrderField::Added;
+        let order = sort_smart_playlist(order);
+
+        self.run(move |conn, table| {
+            // the added time is kept at audio files
+            let source = match is_added_required {
+                true => format!(
+                    "(SELECT {table}.*, \
+                        (SELECT added_timestamp FROM {audio_file} WHERE audio_tag_refer = {table}.id LIMIT 1) AS added_timestamp \
+                    FROM {table})",
+                    table = table,
+                    audio_file = AUDIO_FILE_TABLE
+                ),
+                false => table.to_string(),
+            };
+
+            let mut stmt = conn.prepare(&format!(
+                "SELECT id FROM {} WHERE {} ORDER BY {} LIMIT ?",
+                source,
+                condition,
+                order
+            ))?;
+
+            let ids = stmt
+                .query_map(params_from_iter(params.iter()), |row| row.get::<_, String>(0))?
+                .collect::<Result<Vec<_>, _>>()?
+                .iter()
+                .map(|item| ObjectId::parse_str(item))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            Ok(ids)
+        }).await
+    }
+}
+
+#[async_trait]
+impl ArtworkStorage for SqliteTable<dto::Artwork> {
+    async fn get_artwork(&self, artwork_id: &str) -> Result<Option<dto::Artwork>, anyhow::Error> {
+        let artwork_id = artwork_id.to_string();
+
+        self.run(move |conn, table| {
+            query_doc(conn, &format!("SELECT doc FROM {} WHERE id = ?1", table), params![artwork_id])
+        }).await
+    }
+}
+
+#[async_trait]
+impl PlaylistStorage for SqliteTable<dto::Playlist> {
+    async fn get_visible_paginated(
+        &self,
+        owner_id: &ObjectId,
+        limit: i64,
+        page: u64,
+    ) -> Result<Vec<dto::Playlist>, anyhow::Error> {
+        let owner_id = owner_id.to_hex();
+        let offset = limit * (page as i64 - 1);
+
+        self.run(move |conn, table| {
+            query_docs(
+                conn,
+                &format!(
+                    "SELECT doc FROM {} WHERE owner_id = ?1 OR shared OR owner_id IS NULL ORDER BY seq LIMIT ?2 OFFSET ?3",
+                    table
+                ),
+                params![owner_id, limit, offset]
+            )
+        }).await
+    }
+}
+
+#[async_trait]
+impl PlayEventStorage for SqliteTable<dto::PlayEvent> {
+    async fn get_playback(
+        &self,
+        user_id: Option<&ObjectId>,
+        playback_id: &str,
+    ) -> Result<Option<dto::PlayEvent>, anyhow::Error> {
+        let user_key = to_user_key(user_id);
+        let playback_id = playback_id.to_string();
+
+        self.run(move |conn, table| {
+            query_doc(
+                conn,
+                &format!("SELECT doc FROM {} WHERE user_id = ?1 AND playback_id = ?2", table),
+                params![user_key, playback_id]
+            )
+        }).await
+    }
+
+    async fn count_playback(
+        &self,
+        user_id: Option<&ObjectId>,
+        playback_id: &str,
+        position_ms: u64,
+        finished: bool,
+        listened_at: i64,
+    ) -> Result<bool, anyhow::Error> {
+        let user_key = to_user_key(user_id);
+        let playback_id = playback_id.to_string();
+
+        self.run(move |conn, table| {
+            update_doc(
+                conn,
+                table,
+                "user_id = ?1 AND playback_id = ?2 AND listened_at IS NULL",
+                params![user_key, playback_id],
+                |play_event: &mut dto::PlayEvent| {
+                    play_event.position_ms = position_ms;
+                    play_event.finished |= finished;
+                    play_event.listened_at = Some(listened_at);
+                }
+            )
+        }).await
+    }
+
+    async fn update_playback(
+        &self,
+        user_id: Option<&ObjectId>,
+        playback_id: &str,
+        position_ms: u64,
+        finished: bool,
+    ) -> Result<(), anyhow::Error> {
+        let user_key = to_user_key(user_id);
+        let playback_id = playback_id.to_string();
+
+        self.run(move |conn, table| {
+            update_doc(
+                conn,
+                table,
+                "user_id = ?1 AND playback_id = ?2",
+                params![user_key, playback_id],
+                |play_event: &mut dto::PlayEvent| {
+                    play_event.position_ms = position_ms;
+                    play_event.finished |= finished;
+                }
+            )?;
+
+            Ok(())
+        }).await
+    }
+
+    async fn get_history(
+        &self,
+        query: &PlayHistoryQuery<'_>,
+        ascending: bool,
+        skip: Option<u64>,
+        limit: Option<i64>,
+    ) -> Result<Vec<dto::PlayEvent>, anyhow::Error> {
+        let mut filter = SqlFilter::default();
+        filter.add("user_id = ?".to_string(), vec![to_user_key(query.user_id).into()]);
+        filter.add("listened_at IS NOT NULL".to_string(), Vec::new());
+        filter.add_range("listened_at", ((query.since > 0).then_some(query.since), (query.until > 0).then_some(query.until)));
+
+        if let Some(audio_tag_id) = query.audio_tag_id {
+            filter.add("audio_tag_id = ?".to_string(), vec![audio_tag_id.to_hex().into()]);
+        }
+
+        filter.params.push(limit.unwrap_or(-1).into());
+        filter.params.push((skip.unwrap_or_default() as i64).into());
+
+        let direction = if ascending { "ASC" } else { "DESC" };
+
+        self.run(move |conn, table| {
+            query_docs(
+                conn,
+                &format!(
+                    "SELECT doc FROM {table} {where_clause} ORDER BY listened_at {direction}, id {direction} LIMIT ? OFFSET ?",
+                    table = table,
+                    where_clause = filter.to_where_clause(),
+                    direction = direction
+                ),
+                params_from_iter(filter.params.iter())
+            )
+        }).await
+    }
+}
+
+#[async_trait]
+impl PlayStatStorage for SqliteTable<dto::PlayStat> {
+    async fn increase_play_count(
+        &self,
+        user_id: Option<&ObjectId>,
+        audio_tag_id: &ObjectId,
+        timestamp: i64,
+    ) -> Result<(), anyhow::Error> {
+        let user_id = user_id.copied();
+        let audio_tag_id = *audio_tag_id;
+
+        self.run(move |conn, table| {
+            let updated = update_doc(
+                conn,
+                table,
+                "user_id = ?1 AND audio_tag_id = ?2",
+                params![to_user_key(user_id.as_ref()), audio_tag_id.to_hex()],
+                |play_stat: &mut dto::PlayStat| {
+                    play_stat.play_count += 1;
+                    play_stat.last_played_timestamp = play_stat.last_played_timestamp.max(timestamp);
+                }
+            )?;
+
+            // queries of the connection are serialized, so that the stat is not created twice
+            if !updated {
+                let play_stat = dto::PlayStat {
+                    id: Some(ObjectId::new()),
+                    user_id,
+                    audio_tag_id,
+                    play_count: 1,
+                    last_played_timestamp: timestamp,
+                };
+
+                insert_row::<dto::PlayStat>(conn, table, &Row::new(&play_stat)?)?;
+            }
+
+            Ok(())
+        }).await
+    }
+
+    async fn get_by_audio_tags(
+        &self,
+        user_id: Option<&ObjectId>,
+        audio_tag_ids: &[ObjectId],
+    ) -> Result<Vec<dto::PlayStat>, anyhow::Error> {
+        let user_key = to_user_key(user_id);
+        let audio_tag_ids = audio_tag_ids.iter().map(|item| item.to_hex()).collect::<Vec<_>>();
+
+        self.run(move |conn, table| {
+            let mut play_stats = Vec::with_capacity(audio_tag_ids.len());
+
+            for chunk in audio_tag_ids.chunks(MAX_QUERY_IDS) {
+                let sql = format!(
+                    "SELECT doc FROM {} WHERE user_id = ? AND audio_tag_id IN ({})",
+                    table,
+                    create_placeholders(chunk.len())
+                );
+
+                play_stats.extend(query_docs(
+                    conn,
+                    &sql,
+                    params_from_iter(std::iter::once(&user_key).chain(chunk.iter()))
+                )?);
+            }
+
+            Ok(play_stats)
+        }).await
+    }
+}
+
+#[async_trait]
+impl UserStorage for SqliteTable<dto::User> {
+    async fn get_by_username(&self, username: &str) -> Result<Option<dto::User>, anyhow::Error> {
+        let username = username.to_string();
+
+        self.run(move |conn, table| {
+            query_doc(conn, &format!("SELECT doc FROM {} WHERE username = ?1", table), params![username])
+        }).await
+    }
+
+    async fn revoke_tokens(&self, id: &ObjectId) -> Result<(), anyhow::Error> {
+        let id = id.to_hex();
+
+        self.run(move |conn, table| {
+            update_doc(conn, table, "id = ?1", params![id], |user: &mut dto::User| {
+                user.token_version += 1;
+            })?;
+
+            Ok(())
+        }).await
+    }
+}
+
+#[async_trait]
+impl JobStorage for SqliteTable<dto::Job> {
+    async fn get_unfinished(&self) -> Result<Vec<dto::Job>, anyhow::Error> {
+        self.run(|conn, table| {
+            query_docs(conn, &format!("SELECT doc FROM {} WHERE NOT finished", table), [])
+        }).await
+    }
+}
+
+fn open_storage(conn: Connection) -> Result<Storage, anyhow::Error> {
+    create_table::<dto::AudioLibrary>(&conn, "library_roots", "CREATE INDEX IF NOT EXISTS library_roots_path ON library_roots (path);")?;
+    create_table::<dto::AudioLibrary>(&conn, "libraries", "CREATE INDEX IF NOT EXISTS libraries_path ON libraries (path);")?;
+    create_table::<dto::AudioFile>(&conn, AUDIO_FILE_TABLE, "
+        CREATE INDEX IF NOT EXISTS audio_path ON audio (path, filename);
+        CREATE INDEX IF NOT EXISTS audio_audio_tag_refer ON audio (audio_tag_refer);")?;
+    create_table::<dto::AudioTag>(&conn, AUDIO_TAG_TABLE, AUDIO_TAG_SCHEMA_SQL)?;
+    create_table::<dto::Artwork>(&conn, "artworks", "")?;
+    create_table::<dto::Playlist>(&conn, "playlists", "")?;
+    create_table::<dto::SmartPlaylist>(&conn, "smart_playlists", "")?;
+    create_table::<dto::PlayEvent>(&conn, "play_events", PLAY_EVENT_SCHEMA_SQL)?;
+    create_table::<dto::PlayStat>(&conn, "play_stats", PLAY_STAT_SCHEMA_SQL)?;
+    create_table::<dto::User>(&conn, "users", "CREATE UNIQUE INDEX IF NOT EXISTS users_username ON users (username);")?;
+    create_table::<dto::Job>(&conn, "jobs", "CREATE INDEX IF NOT EXISTS jobs_finished ON jobs (finished);")?;
+
+    let conn = Arc::new(Mutex::new(conn));
+
+    Ok(Storage {
+        library_root: Box::new(SqliteTable::new(conn.clone(), "library_roots")),
+        library: Box::new(SqliteTable::new(conn.clone(), "libraries")),
+        audio_file: Box::new(SqliteTable::new(conn.clone(), AUDIO_FILE_TABLE)),
+        audio_tag: Box::new(SqliteTable::new(conn.clone(), AUDIO_TAG_TABLE)),
+        artwork: Box::new(SqliteTable::new(conn.clone(), "artworks")),
+        playlist: Box::new(SqliteTable::new(conn.clone(), "playlists")),
+        smart_playlist: Box::new(SqliteTable::new(conn.clone(), "smart_playlists")),
+        play_event: Box::new(SqliteTable::new(conn.clone(), "play_events")),
+        play_stat: Box::new(SqliteTable::new(conn.clone(), "play_stats")),
+        user: Box::new(SqliteTable::new(conn.clone(), "users")),
+        job: Box::new(SqliteTable::new(conn, "jobs")),
+    })
+}
+
+pub fn create_storage(path: &Path) -> Result<Storage, anyhow::Error> {
+    if let Some(parent_path) = path.parent() {
+        std::fs::create_dir_all(parent_path)?;
+    }
+
+    let conn = Connection::open(path)?;
+    // readers are not blocked while the library is synced
+    conn.execute_batch("PRAGMA journal_mode = WAL;")?;
+
+    info!("opened sqlite storage at {:?}", path);
+
+    open_storage(conn)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TagProps {
+        title: &'static str,
+        artist: &'static str,
+        album: &'static str,
+        album_artist: Option<&'static str>,
+        genre: &'static str,
+        year: i32,
+        track: u32,
+    }
+
+    fn create_audio_tag(props: TagProps) -> dto::AudioTag {
+        dto::AudioTag {
+            id: Some(ObjectId::new()),
+            title: Some(props.title.to_string()),
+            artist: Some(props.artist.to_string()),
+            album: Some(props.album.to_string()),
+            album_artist: props.album_artist.map(|item| item.to_string()),
+            genre: Some(props.genre.to_string()),
+            year: Some(props.year),
+            duration: Some(200 + props.track),
+            disc: Some(1),
+            track: Some(props.track),
+            ..Default::default()
+        }
+    }
+
+    // tags are created in order, so that their ids are in order as well
+    async fn create_test_storage() -> (Storage, Vec<dto::AudioTag>) {
+        let storage = open_storage(Connection::open_in_memory().unwrap()).unwrap();
+
+        let audio_tags = vec![
+            create_audio_tag(TagProps { title: "So What", artist: "Miles Davis", album: "Kind of Blue", album_artist: None, genre: "Jazz", year: 1959, track: 1 }),
+            create_audio_tag(TagProps { title: "Blue in Green", artist: "Miles Davis", album: "Kind of Blue", album_artist: None, genre: "Jazz", year: 1959, track: 3 }),
+            create_audio_tag(TagProps { title: "Naima", artist: "John Coltrane", album: "Giant Steps", album_artist: None, genre: "Jazz", year: 1960, track: 6 }),
+            create_audio_tag(TagProps { title: "Blue Monday", artist: "New Order", album: "Singles", album_artist: Some("Various Artists"), genre: "Electronic", year: 1983, track: 1 }),
+            create_audio_tag(TagProps { title: "Miles Away", artist: "Madonna", album: "Singles", album_artist: Some("Various Artists"), genre: "Pop", year: 2008, track: 2 }),
+        ];
+
+        for audio_tag in audio_tags.iter() {
+            storage.audio_tag.create(audio_tag).await.unwrap();
+        }
+
+        (storage, audio_tags)
+    }
+
+    fn get_ids(audio_tags: &[dto::AudioTag], indexes: &[usize]) -> Vec<ObjectId> {
+        indexes.iter().map(|item| audio_tags[*item].id.unwrap()).collect()
+    }
+
+    fn order_by(field: SmartPlaylistOrderField, descending: bool) -> dto::SmartPlaylistOrder {
+        dto::SmartPlaylistOrder { field, descending }
+    }
+
+    #[test]
+    fn test_glob_to_like() {
+        assert_eq!(glob_to_like("The *"), "The %");
+        assert_eq!(glob_to_like("?ound"), "_ound");
+        assert_eq!(glob_to_like("100%_\\"), "100\\%\\_\\\\");
+    }
+
+    #[test]
+    fn test_query_empty_smart_rules() {
+        assert_eq!(query_smart_rule(&SmartRule::All { rules: Vec::new() }, 0).0, "1");
+        assert_eq!(query_smart_rule(&SmartRule::Any { rules: Vec::new() }, 0).0, "0");
+        assert_eq!(query_smart_rule(&SmartRule::GenreIn { genres: Vec::new() }, 0).0, "0");
+    }
+
+    #[tokio::test]
+    async fn test_evaluate_smart_rule() {
+        let (storage, audio_tags) = create_test_storage().await;
+
+        let rule = SmartRule::All {
+            rules: vec![
+                SmartRule::GenreIn { genres: vec!["Jazz".to_string(), "Pop".to_string()] },
+                SmartRule::Any {
+                    rules: vec![
+                        SmartRule::YearBetween { min: None, max: Some(1959) },
+                        SmartRule::TextMatch { field: dto::SmartRuleTextField::Title, pattern: "*a?".to_string() },
+                        SmartRule::TextMatch { field: dto::SmartRuleTextField::Title, pattern: "n?ima".to_string() },
+                    ],
+                },
+            ],
+        };
+
+        let ids = storage.audio_tag
+            .evaluate_smart_rule(&rule, &order_by(SmartPlaylistOrderField::Year, true), None)
+            .await
+            .unwrap();
+        // tags of the same year are ordered by their ids
+        assert_eq!(ids, get_ids(&audio_tags, &[4, 2, 0, 1]));
+
+        let ids = storage.audio_tag
+            .evaluate_smart_rule(&rule, &order_by(SmartPlaylistOrderField::Artist, false), Some(2))
+            .await
+            .unwrap();
+        assert_eq!(ids, get_ids(&audio_tags, &[2, 4]));
+
+        // case of ASCII letters is ignored
+        let rule = SmartRule::TextMatch { field: dto::SmartRuleTextField::AlbumArtist, pattern: "various *".to_string() };
+        let ids = storage.audio_tag
+            .evaluate_smart_rule(&rule, &order_by(SmartPlaylistOrderField::Title, false), None)
+            .await
+            .unwrap();
+        assert_eq!(ids, get_ids(&audio_tags, &[3, 4]));
+    }
+
+    #[tokio::test]
+    async fn test_evaluate_added_smart_rule() {
+        let (storage, audio_tags) = create_test_storage().await;
+        let now = Utc::now().timestamp();
+
+        for (audio_tag, added_days_ago) in audio_tags.iter().zip([1, 40, 3, 2, 100]) {
+            let audio_file = dto::AudioFile {
+                id: Some(ObjectId::new()),
+                modified_timestamp: now,
+                parent_path: "/music".to_string(),
+                filename: format!("{}.flac", audio_tag.title.as_ref().unwrap()),
+                audio_tag_refer: audio_tag.id,
+                added_timestamp: Some(now - added_days_ago * 24 * 60 * 60),
+            };
+
+            storage.audio_file.create(&audio_file).await.unwrap();
+        }
+
+        let rule = SmartRule::AddedWithin { days: 30 };
+        let ids = storage.audio_tag
+            .evaluate_smart_rule(&rule, &order_by(SmartPlaylistOrderField::Added, true), None)
+            .await
+            .unwrap();
+        assert_eq!(ids, get_ids(&audio_tags, &[0, 3, 2]));
+
+        let rule = SmartRule::All { rules: Vec::new() };
+        let ids = storage.audio_tag
+            .evaluate_smart_rule(&rule, &order_by(SmartPlaylistOrderField::Added, false), None)
+            .await
+            .unwrap();
+        assert_eq!(ids, get_ids(&audio_tags, &[4, 1, 2, 3, 0]));
+    }
+
+    #[tokio::test]
+    async fn test_search() {
+        let (storage, audio_tags) = create_test_storage().await;
+
+        let filter = SearchFilter {
+            query: "blue",
+            genres: &[],
+            year_range: (None, None),
+            duration_range: (None, None),
+        };
+        let search_res = storage.audio_tag.search(&filter, 0, 10).await.unwrap();
+
+        // titles are weighted over albums
+        let hit_ids = search_res.hits.iter().map(|(item, _)| item.id.unwrap()).collect::<Vec<_>>();
+        assert_eq!(search_res.total, 3);
+        assert_eq!(hit_ids[2], audio_tags[0].id.unwrap());
+        assert_eq!(search_res.genres, vec![("Jazz".to_string(), 2), ("Electronic".to_string(), 1)]);
+        assert_eq!(search_res.years, vec![(1983, 1), (1959, 2)]);
+
+        // facets are not filtered by themselves
+        let genres = vec!["Jazz".to_string()];
+        let filter = SearchFilter {
+            query: "",
+            genres: &genres,
+            year_range: (Some(1960), None),
+            duration_range: (None, None),
+        };
+        let search_res = storage.audio_tag.search(&filter, 0, 10).await.unwrap();
+
+        let hit_ids = search_res.hits.iter().map(|(item, _)| item.id.unwrap()).collect::<Vec<_>>();
+        assert_eq!(hit_ids, get_ids(&audio_tags, &[2]));
+        assert_eq!(search_res.genres, vec![("Electronic".to_string(), 1), ("Jazz".to_string(), 1), ("Pop".to_string(), 1)]);
+        assert_eq!(search_res.years, vec![(1960, 1), (1959, 2)]);
+
+        let search_res = storage.audio_tag.search(&filter, 1, 10).await.unwrap();
+        assert!(search_res.hits.is_empty());
+        assert_eq!(search_res.total, 1);
+    }
+
+    #[tokio::test]
+    async fn test_get_albums_and_artists() {
+        let (storage, audio_tags) = create_test_storage().await;
+
+        let albums = storage.audio_tag.get_albums(AlbumQuery::All, None, None).await.unwrap();
+        let album_names = albums.iter().map(|item| (item.album.as_str(), item.album_artist.as_str())).collect::<Vec<_>>();
+        assert_eq!(album_names, vec![
+            ("Giant Steps", "John Coltrane"),
+            ("Kind of Blue", "Miles Davis"),
+            ("Singles", "Various Artists"),
+        ]);
+        assert_eq!((albums[1].track_count, albums[1].disc_count, albums[1].duration), (2, 1, 404));
+        assert!(!albums[1].compilation);
+        assert_eq!(albums[2].year, Some(2008));
+        assert!(albums[2].compilation);
+
+        let albums = storage.audio_tag.get_albums(AlbumQuery::ArtistAppearances("Madonna"), None, None).await.unwrap();
+        assert_eq!(albums.len(), 1);
+        assert_eq!(albums[0].track_count, 2);
+
+        let album_tracks = storage.audio_tag.get_album_tracks("Singles", "Various Artists").await.unwrap();
+        assert_eq!(album_tracks.len(), 2);
+
+        let artists = storage.audio_tag.get_artists(1, 3).await.unwrap();
+        let artist_counts = artists
+            .iter()
+            .map(|item| (item.name.as_str(), item.album_count, item.track_count))
+            .collect::<Vec<_>>();
+        assert_eq!(artist_counts, vec![
+            ("Madonna", 0, 1),
+            ("Miles Davis", 1, 2),
+            ("New Order", 0, 1),
+        ]);
+
+        assert_eq!(storage.audio_tag.count_artist_tracks("Miles Davis").await.unwrap(), 2);
+
+        // the search index follows updates and deletions
+        storage.audio_tag.delete(&audio_tags[0].id.unwrap()).await.unwrap();
+        let filter = SearchFilter {
+            query: "what",
+            genres: &[],
+            year_range: (None, None),
+            duration_range: (None, None),
+        };
+        assert_eq!(storage.audio_tag.search(&filter, 0, 10).await.unwrap().total, 0);
+    }
+}
@@ -0,0 +1,312 @@
+use std::{
+    convert::Infallible,
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use axum::{
+    async_trait,
+    body::{Body, HttpBody},
+    extract::{FromRequestParts, Query, State},
+    http::{header, request::Parts, HeaderValue, Method, Request, Response, StatusCode},
+    response::IntoResponse,
+    routing::{get, post},
+    Json, Router,
+};
+use cirrus_protobuf::{
+    api::{AudioLibraryReq, AudioLibraryRes, AudioMetaReq, AudioMetaRes, AudioTagRes, AudioTagSearchReq, AudioTagSearchRes, JobRes},
+    common::{ListRequest, RequestAction, Response as CirrusResponse},
+    audio_data_svc_server::AudioDataSvc,
+    audio_library_svc_server::AudioLibrarySvc,
+    audio_tag_svc_server::AudioTagSvc,
+};
+use tokio_stream::StreamExt;
+use tonic::{
+    body::BoxBody,
+    metadata::MetadataMap,
+    service::Interceptor,
+    transport::{server::{TcpConnectInfo, TlsConnectInfo}, NamedService},
+    Code, Status,
+};
+use tower::Service;
+use tower_http::cors::{AllowOrigin, Any, CorsLayer};
+
+use crate::service::{AudioDataSvcImpl, AudioLibrarySvcImpl, AudioTagSvcImpl, AuthInterceptor};
+
+// REST/JSON calls of tag, library and meta services, which are served under `/api` of the gRPC
+// port. Requests and responses are the JSON of the protobuf messages
+#[derive(Clone)]
+pub struct RestApi {
+    audio_tag: Arc<AudioTagSvcImpl>,
+    audio_library: Arc<AudioLibrarySvcImpl>,
+    audio_data: Arc<AudioDataSvcImpl>,
+    listener: AuthInterceptor,
+    admin: AuthInterceptor,
+}
+
+impl RestApi {
+    pub fn new(
+        audio_tag: Arc<AudioTagSvcImpl>,
+        audio_library: Arc<AudioLibrarySvcImpl>,
+        audio_data: Arc<AudioDataSvcImpl>,
+        listener: AuthInterceptor,
+        admin: AuthInterceptor,
+    ) -> Self {
+        Self {
+            audio_tag,
+            audio_library,
+            audio_data,
+            listener,
+            admin,
+        }
+    }
+
+    pub fn into_service(self, cors_allowed_origins: &[String]) -> RestApiSvc {
+        let router = Router::new()
+            .route("/api/tags", get(list_audio_tags))
+            .route("/api/tags/search", post(search_audio_tags))
+            .route("/api/libraries", get(list_audio_libraries).post(add_audio_library).delete(remove_audio_library))
+            .route("/api/libraries/analyze", post(analyze_audio_library))
+            .route("/api/libraries/refresh", post(refresh_audio_library))
+            .route("/api/meta", post(get_meta))
+            .fallback(not_found)
+            .with_state(self)
+            .layer(create_cors_layer(cors_allowed_origins));
+
+        RestApiSvc { router }
+    }
+}
+
+// Origins that browsers are allowed to call from, and "*" allows any
+pub(crate) fn create_cors_layer(allowed_origins: &[String]) -> CorsLayer {
+    let cors = CorsLayer::new()
+        .allow_methods([Method::GET, Method::POST, Method::DELETE])
+        .allow_headers([header::AUTHORIZATION, header::CONTENT_TYPE]);
+
+    match allowed_origins.iter().any(|item| item == "*") {
+        true => cors.allow_origin(Any),
+        false => cors.allow_origin(AllowOrigin::list(
+            allowed_origins.iter().filter_map(|item| item.parse::<HeaderValue>().ok())
+        )),
+    }
+}
+
+// Routes of the API as a service of the tonic server, which routes `/api/*` to it by the name
+#[derive(Clone)]
+pub struct RestApiSvc {
+    router: Router,
+}
+
+impl NamedService for RestApiSvc {
+    const NAME: &'static str = "api";
+}
+
+impl Service<Request<Body>> for RestApiSvc {
+    type Response = Response<BoxBody>;
+    type Error = Infallible;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        call_router(self.router.clone(), request)
+    }
+}
+
+// Calls the router with a request of the tonic server, and returns the response with its body
+pub(crate) fn call_router(
+    mut router: Router,
+    request: Request<Body>,
+) -> Pin<Box<dyn Future<Output = Result<Response<BoxBody>, Infallible>> + Send>> {
+    Box::pin(async move {
+        let res = router.call(request).await?;
+
+        Ok(res.map(|body| body
+            .map_err(|err| Status::from_error(err.into_inner()))
+            .boxed_unsync()
+        ))
+    })
+}
+
+// Headers and the connection of an HTTP request, which services read as those of gRPC requests
+pub(crate) struct GrpcRequest(tonic::Request<()>);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for GrpcRequest {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let mut request = tonic::Request::new(());
+        *request.metadata_mut() = MetadataMap::from_headers(parts.headers.clone());
+
+        // the remote address is read from these
+        if let Some(connect_info) = parts.extensions.get::<TcpConnectInfo>() {
+            request.extensions_mut().insert(connect_info.clone());
+        }
+
+        if let Some(connect_info) = parts.extensions.get::<TlsConnectInfo<TcpConnectInfo>>() {
+            request.extensions_mut().insert(connect_info.clone());
+        }
+
+        Ok(Self(request))
+    }
+}
+
+impl GrpcRequest {
+    // Players that can not set headers (e.g. `<audio>` elements) send the token at the query,
+    // which is used if the header is not set
+    pub(crate) fn with_access_token(mut self, access_token: Option<&str>) -> Self {
+        let metadata = self.0.metadata_mut();
+
+        if let Some(access_token) = access_token.filter(|_| !metadata.contains_key("authorization")) {
+            if let Ok(value) = format!("Bearer {}", access_token).parse() {
+                metadata.insert("authorization", value);
+            }
+        }
+
+        self
+    }
+
+    // Authorizes the request as the gRPC service does, and sets the message to it
+    pub(crate) fn authorize<T>(self, mut interceptor: AuthInterceptor, message: T) -> Result<tonic::Request<T>, Status> {
+        let (metadata, extensions, _) = interceptor.call(self.0)?.into_parts();
+
+        Ok(tonic::Request::from_parts(metadata, extensions, message))
+    }
+}
+
+pub(crate) struct ApiError(Status);
+
+impl From<Status> for ApiError {
+    fn from(status: Status) -> Self {
+        Self(status)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> axum::response::Response {
+        let body = serde_json::json!({
+            "code": self.0.code() as i32,
+            "message": self.0.message(),
+        });
+
+        (get_http_status(self.0.code()), Json(body)).into_response()
+    }
+}
+
+fn get_http_status(code: Code) -> StatusCode {
+    match code {
+        Code::Ok => StatusCode::OK,
+        Code::InvalidArgument | Code::FailedPrecondition | Code::OutOfRange => StatusCode::BAD_REQUEST,
+        Code::Unauthenticated => StatusCode::UNAUTHORIZED,
+        Code::PermissionDenied => StatusCode::FORBIDDEN,
+        Code::NotFound => StatusCode::NOT_FOUND,
+        Code::AlreadyExists | Code::Aborted => StatusCode::CONFLICT,
+        Code::ResourceExhausted => StatusCode::TOO_MANY_REQUESTS,
+        Code::Cancelled => StatusCode::REQUEST_TIMEOUT,
+        Code::Unimplemented => StatusCode::NOT_IMPLEMENTED,
+        Code::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+        Code::DeadlineExceeded => StatusCode::GATEWAY_TIMEOUT,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+async fn not_found() -> ApiError {
+    ApiError(Status::not_found("no such API"))
+}
+
+async fn list_audio_tags(
+    State(api): State<RestApi>,
+    request: GrpcRequest,
+    Query(req): Query<ListRequest>,
+) -> Result<Json<Vec<AudioTagRes>>, ApiError> {
+    let request = request.authorize(api.listener.clone(), req)?;
+    let res = api.audio_tag.list_audio_tags(request).await?
+        .into_inner()
+        .collect::<Result<Vec<_>, Status>>()
+        .await?;
+
+    Ok(Json(res))
+}
+
+async fn search_audio_tags(
+    State(api): State<RestApi>,
+    request: GrpcRequest,
+    Json(req): Json<AudioTagSearchReq>,
+) -> Result<Json<AudioTagSearchRes>, ApiError> {
+    let request = request.authorize(api.listener.clone(), req)?;
+    let res = api.audio_tag.search_audio_tags(request).await?;
+
+    Ok(Json(res.into_inner()))
+}
+
+async fn list_audio_libraries(
+    State(api): State<RestApi>,
+    request: GrpcRequest,
+    Query(req): Query<ListRequest>,
+) -> Result<Json<Vec<AudioLibraryRes>>, ApiError> {
+    let request = request.authorize(api.admin.clone(), req)?;
+    let res = api.audio_library.list_audio_libraries(request).await?
+        .into_inner()
+        .collect::<Result<Vec<_>, Status>>()
+        .await?;
+
+    Ok(Json(res))
+}
+
+async fn add_audio_library(
+    State(api): State<RestApi>,
+    request: GrpcRequest,
+    Json(req): Json<AudioLibraryReq>,
+) -> Result<Json<CirrusResponse>, ApiError> {
+    let request = request.authorize(api.admin.clone(), req)?;
+    let res = api.audio_library.add_audio_library(request).await?;
+
+    Ok(Json(res.into_inner()))
+}
+
+async fn remove_audio_library(
+    State(api): State<RestApi>,
+    request: GrpcRequest,
+    Json(req): Json<AudioLibraryReq>,
+) -> Result<Json<CirrusResponse>, ApiError> {
+    let request = request.authorize(api.admin.clone(), req)?;
+    let res = api.audio_library.remove_audio_library(request).await?;
+
+    Ok(Json(res.into_inner()))
+}
+
+async fn analyze_audio_library(
+    State(api): State<RestApi>,
+    request: GrpcRequest,
+) -> Result<Json<JobRes>, ApiError> {
+    let request = request.authorize(api.admin.clone(), RequestAction::default())?;
+    let res = api.audio_library.analyze_audio_library(request).await?;
+
+    Ok(Json(res.into_inner()))
+}
+
+async fn refresh_audio_library(
+    State(api): State<RestApi>,
+    request: GrpcRequest,
+) -> Result<Json<JobRes>, ApiError> {
+    let request = request.authorize(api.admin.clone(), RequestAction::default())?;
+    let res = api.audio_library.refresh_audio_library(request).await?;
+
+    Ok(Json(res.into_inner()))
+}
+
+async fn get_meta(
+    State(api): State<RestApi>,
+    request: GrpcRequest,
+    Json(req): Json<AudioMetaReq>,
+) -> Result<Json<AudioMetaRes>, ApiError> {
+    let request = request.authorize(api.listener.clone(), req)?;
+    let res = api.audio_data.get_meta(request).await?;
+
+    Ok(Json(res.into_inner()))
+}
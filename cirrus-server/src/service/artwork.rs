@@ -0,0 +1,49 @@
+use cirrus_protobuf::{
+    api::{ArtworkReq, ArtworkRes},
+    artwork_svc_server::ArtworkSvc,
+};
+use tonic::{Status, Response, Request};
+use tracing::{info, instrument};
+
+use crate::logic;
+
+use super::format_remote_addr;
+
+pub struct ArtworkSvcImpl {
+    logic: logic::Artwork,
+}
+
+impl ArtworkSvcImpl {
+    pub fn new(artwork: logic::Artwork) -> Self {
+        Self {
+            logic: artwork,
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl ArtworkSvc for ArtworkSvcImpl {
+    #[instrument(skip_all, fields(remote_addr = %format_remote_addr(&request)))]
+    async fn get_artwork(
+        &self,
+        request: Request<ArtworkReq>
+    ) -> Result<Response<ArtworkRes>, Status> {
+        info!("get artwork");
+
+        let req = request.get_ref();
+
+        let res = match self.logic.get_artwork(
+            &req.artwork_id,
+            req.size
+        ).await {
+            Ok(res) => Response::new(res),
+            Err(err) => return Err(match err.downcast_ref::<logic::ArtworkError>() {
+                Some(logic::ArtworkError::NotFound { .. }) => Status::not_found(err.to_string()),
+                Some(logic::ArtworkError::SizeNotAllowed { .. }) => Status::invalid_argument(err.to_string()),
+                None => Status::internal(err.to_string()),
+            }),
+        };
+
+        Ok(res)
+    }
+}
@@ -0,0 +1,162 @@
+use std::sync::Arc;
+
+use cirrus_protobuf::{
+    api::{CreateUserReq, LoginReq, RefreshTokenReq, TokenRes, UserRes},
+    common::Response as CirrusResponse,
+    auth_svc_server::AuthSvc,
+};
+use tonic::{metadata::MetadataMap, service::Interceptor, Status, Response, Code, Request};
+use tracing::{info, instrument};
+
+use crate::{logic, model::dto};
+
+use super::format_remote_addr;
+
+// Checks `authorization: Bearer <access token>` metadata, and returns claims of the token if
+// the user has the role or a higher one
+fn authorize(
+    auth: &logic::Auth,
+    metadata: &MetadataMap,
+    role: dto::UserRole,
+) -> Result<Option<logic::Claims>, Status> {
+    if !auth.is_enabled() {
+        return Ok(None);
+    }
+
+    let access_token = metadata
+        .get("authorization")
+        .and_then(|item| item.to_str().ok())
+        .and_then(|item| item.strip_prefix("Bearer "));
+
+    let access_token = match access_token {
+        Some(access_token) => access_token.trim(),
+        None => return Err(Status::unauthenticated("bearer token is not set")),
+    };
+
+    let claims = match auth.verify_access_token(access_token) {
+        Ok(claims) => claims,
+        Err(err) => return Err(Status::unauthenticated(err.to_string())),
+    };
+
+    if claims.role < role {
+        return Err(Status::permission_denied(format!("requires {:?} role", role)));
+    }
+
+    Ok(Some(claims))
+}
+
+#[derive(Clone)]
+pub struct AuthInterceptor {
+    auth: Arc<logic::Auth>,
+    role: dto::UserRole,
+}
+
+impl AuthInterceptor {
+    pub fn new(auth: Arc<logic::Auth>, role: dto::UserRole) -> Self {
+        Self {
+            auth,
+            role,
+        }
+    }
+}
+
+impl Interceptor for AuthInterceptor {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        if let Some(claims) = authorize(&self.auth, request.metadata(), self.role)? {
+            request.extensions_mut().insert(claims);
+        }
+
+        Ok(request)
+    }
+}
+
+pub struct AuthSvcImpl {
+    logic: Arc<logic::Auth>,
+}
+
+impl AuthSvcImpl {
+    pub fn new(auth: Arc<logic::Auth>) -> Self {
+        Self {
+            logic: auth,
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl AuthSvc for AuthSvcImpl {
+    #[instrument(skip_all, fields(remote_addr = %format_remote_addr(&request)))]
+    async fn login(
+        &self,
+        request: Request<LoginReq>
+    ) -> Result<Response<TokenRes>, Status> {
+        info!("login");
+
+        let req = request.get_ref();
+
+        let res = match self.logic.login(
+            &req.username,
+            &req.password
+        ).await {
+            Ok(res) => Response::new(res),
+            Err(err) => return Err(Status::unauthenticated(err.to_string())),
+        };
+
+        Ok(res)
+    }
+
+    #[instrument(skip_all, fields(remote_addr = %format_remote_addr(&request)))]
+    async fn refresh_token(
+        &self,
+        request: Request<RefreshTokenReq>
+    ) -> Result<Response<TokenRes>, Status> {
+        info!("refresh token");
+
+        let res = match self.logic.refresh_token(
+            &request.get_ref().refresh_token
+        ).await {
+            Ok(res) => Response::new(res),
+            Err(err) => return Err(Status::unauthenticated(err.to_string())),
+        };
+
+        Ok(res)
+    }
+
+    #[instrument(skip_all, fields(remote_addr = %format_remote_addr(&request)))]
+    async fn logout(
+        &self,
+        request: Request<RefreshTokenReq>
+    ) -> Result<Response<CirrusResponse>, Status> {
+        info!("logout");
+
+        let res = match self.logic.logout(
+            &request.get_ref().refresh_token
+        ).await {
+            Ok(_) => Response::new(CirrusResponse {
+                code: Code::Ok as u32,
+                status: "Logged out".to_string(),
+            }),
+            Err(err) => return Err(Status::unauthenticated(err.to_string())),
+        };
+
+        Ok(res)
+    }
+
+    #[instrument(skip_all, fields(remote_addr = %format_remote_addr(&request)))]
+    async fn create_user(
+        &self,
+        request: Request<CreateUserReq>
+    ) -> Result<Response<UserRes>, Status> {
+        info!("create user");
+
+        authorize(&self.logic, request.metadata(), dto::UserRole::Admin)?;
+
+        let res = match self.logic.create_user(
+            request.get_ref()
+        ).await {
+            Ok(res) => Response::new(res),
+            Err(err) => return Err(Status::invalid_argument(err.to_string())),
+        };
+
+        Ok(res)
+    }
+}
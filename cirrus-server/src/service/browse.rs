@@ -0,0 +1,133 @@
+use std::sync::Arc;
+
+use cirrus_protobuf::{
+    api::{AlbumReq, AlbumRes, AlbumSummary, ArtistReq, ArtistRes, ArtistSummary},
+    audio_browse_svc_server::AudioBrowseSvc,
+    common::ListRequest,
+};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Status, Response, Request};
+use tracing::{info, instrument};
+
+use crate::{logic, model::Storage};
+
+use super::format_remote_addr;
+
+pub struct AudioBrowseSvcImpl {
+    logic: logic::AudioBrowse,
+}
+
+impl AudioBrowseSvcImpl {
+    pub fn new(storage: Arc<Storage>) -> Self {
+        Self {
+            logic: logic::AudioBrowse::new(storage),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl AudioBrowseSvc for AudioBrowseSvcImpl {
+    type ListAlbumsStream = ReceiverStream<Result<AlbumSummary, Status>>;
+
+    #[instrument(skip_all, fields(remote_addr = %format_remote_addr(&request)))]
+    async fn list_albums(
+        &self,
+        request: Request<ListRequest>
+    ) -> Result<Response<Self::ListAlbumsStream>, Status> {
+        info!("list albums");
+
+        let req_page = request.get_ref().page;
+        let req_items_per_page = request.get_ref().items_per_page;
+
+        let (tx, rx) = mpsc::channel(16);
+
+        let res = match self.logic.list_albums(
+            req_items_per_page,
+            req_page
+        ).await {
+            Ok(res) => res,
+            Err(err) => return Err(Status::internal(err.to_string())),
+        };
+
+        tokio::spawn(async move {
+            for r in res.into_iter() {
+                if let Err(_err) = tx.send(Ok(r)).await {
+                    break;
+                }
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    #[instrument(skip_all, fields(remote_addr = %format_remote_addr(&request)))]
+    async fn get_album(
+        &self,
+        request: Request<AlbumReq>
+    ) -> Result<Response<AlbumRes>, Status> {
+        info!("get album");
+
+        let req = request.get_ref();
+
+        let res = match self.logic.get_album(
+            &req.album,
+            &req.album_artist
+        ).await {
+            Ok(res) => Response::new(res),
+            Err(err) => return Err(Status::not_found(err.to_string())),
+        };
+
+        Ok(res)
+    }
+
+    type ListArtistsStream = ReceiverStream<Result<ArtistSummary, Status>>;
+
+    #[instrument(skip_all, fields(remote_addr = %format_remote_addr(&request)))]
+    async fn list_artists(
+        &self,
+        request: Request<ListRequest>
+    ) -> Result<Response<Self::ListArtistsStream>, Status> {
+        info!("list artists");
+
+        let req_page = request.get_ref().page;
+        let req_items_per_page = request.get_ref().items_per_page;
+
+        let (tx, rx) = mpsc::channel(16);
+
+        let res = match self.logic.list_artists(
+            req_items_per_page,
+            req_page
+        ).await {
+            Ok(res) => res,
+            Err(err) => return Err(Status::internal(err.to_string())),
+        };
+
+        tokio::spawn(async move {
+            for r in res.into_iter() {
+                if let Err(_err) = tx.send(Ok(r)).await {
+                    break;
+                }
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    #[instrument(skip_all, fields(remote_addr = %format_remote_addr(&request)))]
+    async fn get_artist(
+        &self,
+        request: Request<ArtistReq>
+    ) -> Result<Response<ArtistRes>, Status> {
+        info!("get artist");
+
+        let res = match self.logic.get_artist(
+            &request.get_ref().name
+        ).await {
+            Ok(res) => Response::new(res),
+            Err(err) => return Err(Status::not_found(err.to_string())),
+        };
+
+        Ok(res)
+    }
+}
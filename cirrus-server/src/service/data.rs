@@ -1,58 +1,55 @@
-use async_trait::async_trait;
+use std::sync::Arc;
+
 use cirrus_protobuf::{api::{AudioMetaReq, AudioDataRes, AudioDataReq, AudioMetaRes}, audio_data_svc_server::AudioDataSvc};
-use mongodb::Client;
 use tokio::sync::mpsc;
 use tokio_stream::wrappers::ReceiverStream;
 use tonic::{Status, Response, Code, Request};
+use tracing::{debug, info, instrument, warn, Instrument, Span};
 
-use crate::{logic, model};
+use crate::{logic, metrics, model::Storage, state::ServerState};
 
-use super::GetMongoClient;
+use super::{format_remote_addr, get_user_id};
 
 pub struct AudioDataSvcImpl {
     logic: logic::AudioFile,
+    play_history: Arc<logic::PlayHistory>,
 }
 
-impl Default for AudioDataSvcImpl {
-    fn default() -> Self {
+impl AudioDataSvcImpl {
+    pub fn new(
+        state: Arc<ServerState>,
+        storage: Arc<Storage>,
+        transcode_cache: Arc<logic::TranscodeCache>,
+        play_history: Arc<logic::PlayHistory>,
+    ) -> Self {
         Self { 
-            logic: logic::AudioFile::default(),
+            logic: logic::AudioFile::new(state, storage, transcode_cache),
+            play_history,
         }
     }
 }
 
-#[async_trait]
-impl GetMongoClient for AudioDataSvcImpl {
-    async fn create_db_client(&self) -> Result<Client, Status> {
-        let db = match model::create_db_client().await {
-            Ok(db) => db,
-            Err(err) => {
-                return Err(Status::new(Code::Internal, err.to_string()))
-            },
-        };
-
-        Ok(db)
-    }
-}
-
 #[tonic::async_trait]
 impl AudioDataSvc for AudioDataSvcImpl {
+    #[instrument(skip_all, fields(
+        remote_addr = %format_remote_addr(&request),
+        audio_tag_id = %request.get_ref().audio_tag_id,
+    ))]
     async fn get_meta(
         &self,
         request: Request<AudioMetaReq>
     ) -> Result<Response<AudioMetaRes>, Status> {
-        let audio_tag_id = &request.get_ref().audio_tag_id;
-        if let Some(remote_addr) = request.remote_addr() {
-            println!("info: {} requests 'get audio metadata'", remote_addr);
-        } else {
-            println!("warn: unknown remote address tries to request");
-        }
+        let req = request.get_ref();
+        info!("get audio metadata");
 
         let res = match self.logic.read_meta(
-            self.create_db_client().await?, 
-            audio_tag_id
+            &req.audio_tag_id,
+            req.channels,
+            req.codec,
+            req.encoding_profile.as_ref()
         ).await {
             Ok(res) => Response::new(res),
+            Err(err) if err.is::<logic::InvalidAudioRequestError>() => return Err(Status::invalid_argument(err.to_string())),
             Err(err) => return Err(Status::new(Code::Internal, err.to_string())),
         };
 
@@ -61,30 +58,58 @@ impl AudioDataSvc for AudioDataSvcImpl {
 
     type GetDataStream = ReceiverStream<Result<AudioDataRes, Status>>;
 
+    #[instrument(skip_all, fields(
+        remote_addr = %format_remote_addr(&request),
+        audio_tag_id = %request.get_ref().audio_tag_id,
+        packet_start_idx = request.get_ref().packet_start_idx,
+        packet_num = request.get_ref().packet_num,
+        start = ?request.get_ref().start,
+    ))]
     async fn get_data(
         &self,
         request: Request<AudioDataReq>
     ) -> Result<Response<Self::GetDataStream>, Status> {
         let (tx, rx) = mpsc::channel(16);
         let req = request.get_ref();
-        if let Some(remote_addr) = request.remote_addr() {
-            println!("info: {} requests 'get audio data'", remote_addr);
-        } else {
-            println!("warn: unknown remote address tries to request");
-        }
+        let remote_addr = request.remote_addr();
+        let user_id = get_user_id(&request)?;
+        info!("get audio data");
+
+        // the first packet of a seek is trimmed by the client
+        let (packet_start_idx, mut trim_start_samples) = match &req.start {
+            Some(start) => match self.logic.resolve_start(
+                &req.audio_tag_id,
+                req.channels,
+                req.codec,
+                req.encoding_profile.as_ref(),
+                start,
+            ).await {
+                Ok(res) => res,
+                Err(err) => return Err(Status::new(Code::InvalidArgument, err.to_string())),
+            },
+            None => (req.packet_start_idx, 0),
+        };
 
         let mut packets = match self.logic.get_audio_sample_iterator(
-            self.create_db_client().await?, 
             &req.audio_tag_id, 
-            req.packet_start_idx.try_into().unwrap(), 
+            packet_start_idx.try_into().unwrap(), 
             req.packet_num.try_into().unwrap(), 
-            req.channels
+            req.channels,
+            req.codec,
+            req.encoding_profile.as_ref()
         ).await {
             Ok(iter) => iter,
+            Err(err) if err.is::<logic::InvalidAudioRequestError>() => return Err(Status::invalid_argument(err.to_string())),
             Err(err) => return Err(Status::new(Code::Internal, err.to_string())),
         };
 
+        let play_history = self.play_history.clone();
+        let audio_tag_id = req.audio_tag_id.clone();
+
         tokio::spawn(async move {
+            let mut sent_packets = 0;
+            metrics::ACTIVE_DATA_STREAMS.inc();
+
             while let Some(packet) = packets.next() {
                 let packet_res = AudioDataRes {
                     packet_idx: packet.idx.try_into().unwrap(),
@@ -94,14 +119,34 @@ impl AudioDataSvc for AudioDataSvcImpl {
                     sp_frame_num: packet.frame_len.try_into().unwrap(),
                     packet_start_ts: packet.next_pkt_seek_ts,
 
-                    encoded_samples: packet.frame.to_owned()
+                    encoded_samples: packet.frame.to_owned(),
+                    trim_start_samples: std::mem::take(&mut trim_start_samples),
                 };
 
                 if let Err(_err) = tx.send(Ok(packet_res)).await {
                     break;
                 }
+
+                sent_packets += 1;
             }
-        });
+
+            metrics::ACTIVE_DATA_STREAMS.dec();
+
+            // counts plays of clients that do not report playback
+            if let Some(remote_addr) = remote_addr {
+                if let Err(err) = play_history.add_streamed_packets(
+                    user_id.as_ref(),
+                    remote_addr.ip(),
+                    &audio_tag_id,
+                    packet_start_idx,
+                    sent_packets
+                ).await {
+                    warn!("failed to count streamed packets: {}", err);
+                }
+            }
+
+            debug!(sent_packets, "streamed packets");
+        }.instrument(Span::current()));
 
         Ok(Response::new(ReceiverStream::new(rx)))
     }
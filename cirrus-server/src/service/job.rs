@@ -0,0 +1,149 @@
+use std::sync::Arc;
+
+use cirrus_protobuf::{
+    api::{job_event, JobEvent, JobReq, JobRes},
+    common::{ListRequest, Response as CirrusResponse},
+    job_svc_server::JobSvc,
+};
+use tokio::sync::{broadcast, mpsc};
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Status, Response, Code, Request};
+use tracing::{info, instrument};
+
+use crate::logic;
+
+use super::format_remote_addr;
+
+pub struct JobSvcImpl {
+    logic: Arc<logic::JobManager>,
+}
+
+impl JobSvcImpl {
+    pub fn new(job_manager: Arc<logic::JobManager>) -> Self {
+        Self {
+            logic: job_manager,
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl JobSvc for JobSvcImpl {
+    type ListJobsStream = ReceiverStream<Result<JobRes, Status>>;
+
+    #[instrument(skip_all, fields(remote_addr = %format_remote_addr(&request)))]
+    async fn list_jobs(
+        &self,
+        request: Request<ListRequest>
+    ) -> Result<Response<Self::ListJobsStream>, Status> {
+        info!("list jobs");
+
+        let req_page = request.get_ref().page;
+        let req_items_per_page = request.get_ref().items_per_page;
+
+        let (tx, rx) = mpsc::channel(16);
+
+        let res = match self.logic.list_jobs(
+            req_items_per_page,
+            req_page
+        ).await {
+            Ok(res) => res,
+            Err(err) => return Err(Status::internal(err.to_string())),
+        };
+
+        tokio::spawn(async move {
+            for r in res.into_iter() {
+                if let Err(_err) = tx.send(Ok(r)).await {
+                    break;
+                }
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    #[instrument(skip_all, fields(remote_addr = %format_remote_addr(&request)))]
+    async fn get_job(
+        &self,
+        request: Request<JobReq>
+    ) -> Result<Response<JobRes>, Status> {
+        info!("get job");
+
+        let res = match self.logic.get_job(&request.get_ref().job_id).await {
+            Ok(res) => Response::new(res),
+            Err(err) => return Err(Status::not_found(err.to_string())),
+        };
+
+        Ok(res)
+    }
+
+    #[instrument(skip_all, fields(remote_addr = %format_remote_addr(&request)))]
+    async fn cancel_job(
+        &self,
+        request: Request<JobReq>
+    ) -> Result<Response<CirrusResponse>, Status> {
+        info!("cancel job");
+
+        let res = match self.logic.cancel_job(&request.get_ref().job_id).await {
+            Ok(_) => Response::new(CirrusResponse {
+                code: Code::Ok as u32,
+                status: "Requested job cancellation".to_string(),
+            }),
+            Err(err) => return Err(Status::failed_precondition(err.to_string())),
+        };
+
+        Ok(res)
+    }
+
+    type WatchJobStream = ReceiverStream<Result<JobEvent, Status>>;
+
+    #[instrument(skip_all, fields(remote_addr = %format_remote_addr(&request)))]
+    async fn watch_job(
+        &self,
+        request: Request<JobReq>
+    ) -> Result<Response<Self::WatchJobStream>, Status> {
+        info!("watch job");
+
+        let (job, events) = match self.logic.watch_job(
+            &request.get_ref().job_id
+        ).await {
+            Ok(res) => res,
+            Err(err) => return Err(Status::not_found(err.to_string())),
+        };
+
+        let (tx, rx) = mpsc::channel(16);
+
+        tokio::spawn(async move {
+            let status_event = JobEvent {
+                kind: job_event::Kind::Status as i32,
+                job: Some(job),
+                path: String::new(),
+                error: String::new(),
+            };
+
+            if let Err(_err) = tx.send(Ok(status_event)).await {
+                return;
+            }
+
+            // finished job has no further events
+            let mut events = match events {
+                Some(events) => events,
+                None => return,
+            };
+
+            loop {
+                match events.recv().await {
+                    Ok(event) => {
+                        if let Err(_err) = tx.send(Ok(event)).await {
+                            break;
+                        }
+                    },
+                    // slow watcher misses some progress events, and receives following ones
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+}
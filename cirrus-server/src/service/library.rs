@@ -1,61 +1,54 @@
-use std::path::Path;
+use std::{path::Path, sync::Arc};
 
-use async_trait::async_trait;
 use cirrus_protobuf::{
-    api::AudioLibraryReq,
+    api::{AudioLibraryReq, AudioLibraryRes, AudioLibraryWatchReq, JobRes},
 
-    common::{Response as CirrusResponse, RequestAction},
+    common::{ListRequest, Response as CirrusResponse, RequestAction},
 
     audio_library_svc_server::AudioLibrarySvc
 };
-use mongodb::Client;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
 use tonic::{Status, Response, Code, Request};
+use tracing::{info, instrument};
 
-use crate::{logic, model};
+use crate::{logic, model::{dto, Storage}, state::ServerState};
 
-use super::GetMongoClient;
+use super::format_remote_addr;
 
 pub struct AudioLibrarySvcImpl {
-    logic: logic::AudioLibrary,
+    logic: Arc<logic::AudioLibrary>,
+    job_manager: Arc<logic::JobManager>,
 }
 
-impl Default for AudioLibrarySvcImpl {
-    fn default() -> Self {
+impl AudioLibrarySvcImpl {
+    pub fn new(
+        state: Arc<ServerState>,
+        storage: Arc<Storage>,
+        transcode_cache: Arc<logic::TranscodeCache>,
+        library_watcher: Arc<logic::LibraryWatcher>,
+        job_manager: Arc<logic::JobManager>,
+        smart_playlist: Arc<logic::SmartPlaylist>,
+    ) -> Self {
         Self { 
-            logic: Default::default()
+            logic: Arc::new(logic::AudioLibrary::new(state, storage, transcode_cache, library_watcher, smart_playlist)),
+            job_manager,
         }
     }
 }
 
-#[async_trait]
-impl GetMongoClient for AudioLibrarySvcImpl {
-    async fn create_db_client(&self) -> Result<Client, Status> {
-        let db = match model::create_db_client().await {
-            Ok(db) => db,
-            Err(err) => {
-                return Err(Status::new(Code::Internal, err.to_string()))
-            },
-        };
-
-        Ok(db)
-    }
-}
-
 #[tonic::async_trait]
 impl AudioLibrarySvc for AudioLibrarySvcImpl {
+    #[instrument(skip_all, fields(remote_addr = %format_remote_addr(&request)))]
     async fn add_audio_library(
         &self,
         request: Request<AudioLibraryReq>
     ) -> Result<Response<CirrusResponse>, Status> {
         let path = &request.get_ref().path;
         let path = Path::new(path);
-        if let Some(remote_addr) = request.remote_addr() {
-            println!("info: {} requests 'add audio library'", remote_addr);
-        } else {
-            println!("warn: unknown remote address tries to request");
-        }
+        info!("add audio library");
 
-        let res = match self.logic.add_audio_library(self.create_db_client().await?, path).await {
+        let res = match self.logic.add_audio_library(path).await {
             Ok(_) => Response::new(CirrusResponse {
                 code: Code::Ok as u32,
                 status: String::new(),
@@ -66,6 +59,7 @@ impl AudioLibrarySvc for AudioLibrarySvcImpl {
         Ok(res)
     }
 
+    #[instrument(skip_all, fields(remote_addr = %format_remote_addr(&request)))]
     async fn remove_audio_library(
         &self,
         request: Request<AudioLibraryReq>
@@ -73,13 +67,9 @@ impl AudioLibrarySvc for AudioLibrarySvcImpl {
         let path = request.get_ref().path.clone();
         let path = Path::new(path.as_str());
 
-        if let Some(remote_addr) = request.remote_addr() {
-            println!("info: {} requests 'remove audio library'", remote_addr);
-        } else {
-            println!("warn: unknown remote address tries to request");
-        }
+        info!("remove audio library");
 
-        let res = match self.logic.remove_audio_library(self.create_db_client().await?, path).await {
+        let res = match self.logic.remove_audio_library(path).await {
             Ok(res) => Response::new(CirrusResponse {
                 code: Code::Ok as u32,
                 status: res,
@@ -90,45 +80,100 @@ impl AudioLibrarySvc for AudioLibrarySvcImpl {
         Ok(res)
     }
 
+    // the request is used only for the remote address, which is a field of the event
+    #[instrument(skip_all)]
     async fn analyze_audio_library(
         &self,
         request: Request<RequestAction>
-    ) -> Result<Response<CirrusResponse>, Status> {
-        if let Some(remote_addr) = request.remote_addr() {
-            println!("info: {} requests 'analyze audio library'", remote_addr);
-        } else {
-            println!("warn: unknown remote address tries to request");
-        }
-
-        let res = match self.logic.analyze_audio_library(self.create_db_client().await?).await {
-            Ok(_) => Response::new(CirrusResponse {
-                code: Code::Ok as u32,
-                status: "Refreshed audio library".to_string()
-            }),
+    ) -> Result<Response<JobRes>, Status> {
+        info!(remote_addr = %format_remote_addr(&request), "analyze audio library");
+
+        let audio_library = self.logic.clone();
+
+        let res = match self.job_manager.spawn_job(
+            dto::JobKind::AnalyzeAudioLibrary,
+            move |job| async move {
+                audio_library.analyze_audio_library(&job).await
+            }
+        ).await {
+            Ok(job) => Response::new(job),
+            Err(err) if err.is::<logic::JobRunningError>() => return Err(Status::failed_precondition(err.to_string())),
             Err(err) => return Err(Status::internal(err.to_string())),
         };
 
         Ok(res)
     }
 
+    // the request is used only for the remote address, which is a field of the event
+    #[instrument(skip_all)]
     async fn refresh_audio_library(
         &self,
         request: Request<RequestAction>
+    ) -> Result<Response<JobRes>, Status> {
+        info!(remote_addr = %format_remote_addr(&request), "refresh audio library");
+
+        let audio_library = self.logic.clone();
+
+        let res = match self.job_manager.spawn_job(
+            dto::JobKind::RefreshAudioLibrary,
+            move |job| async move {
+                audio_library.refresh_audio_library(&job).await
+            }
+        ).await {
+            Ok(job) => Response::new(job),
+            Err(err) if err.is::<logic::JobRunningError>() => return Err(Status::failed_precondition(err.to_string())),
+            Err(err) => return Err(Status::internal(err.to_string())),
+        };
+
+        Ok(res)
+    }
+
+    #[instrument(skip_all, fields(remote_addr = %format_remote_addr(&request)))]
+    async fn set_audio_library_watch(
+        &self,
+        request: Request<AudioLibraryWatchReq>
     ) -> Result<Response<CirrusResponse>, Status> {
-        if let Some(remote_addr) = request.remote_addr() {
-            println!("info: {} requests 'refresh audio library'", remote_addr);
-        } else {
-            println!("warn: unknown remote address tries to request");
-        }
+        let req = request.get_ref();
+        let path = Path::new(&req.path);
 
-        let res = match self.logic.refresh_audio_library(self.create_db_client().await?).await {
+        info!("set audio library watch");
+
+        let res = match self.logic.set_audio_library_watch(path, req.enabled).await {
             Ok(_) => Response::new(CirrusResponse {
                 code: Code::Ok as u32,
-                status: "Refreshed audio library".to_string()
+                status: format!("Set watch of audio library to {}", req.enabled),
             }),
-            Err(err) => return Err(Status::internal(err.to_string())),
+            Err(err) => return Err(Status::not_found(err.to_string())),
         };
 
         Ok(res)
     }
-}
\ No newline at end of file
+
+    type ListAudioLibrariesStream = ReceiverStream<Result<AudioLibraryRes, Status>>;
+
+    #[instrument(skip_all, fields(remote_addr = %format_remote_addr(&request)))]
+    async fn list_audio_libraries(
+        &self,
+        request: Request<ListRequest>
+    ) -> Result<Response<Self::ListAudioLibrariesStream>, Status> {
+        let req = request.get_ref();
+        info!("list audio libraries");
+
+        let res = match self.logic.list_audio_libraries(req.items_per_page, req.page).await {
+            Ok(res) => res,
+            Err(err) => return Err(Status::internal(err.to_string())),
+        };
+
+        let (tx, rx) = mpsc::channel(res.len().max(1));
+
+        tokio::spawn(async move {
+            for item in res.into_iter() {
+                if tx.send(Ok(item)).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+}
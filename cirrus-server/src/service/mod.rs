@@ -1,16 +1,48 @@
 mod tag;
+mod browse;
 mod data;
+mod job;
+mod artwork;
+mod auth;
+mod play_history;
+mod playlist;
+mod smart_playlist;
 mod library;
 
-use async_trait::async_trait;
-use mongodb::Client;
-use tonic::Status;
+use bson::oid::ObjectId;
+use mongodb::bson;
+use tonic::{Request, Status};
 
+use crate::logic;
+
+pub use artwork::ArtworkSvcImpl;
+pub use auth::{AuthInterceptor, AuthSvcImpl};
+pub use browse::AudioBrowseSvcImpl;
 pub use data::AudioDataSvcImpl;
 pub use tag::AudioTagSvcImpl;
 pub use library::AudioLibrarySvcImpl;
+pub use job::JobSvcImpl;
+pub use play_history::PlayHistorySvcImpl;
+pub use playlist::PlaylistSvcImpl;
+pub use smart_playlist::SmartPlaylistSvcImpl;
+
+// Remote address of a request, which is recorded at the span of the request
+pub(crate) fn format_remote_addr<T>(request: &Request<T>) -> String {
+    match request.remote_addr() {
+        Some(remote_addr) => remote_addr.to_string(),
+        None => "unknown".to_string(),
+    }
+}
+
+// Id of the user of a request, which is not set if authentication is disabled
+pub(crate) fn get_user_id<T>(request: &Request<T>) -> Result<Option<ObjectId>, Status> {
+    let claims = match request.extensions().get::<logic::Claims>() {
+        Some(claims) => claims,
+        None => return Ok(None),
+    };
 
-#[async_trait]
-trait GetMongoClient {
-    async fn create_db_client(&self) -> Result<Client, Status>;
+    match ObjectId::parse_str(&claims.sub) {
+        Ok(user_id) => Ok(Some(user_id)),
+        Err(_) => Err(Status::unauthenticated(format!("invalid user id of the token: {}", claims.sub))),
+    }
 }
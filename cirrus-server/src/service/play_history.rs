@@ -0,0 +1,133 @@
+use std::sync::Arc;
+
+use cirrus_protobuf::{
+    api::{
+        ListensExportReq, ListensExportRes, PlayHistoryItem, PlayHistoryReq, PlayStatsReq,
+        PlayStatsRes, PlaybackReportReq,
+    },
+    common::Response as CirrusResponse,
+    play_history_svc_server::PlayHistorySvc,
+};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Status, Response, Code, Request};
+use tracing::{info, instrument};
+
+use crate::logic;
+
+use super::{format_remote_addr, get_user_id};
+
+pub struct PlayHistorySvcImpl {
+    logic: Arc<logic::PlayHistory>,
+}
+
+impl PlayHistorySvcImpl {
+    pub fn new(play_history: Arc<logic::PlayHistory>) -> Self {
+        Self {
+            logic: play_history,
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl PlayHistorySvc for PlayHistorySvcImpl {
+    #[instrument(skip_all, fields(
+        remote_addr = %format_remote_addr(&request),
+        audio_tag_id = %request.get_ref().audio_tag_id,
+    ))]
+    async fn report_playback(
+        &self,
+        request: Request<PlaybackReportReq>
+    ) -> Result<Response<CirrusResponse>, Status> {
+        let remote_addr = request.remote_addr();
+        info!("report playback");
+
+        let user_id = get_user_id(&request)?;
+
+        let res = match self.logic.report_playback(
+            user_id.as_ref(),
+            remote_addr.map(|item| item.ip()),
+            request.get_ref()
+        ).await {
+            Ok(_) => Response::new(CirrusResponse {
+                code: Code::Ok as u32,
+                status: "Reported playback".to_string(),
+            }),
+            Err(err) => return Err(Status::invalid_argument(err.to_string())),
+        };
+
+        Ok(res)
+    }
+
+    #[instrument(skip_all, fields(remote_addr = %format_remote_addr(&request)))]
+    async fn get_play_stats(
+        &self,
+        request: Request<PlayStatsReq>
+    ) -> Result<Response<PlayStatsRes>, Status> {
+        info!("get play stats");
+
+        let user_id = get_user_id(&request)?;
+
+        let res = match self.logic.get_play_stats(
+            user_id.as_ref(),
+            &request.get_ref().audio_tag_ids
+        ).await {
+            Ok(res) => Response::new(res),
+            Err(err) => return Err(Status::invalid_argument(err.to_string())),
+        };
+
+        Ok(res)
+    }
+
+    type ListPlayHistoryStream = ReceiverStream<Result<PlayHistoryItem, Status>>;
+
+    #[instrument(skip_all, fields(remote_addr = %format_remote_addr(&request)))]
+    async fn list_play_history(
+        &self,
+        request: Request<PlayHistoryReq>
+    ) -> Result<Response<Self::ListPlayHistoryStream>, Status> {
+        info!("list play history");
+
+        let (tx, rx) = mpsc::channel(16);
+
+        let user_id = get_user_id(&request)?;
+
+        let res = match self.logic.list_play_history(
+            user_id.as_ref(),
+            request.get_ref()
+        ).await {
+            Ok(res) => res,
+            Err(err) => return Err(Status::invalid_argument(err.to_string())),
+        };
+
+        tokio::spawn(async move {
+            for r in res.into_iter() {
+                if let Err(_err) = tx.send(Ok(r)).await {
+                    break;
+                }
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    #[instrument(skip_all, fields(remote_addr = %format_remote_addr(&request)))]
+    async fn export_listens(
+        &self,
+        request: Request<ListensExportReq>
+    ) -> Result<Response<ListensExportRes>, Status> {
+        info!("export listens");
+
+        let user_id = get_user_id(&request)?;
+
+        let res = match self.logic.export_listens(
+            user_id.as_ref(),
+            request.get_ref()
+        ).await {
+            Ok(res) => Response::new(res),
+            Err(err) => return Err(Status::internal(err.to_string())),
+        };
+
+        Ok(res)
+    }
+}
@@ -0,0 +1,284 @@
+use std::sync::Arc;
+
+use cirrus_protobuf::{
+    api::{
+        CreatePlaylistReq, ImportPlaylistM3u8Req, ImportPlaylistM3u8Res, InsertPlaylistEntriesReq,
+        MovePlaylistEntryReq, PlaylistM3u8Res, PlaylistReq, PlaylistRes, RemovePlaylistEntriesReq,
+        RenamePlaylistReq, SetPlaylistSharedReq,
+    },
+    common::{ListRequest, Response as CirrusResponse},
+    playlist_svc_server::PlaylistSvc,
+};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Status, Response, Code, Request};
+use tracing::{info, instrument};
+
+use crate::{logic, model::Storage};
+
+use super::format_remote_addr;
+
+pub struct PlaylistSvcImpl {
+    logic: logic::Playlist,
+}
+
+impl PlaylistSvcImpl {
+    pub fn new(storage: Arc<Storage>) -> Self {
+        Self {
+            logic: logic::Playlist::new(storage),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl PlaylistSvc for PlaylistSvcImpl {
+    type ListPlaylistsStream = ReceiverStream<Result<PlaylistRes, Status>>;
+
+    #[instrument(skip_all, fields(remote_addr = %format_remote_addr(&request)))]
+    async fn list_playlists(
+        &self,
+        request: Request<ListRequest>
+    ) -> Result<Response<Self::ListPlaylistsStream>, Status> {
+        info!("list playlists");
+
+        let req_page = request.get_ref().page;
+        let req_items_per_page = request.get_ref().items_per_page;
+
+        let (tx, rx) = mpsc::channel(16);
+
+        let res = match self.logic.list_playlists(
+            request.extensions().get::<logic::Claims>(),
+            req_items_per_page,
+            req_page
+        ).await {
+            Ok(res) => res,
+            Err(err) => return Err(Status::internal(err.to_string())),
+        };
+
+        tokio::spawn(async move {
+            for r in res.into_iter() {
+                if let Err(_err) = tx.send(Ok(r)).await {
+                    break;
+                }
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    #[instrument(skip_all, fields(remote_addr = %format_remote_addr(&request)))]
+    async fn get_playlist(
+        &self,
+        request: Request<PlaylistReq>
+    ) -> Result<Response<PlaylistRes>, Status> {
+        info!("get playlist");
+
+        let res = match self.logic.get_playlist(
+            request.extensions().get::<logic::Claims>(),
+            &request.get_ref().playlist_id
+        ).await {
+            Ok(res) => Response::new(res),
+            Err(err) if err.is::<logic::PlaylistPermissionError>() => return Err(Status::permission_denied(err.to_string())),
+            Err(err) => return Err(Status::not_found(err.to_string())),
+        };
+
+        Ok(res)
+    }
+
+    #[instrument(skip_all, fields(remote_addr = %format_remote_addr(&request)))]
+    async fn create_playlist(
+        &self,
+        request: Request<CreatePlaylistReq>
+    ) -> Result<Response<PlaylistRes>, Status> {
+        info!("create playlist");
+
+        let res = match self.logic.create_playlist(
+            request.extensions().get::<logic::Claims>(),
+            &request.get_ref().name,
+            request.get_ref().shared
+        ).await {
+            Ok(res) => Response::new(res),
+            Err(err) => return Err(Status::internal(err.to_string())),
+        };
+
+        Ok(res)
+    }
+
+    #[instrument(skip_all, fields(remote_addr = %format_remote_addr(&request)))]
+    async fn rename_playlist(
+        &self,
+        request: Request<RenamePlaylistReq>
+    ) -> Result<Response<PlaylistRes>, Status> {
+        info!("rename playlist");
+
+        let req = request.get_ref();
+
+        let res = match self.logic.rename_playlist(
+            request.extensions().get::<logic::Claims>(),
+            &req.playlist_id,
+            &req.name
+        ).await {
+            Ok(res) => Response::new(res),
+            Err(err) if err.is::<logic::PlaylistPermissionError>() => return Err(Status::permission_denied(err.to_string())),
+            Err(err) => return Err(Status::internal(err.to_string())),
+        };
+
+        Ok(res)
+    }
+
+    #[instrument(skip_all, fields(remote_addr = %format_remote_addr(&request)))]
+    async fn set_playlist_shared(
+        &self,
+        request: Request<SetPlaylistSharedReq>
+    ) -> Result<Response<PlaylistRes>, Status> {
+        info!("set playlist shared");
+
+        let req = request.get_ref();
+
+        let res = match self.logic.set_playlist_shared(
+            request.extensions().get::<logic::Claims>(),
+            &req.playlist_id,
+            req.shared
+        ).await {
+            Ok(res) => Response::new(res),
+            Err(err) if err.is::<logic::PlaylistPermissionError>() => return Err(Status::permission_denied(err.to_string())),
+            Err(err) => return Err(Status::internal(err.to_string())),
+        };
+
+        Ok(res)
+    }
+
+    #[instrument(skip_all, fields(remote_addr = %format_remote_addr(&request)))]
+    async fn delete_playlist(
+        &self,
+        request: Request<PlaylistReq>
+    ) -> Result<Response<CirrusResponse>, Status> {
+        info!("delete playlist");
+
+        let res = match self.logic.delete_playlist(
+            request.extensions().get::<logic::Claims>(),
+            &request.get_ref().playlist_id
+        ).await {
+            Ok(_) => Response::new(CirrusResponse {
+                code: Code::Ok as u32,
+                status: "Deleted playlist".to_string(),
+            }),
+            Err(err) if err.is::<logic::PlaylistPermissionError>() => return Err(Status::permission_denied(err.to_string())),
+            Err(err) => return Err(Status::not_found(err.to_string())),
+        };
+
+        Ok(res)
+    }
+
+    #[instrument(skip_all, fields(remote_addr = %format_remote_addr(&request)))]
+    async fn insert_playlist_entries(
+        &self,
+        request: Request<InsertPlaylistEntriesReq>
+    ) -> Result<Response<PlaylistRes>, Status> {
+        info!("insert playlist entries");
+
+        let req = request.get_ref();
+
+        let res = match self.logic.insert_playlist_entries(
+            request.extensions().get::<logic::Claims>(),
+            &req.playlist_id,
+            &req.audio_tag_ids,
+            req.position
+        ).await {
+            Ok(res) => Response::new(res),
+            Err(err) if err.is::<logic::PlaylistPermissionError>() => return Err(Status::permission_denied(err.to_string())),
+            Err(err) => return Err(Status::internal(err.to_string())),
+        };
+
+        Ok(res)
+    }
+
+    #[instrument(skip_all, fields(remote_addr = %format_remote_addr(&request)))]
+    async fn move_playlist_entry(
+        &self,
+        request: Request<MovePlaylistEntryReq>
+    ) -> Result<Response<PlaylistRes>, Status> {
+        info!("move playlist entry");
+
+        let req = request.get_ref();
+
+        let res = match self.logic.move_playlist_entry(
+            request.extensions().get::<logic::Claims>(),
+            &req.playlist_id,
+            &req.entry_id,
+            req.position
+        ).await {
+            Ok(res) => Response::new(res),
+            Err(err) if err.is::<logic::PlaylistPermissionError>() => return Err(Status::permission_denied(err.to_string())),
+            Err(err) => return Err(Status::internal(err.to_string())),
+        };
+
+        Ok(res)
+    }
+
+    #[instrument(skip_all, fields(remote_addr = %format_remote_addr(&request)))]
+    async fn remove_playlist_entries(
+        &self,
+        request: Request<RemovePlaylistEntriesReq>
+    ) -> Result<Response<PlaylistRes>, Status> {
+        info!("remove playlist entries");
+
+        let req = request.get_ref();
+
+        let res = match self.logic.remove_playlist_entries(
+            request.extensions().get::<logic::Claims>(),
+            &req.playlist_id,
+            &req.entry_ids
+        ).await {
+            Ok(res) => Response::new(res),
+            Err(err) if err.is::<logic::PlaylistPermissionError>() => return Err(Status::permission_denied(err.to_string())),
+            Err(err) => return Err(Status::internal(err.to_string())),
+        };
+
+        Ok(res)
+    }
+
+    #[instrument(skip_all, fields(remote_addr = %format_remote_addr(&request)))]
+    async fn export_playlist_m3u8(
+        &self,
+        request: Request<PlaylistReq>
+    ) -> Result<Response<PlaylistM3u8Res>, Status> {
+        info!("export playlist m3u8");
+
+        let res = match self.logic.export_playlist_m3u8(
+            request.extensions().get::<logic::Claims>(),
+            &request.get_ref().playlist_id
+        ).await {
+            Ok(content) => Response::new(PlaylistM3u8Res { content }),
+            Err(err) if err.is::<logic::PlaylistPermissionError>() => return Err(Status::permission_denied(err.to_string())),
+            Err(err) => return Err(Status::internal(err.to_string())),
+        };
+
+        Ok(res)
+    }
+
+    #[instrument(skip_all, fields(remote_addr = %format_remote_addr(&request)))]
+    async fn import_playlist_m3u8(
+        &self,
+        request: Request<ImportPlaylistM3u8Req>
+    ) -> Result<Response<ImportPlaylistM3u8Res>, Status> {
+        info!("import playlist m3u8");
+
+        let req = request.get_ref();
+
+        let res = match self.logic.import_playlist_m3u8(
+            request.extensions().get::<logic::Claims>(),
+            &req.name,
+            &req.content,
+            req.shared
+        ).await {
+            Ok((playlist, unmatched_paths)) => Response::new(ImportPlaylistM3u8Res {
+                playlist: Some(playlist),
+                unmatched_paths,
+            }),
+            Err(err) => return Err(Status::internal(err.to_string())),
+        };
+
+        Ok(res)
+    }
+}
@@ -0,0 +1,134 @@
+use std::sync::Arc;
+
+use cirrus_protobuf::{
+    api::{SaveSmartPlaylistReq, SmartPlaylistReq, SmartPlaylistRes},
+    common::{ListRequest, Response as CirrusResponse},
+    smart_playlist_svc_server::SmartPlaylistSvc,
+};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Status, Response, Code, Request};
+use tracing::{info, instrument};
+
+use crate::logic;
+
+use super::format_remote_addr;
+
+pub struct SmartPlaylistSvcImpl {
+    logic: Arc<logic::SmartPlaylist>,
+}
+
+impl SmartPlaylistSvcImpl {
+    pub fn new(smart_playlist: Arc<logic::SmartPlaylist>) -> Self {
+        Self {
+            logic: smart_playlist,
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl SmartPlaylistSvc for SmartPlaylistSvcImpl {
+    type ListSmartPlaylistsStream = ReceiverStream<Result<SmartPlaylistRes, Status>>;
+
+    #[instrument(skip_all, fields(remote_addr = %format_remote_addr(&request)))]
+    async fn list_smart_playlists(
+        &self,
+        request: Request<ListRequest>
+    ) -> Result<Response<Self::ListSmartPlaylistsStream>, Status> {
+        info!("list smart playlists");
+
+        let req_page = request.get_ref().page;
+        let req_items_per_page = request.get_ref().items_per_page;
+
+        let (tx, rx) = mpsc::channel(16);
+
+        let res = match self.logic.list_smart_playlists(
+            req_items_per_page,
+            req_page
+        ).await {
+            Ok(res) => res,
+            Err(err) => return Err(Status::internal(err.to_string())),
+        };
+
+        tokio::spawn(async move {
+            for r in res.into_iter() {
+                if let Err(_err) = tx.send(Ok(r)).await {
+                    break;
+                }
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    #[instrument(skip_all, fields(remote_addr = %format_remote_addr(&request)))]
+    async fn get_smart_playlist(
+        &self,
+        request: Request<SmartPlaylistReq>
+    ) -> Result<Response<SmartPlaylistRes>, Status> {
+        info!("get smart playlist");
+
+        let res = match self.logic.get_smart_playlist(
+            &request.get_ref().smart_playlist_id
+        ).await {
+            Ok(res) => Response::new(res),
+            Err(err) => return Err(Status::not_found(err.to_string())),
+        };
+
+        Ok(res)
+    }
+
+    #[instrument(skip_all, fields(remote_addr = %format_remote_addr(&request)))]
+    async fn create_smart_playlist(
+        &self,
+        request: Request<SaveSmartPlaylistReq>
+    ) -> Result<Response<SmartPlaylistRes>, Status> {
+        info!("create smart playlist");
+
+        let res = match self.logic.create_smart_playlist(
+            request.get_ref()
+        ).await {
+            Ok(res) => Response::new(res),
+            Err(err) => return Err(Status::internal(err.to_string())),
+        };
+
+        Ok(res)
+    }
+
+    #[instrument(skip_all, fields(remote_addr = %format_remote_addr(&request)))]
+    async fn update_smart_playlist(
+        &self,
+        request: Request<SaveSmartPlaylistReq>
+    ) -> Result<Response<SmartPlaylistRes>, Status> {
+        info!("update smart playlist");
+
+        let res = match self.logic.update_smart_playlist(
+            request.get_ref()
+        ).await {
+            Ok(res) => Response::new(res),
+            Err(err) => return Err(Status::internal(err.to_string())),
+        };
+
+        Ok(res)
+    }
+
+    #[instrument(skip_all, fields(remote_addr = %format_remote_addr(&request)))]
+    async fn delete_smart_playlist(
+        &self,
+        request: Request<SmartPlaylistReq>
+    ) -> Result<Response<CirrusResponse>, Status> {
+        info!("delete smart playlist");
+
+        let res = match self.logic.delete_smart_playlist(
+            &request.get_ref().smart_playlist_id
+        ).await {
+            Ok(_) => Response::new(CirrusResponse {
+                code: Code::Ok as u32,
+                status: "Deleted smart playlist".to_string(),
+            }),
+            Err(err) => return Err(Status::not_found(err.to_string())),
+        };
+
+        Ok(res)
+    }
+}
@@ -1,53 +1,41 @@
-use async_trait::async_trait;
-use cirrus_protobuf::{audio_tag_svc_server::AudioTagSvc, api::AudioTagRes, common::ListRequest};
-use mongodb::Client;
+use std::sync::Arc;
+
+use cirrus_protobuf::{
+    audio_tag_svc_server::AudioTagSvc,
+    api::{AudioTagRes, AudioTagSearchReq, AudioTagSearchRes},
+    common::ListRequest,
+};
 use tokio::sync::mpsc;
 use tokio_stream::wrappers::ReceiverStream;
-use tonic::{Status, Response, Code};
+use tonic::{Status, Response};
+use tracing::{info, instrument};
 
-use crate::{logic, model};
+use crate::{logic, model::Storage};
 
-use super::GetMongoClient;
+use super::format_remote_addr;
 
 pub struct AudioTagSvcImpl {
     logic: logic::AudioTag,
 }
 
-impl Default for AudioTagSvcImpl {
-    fn default() -> Self {
+impl AudioTagSvcImpl {
+    pub fn new(storage: Arc<Storage>) -> Self {
         Self { 
-            logic: logic::AudioTag::default(),
+            logic: logic::AudioTag::new(storage),
         }
     }
 }
 
-#[async_trait]
-impl GetMongoClient for AudioTagSvcImpl {
-    async fn create_db_client(&self) -> Result<Client, Status> {
-        let db = match model::create_db_client().await {
-            Ok(db) => db,
-            Err(err) => {
-                return Err(Status::new(Code::Internal, err.to_string()))
-            },
-        };
-
-        Ok(db)
-    }
-}
-
 #[tonic::async_trait]
 impl AudioTagSvc for AudioTagSvcImpl {
     type ListAudioTagsStream = ReceiverStream<Result<AudioTagRes, Status>>;
 
+    #[instrument(skip_all, fields(remote_addr = %format_remote_addr(&request)))]
     async fn list_audio_tags(
         &self,
         request: tonic::Request<ListRequest>
     ) -> Result<Response<Self::ListAudioTagsStream>, Status> {
-        if let Some(remote_addr) = request.remote_addr() {
-            println!("info: {} requests 'list audio tags'", remote_addr);
-        } else {
-            println!("warn: unknown remote address tries to request");
-        }
+        info!("list audio tags");
 
         let req_page = request.get_ref().page;
         let req_items_per_page = request.get_ref().items_per_page;
@@ -55,7 +43,6 @@ impl AudioTagSvc for AudioTagSvcImpl {
         let (tx, rx) = mpsc::channel(req_items_per_page as usize);
 
         let res = self.logic.list_audio_tags(
-            self.create_db_client().await?, 
             req_items_per_page, 
             req_page
         ).await.unwrap();
@@ -68,4 +55,24 @@ impl AudioTagSvc for AudioTagSvcImpl {
 
         Ok(Response::new(ReceiverStream::new(rx)))
     }
+    #[instrument(skip_all, fields(remote_addr = %format_remote_addr(&request)))]
+    async fn search_audio_tags(
+        &self,
+        request: tonic::Request<AudioTagSearchReq>
+    ) -> Result<Response<AudioTagSearchRes>, Status> {
+        info!("search audio tags");
+
+        if let Err(err) = logic::AudioTag::check_search_req(request.get_ref()) {
+            return Err(Status::invalid_argument(err.to_string()));
+        }
+
+        let res = match self.logic.search_audio_tags(
+            request.get_ref()
+        ).await {
+            Ok(res) => Response::new(res),
+            Err(err) => return Err(Status::internal(err.to_string())),
+        };
+
+        Ok(res)
+    }
 }
\ No newline at end of file
@@ -1,8 +1,32 @@
-use std::env;
-use config::{Config, File, ConfigError};
+use std::{env, net::SocketAddr, path::{Path, PathBuf}, str::FromStr};
+use config::{Config, Environment, File, ConfigError};
 use serde_derive::{Serialize, Deserialize};
 
-const CONFIG_PATH: &'static str = "configs/cirrus/server.toml";
+const DEFAULT_CONFIG_PATH: &'static str = "configs/cirrus/server.toml";
+const ENV_PREFIX: &'static str = "CIRRUS";
+// sample rates that Opus encodes
+const OPUS_SAMPLE_RATES: [u32; 5] = [8000, 12000, 16000, 24000, 48000];
+const DEFAULT_AUDIO_TYPES: [&'static str; 8] = ["aiff", "aif", "flac", "wav", "mp3", "ogg", "oga", "m4a"];
+const DEFAULT_ENCODING_BITRATE: u32 = 128_000;
+const DEFAULT_ENCODING_ALLOWED_BITRATES: [u32; 6] = [64_000, 96_000, 128_000, 160_000, 192_000, 256_000];
+const DEFAULT_ENCODING_COMPLEXITY: u32 = 10;
+const DEFAULT_TRANSCODE_CACHE_PATH: &'static str = "cache/cirrus/transcode";
+const DEFAULT_TRANSCODE_CACHE_MAX_SIZE_MB: u64 = 2048;
+// 5 seconds of 20ms packets
+const DEFAULT_TRANSCODE_CACHE_CHUNK_PACKETS: u32 = 250;
+const DEFAULT_LIBRARY_WATCHER_DEBOUNCE_MS: u64 = 2000;
+const DEFAULT_ARTWORK_CACHE_PATH: &'static str = "cache/cirrus/artwork";
+const DEFAULT_ARTWORK_ALLOWED_SIZES: [u32; 5] = [64, 128, 256, 512, 1024];
+const DEFAULT_ARTWORK_JPEG_QUALITY: u8 = 85;
+const DEFAULT_AUTH_ACCESS_TOKEN_TTL_SEC: i64 = 15 * 60;
+const DEFAULT_AUTH_REFRESH_TOKEN_TTL_SEC: i64 = 30 * 24 * 60 * 60;
+const DEFAULT_STORAGE_SQLITE_PATH: &'static str = "data/cirrus/metadata.db";
+const DEFAULT_SERVER_SHUTDOWN_TIMEOUT_SEC: u64 = 30;
+const DEFAULT_METRICS_LISTEN_ADDRESS: &'static str = "127.0.0.1";
+const DEFAULT_METRICS_LISTEN_PORT: u32 = 50080;
+const DEFAULT_SUBSONIC_LISTEN_ADDRESS: &'static str = "127.0.0.1";
+// the default port of Subsonic servers, which clients suggest
+const DEFAULT_SUBSONIC_LISTEN_PORT: u32 = 4040;
 
 #[derive(Serialize, Deserialize)]
 #[allow(unused)]
@@ -12,6 +36,8 @@ pub struct Server {
     pub tls: bool,
     pub cert_path: String,
     pub key_path: String,
+    // in-flight streams are dropped if they do not finish within this after a shutdown signal
+    pub shutdown_timeout_sec: u64,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -20,6 +46,31 @@ pub struct MongoDB {
     pub address: String,
 }
 
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    MongoDB,
+    Sqlite,
+}
+
+impl FromStr for StorageBackend {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mongodb" => Ok(Self::MongoDB),
+            "sqlite" => Ok(Self::Sqlite),
+            _ => Err(anyhow::anyhow!("unknown storage backend: {}", s)),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[allow(unused)]
+pub struct Storage {
+    pub backend: StorageBackend,
+    pub sqlite_path: String,
+}
 
 #[derive(Serialize, Deserialize)]
 #[allow(unused)]
@@ -28,23 +79,264 @@ pub struct AudioSamleFramePacket {
     pub len: u32,
 }
 
+#[derive(Serialize, Deserialize)]
+#[allow(unused)]
+pub struct AudioLibrary {
+    pub audio_types: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[allow(unused)]
+pub struct Encoding {
+    pub default_bitrate: u32,
+    pub allowed_bitrates: Vec<u32>,
+    pub default_complexity: u32,
+    pub max_complexity: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+#[allow(unused)]
+pub struct TranscodeCache {
+    pub enabled: bool,
+    pub path: String,
+    pub max_size_mb: u64,
+    pub chunk_packets: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+#[allow(unused)]
+pub struct LibraryWatcher {
+    pub enabled: bool,
+    pub debounce_ms: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+#[allow(unused)]
+pub struct Artwork {
+    pub cache_path: String,
+    pub allowed_sizes: Vec<u32>,
+    pub jpeg_quality: u8,
+}
+
+#[derive(Serialize, Deserialize)]
+#[allow(unused)]
+pub struct Auth {
+    pub enabled: bool,
+    pub token_secret: String,
+    pub access_token_ttl_sec: i64,
+    pub refresh_token_ttl_sec: i64,
+    pub initial_admin_username: String,
+    pub initial_admin_password: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Pretty,
+    Json,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum LogRotation {
+    Hourly,
+    Daily,
+    Never,
+}
+
+#[derive(Serialize, Deserialize)]
+#[allow(unused)]
+pub struct Log {
+    pub format: LogFormat,
+    // directives of `tracing_subscriber::EnvFilter`, e.g. "info,cirrus_server::service=debug"
+    pub filter: String,
+    // logs are written to files at this directory as well, if it is set
+    pub file_dir: String,
+    pub file_rotation: LogRotation,
+}
+
+#[derive(Serialize, Deserialize)]
+#[allow(unused)]
+pub struct Metrics {
+    pub enabled: bool,
+    pub listen_address: String,
+    pub listen_port: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+#[allow(unused)]
+pub struct Web {
+    // gRPC-Web and the REST/JSON API at `/api` are served at the server port for browsers
+    pub enabled: bool,
+    // origins of pages that call the server, and "*" allows any
+    pub cors_allowed_origins: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[allow(unused)]
+pub struct Subsonic {
+    pub enabled: bool,
+    pub listen_address: String,
+    pub listen_port: u32,
+}
+
 #[derive(Serialize, Deserialize)]
 #[allow(unused)]
 pub struct Settings {
     pub server: Server,
     pub mongodb: MongoDB,
+    pub storage: Storage,
     pub audio_sample_frame_packet: AudioSamleFramePacket,
+    pub audio_library: AudioLibrary,
+    pub encoding: Encoding,
+    pub transcode_cache: TranscodeCache,
+    pub library_watcher: LibraryWatcher,
+    pub artwork: Artwork,
+    pub auth: Auth,
+    pub log: Log,
+    pub metrics: Metrics,
+    pub web: Web,
+    pub subsonic: Subsonic,
 }
 
 impl Settings {
-    pub fn get() -> Result<Self, ConfigError> {
-        let current_dir = env::current_dir().unwrap();
-        let server_config_path = current_dir.join(CONFIG_PATH);
-        
+    // Used if the path is not set with `--config` or `CIRRUS_CONFIG`
+    pub fn get_default_path() -> PathBuf {
+        env::current_dir().unwrap().join(DEFAULT_CONFIG_PATH)
+    }
+
+    // Values of the file are overridden with `CIRRUS_<SECTION>__<KEY>` environment variables,
+    // e.g. `CIRRUS_MONGODB__ADDRESS` for `mongodb.address`
+    pub fn load(config_path: &Path) -> Result<Self, ConfigError> {
         let s = Config::builder()
-            .add_source(File::from(server_config_path))
+            .set_default("server.shutdown_timeout_sec", DEFAULT_SERVER_SHUTDOWN_TIMEOUT_SEC)?
+            .set_default("storage.backend", "mongodb")?
+            .set_default("storage.sqlite_path", DEFAULT_STORAGE_SQLITE_PATH)?
+            .set_default("audio_library.audio_types", DEFAULT_AUDIO_TYPES.to_vec())?
+            .set_default("encoding.default_bitrate", DEFAULT_ENCODING_BITRATE)?
+            .set_default("encoding.allowed_bitrates", DEFAULT_ENCODING_ALLOWED_BITRATES.to_vec())?
+            .set_default("encoding.default_complexity", DEFAULT_ENCODING_COMPLEXITY)?
+            .set_default("encoding.max_complexity", DEFAULT_ENCODING_COMPLEXITY)?
+            .set_default("transcode_cache.enabled", true)?
+            .set_default("transcode_cache.path", DEFAULT_TRANSCODE_CACHE_PATH)?
+            .set_default("transcode_cache.max_size_mb", DEFAULT_TRANSCODE_CACHE_MAX_SIZE_MB)?
+            .set_default("transcode_cache.chunk_packets", DEFAULT_TRANSCODE_CACHE_CHUNK_PACKETS)?
+            .set_default("library_watcher.enabled", true)?
+            .set_default("library_watcher.debounce_ms", DEFAULT_LIBRARY_WATCHER_DEBOUNCE_MS)?
+            .set_default("artwork.cache_path", DEFAULT_ARTWORK_CACHE_PATH)?
+            .set_default("artwork.allowed_sizes", DEFAULT_ARTWORK_ALLOWED_SIZES.to_vec())?
+            .set_default("artwork.jpeg_quality", DEFAULT_ARTWORK_JPEG_QUALITY)?
+            .set_default("auth.enabled", true)?
+            .set_default("auth.token_secret", "")?
+            .set_default("auth.access_token_ttl_sec", DEFAULT_AUTH_ACCESS_TOKEN_TTL_SEC)?
+            .set_default("auth.refresh_token_ttl_sec", DEFAULT_AUTH_REFRESH_TOKEN_TTL_SEC)?
+            .set_default("auth.initial_admin_username", "")?
+            .set_default("auth.initial_admin_password", "")?
+            .set_default("log.format", "pretty")?
+            .set_default("log.filter", "info")?
+            .set_default("log.file_dir", "")?
+            .set_default("log.file_rotation", "daily")?
+            .set_default("metrics.enabled", false)?
+            .set_default("metrics.listen_address", DEFAULT_METRICS_LISTEN_ADDRESS)?
+            .set_default("metrics.listen_port", DEFAULT_METRICS_LISTEN_PORT)?
+            .set_default("web.enabled", false)?
+            .set_default("web.cors_allowed_origins", Vec::<String>::new())?
+            .set_default("subsonic.enabled", false)?
+            .set_default("subsonic.listen_address", DEFAULT_SUBSONIC_LISTEN_ADDRESS)?
+            .set_default("subsonic.listen_port", DEFAULT_SUBSONIC_LISTEN_PORT)?
+            .add_source(File::from(config_path))
+            .add_source(
+                Environment::with_prefix(ENV_PREFIX)
+                    .prefix_separator("_")
+                    .separator("__")
+            )
             .build()?;
 
         s.try_deserialize()
     }
-}
\ No newline at end of file
+
+    // Problems of values that would fail at start or at requests. Auth is validated by
+    // `logic::Auth::check_settings`
+    pub fn validate(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+
+        let listen_address = format!("{}:{}", self.server.listen_address, self.server.listen_port);
+        if listen_address.parse::<SocketAddr>().is_err() {
+            errors.push(format!("server: invalid listen address '{}'", listen_address));
+        }
+
+        if self.metrics.enabled {
+            let metrics_listen_address = format!("{}:{}", self.metrics.listen_address, self.metrics.listen_port);
+
+            match metrics_listen_address.parse::<SocketAddr>() {
+                Ok(_) if metrics_listen_address == listen_address => {
+                    errors.push("metrics: listen address should differ from server".to_string());
+                },
+                Ok(_) => (),
+                Err(_) => errors.push(format!("metrics: invalid listen address '{}'", metrics_listen_address)),
+            }
+        }
+
+        if self.subsonic.enabled {
+            let subsonic_listen_address = format!("{}:{}", self.subsonic.listen_address, self.subsonic.listen_port);
+
+            match subsonic_listen_address.parse::<SocketAddr>() {
+                Ok(_) if subsonic_listen_address == listen_address => {
+                    errors.push("subsonic: listen address should differ from server".to_string());
+                },
+                Ok(_) => (),
+                Err(_) => errors.push(format!("subsonic: invalid listen address '{}'", subsonic_listen_address)),
+            }
+        }
+
+        for origin in self.web.cors_allowed_origins.iter() {
+            if origin != "*" && origin.parse::<http::HeaderValue>().is_err() {
+                errors.push(format!("web: invalid origin '{}'", origin));
+            }
+        }
+
+        if self.server.tls {
+            for path in [&self.server.cert_path, &self.server.key_path] {
+                if !Path::new(path).is_file() {
+                    errors.push(format!("server: tls file '{}' does not exist", path));
+                }
+            }
+        }
+
+        if !OPUS_SAMPLE_RATES.contains(&self.audio_sample_frame_packet.sample_rate) {
+            errors.push(format!(
+                "audio_sample_frame_packet: sample_rate should be one of {:?}", OPUS_SAMPLE_RATES
+            ));
+        }
+
+        if self.audio_sample_frame_packet.len == 0 {
+            errors.push("audio_sample_frame_packet: len should be greater than 0".to_string());
+        }
+
+        if self.audio_library.audio_types.is_empty() {
+            errors.push("audio_library: audio_types is empty".to_string());
+        }
+
+        if !self.encoding.allowed_bitrates.contains(&self.encoding.default_bitrate) {
+            errors.push("encoding: default_bitrate is not in allowed_bitrates".to_string());
+        }
+
+        if self.transcode_cache.chunk_packets == 0 {
+            errors.push("transcode_cache: chunk_packets should be greater than 0".to_string());
+        }
+
+        if tracing_subscriber::EnvFilter::try_new(&self.log.filter).is_err() {
+            errors.push(format!("log: invalid filter '{}'", self.log.filter));
+        }
+
+        if self.artwork.allowed_sizes.is_empty() {
+            errors.push("artwork: allowed_sizes is empty".to_string());
+        }
+
+        if self.artwork.jpeg_quality == 0 || self.artwork.jpeg_quality > 100 {
+            errors.push("artwork: jpeg_quality should be 1 ~ 100".to_string());
+        }
+
+        errors
+    }
+}
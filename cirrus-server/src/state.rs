@@ -0,0 +1,99 @@
+use std::{
+    path::PathBuf,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use notify::{DebouncedEvent, RecursiveMode, Watcher};
+use tracing::{info, warn};
+
+use crate::settings::Settings;
+
+const SETTINGS_RELOAD_DEBOUNCE_MS: u64 = 500;
+
+// State which is created once at startup and shared by services and logic
+pub struct ServerState {
+    config_path: PathBuf,
+    settings: RwLock<Arc<Settings>>,
+}
+
+impl ServerState {
+    pub fn new(config_path: PathBuf) -> Result<Self, anyhow::Error> {
+        let settings = Settings::load(&config_path)?;
+
+        Ok(Self {
+            config_path,
+            settings: RwLock::new(Arc::new(settings)),
+        })
+    }
+
+    // Current settings, which are replaced when the config file is changed
+    pub fn settings(&self) -> Arc<Settings> {
+        self.settings.read().unwrap().clone()
+    }
+
+    // Settings that are read at startup, such as the listen address, mongodb, storage and auth,
+    // still require a restart
+    pub fn watch_settings(self: &Arc<Self>) -> Result<(), anyhow::Error> {
+        let config_path = self.config_path.clone();
+        let config_dir = match config_path.parent() {
+            Some(config_dir) => config_dir.to_path_buf(),
+            None => return Err(anyhow::anyhow!("invalid config path: {:?}", config_path)),
+        };
+
+        let (watcher_tx, watcher_rx) = std::sync::mpsc::channel();
+        let mut watcher = notify::watcher(watcher_tx, Duration::from_millis(SETTINGS_RELOAD_DEBOUNCE_MS))?;
+
+        // editors may replace the file on save, so the directory is watched instead of the file
+        watcher.watch(&config_dir, RecursiveMode::NonRecursive)?;
+
+        info!("watch settings {:?}", config_path);
+
+        let state = self.clone();
+
+        std::thread::spawn(move || {
+            // the watcher stops when it is dropped
+            let _watcher = watcher;
+
+            while let Ok(event) = watcher_rx.recv() {
+                let path = match event {
+                    DebouncedEvent::Create(path) |
+                    DebouncedEvent::Write(path) |
+                    DebouncedEvent::Rename(_, path) => path,
+                    _ => continue,
+                };
+
+                if path.file_name() == config_path.file_name() {
+                    state.reload_settings();
+                }
+            }
+        });
+
+        Ok(())
+    }
+
+    fn reload_settings(&self) {
+        let settings = match Settings::load(&self.config_path) {
+            Ok(settings) => settings,
+            Err(err) => {
+                warn!("failed to reload settings, previous settings are kept: {}", err);
+                return;
+            },
+        };
+
+        // a partially edited file may be loaded, and should not replace valid settings
+        let errors = settings.validate();
+        if !errors.is_empty() {
+            for error in errors.iter() {
+                warn!("invalid settings: {}", error);
+            }
+
+            warn!("failed to reload settings, previous settings are kept: {} errors", errors.len());
+            return;
+        }
+
+        *self.settings.write().unwrap() = Arc::new(settings);
+
+        info!("reloaded settings");
+    }
+}
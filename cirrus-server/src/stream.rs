@@ -0,0 +1,205 @@
+use std::{
+    convert::Infallible,
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use axum::{
+    body::{self, Body, StreamBody},
+    extract::{Path, Query, State},
+    http::{header, Request, Response},
+    response::IntoResponse,
+    routing::get,
+    Router,
+};
+use bson::oid::ObjectId;
+use cirrus_protobuf::api::AudioEncodingProfile;
+use serde::Deserialize;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{body::BoxBody, transport::NamedService, Status};
+use tower::{Service, ServiceExt};
+use tower_http::services::ServeFile;
+use tracing::{debug, info, instrument, Span};
+
+use crate::{
+    logic,
+    model::Storage,
+    rest::{self, ApiError, GrpcRequest},
+    service::{format_remote_addr, AuthInterceptor},
+    state::ServerState,
+};
+
+const OGG_OPUS_CONTENT_TYPE: &'static str = "audio/ogg";
+
+#[derive(Deserialize)]
+struct StreamQuery {
+    access_token: Option<String>,
+    // "opus" transcodes to Ogg Opus, and the original file is served if it is not set
+    codec: Option<String>,
+    // seconds of transcoded streams
+    #[serde(default)]
+    start: f64,
+    // bits per second of transcoded streams, which is one of `encoding.allowed_bitrates`
+    bitrate: Option<u32>,
+}
+
+// Tracks over plain HTTP at `/stream/<audio tag id>` of the gRPC port, which media players, curl
+// and `<audio>` elements play without the gRPC client
+#[derive(Clone)]
+pub struct HttpStream {
+    state: Arc<ServerState>,
+    audio_file: Arc<logic::AudioFile>,
+    listener: AuthInterceptor,
+}
+
+impl HttpStream {
+    pub fn new(
+        state: Arc<ServerState>,
+        storage: Arc<Storage>,
+        transcode_cache: Arc<logic::TranscodeCache>,
+        listener: AuthInterceptor,
+    ) -> Self {
+        Self {
+            state: state.clone(),
+            audio_file: Arc::new(logic::AudioFile::new(state, storage, transcode_cache)),
+            listener,
+        }
+    }
+
+    pub fn into_service(self, cors_allowed_origins: &[String]) -> HttpStreamSvc {
+        let router = Router::new()
+            .route("/stream/:audio_tag_id", get(stream_audio))
+            .with_state(self)
+            .layer(rest::create_cors_layer(cors_allowed_origins));
+
+        HttpStreamSvc { router }
+    }
+
+    // The server default is used if the bitrate is not set
+    fn create_encoding_profile(&self, bitrate: Option<u32>) -> Option<AudioEncodingProfile> {
+        let settings = self.state.settings();
+
+        bitrate.map(|bitrate| AudioEncodingProfile {
+            bitrate,
+            complexity: std::cmp::min(settings.encoding.default_complexity, settings.encoding.max_complexity),
+            ..Default::default()
+        })
+    }
+}
+
+// Routes of streams as a service of the tonic server, which routes `/stream/*` to it by the name
+#[derive(Clone)]
+pub struct HttpStreamSvc {
+    router: Router,
+}
+
+impl NamedService for HttpStreamSvc {
+    const NAME: &'static str = "stream";
+}
+
+impl Service<Request<Body>> for HttpStreamSvc {
+    type Response = Response<BoxBody>;
+    type Error = Infallible;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        rest::call_router(self.router.clone(), request)
+    }
+}
+
+#[instrument(skip_all, fields(remote_addr = tracing::field::Empty, audio_tag_id = %audio_tag_id))]
+async fn stream_audio(
+    State(stream): State<HttpStream>,
+    Path(audio_tag_id): Path<String>,
+    Query(query): Query<StreamQuery>,
+    grpc_request: GrpcRequest,
+    request: Request<Body>,
+) -> Result<axum::response::Response, ApiError> {
+    let grpc_request = grpc_request
+        .with_access_token(query.access_token.as_deref())
+        .authorize(stream.listener.clone(), ())?;
+
+    Span::current().record("remote_addr", format_remote_addr(&grpc_request).as_str());
+    info!("stream audio");
+
+    if ObjectId::parse_str(&audio_tag_id).is_err() {
+        return Err(Status::invalid_argument(format!("invalid audio tag id: {}", audio_tag_id)).into());
+    }
+
+    match query.codec.as_deref() {
+        None => stream_original(&stream, &audio_tag_id, request).await,
+        Some("opus") => stream_ogg_opus(&stream, &audio_tag_id, query.start, query.bitrate).await,
+        Some(codec) => Err(Status::invalid_argument(format!("unsupported codec: {}", codec)).into()),
+    }
+}
+
+// `Range`, `HEAD` and conditional requests are handled by `ServeFile`, so players seek by bytes
+async fn stream_original(
+    stream: &HttpStream,
+    audio_tag_id: &str,
+    request: Request<Body>,
+) -> Result<axum::response::Response, ApiError> {
+    let source_path = match stream.audio_file.get_source_path(audio_tag_id).await {
+        Ok(source_path) => source_path,
+        Err(err) => return Err(Status::not_found(err.to_string()).into()),
+    };
+
+    let res = ServeFile::new(source_path)
+        .oneshot(request)
+        .await
+        .map_err(|err| Status::internal(err.to_string()))?;
+
+    Ok(res.map(body::boxed))
+}
+
+// Transcoded streams have no length, and players seek with `start` instead of ranges
+async fn stream_ogg_opus(
+    stream: &HttpStream,
+    audio_tag_id: &str,
+    start_sec: f64,
+    bitrate: Option<u32>,
+) -> Result<axum::response::Response, ApiError> {
+    let encoding_profile = stream.create_encoding_profile(bitrate);
+
+    let pages = match stream.audio_file
+        .get_ogg_opus_pages(audio_tag_id, start_sec, encoding_profile.as_ref())
+        .await {
+            Ok(pages) => pages,
+            Err(err) => return Err(Status::invalid_argument(err.to_string()).into()),
+        };
+
+    let body = create_page_body(pages);
+
+    Ok(([(header::CONTENT_TYPE, OGG_OPUS_CONTENT_TYPE)], body).into_response())
+}
+
+// Pages are encoded while iterating, which blocks, so that these are sent from a blocking thread
+pub fn create_page_body(
+    pages: Box<dyn Iterator<Item = Vec<u8>> + Send>,
+) -> StreamBody<ReceiverStream<Result<Vec<u8>, Infallible>>> {
+    let (tx, rx) = mpsc::channel(16);
+    let span = Span::current();
+
+    tokio::task::spawn_blocking(move || span.in_scope(|| {
+        let mut sent_pages = 0;
+
+        for page in pages {
+            if tx.blocking_send(Ok(page)).is_err() {
+                break;
+            }
+
+            sent_pages += 1;
+        }
+
+        debug!(sent_pages, "streamed pages");
+    }));
+
+    StreamBody::new(ReceiverStream::new(rx))
+}
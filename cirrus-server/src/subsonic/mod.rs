@@ -0,0 +1,684 @@
+mod response;
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{ConnectInfo, Path, Query, State},
+    http::header,
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use bson::oid::ObjectId;
+use chrono::Utc;
+use cirrus_protobuf::api::{
+    playback_report_req, AlbumSummary, AudioEncodingProfile, AudioTagRes, PlaybackReportReq,
+};
+use itertools::Itertools;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use tokio::sync::Mutex;
+use tracing::{debug, info, instrument};
+
+use crate::{logic, model::Storage, state::ServerState, stream};
+
+use self::response::{ApiError, Format};
+
+const ARTIST_ID_PREFIX: &'static str = "ar-";
+const ALBUM_ID_PREFIX: &'static str = "al-";
+// passwords are checked again after this, so that a changed password applies
+const VERIFIED_CREDENTIALS_TTL: Duration = Duration::from_secs(5 * 60);
+const LIST_ARTISTS_PAGE_SIZE: u64 = 500;
+const DEFAULT_SEARCH_COUNT: u64 = 20;
+const MAX_SEARCH_COUNT: u64 = 500;
+// tracks are streamed as Ogg Opus regardless of the source format
+const STREAM_CONTENT_TYPE: &'static str = "audio/ogg";
+const STREAM_SUFFIX: &'static str = "opus";
+
+// Parameters of the query, where some of them repeat (e.g. `id` of scrobble)
+struct Params(Vec<(String, String)>);
+
+impl Params {
+    fn get(&self, name: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn get_all(&self, name: &str) -> Vec<&str> {
+        self.0
+            .iter()
+            .filter(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+            .collect()
+    }
+
+    fn get_parsed<T: FromStr>(&self, name: &str) -> Option<T> {
+        self.get(name).and_then(|item| item.parse().ok())
+    }
+
+    fn require(&self, name: &str) -> Result<&str, ApiError> {
+        match self.get(name) {
+            Some(value) => Ok(value),
+            None => Err(ApiError::missing_param(name)),
+        }
+    }
+}
+
+enum Reply {
+    Body(Option<(&'static str, Value)>),
+    Binary(Response),
+}
+
+fn encode_hex(value: &str) -> String {
+    value.bytes().map(|item| format!("{:02x}", item)).collect()
+}
+
+fn decode_hex(value: &str) -> Option<String> {
+    if !value.is_ascii() || value.len() % 2 != 0 {
+        return None;
+    }
+
+    let bytes = (0..value.len())
+        .step_by(2)
+        .map(|idx| u8::from_str_radix(&value[idx..idx + 2], 16).ok())
+        .collect::<Option<Vec<_>>>()?;
+
+    String::from_utf8(bytes).ok()
+}
+
+// Artists and albums are keyed by names, and their ids are the names encoded
+fn create_artist_id(name: &str) -> String {
+    format!("{}{}", ARTIST_ID_PREFIX, encode_hex(name))
+}
+
+fn parse_artist_id(id: &str) -> Option<String> {
+    decode_hex(id.strip_prefix(ARTIST_ID_PREFIX)?)
+}
+
+fn create_album_id(album: &str, album_artist: &str) -> String {
+    format!("{}{}-{}", ALBUM_ID_PREFIX, encode_hex(album), encode_hex(album_artist))
+}
+
+fn parse_album_id(id: &str) -> Option<(String, String)> {
+    let (album, album_artist) = id.strip_prefix(ALBUM_ID_PREFIX)?.split_once('-')?;
+
+    Some((decode_hex(album)?, decode_hex(album_artist)?))
+}
+
+// `enc:` passwords are hex encoded
+fn decode_password(password: &str) -> Result<String, ApiError> {
+    match password.strip_prefix("enc:") {
+        Some(encoded) => decode_hex(encoded).ok_or_else(ApiError::wrong_credentials),
+        None => Ok(password.to_string()),
+    }
+}
+
+fn get_index_name(name: &str) -> String {
+    match name.chars().next() {
+        Some(c) if c.is_alphabetic() => c.to_uppercase().collect(),
+        _ => "#".to_string(),
+    }
+}
+
+fn validate_audio_tag_id(id: &str) -> Result<(), ApiError> {
+    match ObjectId::parse_str(id) {
+        Ok(_) => Ok(()),
+        Err(_) => Err(ApiError::not_found(format!("song {} does not exist", id))),
+    }
+}
+
+fn to_album_json(album: &AlbumSummary) -> Value {
+    let mut res = json!({
+        "id": create_album_id(&album.album, &album.album_artist),
+        "name": album.album,
+        "artist": album.album_artist,
+        "artistId": create_artist_id(&album.album_artist),
+        "songCount": album.track_count,
+        "duration": album.duration / 1000,
+    });
+
+    if album.year > 0 {
+        res["year"] = json!(album.year);
+    }
+
+    if !album.artwork_id.is_empty() {
+        res["coverArt"] = json!(album.artwork_id);
+    }
+
+    res
+}
+
+// Albums are grouped by the album artist, or the track artist if it is not set
+fn get_album_artist(audio_tag: &AudioTagRes) -> &str {
+    match audio_tag.album_artist.is_empty() {
+        true => &audio_tag.artist,
+        false => &audio_tag.album_artist,
+    }
+}
+
+fn to_song_json(audio_tag: &AudioTagRes) -> Value {
+    let album_id = create_album_id(&audio_tag.album, get_album_artist(audio_tag));
+
+    let mut res = json!({
+        "id": audio_tag.id,
+        "parent": album_id,
+        "isDir": false,
+        "title": audio_tag.title,
+        "album": audio_tag.album,
+        "artist": audio_tag.artist,
+        "albumId": album_id,
+        "artistId": create_artist_id(&audio_tag.artist),
+        "duration": audio_tag.duration / 1000,
+        "contentType": STREAM_CONTENT_TYPE,
+        "suffix": STREAM_SUFFIX,
+        "type": "music",
+        "isVideo": false,
+    });
+
+    if audio_tag.track > 0 {
+        res["track"] = json!(audio_tag.track);
+    }
+
+    if audio_tag.disc > 0 {
+        res["discNumber"] = json!(audio_tag.disc);
+    }
+
+    if audio_tag.year > 0 {
+        res["year"] = json!(audio_tag.year);
+    }
+
+    if !audio_tag.genre.is_empty() {
+        res["genre"] = json!(audio_tag.genre);
+    }
+
+    if !audio_tag.artwork_id.is_empty() {
+        res["coverArt"] = json!(audio_tag.artwork_id);
+    }
+
+    res
+}
+
+// Subsonic API for Subsonic clients, which serves libraries, tags and transcoded packets of
+// the server
+pub struct Subsonic {
+    state: Arc<ServerState>,
+    storage: Arc<Storage>,
+    auth: Arc<logic::Auth>,
+    audio_tag: logic::AudioTag,
+    audio_browse: logic::AudioBrowse,
+    audio_file: logic::AudioFile,
+    artwork: logic::Artwork,
+    play_history: Arc<logic::PlayHistory>,
+    // credentials are sent at every request, and hashes of verified ones are kept so that
+    // passwords are not hashed with argon2 at every request
+    verified_credentials: Mutex<HashMap<Vec<u8>, (ObjectId, Instant)>>,
+}
+
+impl Subsonic {
+    pub fn new(
+        state: Arc<ServerState>,
+        storage: Arc<Storage>,
+        auth: Arc<logic::Auth>,
+        transcode_cache: Arc<logic::TranscodeCache>,
+        artwork: logic::Artwork,
+        play_history: Arc<logic::PlayHistory>,
+    ) -> Self {
+        Self {
+            state: state.clone(),
+            storage: storage.clone(),
+            auth,
+            audio_tag: logic::AudioTag::new(storage.clone()),
+            audio_browse: logic::AudioBrowse::new(storage.clone()),
+            audio_file: logic::AudioFile::new(state, storage, transcode_cache),
+            artwork,
+            play_history,
+            verified_credentials: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Returns id of the user, which is not set if authentication is disabled
+    async fn authenticate(&self, params: &Params) -> Result<Option<ObjectId>, ApiError> {
+        if !self.auth.is_enabled() {
+            return Ok(None);
+        }
+
+        let username = params.require("u")?;
+        let password = match (params.get("p"), params.get("t")) {
+            (Some(password), _) => decode_password(password)?,
+            (None, Some(_)) => return Err(ApiError::token_auth_not_supported()),
+            (None, None) => return Err(ApiError::missing_param("p")),
+        };
+
+        let credentials_hash = Sha256::digest(format!("{}:{}", username, password)).to_vec();
+
+        let verified_user_id = self.verified_credentials
+            .lock()
+            .await
+            .get(&credentials_hash)
+            .filter(|(_, verified_at)| verified_at.elapsed() < VERIFIED_CREDENTIALS_TTL)
+            .map(|(user_id, _)| *user_id);
+
+        if let Some(user_id) = verified_user_id {
+            return Ok(Some(user_id));
+        }
+
+        let user_id = match self.auth.authenticate(username, &password).await {
+            Ok(user) => user.id.unwrap(),
+            Err(err) => {
+                debug!("failed to authenticate: {}", err);
+
+                return Err(ApiError::wrong_credentials());
+            },
+        };
+
+        let mut verified_credentials = self.verified_credentials.lock().await;
+        verified_credentials.retain(|_, (_, verified_at)| verified_at.elapsed() < VERIFIED_CREDENTIALS_TTL);
+        verified_credentials.insert(credentials_hash, (user_id, Instant::now()));
+
+        Ok(Some(user_id))
+    }
+
+    async fn call(
+        &self,
+        method: &str,
+        params: &Params,
+        user_id: Option<&ObjectId>,
+        remote_ip: IpAddr,
+    ) -> Result<Reply, ApiError> {
+        match method {
+            "ping" => Ok(Reply::Body(None)),
+            // clients check the license of Subsonic servers before other calls
+            "getLicense" => Ok(Reply::Body(Some(("license", json!({ "valid": true }))))),
+            "getMusicFolders" => self.get_music_folders().await,
+            "getArtists" => self.get_artists().await,
+            "getArtist" => self.get_artist(params).await,
+            "getAlbum" => self.get_album(params).await,
+            "search3" => self.search3(params).await,
+            "stream" => self.stream(params).await,
+            "getCoverArt" => self.get_cover_art(params).await,
+            "scrobble" => self.scrobble(params, user_id, remote_ip).await,
+            _ => Err(ApiError::not_found(format!("method {} is not supported", method))),
+        }
+    }
+
+    // Folders are library roots, and ids are their positions
+    async fn get_music_folders(&self) -> Result<Reply, ApiError> {
+        let library_roots = self.storage.library_root
+            .get_all()
+            .await
+            .map_err(ApiError::generic)?;
+
+        let music_folders = library_roots
+            .iter()
+            .enumerate()
+            .map(|(idx, item)| json!({
+                "id": idx + 1,
+                "name": item.os_path,
+            }))
+            .collect::<Vec<_>>();
+
+        Ok(Reply::Body(Some(("musicFolders", json!({ "musicFolder": music_folders })))))
+    }
+
+    // Artists of all music folders, which are grouped by the first letter
+    async fn get_artists(&self) -> Result<Reply, ApiError> {
+        let mut artists = Vec::new();
+        let mut page = 1;
+
+        loop {
+            let artists_page = self.audio_browse
+                .list_artists(LIST_ARTISTS_PAGE_SIZE, page)
+                .await
+                .map_err(ApiError::generic)?;
+
+            let is_last_page = (artists_page.len() as u64) < LIST_ARTISTS_PAGE_SIZE;
+            artists.extend(artists_page);
+
+            if is_last_page {
+                break;
+            }
+
+            page += 1;
+        }
+
+        let mut indexes: BTreeMap<String, Vec<Value>> = BTreeMap::new();
+
+        for artist in artists.iter().filter(|item| !item.name.is_empty()) {
+            indexes
+                .entry(get_index_name(&artist.name))
+                .or_default()
+                .push(json!({
+                    "id": create_artist_id(&artist.name),
+                    "name": artist.name,
+                    "albumCount": artist.album_count,
+                }));
+        }
+
+        let indexes = indexes
+            .into_iter()
+            .map(|(name, artists)| json!({
+                "name": name,
+                "artist": artists,
+            }))
+            .collect::<Vec<_>>();
+
+        Ok(Reply::Body(Some(("artists", json!({
+            "ignoredArticles": "",
+            "index": indexes,
+        })))))
+    }
+
+    async fn get_artist(&self, params: &Params) -> Result<Reply, ApiError> {
+        let id = params.require("id")?;
+        let name = match parse_artist_id(id) {
+            Some(name) => name,
+            None => return Err(ApiError::not_found(format!("artist {} does not exist", id))),
+        };
+
+        let artist = self.audio_browse
+            .get_artist(&name)
+            .await
+            .map_err(ApiError::not_found)?;
+
+        let albums = artist.albums
+            .iter()
+            .map(to_album_json)
+            .collect::<Vec<_>>();
+
+        Ok(Reply::Body(Some(("artist", json!({
+            "id": id,
+            "name": name,
+            "albumCount": albums.len(),
+            "album": albums,
+        })))))
+    }
+
+    async fn get_album(&self, params: &Params) -> Result<Reply, ApiError> {
+        let id = params.require("id")?;
+        let (album, album_artist) = match parse_album_id(id) {
+            Some(album_key) => album_key,
+            None => return Err(ApiError::not_found(format!("album {} does not exist", id))),
+        };
+
+        let album = self.audio_browse
+            .get_album(&album, &album_artist)
+            .await
+            .map_err(ApiError::not_found)?;
+
+        let mut res = to_album_json(&album.album.unwrap_or_default());
+        res["song"] = album.tracks.iter().map(to_song_json).collect();
+
+        Ok(Reply::Body(Some(("album", res))))
+    }
+
+    // Artists and albums are ones of matched songs, in the order of their ranks
+    async fn search3(&self, params: &Params) -> Result<Reply, ApiError> {
+        // clients search "" (with quotes) to list all songs
+        let query = params.get("query").unwrap_or_default().trim_matches('"');
+
+        let artist_count = params.get_parsed::<usize>("artistCount").unwrap_or(DEFAULT_SEARCH_COUNT as usize);
+        let artist_offset = params.get_parsed::<usize>("artistOffset").unwrap_or(0);
+        let album_count = params.get_parsed::<usize>("albumCount").unwrap_or(DEFAULT_SEARCH_COUNT as usize);
+        let album_offset = params.get_parsed::<usize>("albumOffset").unwrap_or(0);
+        let song_count = params.get_parsed::<u64>("songCount").unwrap_or(DEFAULT_SEARCH_COUNT).min(MAX_SEARCH_COUNT);
+        let song_offset = params.get_parsed::<u64>("songOffset").unwrap_or(0);
+
+        let songs = match song_count {
+            0 => Vec::new(),
+            _ => self.search_audio_tags(query, song_offset, song_count).await?,
+        };
+
+        let hits = match query.is_empty() || (artist_count == 0 && album_count == 0) {
+            true => Vec::new(),
+            false => self.search_audio_tags(query, 0, MAX_SEARCH_COUNT).await?,
+        };
+
+        let artists = hits
+            .iter()
+            .map(|item| item.artist.as_str())
+            .filter(|item| !item.is_empty())
+            .unique()
+            .skip(artist_offset)
+            .take(artist_count)
+            .map(|item| json!({
+                "id": create_artist_id(item),
+                "name": item,
+            }))
+            .collect::<Vec<_>>();
+
+        let albums = hits
+            .iter()
+            .filter(|item| !item.album.is_empty())
+            .unique_by(|item| (item.album.clone(), get_album_artist(item).to_string()))
+            .skip(album_offset)
+            .take(album_count)
+            .map(|item| to_album_json(&AlbumSummary {
+                album: item.album.clone(),
+                album_artist: get_album_artist(item).to_string(),
+                year: item.year,
+                artwork_id: item.artwork_id.clone(),
+                ..Default::default()
+            }))
+            .collect::<Vec<_>>();
+
+        Ok(Reply::Body(Some(("searchResult3", json!({
+            "artist": artists,
+            "album": albums,
+            "song": songs.iter().map(to_song_json).collect::<Vec<_>>(),
+        })))))
+    }
+
+    async fn search_audio_tags(&self, query: &str, skip: u64, limit: u64) -> Result<Vec<AudioTagRes>, ApiError> {
+        self.audio_tag
+            .search_audio_tags_from(query, skip, limit)
+            .await
+            .map_err(ApiError::generic)
+    }
+
+    // Transcodes to Ogg Opus from `timeOffset` seconds, under `maxBitRate` kbps if it is set
+    async fn stream(&self, params: &Params) -> Result<Reply, ApiError> {
+        let id = params.require("id")?;
+        validate_audio_tag_id(id)?;
+
+        let start_sec = params.get_parsed::<f64>("timeOffset").unwrap_or(0.);
+        let encoding_profile = params
+            .get_parsed::<u32>("maxBitRate")
+            .filter(|item| *item > 0)
+            .and_then(|item| self.create_encoding_profile(item.saturating_mul(1000)));
+
+        let pages = self.audio_file
+            .get_ogg_opus_pages(id, start_sec, encoding_profile.as_ref())
+            .await
+            .map_err(ApiError::generic)?;
+
+        let body = stream::create_page_body(pages);
+
+        Ok(Reply::Binary(([(header::CONTENT_TYPE, STREAM_CONTENT_TYPE)], body).into_response()))
+    }
+
+    // The highest allowed bitrate under the limit, or the lowest one. The server default is used
+    // if it is under the limit
+    fn create_encoding_profile(&self, max_bitrate: u32) -> Option<AudioEncodingProfile> {
+        let settings = self.state.settings();
+        let encoding = &settings.encoding;
+
+        if encoding.default_bitrate <= max_bitrate {
+            return None;
+        }
+
+        let bitrate = encoding.allowed_bitrates
+            .iter()
+            .filter(|item| **item <= max_bitrate)
+            .max()
+            .or_else(|| encoding.allowed_bitrates.iter().min())?;
+
+        Some(AudioEncodingProfile {
+            bitrate: *bitrate,
+            complexity: std::cmp::min(encoding.default_complexity, encoding.max_complexity),
+            ..Default::default()
+        })
+    }
+
+    // Ids of cover arts are artwork ids, and sizes are rounded up to allowed ones
+    async fn get_cover_art(&self, params: &Params) -> Result<Reply, ApiError> {
+        let id = params.require("id")?;
+        let size = match params.get_parsed::<u32>("size") {
+            Some(size) => {
+                let settings = self.state.settings();
+
+                // the original is larger than allowed sizes
+                settings.artwork.allowed_sizes
+                    .iter()
+                    .filter(|item| **item >= size)
+                    .min()
+                    .copied()
+                    .unwrap_or(0)
+            },
+            None => 0,
+        };
+
+        let artwork = self.artwork
+            .get_artwork(id, size)
+            .await
+            .map_err(|err| match err.downcast_ref::<logic::ArtworkError>() {
+                Some(logic::ArtworkError::NotFound { .. }) => ApiError::not_found(err.to_string()),
+                _ => ApiError::generic(err.to_string()),
+            })?;
+
+        Ok(Reply::Binary(([(header::CONTENT_TYPE, artwork.mime_type)], artwork.data).into_response()))
+    }
+
+    // Submissions are counted as finished plays, and "now playing" ones stop counting streamed
+    // packets of the song
+    async fn scrobble(&self, params: &Params, user_id: Option<&ObjectId>, remote_ip: IpAddr) -> Result<Reply, ApiError> {
+        let ids = params.get_all("id");
+        if ids.is_empty() {
+            return Err(ApiError::missing_param("id"));
+        }
+
+        let times = params.get_all("time");
+        let submission = params.get("submission") != Some("false");
+
+        for (idx, id) in ids.into_iter().enumerate() {
+            if !submission {
+                self.play_history
+                    .set_now_playing(user_id, remote_ip, id)
+                    .await
+                    .map_err(ApiError::not_found)?;
+
+                continue;
+            }
+
+            let audio_tag = self.audio_tag
+                .get_audio_tag(id)
+                .await
+                .map_err(ApiError::not_found)?;
+
+            // the time of the play identifies the playback, so that a retried scrobble is
+            // counted once
+            let time = match times.get(idx) {
+                Some(time) => time.to_string(),
+                None => Utc::now().timestamp_millis().to_string(),
+            };
+
+            let req = PlaybackReportReq {
+                audio_tag_id: id.to_string(),
+                playback_id: format!("subsonic-{}-{}", id, time),
+                event: playback_report_req::Event::Finished as i32,
+                position_ms: audio_tag.duration as u64,
+            };
+
+            self.play_history
+                .report_playback(user_id, Some(remote_ip), &req)
+                .await
+                .map_err(ApiError::generic)?;
+        }
+
+        Ok(Reply::Body(None))
+    }
+}
+
+// Serves the API at `/rest/<method>`, which clients call with or without `.view`
+pub async fn serve(addr: SocketAddr, subsonic: Arc<Subsonic>) -> Result<(), anyhow::Error> {
+    let router = Router::new()
+        .route("/rest/:method", get(handle_request).post(handle_request))
+        .with_state(subsonic);
+
+    let server = axum::Server::try_bind(&addr)?
+        .serve(router.into_make_service_with_connect_info::<SocketAddr>());
+
+    info!("serve subsonic api at http://{}/rest", addr);
+
+    server.await?;
+
+    Ok(())
+}
+
+#[instrument(skip_all, fields(remote_addr = %remote_addr, method = %method))]
+async fn handle_request(
+    State(subsonic): State<Arc<Subsonic>>,
+    ConnectInfo(remote_addr): ConnectInfo<SocketAddr>,
+    Path(method): Path<String>,
+    Query(params): Query<Vec<(String, String)>>,
+) -> Response {
+    let params = Params(params);
+    let format = Format::from_param(params.get("f"));
+    let method = method.strip_suffix(".view").unwrap_or(&method);
+
+    info!("subsonic request");
+
+    let res = match subsonic.authenticate(&params).await {
+        Ok(user_id) => subsonic.call(method, &params, user_id.as_ref(), remote_addr.ip()).await,
+        Err(err) => Err(err),
+    };
+
+    match res {
+        Ok(Reply::Body(body)) => response::create_response(format, "ok", body),
+        Ok(Reply::Binary(res)) => res,
+        Err(err) => {
+            debug!("failed: {:?}", err);
+
+            err.into_response(format)
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_hex() {
+        assert_eq!(decode_hex(&encode_hex("Kind of Blue")).as_deref(), Some("Kind of Blue"));
+        assert_eq!(decode_hex(&encode_hex("잔나비")).as_deref(), Some("잔나비"));
+        assert_eq!(decode_hex("").as_deref(), Some(""));
+        assert_eq!(decode_hex("4"), None);
+        assert_eq!(decode_hex("zz"), None);
+        // not a utf-8 sequence
+        assert_eq!(decode_hex("ff"), None);
+    }
+
+    #[test]
+    fn test_parse_album_id() {
+        let id = create_album_id("Kind of Blue", "Miles Davis");
+        assert_eq!(parse_album_id(&id), Some(("Kind of Blue".to_string(), "Miles Davis".to_string())));
+
+        // hyphens of names are encoded, so that the separator is not ambiguous
+        let id = create_album_id("A-Side", "B-Side");
+        assert_eq!(parse_album_id(&id), Some(("A-Side".to_string(), "B-Side".to_string())));
+
+        let id = create_album_id("Untitled", "");
+        assert_eq!(parse_album_id(&id), Some(("Untitled".to_string(), String::new())));
+
+        assert_eq!(parse_album_id(&create_artist_id("Miles Davis")), None);
+        assert_eq!(parse_album_id(&format!("{}4b", ALBUM_ID_PREFIX)), None);
+    }
+}
@@ -0,0 +1,167 @@
+use axum::{
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+};
+use serde_json::{json, Map, Value};
+
+pub const API_VERSION: &'static str = "1.16.1";
+const XML_NAMESPACE: &'static str = "http://subsonic.org/restapi";
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Format {
+    Xml,
+    Json,
+}
+
+impl Format {
+    // `f` parameter of requests, and responses are XML by default
+    pub fn from_param(param: Option<&str>) -> Self {
+        match param {
+            Some("json") => Self::Json,
+            _ => Self::Xml,
+        }
+    }
+}
+
+// Errors of the API, which are returned with the status 200 as clients expect
+#[derive(Debug)]
+pub struct ApiError {
+    code: u32,
+    message: String,
+}
+
+impl ApiError {
+    pub fn generic(message: impl ToString) -> Self {
+        Self { code: 0, message: message.to_string() }
+    }
+
+    pub fn missing_param(name: &str) -> Self {
+        Self { code: 10, message: format!("required parameter '{}' is missing", name) }
+    }
+
+    pub fn wrong_credentials() -> Self {
+        Self { code: 40, message: "wrong username or password".to_string() }
+    }
+
+    // passwords are stored as hashes, so that salted tokens of passwords can not be verified
+    pub fn token_auth_not_supported() -> Self {
+        Self { code: 41, message: "token authentication is not supported, use the password".to_string() }
+    }
+
+    pub fn not_found(message: impl ToString) -> Self {
+        Self { code: 70, message: message.to_string() }
+    }
+
+    pub fn into_response(self, format: Format) -> Response {
+        let error = json!({
+            "code": self.code,
+            "message": self.message,
+        });
+
+        create_response(format, "failed", Some(("error", error)))
+    }
+}
+
+// Response of `subsonic-response`, whose body is an object at the key
+pub fn create_response(format: Format, status: &str, body: Option<(&str, Value)>) -> Response {
+    let mut res = Map::new();
+    res.insert("status".to_string(), json!(status));
+    res.insert("version".to_string(), json!(API_VERSION));
+    res.insert("type".to_string(), json!("cirrus"));
+    res.insert("serverVersion".to_string(), json!(env!("CARGO_PKG_VERSION")));
+
+    if let Some((key, value)) = body {
+        res.insert(key.to_string(), value);
+    }
+
+    match format {
+        Format::Json => {
+            let body = json!({ "subsonic-response": res }).to_string();
+
+            (StatusCode::OK, [(header::CONTENT_TYPE, "application/json")], body).into_response()
+        },
+        Format::Xml => {
+            res.insert("xmlns".to_string(), json!(XML_NAMESPACE));
+
+            let mut body = String::from(r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+            write_xml_element(&mut body, "subsonic-response", &Value::Object(res));
+
+            (StatusCode::OK, [(header::CONTENT_TYPE, "text/xml; charset=utf-8")], body).into_response()
+        },
+    }
+}
+
+// Scalars of an object are written as attributes, and objects and arrays as child elements,
+// which is how the JSON of the API maps to its XML
+fn write_xml_element(out: &mut String, name: &str, value: &Value) {
+    out.push('<');
+    out.push_str(name);
+
+    let map = match value {
+        Value::Object(map) => map,
+        Value::Null => {
+            out.push_str("/>");
+            return;
+        },
+        scalar => {
+            out.push('>');
+            out.push_str(&escape_xml(&format_scalar(scalar)));
+            out.push_str(&format!("</{}>", name));
+            return;
+        },
+    };
+
+    let mut children = Vec::new();
+
+    for (key, value) in map.iter() {
+        match value {
+            Value::Object(_) | Value::Array(_) => children.push((key, value)),
+            Value::Null => (),
+            scalar => out.push_str(&format!(r#" {}="{}""#, key, escape_xml(&format_scalar(scalar)))),
+        }
+    }
+
+    if children.is_empty() {
+        out.push_str("/>");
+        return;
+    }
+
+    out.push('>');
+
+    for (key, value) in children {
+        match value {
+            Value::Array(items) => {
+                for item in items.iter() {
+                    write_xml_element(out, key, item);
+                }
+            },
+            _ => write_xml_element(out, key, value),
+        }
+    }
+
+    out.push_str(&format!("</{}>", name));
+}
+
+fn format_scalar(value: &Value) -> String {
+    match value {
+        Value::String(value) => value.clone(),
+        _ => value.to_string(),
+    }
+}
+
+fn escape_xml(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+
+    escaped
+}
@@ -0,0 +1,49 @@
+use std::{fs::File, path::Path};
+
+use symphonia::core::{
+    codecs::CODEC_TYPE_NULL,
+    formats::{FormatOptions, FormatReader, Track},
+    io::MediaSourceStream,
+    meta::MetadataOptions,
+    probe::{Hint, ProbeResult},
+};
+
+pub fn probe_file(path: &Path) -> Result<ProbeResult, anyhow::Error> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(_) => return Err(anyhow::anyhow!("failed to load file")),
+    };
+
+    let mss = MediaSourceStream::new(Box::new(file), Default::default());
+
+    // file extension helps the probe to pick a format reader before sniffing the stream
+    let mut hint = Hint::new();
+    if let Some(extension) = path.extension().and_then(|item| item.to_str()) {
+        hint.with_extension(extension);
+    }
+
+    let meta_opts: MetadataOptions = Default::default();
+    let fmt_opts: FormatOptions = Default::default();
+
+    let probed = symphonia::default::get_probe().format(&hint, mss, &fmt_opts, &meta_opts)?;
+
+    Ok(probed)
+}
+
+pub fn find_audio_track(format: &dyn FormatReader) -> Result<&Track, anyhow::Error> {
+    match format.tracks()
+        .iter()
+        .find(|t| t.codec_params.codec != CODEC_TYPE_NULL) {
+            Some(track) => Ok(track),
+            None => Err(anyhow::anyhow!("no supported audio tracks")),
+        }
+}
+
+pub fn is_audio_type(path: &Path, audio_types: &[String]) -> bool {
+    match path.extension().and_then(|item| item.to_str()) {
+        Some(extension) => audio_types
+            .iter()
+            .any(|audio_type| audio_type.eq_ignore_ascii_case(extension)),
+        None => false,
+    }
+}
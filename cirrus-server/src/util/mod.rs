@@ -1,2 +1,3 @@
 pub mod path;
-pub mod hash;
\ No newline at end of file
+pub mod hash;
+pub mod audio;
\ No newline at end of file
@@ -7,12 +7,21 @@ pub fn replace_with_common_separator(path: &str) -> String {
 }
 
 // ref: https://docs.mongodb.com/manual/tutorial/model-tree-structures-with-materialized-paths/
-pub fn path_to_materialized(path: &Path) -> String {
-    let path = path.to_str().unwrap();
+pub fn path_to_materialized(path: &Path) -> Result<String, anyhow::Error> {
+    let path = match path.to_str() {
+        Some(path) => path,
+        None => return Err(anyhow::anyhow!("path {:?} is not valid unicode", path)),
+    };
     let path = replace_with_common_separator(path);
     let path = path.replace("/", ",");
 
-    format!(",{},", path)
+    Ok(format!(",{},", path))
+}
+
+// Upper bound of materialized paths that start with the path, as strings are compared by bytes
+// and U+10FFFF is the greatest character
+pub fn get_materialized_upper_bound(materialized_path: &str) -> String {
+    format!("{}\u{10ffff}", materialized_path)
 }
 
 pub fn materialized_to_path(materialized_path: &str) -> String {
@@ -22,9 +31,9 @@ pub fn materialized_to_path(materialized_path: &str) -> String {
     path.replace(",", "/")
 }
 
-pub fn get_timestamp(path: &Path) -> i64 {
-    let path_modified_time = path.metadata().unwrap().modified().unwrap();
+pub fn get_timestamp(path: &Path) -> Result<i64, anyhow::Error> {
+    let path_modified_time = path.metadata()?.modified()?;
     let path_modified_time = DateTime::<chrono::Utc>::from(path_modified_time);
 
-    path_modified_time.timestamp()
+    Ok(path_modified_time.timestamp())
 }
\ No newline at end of file
@@ -1,16 +1,58 @@
 use audio::{InterleavedBufMut, buf::Interleaved};
+use cirrus_protobuf::api::AudioCodec;
 use opus::Decoder;
+use symphonia::core::{
+    audio::SampleBuffer,
+    codecs::{CodecParameters, CODEC_TYPE_FLAC},
+    formats::Packet,
+};
+
+use crate::dto::AudioSource;
+
+enum PacketDecoderKind {
+    Opus(Decoder),
+    Flac(Box<dyn symphonia::core::codecs::Decoder>),
+    Pcm { bits_per_sample: u32 },
+}
 
 pub struct PacketDecoder {
-    decoder: Decoder,
+    decoder: PacketDecoderKind,
     buf: audio::buf::Interleaved<f32>,
+    channels: usize,
 }
 impl PacketDecoder {
-    pub fn new() -> Result<Self, anyhow::Error> {
+    pub fn new(source: &AudioSource) -> Result<Self, anyhow::Error> {
+        let channels = source.channels;
+
+        let decoder = match source.codec {
+            AudioCodec::Opus => {
+                let decoder_channels = match channels {
+                    1 => opus::Channels::Mono,
+                    2 => opus::Channels::Stereo,
+                    _ => return Err(anyhow::anyhow!("unsupported packet channels: {}", channels)),
+                };
+
+                PacketDecoderKind::Opus(Decoder::new(48_000, decoder_channels)?)
+            },
+            AudioCodec::Flac => {
+                let mut codec_params = CodecParameters::new();
+                codec_params
+                    .for_codec(CODEC_TYPE_FLAC)
+                    .with_extra_data(source.codec_extra_data.clone().into_boxed_slice());
+
+                PacketDecoderKind::Flac(
+                    symphonia::default::get_codecs().make(&codec_params, &Default::default())?
+                )
+            },
+            AudioCodec::Pcm => PacketDecoderKind::Pcm {
+                bits_per_sample: source.packet_bits_per_sample
+            },
+        };
 
         Ok(Self {
-            decoder: Decoder::new(48_000, opus::Channels::Stereo)?,
-            buf: audio::buf::Interleaved::<f32>::with_topology(2, 960),
+            decoder,
+            buf: audio::buf::Interleaved::<f32>::with_topology(channels, source.packet_len),
+            channels,
         })
     }
 
@@ -18,14 +60,56 @@ impl PacketDecoder {
         &mut self,
         input_packets: &Vec<u8>,
     ) -> Result<&Interleaved<f32>, anyhow::Error> {
+        match &mut self.decoder {
+            PacketDecoderKind::Opus(decoder) => {
+                decoder.decode_float(
+                    &input_packets,
+                    &mut self.buf.as_interleaved_mut(),
+                    false
+                )?;
+            },
+            PacketDecoderKind::Flac(decoder) => {
+                let packet = Packet::new_from_slice(0, 0, 0, input_packets);
+                let decoded = decoder.decode(&packet)?;
+
+                let mut sample_buf = SampleBuffer::<f32>::new(
+                    decoded.capacity() as u64,
+                    *decoded.spec()
+                );
+                sample_buf.copy_interleaved_ref(decoded);
+
+                Self::write_buf(&mut self.buf, self.channels, sample_buf.samples());
+            },
+            PacketDecoderKind::Pcm { bits_per_sample } => {
+                let bytes_per_sample = (*bits_per_sample / 8) as usize;
+                let scale = (1i64 << (*bits_per_sample - 1)) as f32;
+                let shift = 32 - *bits_per_sample;
 
-        self.decoder.decode_float(
-            &input_packets,
-            &mut self.buf.as_interleaved_mut(),
-            false
-        )?;
+                let samples = input_packets
+                    .chunks_exact(bytes_per_sample)
+                    .map(|bytes| {
+                        // place sample at most significant bytes, then sign-extend by shift
+                        let mut sample = [0u8; 4];
+                        sample[4 - bytes_per_sample..].copy_from_slice(bytes);
+
+                        (i32::from_le_bytes(sample) >> shift) as f32 / scale
+                    })
+                    .collect::<Vec<_>>();
+
+                Self::write_buf(&mut self.buf, self.channels, &samples);
+            },
+        }
 
         Ok(&self.buf)
     }
-}
 
+    fn write_buf(buf: &mut Interleaved<f32>, channels: usize, samples: &[f32]) {
+        let frames = samples.len() / channels;
+
+        if buf.as_interleaved_mut().len() != samples.len() {
+            *buf = audio::buf::Interleaved::<f32>::with_topology(channels, frames);
+        }
+
+        buf.as_interleaved_mut().copy_from_slice(samples);
+    }
+}
@@ -1,6 +1,7 @@
 use std::sync::Arc;
 
 use cpal::traits::{HostTrait, DeviceTrait};
+use tracing::debug;
 
 pub struct AudioDeviceContext {
     pub device: cpal::Device,
@@ -15,11 +16,11 @@ impl AudioDeviceContext {
             .default_output_device()
             .ok_or_else(|| anyhow::Error::msg("Default output device is not available"))?;
         
-        println!("Output device: {}", device.name()?);
+        debug!("Output device: {}", device.name()?);
     
         let output_stream_config: cpal::StreamConfig = device.default_output_config()?.into();
 
-        println!("Output stream properties: sample_rate: {}, channel(s): {}", 
+        debug!("Output stream properties: sample_rate: {}, channel(s): {}", 
                  output_stream_config.sample_rate.0, output_stream_config.channels);
 
         Ok(Self {
@@ -6,5 +6,6 @@ mod sample;
 mod packet;
 mod player;
 
-pub use player::{AudioPlayer, AudioPlayerMessage, AudioPlayerRequest, SetPlaybackPosMessage, RequestType};
+pub use decoder::PacketDecoder;
+pub use player::{AudioPlayer, AudioPlayerMessage, AudioPlayerRequest, SetPlaybackPosMessage, RequestType, ServerState};
 pub use stream::UpdatedStreamMessage;
\ No newline at end of file
@@ -4,6 +4,7 @@ use anyhow::anyhow;
 use cirrus_protobuf::api::AudioDataRes;
 use rand::Rng;
 use itertools::Itertools;
+use tracing::{debug, error, warn};
 
 #[derive(Clone, Copy, Debug)]
 pub enum SearchDirection {
@@ -218,14 +219,14 @@ impl BufferContext {
                     ).unwrap();
     
                     if let Err(_) = resolved_current_node.add_packet(packet_idx) {
-                        eprintln!("before current id: {}", before_current_node_id);
-                        eprintln!("resolved current id: {}", resolved_current_node.id);
+                        error!("before current id: {}", before_current_node_id);
+                        error!("resolved current id: {}", resolved_current_node.id);
 
                         for (k, v) in self.buffer_nodes
                             .iter()
                             .sorted_by(|a, b| Ord::cmp(&a.1.buf_start_idx, &b.1.buf_start_idx)) {
 
-                                eprintln!("node: {} {}..{}", k, v.buf_start_idx.unwrap(), v.buf_end_idx.unwrap());
+                                error!("node: {} {}..{}", k, v.buf_start_idx.unwrap(), v.buf_end_idx.unwrap());
                             }
 
                         return Err(anyhow!("failed to resolve node mismatch"));
@@ -323,7 +324,7 @@ impl BufferContext {
             &current_node_id
         ).unwrap();
 
-        println!("node merged: ({}..{}), ({}..{}) -> ({}..{}) ", 
+        debug!("node merged: ({}..{}), ({}..{}) -> ({}..{}) ", 
             current_node.buf_start_idx.unwrap(),
             current_node_buf_prev_end_idx,
 
@@ -412,7 +413,7 @@ impl PacketBuffer {
             audio_data.packet_idx,
             audio_data
         ) {
-            eprintln!("WARN: duplicated item inserted, idx: {}", d.packet_idx);
+            warn!("duplicated item inserted, idx: {}", d.packet_idx);
         }
 
         Ok(())
@@ -551,10 +552,10 @@ impl PacketBuffer {
 
         let avail_fetch_num = max_packet_idx - fetch_start_idx;
 
-        println!("current_node: {}, ({}..{})", current_node.id, current_node.buf_start_idx.unwrap(), current_node.buf_end_idx.unwrap());
+        debug!("current_node: {}, ({}..{})", current_node.id, current_node.buf_start_idx.unwrap(), current_node.buf_end_idx.unwrap());
 
-        println!("max packet idx: {}", max_packet_idx);
-        println!("delta: {}", avail_fetch_num);
+        debug!("max packet idx: {}", max_packet_idx);
+        debug!("delta: {}", avail_fetch_num);
 
         std::cmp::min(
             desired_fetch_packets,
@@ -3,10 +3,12 @@ use std::{
     thread
 };
 
+use cirrus_protobuf::api::AudioCodec;
 use crossbeam_channel::{Sender, Receiver};
 use enum_iterator::Sequence;
 use tokio::runtime::Handle;
 use tonic::transport::ClientTlsConfig;
+use tracing::debug;
 
 use crate::audio::{device::AudioDeviceContext, stream::AudioStream};
 
@@ -113,6 +115,7 @@ pub enum RequestType {
 
 pub struct AddAudioMessage {
     pub audio_tag_id: String,
+    pub codec: AudioCodec,
 }
 
 pub struct SetPlaybackPosMessage {
@@ -128,7 +131,7 @@ fn process_request(
 ) -> Result<(), anyhow::Error> {
     match request {
         AudioPlayerRequest::AddAudio(msg) => {
-            let content_length = audio_player.add_audio(&msg.audio_tag_id, &rt_handle)?;
+            let content_length = audio_player.add_audio(&msg.audio_tag_id, msg.codec, &rt_handle)?;
 
             let (sender, _) = response_channels.get(&RequestType::AddAudio).unwrap();
             sender.send(AudioPlayerResponse::AudioMeta(
@@ -178,7 +181,7 @@ fn process_request(
 }
 
 fn start_audio_player_thread(
-    grpc_endpoint: &str,
+    server_state: ServerState,
     event_sender: Option<Sender<UpdatedStreamMessage>>,
     request_sender: Sender<AudioPlayerRequest>,
     request_receiver: Receiver<AudioPlayerRequest>,
@@ -188,11 +191,9 @@ fn start_audio_player_thread(
     rt_handle: Handle,
 ) -> Result<(), anyhow::Error> {
 
-    let _grpc_endpoint = grpc_endpoint.to_string();
-
     thread::spawn(move || {
         let mut audio_player = AudioPlayerImpl::new(
-            &_grpc_endpoint,
+            server_state,
             event_sender,
             request_sender,
         ).unwrap();
@@ -202,7 +203,7 @@ fn start_audio_player_thread(
             let request = match request_receiver.recv() {
                 Ok(request) => request,
                 Err(err) => {
-                    println!("audio player manager channel disconnected, stop audio player thread");
+                    debug!("audio player manager channel disconnected, stop audio player thread");
                     break;
                 },
             };
@@ -232,7 +233,7 @@ pub struct AudioPlayer {
 impl AudioPlayer {
     pub fn new(
         event_sender: Option<Sender<UpdatedStreamMessage>>,
-        grpc_endpoint: &str,
+        server_state: ServerState,
     ) -> Result<Self, anyhow::Error> {
         let rt_handle = tokio::runtime::Handle::current();
 
@@ -260,7 +261,7 @@ impl AudioPlayer {
         }
         
         start_audio_player_thread(
-            grpc_endpoint,
+            server_state,
             event_sender,
             // None,
             request_sender.clone(),
@@ -280,10 +281,11 @@ impl AudioPlayer {
 
     pub fn add_audio(
         &self,
-        audio_tag_id: &str
+        audio_tag_id: &str,
+        codec: AudioCodec,
     ) -> Result<AudioMeta, anyhow::Error> {
         self.request_sender.send(AudioPlayerRequest::AddAudio(
-            AddAudioMessage { audio_tag_id: audio_tag_id.to_string() }
+            AddAudioMessage { audio_tag_id: audio_tag_id.to_string(), codec }
         ))?;
 
         let (_, receiver) = self.response_channels.get(&RequestType::AddAudio).unwrap();
@@ -354,15 +356,16 @@ pub struct AudioPlayerImpl {
     status: usize,
     event_sender: Option<Sender<UpdatedStreamMessage>>,
     request_sender: Sender<AudioPlayerRequest>,
+    server_state: ServerState,
 }
 
 impl AudioPlayerImpl {
     pub fn new(
-        grpc_endpoint: &str,
+        server_state: ServerState,
         event_sender: Option<Sender<UpdatedStreamMessage>>,
         request_sender: Sender<AudioPlayerRequest>,
     ) -> Result<Self, anyhow::Error> {
-        println!("create audio player core");
+        debug!("create audio player core");
 
         Ok(Self {
             device_context: AudioDeviceContext::new()?,
@@ -370,6 +373,7 @@ impl AudioPlayerImpl {
             status: 0,
             event_sender,
             request_sender,
+            server_state,
         })
     }
 
@@ -384,11 +388,12 @@ impl AudioPlayerImpl {
     pub fn add_audio(
         &mut self,
         audio_tag_id: &str,
+        codec: AudioCodec,
         // sender: &Sender<AudioPlayerMessage>,
         rt_handle: &Handle,
         // audio_source: AudioSource,
     ) -> Result<f64, anyhow::Error> {
-        // println!("process add audio request, params: {:?}", audio_source);
+        // debug!("process add audio request, params: {:?}", audio_source);
         // let audio_source = rt_handle.block_on(async move {
         //     AudioSource::new(
         //         "http://localhost:50000",
@@ -399,7 +404,9 @@ impl AudioPlayerImpl {
 
         let audio_stream = AudioStream::new(
             // audio_source.id.clone(),
+            &self.server_state,
             audio_tag_id,
+            codec,
             rt_handle,
             &self.device_context,
             // audio_source,
@@ -415,7 +422,7 @@ impl AudioPlayerImpl {
     }
 
     pub fn play(&mut self) -> Result<(), anyhow::Error> {
-        println!("process play request");
+        debug!("process play request");
         
         let audio_stream = self.streams.get_mut(0).unwrap();
         audio_stream.play()?;
@@ -425,7 +432,7 @@ impl AudioPlayerImpl {
     }
 
     pub fn stop(&mut self) -> Result<(), anyhow::Error> {
-        println!("process stop request");
+        debug!("process stop request");
 
         self.streams.clear();
 
@@ -433,7 +440,7 @@ impl AudioPlayerImpl {
     }
 
     pub fn pause(&self) -> Result<(), anyhow::Error> {
-        println!("process pause request");
+        debug!("process pause request");
 
         self.streams.get(0).unwrap().pause()?;
         
@@ -459,7 +466,7 @@ impl AudioPlayerImpl {
     }
 
     pub fn set_playback_position(&self, position_sec: f64) -> Result<(), anyhow::Error> {
-        println!("process set_playback_position request, params: {}", position_sec);
+        debug!("process set_playback_position request, params: {}", position_sec);
 
         self.streams.get(0).unwrap().set_playback_position(position_sec)?;
 
@@ -467,7 +474,7 @@ impl AudioPlayerImpl {
     }
 
     // pub fn get_player_status(&self) -> PlayerStatus {
-    //     println!("process get_player_status");
+    //     debug!("process get_player_status");
 
     //     PlayerStatus {
     //         status: 0,
@@ -1,4 +1,4 @@
-use audio::{Buf, buf::Interleaved, BufMut};
+use audio::{Buf, buf::Interleaved, BufMut, ChannelMut};
 use rubato::Resampler;
 
 pub struct AudioResampler {
@@ -8,29 +8,35 @@ pub struct AudioResampler {
     input_buf: audio::wrap::Dynamic<Vec<Vec<f32>>>,
     output_buf: audio::buf::Interleaved<f32>,
 
-    channels: usize,
+    input_sample_rate: usize,
+    output_sample_rate: usize,
+    input_channels: usize,
+    output_channels: usize,
 }
 
 impl AudioResampler {
     pub fn new(
+        input_sample_rate: usize,
+        input_frames: usize,
         output_sample_rate: usize,
-        channels: usize
+        input_channels: usize,
+        output_channels: usize
     ) -> Result<Self, anyhow::Error> {
         let chunk_size_out = output_sample_rate / 50;
 
         let resampler = rubato::FftFixedOut::<f32>::new(
-            48_000,
+            input_sample_rate,
             output_sample_rate,
             chunk_size_out,
             2,
-            channels
+            input_channels
         )?;
 
         let resampler_output_buf = resampler.output_buffer_allocate();
 
-        let input_buf = audio::wrap::dynamic(vec![vec![0.; 960]; 2]);
+        let input_buf = audio::wrap::dynamic(vec![vec![0.; input_frames]; input_channels]);
         let output_buf = audio::buf::Interleaved::with_topology(
-            channels,
+            output_channels,
             resampler.output_frames_max()
         );
 
@@ -39,7 +45,10 @@ impl AudioResampler {
             resampler_output_buf,
             input_buf,
             output_buf,
-            channels,
+            input_sample_rate,
+            output_sample_rate,
+            input_channels,
+            output_channels,
         })
     }
 
@@ -49,7 +58,7 @@ impl AudioResampler {
     ) -> Result<&Interleaved<f32>, anyhow::Error> {
         let decoded_samples = audio::io::Read::new(decoded_samples);
 
-        for ch_idx in 0..self.channels {
+        for ch_idx in 0..self.input_channels {
             audio::channel::copy(
                 decoded_samples.get(ch_idx).unwrap(),
                 self.input_buf.get_mut(ch_idx).unwrap()
@@ -62,17 +71,36 @@ impl AudioResampler {
             None
         ).unwrap();
 
-        for ch_idx in 0..self.channels {
-            audio::channel::copy(
-                audio::channel::LinearChannel::new(self.resampler_output_buf.get(ch_idx).unwrap()), 
-                self.output_buf.get_mut(ch_idx).unwrap(),
-            )
+        for ch_idx in 0..self.output_channels {
+            match self.get_input_channel_idx(ch_idx) {
+                Some(input_ch_idx) => audio::channel::copy(
+                    audio::channel::LinearChannel::new(self.resampler_output_buf.get(input_ch_idx).unwrap()), 
+                    self.output_buf.get_mut(ch_idx).unwrap(),
+                ),
+                None => self.output_buf.get_mut(ch_idx).unwrap().fill(0.),
+            }
         }
 
         Ok(&self.output_buf)
     }
 
     pub fn get_processed_sample_len(&self) -> usize {
-        self.resampler.output_frames_max() * self.channels
+        self.resampler.output_frames_max() * self.output_channels
+    }
+
+    // Output samples which correspond to the input frames, used to trim the start of a seek
+    pub fn get_output_sample_len(&self, input_frames: usize) -> usize {
+        input_frames * self.output_sample_rate / self.input_sample_rate * self.output_channels
+    }
+
+    // Mono input is duplicated to front left and right, remain device channels are silent
+    fn get_input_channel_idx(&self, output_ch_idx: usize) -> Option<usize> {
+        if output_ch_idx < self.input_channels {
+            Some(output_ch_idx)
+        } else if self.input_channels == 1 && output_ch_idx < 2 {
+            Some(0)
+        } else {
+            None
+        }
     }
 }
\ No newline at end of file
@@ -1,9 +1,10 @@
 use std::{sync::{Arc, Mutex, Condvar, atomic::{AtomicUsize, Ordering, AtomicU32}}, fmt::Display};
 use anyhow::anyhow;
 use audio::InterleavedBuf;
-use cirrus_protobuf::api::AudioDataRes;
+use cirrus_protobuf::api::{audio_data_req::Start, AudioDataRes};
 use tokio::{runtime::Handle, sync::RwLock};
 use tokio_stream::StreamExt;
+use tracing::{debug, debug_span, error, info_span, warn, Instrument};
 
 use crate::{dto::AudioSource, request};
 
@@ -115,6 +116,15 @@ impl Display for SetPlaybackPositionError {
 //     BufferStatus(FetchBufferStatus),
 // }
 
+// Position of a seek. The packet of the position is requested with `start_sec`, and the
+// server tells the samples to trim at the start of the packet
+#[derive(Debug, Clone)]
+pub struct SeekStart {
+    pub packet_idx: u32,
+    pub start_sec: f64,
+    pub trim_start_samples: Option<u32>,
+}
+
 pub struct FetchBufferSpec {
     pub init_fetch_sec: Option<u32>,
     pub buffer_margin_sec: u32,
@@ -238,16 +248,20 @@ impl AudioSampleInner {
         fetch_buffer_spec: FetchBufferSpec,
     ) -> Result<Self, anyhow::Error> {
         let packet_buffer = PacketBuffer::new(source.content_packets);
-        let packet_decoder = PacketDecoder::new()?;
+        let packet_decoder = PacketDecoder::new(&source)?;
+        let resampler = AudioResampler::new(
+            source.packet_sample_rate,
+            source.packet_len,
+            output_stream_config.sample_rate.0.try_into()?,
+            source.channels,
+            output_stream_config.channels.into(),
+        )?;
 
         Ok(Self {
             source,
             packet_buffer: Arc::new(RwLock::new(packet_buffer)),
             packet_decoder,
-            resampler: AudioResampler::new(
-                output_stream_config.sample_rate.0.try_into()?,
-                output_stream_config.channels.into(),
-            )?,
+            resampler,
             context: AudioSampleContext::default(),
             audio_stream_buf_producer,
             fetch_buffer_spec,
@@ -260,12 +274,23 @@ impl AudioSampleInner {
     ) -> Result<(), anyhow::Error> {
         self.set_fetch_buffer_action(Action::Pause, None)?;
 
-        let new_position_idx = position_sec as u32 * 50;
+        // resolved at the sample of packets as the server does
+        let start_sec = position_sec.max(0.);
+        let start_sample = (start_sec * self.source.packet_sample_rate as f64).round() as u64;
+        let new_position_idx = start_sample / self.source.packet_len as u64;
 
-        if new_position_idx >= self.source.content_packets {
+        if new_position_idx >= self.source.content_packets as u64 {
             return Err(anyhow!(SetPlaybackPositionError::ReactEnd));
         }
 
+        let new_position_idx = new_position_idx as u32;
+
+        *self.context.seek_start.lock().unwrap() = Some(SeekStart {
+            packet_idx: new_position_idx,
+            start_sec,
+            trim_start_samples: None,
+        });
+
         self.context.playback_sample_frame_pos.store(new_position_idx, Ordering::SeqCst);
 
         self.context.fetch_buffer_status.store(FetchBufferStatus::Filled as usize, Ordering::SeqCst);
@@ -332,7 +357,12 @@ impl AudioSampleInner {
         rt_handle: &Handle,
         fetch_sec: u32,
     ) -> Result<(), anyhow::Error> {
+        let grpc_endpoint = self.source.server.grpc_endpoint.clone();
+        let tls_config = self.source.server.tls_config.clone();
         let audio_tag_id = self.source.id.clone();
+        let channels = self.source.channels as u32;
+        let encoding_profile = self.source.encoding_profile.clone();
+        let codec = self.source.codec;
         let _fetch_buffer_status = self.context.fetch_buffer_status.clone();
         let _packet_buffer = self.packet_buffer.clone();
 
@@ -340,12 +370,15 @@ impl AudioSampleInner {
         let _fetch_buffer_request = self.context.fetch_buffer_request.clone();
 
         let _playback_sample_frame_pos = self.context.playback_sample_frame_pos.clone();
+        let _seek_start = self.context.seek_start.clone();
         
         let mut fetch_packet_cnt = 0;
         let fetch_required_packet_num = fetch_sec * 50;
 
         let mut is_interrupted = false;
 
+        let fetch_buffer_span = info_span!("fetch_buffer", audio_tag_id = %audio_tag_id);
+
         rt_handle.spawn(async move {
             let (fetch_buffer_mutex, fetch_buffer_condvar) = &*_fetch_buffer_condvar;
 
@@ -377,17 +410,26 @@ impl AudioSampleInner {
                     break;
                 }
 
+                // The packet of a seek is requested by seconds to know the samples to trim
+                let mut start_sec = _seek_start.lock().unwrap()
+                    .as_ref()
+                    .filter(|seek_start| seek_start.packet_idx == fetch_start_idx && seek_start.trim_start_samples.is_none())
+                    .map(|seek_start| seek_start.start_sec);
+
                 let mut audio_data_stream = match request::get_audio_data_stream(
-                    "http://localhost:50000", 
-                    &None, 
+                    &grpc_endpoint, 
+                    &tls_config, 
                     &audio_tag_id,
                     fetch_start_idx,
                     fetch_size, 
-                    2
+                    channels,
+                    codec,
+                    encoding_profile.clone(),
+                    start_sec.map(Start::StartSec),
                 ).await {
                     Ok(stream) => stream,
                     Err(err) => {
-                        eprintln!("{}", err);
+                        error!("failed to request audio data: {}", err);
                         _fetch_buffer_status.store(FetchBufferStatus::Error as usize, Ordering::SeqCst);
 
                         {
@@ -400,9 +442,10 @@ impl AudioSampleInner {
                     },
                 };
 
-                println!("fetch packet: {}..{}", fetch_start_idx, fetch_start_idx+fetch_size);
+                let fetch_span = debug_span!("fetch_packets", start_idx = fetch_start_idx, size = fetch_size);
+                debug!(parent: &fetch_span, "fetch packet: {}..{}", fetch_start_idx, fetch_start_idx+fetch_size);
         
-                while let Some(res) = audio_data_stream.next().await {
+                while let Some(res) = audio_data_stream.next().instrument(fetch_span.clone()).await {
                     if FetchBufferRequest::Stop == FetchBufferRequest::from(_fetch_buffer_request.load(Ordering::SeqCst)) {
                         _fetch_buffer_request.store(FetchBufferRequest::None as usize, Ordering::SeqCst);
                         is_interrupted = true;
@@ -413,15 +456,24 @@ impl AudioSampleInner {
                     let audio_data = match res {
                         Ok(data) => data,
                         Err(e) => {
-                            println!("err: {}", e);
+                            error!(parent: &fetch_span, "failed to receive audio data: {}", e);
                             _fetch_buffer_status.store(FetchBufferStatus::Error as usize, Ordering::SeqCst);
 
                             break;
                         }
                     };
 
+                    // Set before the packet is inserted, so the packet is not processed without the trim
+                    if start_sec.take().is_some() {
+                        if let Some(seek_start) = _seek_start.lock().unwrap().as_mut() {
+                            if seek_start.packet_idx == audio_data.packet_idx {
+                                seek_start.trim_start_samples = Some(audio_data.trim_start_samples);
+                            }
+                        }
+                    }
+
                     if let Err(e) = _packet_buffer.write().await.insert(audio_data) {
-                        eprintln!("failed to insert audio data: {}", e.to_string());
+                        warn!(parent: &fetch_span, "failed to insert audio data: {}", e);
                     }
 
                     fetch_packet_cnt += 1;
@@ -434,8 +486,8 @@ impl AudioSampleInner {
                 fetch_buffer_condvar.notify_one();
             }
 
-            println!("done fetch buffer, fetch cnt: {}", fetch_packet_cnt )
-        });
+            debug!(fetch_packet_cnt, "done fetch buffer");
+        }.instrument(fetch_buffer_span));
 
         Ok(())
     }
@@ -495,13 +547,22 @@ impl AudioSampleInner {
         
         // Check a processing of audio data is required
         let data = self.check_process_available()?;
+        let trimmed_len = self.resampler.get_output_sample_len(
+            self.take_trim_start_samples(data.packet_idx) as usize
+        );
 
         // Process audio data
-        let samples = self.packet_decoder.decode(&data.encoded_samples)?;
-        let samples = self.resampler.resample(samples)?;
+        let samples = {
+            let _span = debug_span!("decode", packet_idx = data.packet_idx).entered();
+
+            let samples = self.packet_decoder.decode(&data.encoded_samples)?;
+            self.resampler.resample(samples)?
+        };
+
+        // Push audio samples into the stream buffer, without samples before the position of a seek
+        let samples = samples.as_interleaved();
 
-        // Push audio samples into the stream buffer
-        self.audio_stream_buf_producer.push_slice(samples.as_interleaved());
+        self.audio_stream_buf_producer.push_slice(&samples[trimmed_len.min(samples.len())..]);
 
         self.context.playback_sample_frame_pos.store(
             self.context.playback_sample_frame_pos.load(Ordering::SeqCst) +1,
@@ -510,6 +571,23 @@ impl AudioSampleInner {
 
         Ok(())
     }
+
+    fn take_trim_start_samples(
+        &self,
+        packet_idx: u32,
+    ) -> u32 {
+        match self.context.seek_start.lock().unwrap().take() {
+            Some(seek_start) if seek_start.packet_idx == packet_idx => {
+                // The packet was fetched before the seek, so the trim is resolved as the server does
+                seek_start.trim_start_samples.unwrap_or_else(|| {
+                    let start_sample = (seek_start.start_sec * self.source.packet_sample_rate as f64).round() as u64;
+
+                    (start_sample % self.source.packet_len as u64) as u32
+                })
+            },
+            _ => 0,
+        }
+    }
 }
 
 // fn create_
@@ -524,6 +602,8 @@ pub struct AudioSampleContext {
     pub process_sample_condvar: Arc<(Mutex<bool>, Condvar)>,
 
     pub fetch_buffer_request: Arc<AtomicUsize>,
+
+    pub seek_start: Arc<Mutex<Option<SeekStart>>>,
 }
 
 impl Default for AudioSampleContext {
@@ -539,6 +619,8 @@ impl Default for AudioSampleContext {
             process_sample_condvar: Arc::new((Mutex::new(false), Condvar::new())),
 
             fetch_buffer_request: Arc::new(AtomicUsize::new(FetchBufferRequest::None as usize)),
+
+            seek_start: Arc::new(Mutex::new(None)),
         }
     }
 }
\ No newline at end of file
@@ -4,9 +4,13 @@ use ringbuf::{HeapRb, SharedRb, Consumer, Producer};
 
 use cpal::traits::{DeviceTrait, StreamTrait};
 use tokio::{runtime::Handle, sync::RwLock};
+use tonic::transport::ClientTlsConfig;
+use tracing::{error, warn};
 
-use super::{sample::{AudioSample, FetchBufferSpec, ProcessAudioDataStatus, SetPlaybackPositionError}, device::AudioDeviceContext, AudioPlayerRequest};
-use crate::dto::AudioSource;
+use super::{sample::{AudioSample, FetchBufferSpec, ProcessAudioDataStatus, SetPlaybackPositionError}, device::AudioDeviceContext, AudioPlayerRequest, ServerState};
+use cirrus_protobuf::api::{AudioCodec, playback_report_req};
+
+use crate::{dto::AudioSource, request};
 
 #[derive(Debug, PartialEq, Clone, serde_derive::Serialize)]
 pub enum StreamStatus {
@@ -62,6 +66,65 @@ pub struct UpdatedStreamMessage {
     pub(crate) message: UpdatedPlaybackMessage,
 }
 
+// Reports playback events to the server, which counts plays of the audio with these
+pub struct PlaybackReporter {
+    grpc_endpoint: String,
+    tls_config: Option<ClientTlsConfig>,
+    rt_handle: Handle,
+
+    audio_tag_id: String,
+    playback_id: String,
+    content_length: f64,
+
+    // frames that are actually played, seeking is not counted
+    played_frames: usize,
+    started: bool,
+    halfway: bool,
+    finished: bool,
+}
+
+impl PlaybackReporter {
+    pub fn new(
+        audio_source: &AudioSource,
+        rt_handle: &Handle,
+    ) -> Self {
+        Self {
+            grpc_endpoint: audio_source.server.grpc_endpoint.clone(),
+            tls_config: audio_source.server.tls_config.clone(),
+            rt_handle: rt_handle.clone(),
+
+            audio_tag_id: audio_source.id.clone(),
+            playback_id: format!("{:016x}", rand::random::<u64>()),
+            content_length: audio_source.length,
+
+            played_frames: 0,
+            started: false,
+            halfway: false,
+            finished: false,
+        }
+    }
+
+    fn report(&self, event: playback_report_req::Event, position_ms: u64) {
+        let grpc_endpoint = self.grpc_endpoint.clone();
+        let tls_config = self.tls_config.clone();
+        let audio_tag_id = self.audio_tag_id.clone();
+        let playback_id = self.playback_id.clone();
+
+        self.rt_handle.spawn(async move {
+            if let Err(err) = request::report_playback(
+                &grpc_endpoint,
+                &tls_config,
+                &audio_tag_id,
+                &playback_id,
+                event,
+                position_ms
+            ).await {
+                warn!("failed to report playback: {}", err);
+            }
+        });
+    }
+}
+
 pub struct StreamPlaybackContext {
     pub stream_id: String,
 
@@ -72,6 +135,7 @@ pub struct StreamPlaybackContext {
     host_stream_config: Arc<cpal::StreamConfig>,
 
     notify_update_sender: Option<Sender<UpdatedStreamMessage>>,
+    playback_reporter: PlaybackReporter,
 }
 
 impl StreamPlaybackContext {
@@ -79,6 +143,7 @@ impl StreamPlaybackContext {
         stream_id: String,
         host_stream_config: Arc<cpal::StreamConfig>,
         notify_update_sender: Option<Sender<UpdatedStreamMessage>>,
+        playback_reporter: PlaybackReporter,
     ) -> Self {
 
         Self {
@@ -89,6 +154,7 @@ impl StreamPlaybackContext {
             stream_status: Arc::new(AtomicUsize::new(StreamStatus::Pause as usize)),
             host_stream_config,
             notify_update_sender,
+            playback_reporter,
         }
     }
 
@@ -102,6 +168,36 @@ impl StreamPlaybackContext {
         let increased_sample_pos = self.sample_pos + value;
 
         self.set_sample_pos(increased_sample_pos);
+
+        self.playback_reporter.played_frames += value;
+        let played_sec = self.playback_reporter.played_frames as f64 / self.host_stream_config.sample_rate.0 as f64;
+
+        if !self.playback_reporter.halfway && played_sec >= self.playback_reporter.content_length / 2. {
+            self.playback_reporter.halfway = true;
+            self.playback_reporter.report(playback_report_req::Event::Halfway, self.get_position_ms());
+        }
+    }
+
+    fn report_playback_started(&mut self) {
+        if self.playback_reporter.started {
+            return;
+        }
+
+        self.playback_reporter.started = true;
+        self.playback_reporter.report(playback_report_req::Event::Started, self.get_position_ms());
+    }
+
+    fn report_playback_finished(&mut self) {
+        if self.playback_reporter.finished {
+            return;
+        }
+
+        self.playback_reporter.finished = true;
+        self.playback_reporter.report(playback_report_req::Event::Finished, self.get_position_ms());
+    }
+
+    fn get_position_ms(&self) -> u64 {
+        (self.sample_pos as f64 * 1000. / self.host_stream_config.sample_rate.0 as f64) as u64
     }
 
     fn set_sample_pos(&mut self, sample_pos: usize) {
@@ -162,7 +258,9 @@ pub struct AudioStream {
 impl AudioStream {
     pub fn new(
         // stream_id: String,
+        server_state: &ServerState,
         audio_tag_id: &str,
+        codec: AudioCodec,
         rt_handle: &Handle,
         device_context: &AudioDeviceContext,
         // source: AudioSource,
@@ -171,11 +269,20 @@ impl AudioStream {
         notify_update_sender: Option<Sender<UpdatedStreamMessage>>,
         request_sender: Sender<AudioPlayerRequest>,
     ) -> Result<Self, anyhow::Error> {
+        // request mono packets for mono output device, otherwise follow the source layout
+        let request_channels = match device_context.output_stream_config.channels {
+            1 => 1,
+            _ => 0,
+        };
+
         let audio_source = rt_handle.block_on(async move {
             AudioSource::new(
-                "http://localhost:50000",
-                &None,
-                audio_tag_id
+                &server_state.grpc_endpoint,
+                &server_state.tls_config,
+                audio_tag_id,
+                request_channels,
+                codec,
+                None
             ).await.unwrap()
         });
 
@@ -193,6 +300,7 @@ impl AudioStream {
                     audio_source.id.clone(),
                     device_context.output_stream_config.clone(),
                     notify_update_sender,
+                    PlaybackReporter::new(&audio_source, rt_handle),
                 )
             )
         );
@@ -215,8 +323,9 @@ impl AudioStream {
         let _audio_stream_buf_consumer = audio_stream_buf_consumer.clone();
         let _process_audio_data_status = audio_sample.inner.lock().unwrap().context.process_audio_data_status.clone();
         let _request_sender = request_sender.clone();
+        let _output_channels = device_context.output_stream_config.channels as usize;
 
-        let err_fn = |err| eprintln!("an error occurred on stream: {}", err);
+        let err_fn = |err| error!("an error occurred on stream: {}", err);
 
         let output_data_fn = move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
             let mut consumed_ch_samples = 0;
@@ -254,7 +363,7 @@ impl AudioStream {
                 }
 
             if consumed_ch_samples > 0 {
-                _stream_playback_context.blocking_write().increase_sample_pos(consumed_ch_samples / 2);
+                _stream_playback_context.blocking_write().increase_sample_pos(consumed_ch_samples / _output_channels);
             }
         };
 
@@ -277,9 +386,11 @@ impl AudioStream {
         stream_playback_context: &Arc<RwLock<StreamPlaybackContext>>,
         request_sender: &Sender<AudioPlayerRequest>,
     ) {
-        stream_playback_context
-            .blocking_read()
-            .update_stream_status(StreamStatus::ReachEnd);
+        let mut stream_playback_context = stream_playback_context.blocking_write();
+
+        // called at every output callback until the next stream is played
+        stream_playback_context.report_playback_finished();
+        stream_playback_context.update_stream_status(StreamStatus::ReachEnd);
 
         request_sender
             .send(AudioPlayerRequest::StreamReactEnd)
@@ -298,6 +409,7 @@ impl AudioStream {
             }
         );
         self.stream_playback_context.blocking_read().update_stream_status(StreamStatus::Play);
+        self.stream_playback_context.blocking_write().report_playback_started();
 
         Ok(())
     }
@@ -1,3 +1,4 @@
+use cirrus_protobuf::api::{AudioCodec, AudioEncodingProfile};
 use tonic::transport::ClientTlsConfig;
 
 use crate::request;
@@ -18,19 +19,32 @@ pub struct AudioSource {
     pub sample_rate: usize,
     pub packet_dur: f64,
     pub content_packets: u32,
+    pub encoding_profile: Option<AudioEncodingProfile>,
+    pub codec: AudioCodec,
+    pub packet_sample_rate: usize,
+    // frames of a packet
+    pub packet_len: usize,
+    pub packet_bits_per_sample: u32,
+    pub codec_extra_data: Vec<u8>,
 }
 
 impl AudioSource {
     pub async fn new(
         grpc_endpoint: &str,
         tls_config: &Option<ClientTlsConfig>,
-        audio_tag_id: &str
+        audio_tag_id: &str,
+        channels: u32,
+        codec: AudioCodec,
+        encoding_profile: Option<AudioEncodingProfile>,
     ) -> Result<Self, anyhow::Error> {
         let metadata_res = request::get_audio_meta(
             grpc_endpoint,
             tls_config,
-            audio_tag_id
-        ).await.unwrap().into_inner();
+            audio_tag_id,
+            channels,
+            codec,
+            encoding_profile
+        ).await?.into_inner();
 
         let server = Server {
             grpc_endpoint: grpc_endpoint.to_string(),
@@ -46,6 +60,13 @@ impl AudioSource {
             sample_rate: metadata_res.orig_sample_rate as usize,
             packet_dur: metadata_res.packet_dur,
             content_packets: metadata_res.sp_packets,
+            // request packets with the profile which is resolved by the server
+            encoding_profile: metadata_res.encoding_profile,
+            codec: AudioCodec::from_i32(metadata_res.codec).unwrap_or(AudioCodec::Opus),
+            packet_sample_rate: metadata_res.packet_sample_rate as usize,
+            packet_len: metadata_res.packet_len as usize,
+            packet_bits_per_sample: metadata_res.packet_bits_per_sample,
+            codec_extra_data: metadata_res.codec_extra_data,
         })
 
     }
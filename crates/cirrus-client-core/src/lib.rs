@@ -1,7 +1,8 @@
 pub mod request;
 mod dto;
-mod tls;
+pub mod tls;
 
 pub mod audio;
 
-pub use crate::audio::AudioPlayer;
\ No newline at end of file
+pub use crate::audio::AudioPlayer;
+pub use crate::dto::AudioSource;
\ No newline at end of file
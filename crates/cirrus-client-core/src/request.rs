@@ -1,26 +1,116 @@
+use std::sync::RwLock;
+
 use tokio_stream::StreamExt;
 use tonic::{Request, Response, Streaming, transport::{ClientTlsConfig, Channel, Endpoint}};
 
 use cirrus_protobuf::{
-    api::{AudioDataReq, AudioDataRes, AudioMetaReq, AudioMetaRes, AudioTagRes},
+    api::{
+        AlbumReq, AlbumRes, AlbumSummary, ArtistReq, ArtistRes, ArtistSummary,
+        AudioCodec, AudioDataReq, AudioDataRes, AudioEncodingProfile, AudioMetaReq, AudioMetaRes, AudioTagRes,
+        LoginReq, PlaybackReportReq, RefreshTokenReq, TokenRes, audio_data_req, playback_report_req,
+    },
     common::ListRequest,
+    audio_browse_svc_client::AudioBrowseSvcClient,
+    auth_svc_client::AuthSvcClient,
     audio_data_svc_client::AudioDataSvcClient,
     audio_tag_svc_client::AudioTagSvcClient,
+    play_history_svc_client::PlayHistorySvcClient,
 };
 
+// sent as a bearer token with requests, and set after login
+static ACCESS_TOKEN: RwLock<Option<String>> = RwLock::new(None);
+
+pub fn set_access_token(access_token: Option<String>) {
+    *ACCESS_TOKEN.write().unwrap() = access_token;
+}
+
+pub async fn login(
+    grpc_endpoint: &str,
+    tls_config: &Option<ClientTlsConfig>,
+    username: &str,
+    password: &str,
+) -> Result<TokenRes, anyhow::Error> {
+    let endpoint = create_endpoint(grpc_endpoint.to_string(), tls_config)?;
+    let tonic_channels = endpoint.connect().await?;
+
+    let mut client = AuthSvcClient::new(tonic_channels);
+
+    let request = Request::new(
+        LoginReq {
+            username: username.to_string(),
+            password: password.to_string(),
+        }
+    );
+
+    let response = client.login(request).await?.into_inner();
+    set_access_token(Some(response.access_token.clone()));
+
+    Ok(response)
+}
+
+pub async fn refresh_token(
+    grpc_endpoint: &str,
+    tls_config: &Option<ClientTlsConfig>,
+    refresh_token: &str,
+) -> Result<TokenRes, anyhow::Error> {
+    let endpoint = create_endpoint(grpc_endpoint.to_string(), tls_config)?;
+    let tonic_channels = endpoint.connect().await?;
+
+    let mut client = AuthSvcClient::new(tonic_channels);
+
+    let request = Request::new(
+        RefreshTokenReq {
+            refresh_token: refresh_token.to_string(),
+        }
+    );
+
+    let response = client.refresh_token(request).await?.into_inner();
+    set_access_token(Some(response.access_token.clone()));
+
+    Ok(response)
+}
+
+pub async fn logout(
+    grpc_endpoint: &str,
+    tls_config: &Option<ClientTlsConfig>,
+    refresh_token: &str,
+) -> Result<(), anyhow::Error> {
+    let endpoint = create_endpoint(grpc_endpoint.to_string(), tls_config)?;
+    let tonic_channels = endpoint.connect().await?;
+
+    let mut client = AuthSvcClient::new(tonic_channels);
+
+    let request = Request::new(
+        RefreshTokenReq {
+            refresh_token: refresh_token.to_string(),
+        }
+    );
+
+    client.logout(request).await?;
+    set_access_token(None);
+
+    Ok(())
+}
+
 pub async fn get_audio_meta(
     grpc_endpoint: &str,
     tls_config: &Option<ClientTlsConfig>,
-    audio_tag_id: &str
+    audio_tag_id: &str,
+    channels: u32,
+    codec: AudioCodec,
+    encoding_profile: Option<AudioEncodingProfile>,
 ) -> Result<Response<AudioMetaRes>, anyhow::Error> {
     let endpoint = create_endpoint(grpc_endpoint.to_string(), tls_config)?;
     let tonic_channels = endpoint.connect().await?;
 
     let mut client = AudioDataSvcClient::new(tonic_channels);
 
-    let request = Request::new({
+    let request = create_request({
         AudioMetaReq {
-            audio_tag_id: audio_tag_id.to_string()
+            audio_tag_id: audio_tag_id.to_string(),
+            channels,
+            encoding_profile,
+            codec: codec as i32,
         }
     });
 
@@ -36,18 +126,24 @@ pub async fn get_audio_data_stream(
     packet_start_idx: u32,
     packet_num: u32,
     channels: u32,
+    codec: AudioCodec,
+    encoding_profile: Option<AudioEncodingProfile>,
+    start: Option<audio_data_req::Start>,
 ) -> Result<Streaming<AudioDataRes>, anyhow::Error> {
     let endpoint = create_endpoint(grpc_endpoint.to_string(), tls_config)?;
     let tonic_channels = endpoint.connect().await?;
 
     let mut client = AudioDataSvcClient::new(tonic_channels);
 
-    let request = Request::new({
+    let request = create_request({
         AudioDataReq {
             audio_tag_id: audio_tag_id.to_string(),
             packet_start_idx,
             packet_num,
             channels,
+            encoding_profile,
+            codec: codec as i32,
+            start,
         }
     });
 
@@ -69,7 +165,7 @@ pub async fn get_audio_tags(
 
     let mut client = AudioTagSvcClient::new(tonic_channels);
 
-    let request = Request::new( 
+    let request = create_request( 
         ListRequest {
             items_per_page,
             page,
@@ -91,6 +187,147 @@ pub async fn get_audio_tags(
     Ok(res)
 }
 
+pub async fn get_albums(
+    grpc_endpoint: &str,
+    tls_config: &Option<ClientTlsConfig>,
+    items_per_page: u64,
+    page: u64
+) -> Result<Vec<AlbumSummary>, anyhow::Error> {
+    let endpoint = create_endpoint(grpc_endpoint.to_string(), tls_config)?;
+    let tonic_channels = endpoint.connect().await?;
+
+    let mut client = AudioBrowseSvcClient::new(tonic_channels);
+
+    let request = create_request(
+        ListRequest {
+            items_per_page,
+            page,
+        }
+    );
+
+    let response = client.list_albums(request).await?;
+    let mut stream = response.into_inner();
+    let mut res: Vec<_> = Vec::new();
+
+    while let Some(item) = stream.next().await {
+        res.push(item?);
+    }
+
+    Ok(res)
+}
+
+pub async fn get_album(
+    grpc_endpoint: &str,
+    tls_config: &Option<ClientTlsConfig>,
+    album: &str,
+    album_artist: &str,
+) -> Result<AlbumRes, anyhow::Error> {
+    let endpoint = create_endpoint(grpc_endpoint.to_string(), tls_config)?;
+    let tonic_channels = endpoint.connect().await?;
+
+    let mut client = AudioBrowseSvcClient::new(tonic_channels);
+
+    let request = create_request(
+        AlbumReq {
+            album: album.to_string(),
+            album_artist: album_artist.to_string(),
+        }
+    );
+
+    let response = client.get_album(request).await?;
+
+    Ok(response.into_inner())
+}
+
+pub async fn get_artists(
+    grpc_endpoint: &str,
+    tls_config: &Option<ClientTlsConfig>,
+    items_per_page: u64,
+    page: u64
+) -> Result<Vec<ArtistSummary>, anyhow::Error> {
+    let endpoint = create_endpoint(grpc_endpoint.to_string(), tls_config)?;
+    let tonic_channels = endpoint.connect().await?;
+
+    let mut client = AudioBrowseSvcClient::new(tonic_channels);
+
+    let request = create_request(
+        ListRequest {
+            items_per_page,
+            page,
+        }
+    );
+
+    let response = client.list_artists(request).await?;
+    let mut stream = response.into_inner();
+    let mut res: Vec<_> = Vec::new();
+
+    while let Some(item) = stream.next().await {
+        res.push(item?);
+    }
+
+    Ok(res)
+}
+
+pub async fn get_artist(
+    grpc_endpoint: &str,
+    tls_config: &Option<ClientTlsConfig>,
+    name: &str,
+) -> Result<ArtistRes, anyhow::Error> {
+    let endpoint = create_endpoint(grpc_endpoint.to_string(), tls_config)?;
+    let tonic_channels = endpoint.connect().await?;
+
+    let mut client = AudioBrowseSvcClient::new(tonic_channels);
+
+    let request = create_request(
+        ArtistReq {
+            name: name.to_string(),
+        }
+    );
+
+    let response = client.get_artist(request).await?;
+
+    Ok(response.into_inner())
+}
+
+pub async fn report_playback(
+    grpc_endpoint: &str,
+    tls_config: &Option<ClientTlsConfig>,
+    audio_tag_id: &str,
+    playback_id: &str,
+    event: playback_report_req::Event,
+    position_ms: u64,
+) -> Result<(), anyhow::Error> {
+    let endpoint = create_endpoint(grpc_endpoint.to_string(), tls_config)?;
+    let tonic_channels = endpoint.connect().await?;
+
+    let mut client = PlayHistorySvcClient::new(tonic_channels);
+
+    let request = create_request(
+        PlaybackReportReq {
+            audio_tag_id: audio_tag_id.to_string(),
+            playback_id: playback_id.to_string(),
+            event: event as i32,
+            position_ms,
+        }
+    );
+
+    client.report_playback(request).await?;
+
+    Ok(())
+}
+
+fn create_request<T>(message: T) -> Request<T> {
+    let mut request = Request::new(message);
+
+    if let Some(access_token) = &*ACCESS_TOKEN.read().unwrap() {
+        if let Ok(value) = format!("Bearer {}", access_token).parse() {
+            request.metadata_mut().insert("authorization", value);
+        }
+    }
+
+    request
+}
+
 fn create_endpoint(
     grpc_endpoint: String, 
     tls_config: &Option<ClientTlsConfig>
@@ -3,11 +3,14 @@ use std::{fs::File, io::{BufReader, Read}, path::PathBuf};
 use tonic::transport::{Certificate, ClientTlsConfig};
 
 pub fn load_cert(cert_path: &PathBuf, domain_name: &str) -> Result<ClientTlsConfig, anyhow::Error> {
-    let pem_file = File::open(cert_path).unwrap();
+    let pem_file = match File::open(cert_path) {
+        Ok(pem_file) => pem_file,
+        Err(err) => return Err(anyhow::anyhow!("failed to open certificate {:?}: {}", cert_path, err)),
+    };
     let mut pem_file = BufReader::new(pem_file);
     let mut pem = Vec::new();
 
-    pem_file.read_to_end(&mut pem).unwrap();
+    pem_file.read_to_end(&mut pem)?;
 
     let ca = Certificate::from_pem(pem);
     let tls_config = ClientTlsConfig::new()
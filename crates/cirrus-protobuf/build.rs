@@ -14,9 +14,30 @@ fn main() -> Result<(), anyhow::Error> {
         .collect::<PathBuf>();
     let project_path = dunce::canonicalize(&project_path).unwrap();
     let proto_path = project_path.join("protobuf");
+    let out_path = PathBuf::from(env::var("OUT_DIR").unwrap());
 
     let mut tonic_builder = tonic_build::configure()
         .type_attribute(".cirrus.api.AudioTagRes", "#[derive(serde::Serialize, serde::Deserialize)]")
+        .type_attribute(".cirrus.api.AlbumSummary", "#[derive(serde::Serialize, serde::Deserialize)]")
+        .type_attribute(".cirrus.api.AlbumRes", "#[derive(serde::Serialize, serde::Deserialize)]")
+        .type_attribute(".cirrus.api.ArtistSummary", "#[derive(serde::Serialize, serde::Deserialize)]")
+        .type_attribute(".cirrus.api.ArtistRes", "#[derive(serde::Serialize, serde::Deserialize)]")
+        .type_attribute(".cirrus.api.AudioLibraryRes", "#[derive(serde::Serialize, serde::Deserialize)]")
+        .type_attribute(".cirrus.api.AudioTagSearchRes", "#[derive(serde::Serialize, serde::Deserialize)]")
+        .type_attribute(".cirrus.api.AudioTagSearchHit", "#[derive(serde::Serialize, serde::Deserialize)]")
+        .type_attribute(".cirrus.api.FacetCount", "#[derive(serde::Serialize, serde::Deserialize)]")
+        .type_attribute(".cirrus.api.AudioMetaRes", "#[derive(serde::Serialize, serde::Deserialize)]")
+        .type_attribute(".cirrus.api.AudioEncodingProfile", "#[derive(serde::Serialize, serde::Deserialize)]")
+        .type_attribute(".cirrus.api.JobRes", "#[derive(serde::Serialize, serde::Deserialize)]")
+        .type_attribute(".cirrus.api.JobFileError", "#[derive(serde::Serialize, serde::Deserialize)]")
+        .type_attribute(".cirrus.common.Response", "#[derive(serde::Serialize, serde::Deserialize)]")
+        // requests of the REST API
+        .type_attribute(".cirrus.common.ListRequest", "#[derive(serde::Serialize, serde::Deserialize)]")
+        .type_attribute(".cirrus.api.AudioLibraryReq", "#[derive(serde::Serialize, serde::Deserialize)]")
+        .type_attribute(".cirrus.api.AudioTagSearchReq", "#[derive(serde::Serialize, serde::Deserialize)] #[serde(default)]")
+        .type_attribute(".cirrus.api.AudioMetaReq", "#[derive(serde::Serialize, serde::Deserialize)] #[serde(default)]")
+        // served by the server reflection
+        .file_descriptor_set_path(out_path.join("cirrus_descriptor.bin"))
         .build_server(false)
         .build_client(false);
 
@@ -2,6 +2,9 @@ use tonic;
 
 tonic::include_proto!("cirrus");
 
+#[cfg(feature = "server")]
+pub const FILE_DESCRIPTOR_SET: &[u8] = tonic::include_file_descriptor_set!("cirrus_descriptor");
+
 pub mod api {
     tonic::include_proto!("cirrus.api");
 }
@@ -1,10 +1,45 @@
 use tauri::{State, Window, Runtime};
 
-use cirrus_client_core::request;
-use cirrus_protobuf::api::AudioTagRes;
+use cirrus_client_core::{audio::ServerState, request};
+use cirrus_protobuf::api::{AlbumRes, AlbumSummary, ArtistRes, ArtistSummary, AudioCodec, AudioTagRes};
+use tracing::debug;
 
 use crate::state::AudioEventChannelState;
 use crate::state::AudioPlayerState;
+use crate::state::{AuthState, AuthStatus};
+
+#[tauri::command]
+pub async fn login(
+    username: String,
+    password: String,
+    state: State<'_, AuthState>,
+) -> Result<AuthStatus, String> {
+    debug!("got login command");
+
+    match state.login(&username, &password).await {
+        Ok(status) => Ok(status),
+        Err(err) => Err(format!("failed to login: {}", err)),
+    }
+}
+
+#[tauri::command]
+pub async fn logout(
+    state: State<'_, AuthState>,
+) -> Result<(), String> {
+    debug!("got logout command");
+
+    match state.logout().await {
+        Ok(_) => Ok(()),
+        Err(err) => Err(format!("failed to logout: {}", err)),
+    }
+}
+
+#[tauri::command]
+pub async fn get_auth_status(
+    state: State<'_, AuthState>,
+) -> Result<AuthStatus, &'static str> {
+    Ok(state.get_status().await)
+}
 
 #[tauri::command]
 pub fn set_playback_position(
@@ -19,12 +54,20 @@ pub fn set_playback_position(
 #[tauri::command]
 pub async fn load_audio(
     state: State<'_, AudioPlayerState>,
-    audio_tag_id: String
+    audio_tag_id: String,
+    codec: Option<String>,
 ) -> Result<f64, &'static str> {
 
-    println!("got load audio command");
+    debug!("got load audio command");
+
+    let codec = match codec.as_deref() {
+        None | Some("opus") => AudioCodec::Opus,
+        Some("flac") => AudioCodec::Flac,
+        Some("pcm") => AudioCodec::Pcm,
+        Some(_) => return Err("unsupported audio codec"),
+    };
 
-    let res = state.0.add_audio(&audio_tag_id).unwrap();
+    let res = state.0.add_audio(&audio_tag_id, codec).unwrap();
 
     Ok(res.content_length)
 }
@@ -34,7 +77,7 @@ pub fn start_audio(
     state: State<'_, AudioPlayerState>,
 ) -> Result<(), &'static str> {
 
-    println!("got start audio command");
+    debug!("got start audio command");
 
     state.0.play().unwrap();
    
@@ -46,7 +89,7 @@ pub fn stop_audio(
     state: State<'_, AudioPlayerState>,
 ) -> Result<(), &'static str> {
 
-    println!("got stop audio command");
+    debug!("got stop audio command");
     state.0.stop().unwrap();
 
     Ok(())
@@ -56,7 +99,7 @@ pub fn stop_audio(
 pub fn pause_audio(
     state: State<'_, AudioPlayerState>,
 ) -> Result<(), &'static str> {
-    println!("got pause audio command");
+    debug!("got pause audio command");
 
     state.0.pause().unwrap();
 
@@ -69,7 +112,7 @@ pub fn set_listen_updated_events<R: Runtime>(
     window: Window<R>,
     state: State<'_, AudioEventChannelState<R>>,
 ) {
-    println!("got set_listen_updated_events");
+    debug!("got set_listen_updated_events");
 
     let _send_event_condvar = state.send_event_condvar.clone();
     let (send_event_mutex, send_event_cv) = &*_send_event_condvar;
@@ -87,8 +130,9 @@ pub fn set_listen_updated_events<R: Runtime>(
 pub async fn get_audio_tags(
     items_per_page: u64,
     page: u32,
+    server_state: State<'_, ServerState>,
 ) -> Result<Vec<AudioTagRes>, &'static str> {
-    println!("got get-audio-tags command");
+    debug!("got get-audio-tags command");
 
     // match request::get_audio_tags(
     //     &state.audio_player.server_state.grpc_endpoint,
@@ -100,8 +144,8 @@ pub async fn get_audio_tags(
     //     Err(_) => return Err("failed to get audio tags from server"),
     // }
     match request::get_audio_tags(
-        "http://localhost:50000",
-        &None,
+        &server_state.grpc_endpoint,
+        &server_state.tls_config,
         items_per_page, 
         page as u64
     ).await {
@@ -109,3 +153,77 @@ pub async fn get_audio_tags(
         Err(_) => return Err("failed to get audio tags from server"),
     }
 }
+
+#[tauri::command]
+pub async fn get_albums(
+    items_per_page: u64,
+    page: u32,
+    server_state: State<'_, ServerState>,
+) -> Result<Vec<AlbumSummary>, &'static str> {
+    debug!("got get-albums command");
+
+    match request::get_albums(
+        &server_state.grpc_endpoint,
+        &server_state.tls_config,
+        items_per_page,
+        page as u64
+    ).await {
+        Ok(albums) => Ok(albums),
+        Err(_) => Err("failed to get albums from server"),
+    }
+}
+
+#[tauri::command]
+pub async fn get_album(
+    album: String,
+    album_artist: String,
+    server_state: State<'_, ServerState>,
+) -> Result<AlbumRes, &'static str> {
+    debug!("got get-album command");
+
+    match request::get_album(
+        &server_state.grpc_endpoint,
+        &server_state.tls_config,
+        &album,
+        &album_artist
+    ).await {
+        Ok(album) => Ok(album),
+        Err(_) => Err("failed to get album from server"),
+    }
+}
+
+#[tauri::command]
+pub async fn get_artists(
+    items_per_page: u64,
+    page: u32,
+    server_state: State<'_, ServerState>,
+) -> Result<Vec<ArtistSummary>, &'static str> {
+    debug!("got get-artists command");
+
+    match request::get_artists(
+        &server_state.grpc_endpoint,
+        &server_state.tls_config,
+        items_per_page,
+        page as u64
+    ).await {
+        Ok(artists) => Ok(artists),
+        Err(_) => Err("failed to get artists from server"),
+    }
+}
+
+#[tauri::command]
+pub async fn get_artist(
+    name: String,
+    server_state: State<'_, ServerState>,
+) -> Result<ArtistRes, &'static str> {
+    debug!("got get-artist command");
+
+    match request::get_artist(
+        &server_state.grpc_endpoint,
+        &server_state.tls_config,
+        &name
+    ).await {
+        Ok(artist) => Ok(artist),
+        Err(_) => Err("failed to get artist from server"),
+    }
+}
@@ -1,18 +1,23 @@
-use std::sync::{Mutex, Arc, Condvar};
+use std::{path::PathBuf, sync::{Mutex, Arc, Condvar}, time::Duration};
 
 use cirrus_client_core::audio::UpdatedStreamMessage;
 use state::AudioPlayerState;
 use tauri::{
-    Runtime,
+    AppHandle, Runtime,
     plugin::{TauriPlugin, Builder},
     Manager, Window,
 };
+use tracing::{info, warn};
 // use dunce;
 
-use crate::state::AudioEventChannelState;
+use crate::{
+    settings::Settings,
+    state::{AudioEventChannelState, AuthState},
+};
 
 pub mod state;
 pub mod commands;
+mod logging;
 mod settings;
 
 // fn manage_player_event<R: Runtime>(window: &Window<R>) {
@@ -29,11 +34,14 @@ mod settings;
 //     res_path
 // }
 
-// const RES_PATH_STR: &'static str = "resources";
-// const CONFIG_PATH_STR: &'static str = "configs/cirrus/client.toml";
+// client settings bundled as a resource, which also keeps the refresh token after login
+const CONFIG_PATH_STR: &'static str = "resources/configs/cirrus/client.toml";
+const CONFIG_FILENAME: &'static str = "client.toml";
+const RESOURCE_DIR_STR: &'static str = "resources";
 
 
 const UPDATED_AUDIO_PLAYER_EVENT_NAME: &'static str = "update-playback";
+const TOKEN_REFRESH_INTERVAL_SEC: u64 = 30;
 
 fn start_audio_event_send_thread<R: Runtime>() -> AudioEventChannelState<R> {
     let (audio_event_sender, audio_event_receiver) = crossbeam_channel::unbounded::<UpdatedStreamMessage>();
@@ -62,7 +70,7 @@ fn start_audio_event_send_thread<R: Runtime>() -> AudioEventChannelState<R> {
             let window_guard = _window.lock().unwrap();
             if let Some(w) = &*window_guard {
                 if let Err(e) = w.emit(UPDATED_AUDIO_PLAYER_EVENT_NAME, message) {
-                    println!("{:?}", e);
+                    warn!("failed to emit audio player event: {:?}", e);
                 }
             }
 
@@ -88,10 +96,38 @@ fn start_audio_event_send_thread<R: Runtime>() -> AudioEventChannelState<R> {
     }
 }
 
+// client.toml at the app config directory is preferred over the bundled one, so that it is kept
+// when the app is updated
+fn resolve_config_path<R: Runtime>(app: &AppHandle<R>) -> Result<PathBuf, anyhow::Error> {
+    let path_resolver = app.path_resolver();
+
+    if let Some(config_path) = path_resolver.app_dir().map(|item| item.join(CONFIG_FILENAME)) {
+        if config_path.is_file() {
+            return Ok(config_path);
+        }
+    }
+
+    match path_resolver.resolve_resource(CONFIG_PATH_STR) {
+        Some(config_path) if config_path.is_file() => Ok(config_path),
+        _ => Err(anyhow::anyhow!(
+            "{} does not exist at the app config or resource directory, copy client.sample.toml to it",
+            CONFIG_FILENAME
+        )),
+    }
+}
+
 pub fn init<R: Runtime>() -> TauriPlugin<R> {
     Builder::new("cirrus")
         .invoke_handler(tauri::generate_handler![
+            commands::login,
+            commands::logout,
+            commands::get_auth_status,
+
             commands::get_audio_tags,
+            commands::get_albums,
+            commands::get_album,
+            commands::get_artists,
+            commands::get_artist,
 
             commands::load_audio,
             commands::pause_audio,
@@ -106,10 +142,42 @@ pub fn init<R: Runtime>() -> TauriPlugin<R> {
             let _event_sender = audio_event_channel_state.event_sender.clone();
             app.manage(audio_event_channel_state);
 
+            let config_path = resolve_config_path(&app.handle())?;
+            let resource_dir = app.path_resolver()
+                .resolve_resource(RESOURCE_DIR_STR)
+                .expect("failed to resolve resource directory");
+
+            let settings = Settings::new(&config_path)?;
+            logging::init(&settings.log);
+
+            info!("load client settings from {:?}", config_path);
+            let server_state = settings.get_server_state(&resource_dir)?;
+
             app.manage(AudioPlayerState::new(
                 Some(_event_sender),
-                "http://localhost:50000")?
+                server_state.clone())?
             );
+            let app_config_path = app.path_resolver().app_dir().map(|item| item.join(CONFIG_FILENAME));
+            app.manage(AuthState::new(config_path, app_config_path, server_state.clone()));
+            app.manage(server_state);
+
+            let app_handle = app.handle();
+            tauri::async_runtime::spawn(async move {
+                let auth_state = app_handle.state::<AuthState>();
+
+                if let Err(err) = auth_state.restore().await {
+                    warn!("failed to restore login session: {}", err);
+                }
+
+                // keeps the access token valid while the client runs
+                loop {
+                    tokio::time::sleep(Duration::from_secs(TOKEN_REFRESH_INTERVAL_SEC)).await;
+
+                    if let Err(err) = auth_state.refresh_expiring_token().await {
+                        warn!("failed to refresh access token: {}", err);
+                    }
+                }
+            });
 
             Ok(())
         })
@@ -0,0 +1,27 @@
+use tracing::warn;
+use tracing_subscriber::{fmt, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer};
+
+use crate::settings::{self, LogFormat};
+
+const DEFAULT_LOG_FILTER: &'static str = "info";
+
+// The app may have set its own subscriber already, which is kept then
+pub fn init(settings: &settings::Log) {
+    let (filter, filter_err) = match EnvFilter::try_new(&settings.filter) {
+        Ok(filter) => (filter, None),
+        Err(err) => (EnvFilter::new(DEFAULT_LOG_FILTER), Some(err)),
+    };
+
+    let layer = match settings.format {
+        LogFormat::Pretty => fmt::layer().boxed(),
+        LogFormat::Json => fmt::layer().json().boxed(),
+    };
+
+    if tracing_subscriber::registry().with(filter).with(layer).try_init().is_err() {
+        return;
+    }
+
+    if let Some(err) = filter_err {
+        warn!("invalid log.filter, '{}' is used instead: {}", DEFAULT_LOG_FILTER, err);
+    }
+}
@@ -1,4 +1,5 @@
-use std::{env, path::PathBuf};
+use std::path::{Path, PathBuf};
+use cirrus_client_core::{audio::ServerState, tls};
 use config::{Config, File, ConfigError};
 use serde_derive::{Serialize, Deserialize};
 
@@ -17,11 +18,47 @@ pub struct Server {
     pub grpc_endpoint: String,
 }
 
+// Kept after login, so that the session is restored at the next start
+#[derive(Serialize, Deserialize, Default)]
+#[allow(unused)]
+pub struct Auth {
+    pub username: String,
+    pub refresh_token: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Pretty,
+    Json,
+}
+
+#[derive(Serialize, Deserialize)]
+#[allow(unused)]
+pub struct Log {
+    pub format: LogFormat,
+    // directives of `tracing_subscriber::EnvFilter`, e.g. "info,cirrus_client_core=debug"
+    pub filter: String,
+}
+
+impl Default for Log {
+    fn default() -> Self {
+        Self {
+            format: LogFormat::Pretty,
+            filter: "info".to_string(),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 #[allow(unused)]
 pub struct Settings {
     pub server: Server,
     pub tls: Tls,
+    #[serde(default)]
+    pub auth: Auth,
+    #[serde(default)]
+    pub log: Log,
 }
 
 impl Settings {
@@ -32,4 +69,27 @@ impl Settings {
 
         s.try_deserialize()
     }
+
+    // Relative `tls.cert_path` is resolved at the resource directory
+    pub fn get_server_state(&self, resource_dir: &Path) -> Result<ServerState, anyhow::Error> {
+        let tls_config = match self.tls.use_tls {
+            true => {
+                let cert_path = resource_dir.join(&self.tls.cert_path);
+                Some(tls::load_cert(&cert_path, &self.tls.domain_name)?)
+            },
+            false => None,
+        };
+
+        Ok(ServerState {
+            grpc_endpoint: self.server.grpc_endpoint.clone(),
+            tls_config,
+        })
+    }
+
+    pub fn save(&self, config_path: &PathBuf) -> Result<(), anyhow::Error> {
+        let content = toml::to_string(self)?;
+        std::fs::write(config_path, content)?;
+
+        Ok(())
+    }
 }
\ No newline at end of file
@@ -1,7 +1,14 @@
-use std::{sync::{Arc, Mutex, Condvar}};
-use cirrus_client_core::{AudioPlayer, audio::UpdatedStreamMessage};
+use std::{sync::{Arc, Mutex, Condvar}, path::PathBuf, time::{SystemTime, UNIX_EPOCH}};
+use cirrus_client_core::{AudioPlayer, audio::{ServerState, UpdatedStreamMessage}, request};
+use cirrus_protobuf::api::{TokenRes, UserRole};
 use crossbeam_channel::{Receiver, Sender};
 use tauri::{Runtime, Window};
+use tracing::warn;
+
+use crate::settings::{self, Settings};
+
+// access token is refreshed when it expires within this
+const ACCESS_TOKEN_REFRESH_MARGIN_SEC: i64 = 120;
 
 pub struct AudioEventChannelState<R: Runtime> {
     pub event_sender: Sender<UpdatedStreamMessage>,
@@ -35,11 +42,168 @@ pub struct AudioPlayerState(pub AudioPlayer);
 impl AudioPlayerState {
     pub fn new(
         event_sender: Option<Sender<UpdatedStreamMessage>>,
-        grpc_endpoint: &str
+        server_state: ServerState,
     ) -> Result<Self, anyhow::Error> {
 
         Ok(Self {
-            0: AudioPlayer::new(event_sender, grpc_endpoint)?
+            0: AudioPlayer::new(event_sender, server_state)?
         })   
     }
 }
+
+
+#[derive(Clone, serde_derive::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthStatus {
+    pub logged_in: bool,
+    pub username: String,
+    pub role: String,
+}
+
+struct AuthSession {
+    username: String,
+    role: String,
+    refresh_token: String,
+    access_token_expires_at: i64,
+}
+
+impl From<TokenRes> for AuthSession {
+    fn from(token: TokenRes) -> Self {
+        let role = match UserRole::from_i32(token.role) {
+            Some(UserRole::Admin) => "admin",
+            _ => "listener",
+        };
+
+        Self {
+            username: token.username,
+            role: role.to_string(),
+            refresh_token: token.refresh_token,
+            access_token_expires_at: token.access_token_expires_at,
+        }
+    }
+}
+
+// Keeps the login session, and the refresh token is stored at the client settings
+pub struct AuthState {
+    config_path: PathBuf,
+    // client.toml at the app config directory, as the bundled one may not be writable
+    app_config_path: Option<PathBuf>,
+    server_state: ServerState,
+    session: tokio::sync::Mutex<Option<AuthSession>>,
+}
+
+impl AuthState {
+    pub fn new(config_path: PathBuf, app_config_path: Option<PathBuf>, server_state: ServerState) -> Self {
+        Self {
+            config_path,
+            app_config_path,
+            server_state,
+            session: tokio::sync::Mutex::new(None),
+        }
+    }
+
+    pub async fn login(&self, username: &str, password: &str) -> Result<AuthStatus, anyhow::Error> {
+        let token = request::login(&self.server_state.grpc_endpoint, &self.server_state.tls_config, username, password).await?;
+
+        self.set_session(AuthSession::from(token)).await
+    }
+
+    pub async fn logout(&self) -> Result<(), anyhow::Error> {
+        let session = self.session.lock().await.take();
+
+        if let Some(session) = session {
+            if let Err(err) = request::logout(&self.server_state.grpc_endpoint, &self.server_state.tls_config, &session.refresh_token).await {
+                warn!("failed to revoke refresh token: {}", err);
+            }
+        }
+
+        request::set_access_token(None);
+        self.save_settings(settings::Auth::default())
+    }
+
+    // Logs in with the stored refresh token, if the last session is not logged out
+    pub async fn restore(&self) -> Result<(), anyhow::Error> {
+        let settings = Settings::new(&self.config_path)?;
+        if settings.auth.refresh_token.is_empty() {
+            return Ok(());
+        }
+
+        let token = request::refresh_token(&self.server_state.grpc_endpoint, &self.server_state.tls_config, &settings.auth.refresh_token).await?;
+        self.set_session(AuthSession::from(token)).await?;
+
+        Ok(())
+    }
+
+    pub async fn refresh_expiring_token(&self) -> Result<(), anyhow::Error> {
+        let refresh_token = match &*self.session.lock().await {
+            Some(session) if session.access_token_expires_at - get_timestamp() < ACCESS_TOKEN_REFRESH_MARGIN_SEC => {
+                session.refresh_token.clone()
+            },
+            _ => return Ok(()),
+        };
+
+        let token = request::refresh_token(&self.server_state.grpc_endpoint, &self.server_state.tls_config, &refresh_token).await?;
+        self.set_session(AuthSession::from(token)).await?;
+
+        Ok(())
+    }
+
+    pub async fn get_status(&self) -> AuthStatus {
+        match &*self.session.lock().await {
+            Some(session) => AuthStatus {
+                logged_in: true,
+                username: session.username.clone(),
+                role: session.role.clone(),
+            },
+            None => AuthStatus {
+                logged_in: false,
+                username: String::new(),
+                role: String::new(),
+            },
+        }
+    }
+
+    // The session is kept even if it is not saved, and then it is not restored at the next start
+    async fn set_session(&self, session: AuthSession) -> Result<AuthStatus, anyhow::Error> {
+        let auth = settings::Auth {
+            username: session.username.clone(),
+            refresh_token: session.refresh_token.clone(),
+        };
+
+        *self.session.lock().await = Some(session);
+
+        if let Err(err) = self.save_settings(auth) {
+            warn!("failed to save login session: {}", err);
+        }
+
+        Ok(self.get_status().await)
+    }
+
+    // Settings are written to client.toml at the app config directory, which is created from the
+    // loaded settings if it does not exist
+    fn save_settings(&self, auth: settings::Auth) -> Result<(), anyhow::Error> {
+        let app_config_path = match &self.app_config_path {
+            Some(app_config_path) => app_config_path,
+            None => return Err(anyhow::anyhow!("app config directory is not available")),
+        };
+
+        let mut settings = match app_config_path.is_file() {
+            true => Settings::new(app_config_path)?,
+            false => Settings::new(&self.config_path)?,
+        };
+        settings.auth = auth;
+
+        if let Some(app_config_dir) = app_config_path.parent() {
+            std::fs::create_dir_all(app_config_dir)?;
+        }
+
+        settings.save(app_config_path)
+    }
+}
+
+fn get_timestamp() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|item| item.as_secs() as i64)
+        .unwrap_or_default()
+}
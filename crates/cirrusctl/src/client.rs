@@ -0,0 +1,91 @@
+use cirrus_client_core::request;
+use cirrus_protobuf::{
+    audio_data_svc_client::AudioDataSvcClient,
+    audio_library_svc_client::AudioLibrarySvcClient,
+    audio_tag_svc_client::AudioTagSvcClient,
+    job_svc_client::JobSvcClient,
+};
+use tonic::{
+    metadata::{Ascii, MetadataValue},
+    service::{interceptor::InterceptedService, Interceptor},
+    transport::{Channel, ClientTlsConfig, Endpoint},
+    Request, Status,
+};
+
+// Sends the access token as a bearer token, if it is set
+#[derive(Clone)]
+pub struct BearerToken {
+    authorization: Option<MetadataValue<Ascii>>,
+}
+
+impl Interceptor for BearerToken {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        if let Some(authorization) = &self.authorization {
+            request.metadata_mut().insert("authorization", authorization.clone());
+        }
+
+        Ok(request)
+    }
+}
+
+type Service = InterceptedService<Channel, BearerToken>;
+
+pub struct Client {
+    pub grpc_endpoint: String,
+    pub tls_config: Option<ClientTlsConfig>,
+    channel: Channel,
+    bearer_token: BearerToken,
+}
+
+impl Client {
+    pub async fn connect(
+        grpc_endpoint: &str,
+        tls_config: Option<ClientTlsConfig>,
+    ) -> Result<Self, anyhow::Error> {
+        let mut endpoint = Endpoint::from_shared(grpc_endpoint.to_string())?;
+        if let Some(tls_config) = &tls_config {
+            endpoint = endpoint.tls_config(tls_config.clone())?;
+        }
+
+        let channel = match endpoint.connect().await {
+            Ok(channel) => channel,
+            Err(err) => return Err(anyhow::anyhow!("failed to connect to {}: {}", grpc_endpoint, err)),
+        };
+
+        Ok(Self {
+            grpc_endpoint: grpc_endpoint.to_string(),
+            tls_config,
+            channel,
+            bearer_token: BearerToken { authorization: None },
+        })
+    }
+
+    // The token is also used by requests of `cirrus_client_core`, such as streaming audio data
+    pub async fn login(&mut self, username: &str, password: &str) -> Result<(), anyhow::Error> {
+        let token = request::login(&self.grpc_endpoint, &self.tls_config, username, password).await?;
+        self.set_access_token(&token.access_token)
+    }
+
+    pub fn set_access_token(&mut self, access_token: &str) -> Result<(), anyhow::Error> {
+        request::set_access_token(Some(access_token.to_string()));
+        self.bearer_token.authorization = Some(format!("Bearer {}", access_token).parse()?);
+
+        Ok(())
+    }
+
+    pub fn audio_library(&self) -> AudioLibrarySvcClient<Service> {
+        AudioLibrarySvcClient::with_interceptor(self.channel.clone(), self.bearer_token.clone())
+    }
+
+    pub fn audio_tag(&self) -> AudioTagSvcClient<Service> {
+        AudioTagSvcClient::with_interceptor(self.channel.clone(), self.bearer_token.clone())
+    }
+
+    pub fn audio_data(&self) -> AudioDataSvcClient<Service> {
+        AudioDataSvcClient::with_interceptor(self.channel.clone(), self.bearer_token.clone())
+    }
+
+    pub fn job(&self) -> JobSvcClient<Service> {
+        JobSvcClient::with_interceptor(self.channel.clone(), self.bearer_token.clone())
+    }
+}
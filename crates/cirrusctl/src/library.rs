@@ -0,0 +1,93 @@
+use cirrus_protobuf::{
+    api::{job_event, AudioLibraryReq, JobReq, JobRes, JobStatus},
+    common::{ListRequest, RequestAction},
+};
+use tokio_stream::StreamExt;
+
+use crate::{client::Client, output};
+
+pub async fn add(client: &Client, path: &str, json: bool) -> Result<(), anyhow::Error> {
+    let res = client.audio_library()
+        .add_audio_library(AudioLibraryReq { path: path.to_string() })
+        .await?
+        .into_inner();
+
+    output::print(json, &res, |_| println!("added audio library {}", path))
+}
+
+pub async fn remove(client: &Client, path: &str, json: bool) -> Result<(), anyhow::Error> {
+    let res = client.audio_library()
+        .remove_audio_library(AudioLibraryReq { path: path.to_string() })
+        .await?
+        .into_inner();
+
+    output::print(json, &res, |res| println!("removed audio library {}, {}", path, res.status))
+}
+
+pub async fn list(client: &Client, items_per_page: u64, page: u64, json: bool) -> Result<(), anyhow::Error> {
+    let mut stream = client.audio_library()
+        .list_audio_libraries(ListRequest { items_per_page, page })
+        .await?
+        .into_inner();
+
+    let mut libraries = Vec::new();
+    while let Some(library) = stream.next().await {
+        libraries.push(library?);
+    }
+
+    output::print(json, &libraries, |libraries| {
+        for library in libraries.iter() {
+            let watch = if library.watch { "on" } else { "off" };
+            println!("{}  {}  watch: {}", library.id, library.path, watch);
+        }
+    })
+}
+
+pub async fn analyze(client: &Client, wait: bool, json: bool) -> Result<(), anyhow::Error> {
+    let job = client.audio_library()
+        .analyze_audio_library(RequestAction::default())
+        .await?
+        .into_inner();
+
+    print_job(client, job, wait, json).await
+}
+
+pub async fn refresh(client: &Client, wait: bool, json: bool) -> Result<(), anyhow::Error> {
+    let job = client.audio_library()
+        .refresh_audio_library(RequestAction::default())
+        .await?
+        .into_inner();
+
+    print_job(client, job, wait, json).await
+}
+
+// Follows events of the job until it is finished if `wait` is set, and prints the last state
+async fn print_job(client: &Client, mut job: JobRes, wait: bool, json: bool) -> Result<(), anyhow::Error> {
+    if wait {
+        let mut events = client.job()
+            .watch_job(JobReq { job_id: job.id.clone() })
+            .await?
+            .into_inner();
+
+        while let Some(event) = events.next().await {
+            let event = event?;
+
+            if !json && event.kind == job_event::Kind::FileError as i32 {
+                eprintln!("error: {}: {}", event.path, event.error);
+            }
+
+            if let Some(event_job) = event.job {
+                job = event_job;
+            }
+        }
+    }
+
+    let is_failed = job.status == JobStatus::Failed as i32;
+
+    output::print(json, &job, |job| println!("{}", output::format_job(job)))?;
+
+    match is_failed {
+        true => Err(anyhow::anyhow!("job {} is failed", job.id)),
+        false => Ok(()),
+    }
+}
@@ -0,0 +1,250 @@
+mod client;
+mod library;
+mod output;
+mod settings;
+mod tags;
+mod track;
+
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand, ValueEnum};
+use cirrus_protobuf::api::{AudioCodec, AudioTagSearchReq};
+
+use client::Client;
+use settings::Settings;
+
+const DEFAULT_ITEMS_PER_PAGE: u64 = 50;
+
+#[derive(Parser)]
+#[command(version, about = "Cirrus admin command-line tool")]
+struct Cli {
+    /// client.toml of the app, which sets the server endpoint and TLS
+    #[arg(long, global = true, env = "CIRRUSCTL_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// Directory that relative `tls.cert_path` is resolved at [default: current directory]
+    #[arg(long, global = true, env = "CIRRUSCTL_RESOURCE_DIR")]
+    resource_dir: Option<PathBuf>,
+
+    /// gRPC endpoint, which overrides `server.grpc_endpoint` of the config
+    #[arg(long, global = true, env = "CIRRUSCTL_ENDPOINT")]
+    endpoint: Option<String>,
+
+    /// Log in with this user before the command
+    #[arg(long, global = true, env = "CIRRUSCTL_USERNAME")]
+    username: Option<String>,
+
+    #[arg(long, global = true, env = "CIRRUSCTL_PASSWORD", hide_env_values = true)]
+    password: Option<String>,
+
+    /// Access token to send instead of logging in
+    #[arg(long, global = true, env = "CIRRUSCTL_ACCESS_TOKEN", hide_env_values = true)]
+    access_token: Option<String>,
+
+    /// Print results as JSON
+    #[arg(long, global = true)]
+    json: bool,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Manage audio libraries (requires an admin)
+    Library {
+        #[command(subcommand)]
+        command: LibraryCommand,
+    },
+    /// List and search audio tags
+    Tags {
+        #[command(subcommand)]
+        command: TagsCommand,
+    },
+    /// Show information of a track
+    Track {
+        #[command(subcommand)]
+        command: TrackCommand,
+    },
+    /// Decode a track as the player does, and write it to a WAV file for debugging
+    Stream {
+        audio_tag_id: String,
+        output: PathBuf,
+        /// Seconds to start from, which the stream is trimmed to at the sample
+        #[arg(long)]
+        start: Option<f64>,
+        #[command(flatten)]
+        format: FormatArgs,
+    },
+}
+
+#[derive(Subcommand)]
+enum LibraryCommand {
+    /// Add a directory of the server as a library
+    Add { path: String },
+    /// Remove a library, and its audio files and tags
+    Remove { path: String },
+    List {
+        #[command(flatten)]
+        page: PageArgs,
+    },
+    /// Sync changes of audio files of libraries
+    Refresh {
+        /// Wait until the job is finished
+        #[arg(long)]
+        wait: bool,
+    },
+    /// Read tags of audio files
+    Analyze {
+        /// Wait until the job is finished
+        #[arg(long)]
+        wait: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum TagsCommand {
+    List {
+        #[command(flatten)]
+        page: PageArgs,
+    },
+    Search {
+        #[arg(default_value = "")]
+        query: String,
+        /// Matches any of genres, and can be repeated
+        #[arg(long)]
+        genre: Vec<String>,
+        #[arg(long, default_value_t = 0)]
+        year_min: i32,
+        #[arg(long, default_value_t = 0)]
+        year_max: i32,
+        #[command(flatten)]
+        page: PageArgs,
+    },
+}
+
+#[derive(Subcommand)]
+enum TrackCommand {
+    /// Show metadata of streamed packets of a track
+    Meta {
+        audio_tag_id: String,
+        #[command(flatten)]
+        format: FormatArgs,
+    },
+}
+
+#[derive(clap::Args)]
+struct PageArgs {
+    #[arg(long, default_value_t = DEFAULT_ITEMS_PER_PAGE)]
+    items_per_page: u64,
+    /// Starts from 1
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..))]
+    page: u64,
+}
+
+#[derive(clap::Args)]
+struct FormatArgs {
+    #[arg(long, value_enum, default_value_t = Codec::Opus)]
+    codec: Codec,
+    /// 0: follows the source (mono or stereo), 1: mono, 2: stereo
+    #[arg(long, default_value_t = 0)]
+    channels: u32,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Codec {
+    Opus,
+    Flac,
+    Pcm,
+}
+
+impl From<Codec> for AudioCodec {
+    fn from(codec: Codec) -> Self {
+        match codec {
+            Codec::Opus => AudioCodec::Opus,
+            Codec::Flac => AudioCodec::Flac,
+            Codec::Pcm => AudioCodec::Pcm,
+        }
+    }
+}
+
+async fn connect(cli: &Cli) -> Result<Client, anyhow::Error> {
+    let settings = match &cli.config {
+        Some(config_path) => Some(Settings::load(config_path)?),
+        None => None,
+    };
+
+    let grpc_endpoint = match (&cli.endpoint, &settings) {
+        (Some(endpoint), _) => endpoint.clone(),
+        (None, Some(settings)) => settings.server.grpc_endpoint.clone(),
+        (None, None) => return Err(anyhow::anyhow!("set the server with --endpoint or --config")),
+    };
+
+    let tls_config = match &settings {
+        Some(settings) => {
+            let resource_dir = match &cli.resource_dir {
+                Some(resource_dir) => resource_dir.clone(),
+                None => std::env::current_dir()?,
+            };
+
+            settings.get_tls_config(&resource_dir)?
+        },
+        None => None,
+    };
+
+    let mut client = Client::connect(&grpc_endpoint, tls_config).await?;
+
+    match (&cli.access_token, &cli.username) {
+        (Some(access_token), _) => client.set_access_token(access_token)?,
+        (None, Some(username)) => {
+            let password = cli.password.as_deref().unwrap_or_default();
+            client.login(username, password).await?;
+        },
+        (None, None) => (),
+    }
+
+    Ok(client)
+}
+
+#[tokio::main]
+async fn main() -> Result<(), anyhow::Error> {
+    let cli = Cli::parse();
+    let client = connect(&cli).await?;
+    let json = cli.json;
+
+    match cli.command {
+        Command::Library { command } => match command {
+            LibraryCommand::Add { path } => library::add(&client, &path, json).await?,
+            LibraryCommand::Remove { path } => library::remove(&client, &path, json).await?,
+            LibraryCommand::List { page } => library::list(&client, page.items_per_page, page.page, json).await?,
+            LibraryCommand::Refresh { wait } => library::refresh(&client, wait, json).await?,
+            LibraryCommand::Analyze { wait } => library::analyze(&client, wait, json).await?,
+        },
+        Command::Tags { command } => match command {
+            TagsCommand::List { page } => tags::list(&client, page.items_per_page, page.page, json).await?,
+            TagsCommand::Search { query, genre, year_min, year_max, page } => {
+                let req = AudioTagSearchReq {
+                    query,
+                    genres: genre,
+                    year_min,
+                    year_max,
+                    items_per_page: page.items_per_page,
+                    page: page.page,
+                    ..Default::default()
+                };
+
+                tags::search(&client, req, json).await?
+            },
+        },
+        Command::Track { command } => match command {
+            TrackCommand::Meta { audio_tag_id, format } => {
+                track::meta(&client, &audio_tag_id, format.channels, format.codec.into(), json).await?
+            },
+        },
+        Command::Stream { audio_tag_id, output, start, format } => {
+            track::stream(&client, &audio_tag_id, &output, start, format.channels, format.codec.into(), json).await?
+        },
+    }
+
+    Ok(())
+}
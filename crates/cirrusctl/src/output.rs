@@ -0,0 +1,49 @@
+use cirrus_protobuf::api::{AudioTagRes, JobKind, JobRes, JobStatus};
+use serde::Serialize;
+
+// Prints the value as JSON, or as text with the function
+pub fn print<T: Serialize>(json: bool, value: &T, print_text: impl FnOnce(&T)) -> Result<(), anyhow::Error> {
+    match json {
+        true => println!("{}", serde_json::to_string_pretty(value)?),
+        false => print_text(value),
+    }
+
+    Ok(())
+}
+
+pub fn format_audio_tag(audio_tag: &AudioTagRes) -> String {
+    format!(
+        "{}  {} - {} ({}, {})",
+        audio_tag.id,
+        audio_tag.artist,
+        audio_tag.title,
+        audio_tag.album,
+        format_duration_ms(audio_tag.duration as u64),
+    )
+}
+
+pub fn format_job(job: &JobRes) -> String {
+    let kind = JobKind::from_i32(job.kind)
+        .map(|item| format!("{:?}", item))
+        .unwrap_or_default();
+    let status = JobStatus::from_i32(job.status)
+        .map(|item| format!("{:?}", item))
+        .unwrap_or_default();
+
+    let mut res = format!(
+        "job {} ({}): {}, files scanned: {}, tags written: {}, errors: {}",
+        job.id, kind, status, job.files_scanned, job.tags_written, job.error_count,
+    );
+
+    if !job.message.is_empty() {
+        res.push_str(&format!(", message: {}", job.message));
+    }
+
+    res
+}
+
+pub fn format_duration_ms(duration_ms: u64) -> String {
+    let duration_sec = duration_ms / 1000;
+
+    format!("{}:{:02}", duration_sec / 60, duration_sec % 60)
+}
@@ -0,0 +1,49 @@
+use std::path::Path;
+
+use cirrus_client_core::tls;
+use config::{Config, ConfigError, File};
+use serde_derive::Deserialize;
+use tonic::transport::ClientTlsConfig;
+
+#[derive(Deserialize)]
+#[allow(unused)]
+pub struct Tls {
+    pub use_tls: bool,
+    pub domain_name: String,
+    pub cert_path: String,
+}
+
+#[derive(Deserialize)]
+#[allow(unused)]
+pub struct Server {
+    pub grpc_endpoint: String,
+}
+
+// `server` and `tls` of client.toml of the app, so that both connect to the server in the same way
+#[derive(Deserialize)]
+#[allow(unused)]
+pub struct Settings {
+    pub server: Server,
+    pub tls: Tls,
+}
+
+impl Settings {
+    pub fn load(config_path: &Path) -> Result<Self, ConfigError> {
+        let s = Config::builder()
+            .add_source(File::from(config_path))
+            .build()?;
+
+        s.try_deserialize()
+    }
+
+    // Relative `tls.cert_path` is resolved at the resource directory, as the app does
+    pub fn get_tls_config(&self, resource_dir: &Path) -> Result<Option<ClientTlsConfig>, anyhow::Error> {
+        if !self.tls.use_tls {
+            return Ok(None);
+        }
+
+        let cert_path = resource_dir.join(&self.tls.cert_path);
+
+        Ok(Some(tls::load_cert(&cert_path, &self.tls.domain_name)?))
+    }
+}
@@ -0,0 +1,39 @@
+use cirrus_protobuf::{api::AudioTagSearchReq, common::ListRequest};
+use tokio_stream::StreamExt;
+
+use crate::{client::Client, output};
+
+pub async fn list(client: &Client, items_per_page: u64, page: u64, json: bool) -> Result<(), anyhow::Error> {
+    let mut stream = client.audio_tag()
+        .list_audio_tags(ListRequest { items_per_page, page })
+        .await?
+        .into_inner();
+
+    let mut audio_tags = Vec::new();
+    while let Some(audio_tag) = stream.next().await {
+        audio_tags.push(audio_tag?);
+    }
+
+    output::print(json, &audio_tags, |audio_tags| {
+        for audio_tag in audio_tags.iter() {
+            println!("{}", output::format_audio_tag(audio_tag));
+        }
+    })
+}
+
+pub async fn search(client: &Client, req: AudioTagSearchReq, json: bool) -> Result<(), anyhow::Error> {
+    let res = client.audio_tag()
+        .search_audio_tags(req)
+        .await?
+        .into_inner();
+
+    output::print(json, &res, |res| {
+        for hit in res.hits.iter() {
+            if let Some(audio_tag) = &hit.audio_tag {
+                println!("{:.2}  {}", hit.score, output::format_audio_tag(audio_tag));
+            }
+        }
+
+        println!("{} of {} tags", res.hits.len(), res.total);
+    })
+}
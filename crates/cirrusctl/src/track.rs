@@ -0,0 +1,108 @@
+use std::path::Path;
+
+use audio::InterleavedBuf;
+use cirrus_client_core::{audio::PacketDecoder, AudioSource};
+use cirrus_protobuf::api::{audio_data_req::Start, AudioCodec, AudioDataReq, AudioMetaReq};
+use tokio_stream::StreamExt;
+
+use crate::{client::Client, output};
+
+pub async fn meta(
+    client: &Client,
+    audio_tag_id: &str,
+    channels: u32,
+    codec: AudioCodec,
+    json: bool,
+) -> Result<(), anyhow::Error> {
+    let res = client.audio_data()
+        .get_meta(AudioMetaReq {
+            audio_tag_id: audio_tag_id.to_string(),
+            channels,
+            encoding_profile: None,
+            codec: codec as i32,
+        })
+        .await?
+        .into_inner();
+
+    output::print(json, &res, |res| {
+        println!("length: {}", output::format_duration_ms((res.content_length * 1000.) as u64));
+        println!("source: {} Hz, {} channels, {} bps", res.orig_sample_rate, res.orig_channels, res.orig_bit_rate);
+        println!("codec: {:?}", AudioCodec::from_i32(res.codec).unwrap_or(AudioCodec::Opus));
+        println!("packets: {} of {} frames at {} Hz ({} sec each)", res.sp_packets, res.packet_len, res.packet_sample_rate, res.packet_dur);
+        println!("channels: {}", res.channels);
+
+        if let Some(encoding_profile) = &res.encoding_profile {
+            println!("bitrate: {} bps, complexity: {}", encoding_profile.bitrate, encoding_profile.complexity);
+        }
+    })
+}
+
+// Decodes packets as the player does, and writes samples to a 32-bit float WAV file. Samples
+// before the start are trimmed as the server tells
+pub async fn stream(
+    client: &Client,
+    audio_tag_id: &str,
+    output_path: &Path,
+    start_sec: Option<f64>,
+    channels: u32,
+    codec: AudioCodec,
+    json: bool,
+) -> Result<(), anyhow::Error> {
+    let source = AudioSource::new(
+        &client.grpc_endpoint,
+        &client.tls_config,
+        audio_tag_id,
+        channels,
+        codec,
+        None,
+    ).await?;
+
+    let mut decoder = PacketDecoder::new(&source)?;
+    let mut writer = hound::WavWriter::create(output_path, hound::WavSpec {
+        channels: source.channels as u16,
+        sample_rate: source.packet_sample_rate as u32,
+        bits_per_sample: 32,
+        sample_format: hound::SampleFormat::Float,
+    })?;
+
+    let mut packets = client.audio_data()
+        .get_data(AudioDataReq {
+            audio_tag_id: audio_tag_id.to_string(),
+            packet_start_idx: 0,
+            packet_num: source.content_packets,
+            channels,
+            encoding_profile: source.encoding_profile.clone(),
+            codec: source.codec as i32,
+            start: start_sec.map(Start::StartSec),
+        })
+        .await?
+        .into_inner();
+
+    let mut packet_num = 0;
+
+    while let Some(packet) = packets.next().await {
+        let packet = packet?;
+        let samples = decoder.decode(&packet.encoded_samples)?;
+        let trimmed_samples = packet.trim_start_samples as usize * source.channels;
+
+        for sample in samples.as_interleaved().iter().skip(trimmed_samples) {
+            writer.write_sample(*sample)?;
+        }
+
+        packet_num += 1;
+    }
+
+    writer.finalize()?;
+
+    let res = serde_json::json!({
+        "path": output_path,
+        "packets": packet_num,
+        "channels": source.channels,
+        "sample_rate": source.packet_sample_rate,
+    });
+
+    output::print(json, &res, |_| println!(
+        "wrote {} packets ({} channels, {} Hz) to {:?}",
+        packet_num, source.channels, source.packet_sample_rate, output_path,
+    ))
+}